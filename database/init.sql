@@ -12,6 +12,7 @@ CREATE TYPE image_type AS ENUM ('pdf', 'png', 'tiff', 'jpeg', 'gif');
 CREATE TABLE platform.sources (
 	id serial NOT NULL,
 	name text NOT NULL,
+	slug text NOT NULL, -- generated from the name on insert and never changed, used in URLs
 	url text,
 	author text,
 	author_url text,
@@ -19,12 +20,17 @@ CREATE TABLE platform.sources (
 	image_url text, 
 	image_type image_type, -- ENUM defined above
 	ai_generated bool,
-	PRIMARY KEY (id)
+	version int NOT NULL DEFAULT 1, -- bumped on every UPDATE, used for optimistic concurrency control
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
+	PRIMARY KEY (id),
+	CONSTRAINT unique_source_slug UNIQUE(slug)
 );
 
 CREATE TABLE platform.topics (
 	id serial NOT NULL,
 	topic text NOT NULL,
+	slug text NOT NULL, -- generated from the topic on insert and never changed, used in URLs
 	is_verified bool NOT NULL DEFAULT FALSE, 
 	brief_description text,
 	full_description text,
@@ -36,13 +42,18 @@ CREATE TABLE platform.topics (
 	ai_bullet_points text[],
 	ai_parallels text[],
 	ai_examples text[],
+	version int NOT NULL DEFAULT 1, -- bumped on every UPDATE, used for optimistic concurrency control
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	PRIMARY KEY (id),
-	CONSTRAINT unique_topic UNIQUE(topic)
+	CONSTRAINT unique_topic UNIQUE(topic),
+	CONSTRAINT unique_topic_slug UNIQUE(slug)
 );
 
 CREATE TABLE platform.terms (
 	id serial NOT NULL,
 	term text NOT NULL,
+	slug text NOT NULL, -- generated from the term on insert and never changed, used in URLs
 	is_verified bool NOT NULL DEFAULT FALSE, 
 	brief_description text,
 	full_description text,
@@ -54,8 +65,12 @@ CREATE TABLE platform.terms (
 	ai_bullet_points text[],
 	ai_parallels text[],
 	ai_examples text[],
+	version int NOT NULL DEFAULT 1, -- bumped on every UPDATE, used for optimistic concurrency control
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	PRIMARY KEY (id),
-	CONSTRAINT unique_term UNIQUE(term)
+	CONSTRAINT unique_term UNIQUE(term),
+	CONSTRAINT unique_term_slug UNIQUE(slug)
 );
 
 /*
@@ -69,6 +84,8 @@ CREATE TABLE platform.questions (
 	id serial NOT NULL,
 	question text NOT NULL,
 	topic_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	PRIMARY KEY (id),
 	FOREIGN KEY (topic_id) REFERENCES platform.topics(id), 
 	CONSTRAINT unique_question UNIQUE(question)
@@ -79,6 +96,8 @@ CREATE TABLE platform.articles (
 	title text,
 	author text,
 	publish_date DATE,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	PRIMARY KEY (id)
 );
 
@@ -93,6 +112,8 @@ Sample query patterns:
 CREATE TABLE platform.topics_to_sources (
 	topic_id int NOT NULL,
 	source_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	FOREIGN KEY (topic_id) REFERENCES platform.topics(id),
 	FOREIGN KEY (source_id) REFERENCES platform.sources(id),
 	UNIQUE(topic_id, source_id)
@@ -101,6 +122,8 @@ CREATE TABLE platform.topics_to_sources (
 CREATE TABLE platform.terms_to_sources (
 	term_id int NOT NULL,
 	source_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	FOREIGN KEY (term_id) REFERENCES platform.terms(id),
 	FOREIGN KEY (source_id) REFERENCES platform.sources(id),
 	UNIQUE(term_id, source_id)
@@ -109,6 +132,8 @@ CREATE TABLE platform.terms_to_sources (
 CREATE TABLE platform.terms_to_topics (
 	term_id int NOT NULL,
 	topic_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	FOREIGN KEY (term_id) REFERENCES platform.terms(id),
 	FOREIGN KEY (topic_id) REFERENCES platform.topics(id),
 	UNIQUE(term_id, topic_id)
@@ -117,6 +142,8 @@ CREATE TABLE platform.terms_to_topics (
 CREATE TABLE platform.articles_to_topics (
 	article_id int NOT NULL,
 	topic_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	FOREIGN KEY (article_id) REFERENCES platform.articles(id),
 	FOREIGN KEY (topic_id) REFERENCES platform.topics(id),
 	UNIQUE(article_id, topic_id)
@@ -125,6 +152,8 @@ CREATE TABLE platform.articles_to_topics (
 CREATE TABLE platform.articles_to_terms (
 	article_id int NOT NULL,
 	term_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	FOREIGN KEY (article_id) REFERENCES platform.articles(id),
 	FOREIGN KEY (term_id) REFERENCES platform.terms(id),
 	UNIQUE(article_id, term_id)
@@ -133,6 +162,8 @@ CREATE TABLE platform.articles_to_terms (
 CREATE TABLE platform.articles_to_questions (
 	article_id int NOT NULL,
 	question_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	FOREIGN KEY (article_id) REFERENCES platform.articles(id),
 	FOREIGN KEY (question_id) REFERENCES platform.questions(id),
 	UNIQUE(article_id, question_id)
@@ -145,24 +176,214 @@ CREATE TABLE platform.related_topics (
 	id serial NOT NULL,
 	parent_id int NOT NULL,
 	child_id int NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	updated_at timestamptz NOT NULL DEFAULT now(),
 	PRIMARY KEY (id),
 	FOREIGN KEY (parent_id) REFERENCES platform.topics(id),
 	FOREIGN KEY (child_id) REFERENCES platform.topics(id),
 	UNIQUE(parent_id, child_id)
 );
 
+/*
+Timestamps
+
+Every table has created_at / updated_at columns. The trigger below keeps
+updated_at current on every UPDATE, so it also covers rows changed directly in psql.
+*/
+CREATE FUNCTION platform.set_updated_at() RETURNS trigger AS $$
+BEGIN
+	NEW.updated_at = now();
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DO $$
+DECLARE
+	table_name text;
+BEGIN
+	FOR table_name IN
+		SELECT tablename FROM pg_tables WHERE schemaname = 'platform'
+	LOOP
+		EXECUTE format(
+			'CREATE TRIGGER set_updated_at BEFORE UPDATE ON platform.%I
+			FOR EACH ROW EXECUTE FUNCTION platform.set_updated_at()',
+			table_name
+		);
+	END LOOP;
+END;
+$$;
+
+/*
+Row versions
+
+topics, terms and sources carry a version that is incremented on every UPDATE.
+Updates through the API must send the version they were based on and are rejected
+when it is stale.
+*/
+CREATE FUNCTION platform.bump_version() RETURNS trigger AS $$
+BEGIN
+	NEW.version = OLD.version + 1;
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER bump_version BEFORE UPDATE ON platform.sources
+	FOR EACH ROW EXECUTE FUNCTION platform.bump_version();
+CREATE TRIGGER bump_version BEFORE UPDATE ON platform.topics
+	FOR EACH ROW EXECUTE FUNCTION platform.bump_version();
+CREATE TRIGGER bump_version BEFORE UPDATE ON platform.terms
+	FOR EACH ROW EXECUTE FUNCTION platform.bump_version();
+
+/*
+Change feed
+
+Every insert, update and delete of a topic, term, source, question or article, and every
+row added to or removed from a bridge table, is recorded in platform.events and sent on
+the platform_events channel with NOTIFY, so /events also delivers changes made by other
+server instances or directly in psql. Clients resume after a disconnect from the event ids.
+*/
+CREATE TABLE platform.events (
+	id bigserial NOT NULL,
+	-- created, updated, deleted, linked or unlinked
+	action text NOT NULL,
+	entity_type text NOT NULL,
+	entity_id int NOT NULL,
+	-- the other side of a link
+	related_type text,
+	related_id int,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	PRIMARY KEY (id)
+);
+
+-- TG_ARGV[0] is the entity type of the table, e.g. 'term' for platform.terms
+CREATE FUNCTION platform.record_entity_event() RETURNS trigger AS $$
+BEGIN
+	INSERT INTO platform.events (action, entity_type, entity_id)
+	VALUES (
+		CASE TG_OP WHEN 'INSERT' THEN 'created' WHEN 'UPDATE' THEN 'updated' ELSE 'deleted' END,
+		TG_ARGV[0],
+		CASE TG_OP WHEN 'DELETE' THEN OLD.id ELSE NEW.id END
+	);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- TG_ARGV is the two entity types of the bridge table, e.g. 'term', 'topic' for terms_to_topics
+CREATE FUNCTION platform.record_link_event() RETURNS trigger AS $$
+DECLARE
+	link jsonb;
+BEGIN
+	IF TG_OP = 'DELETE' THEN
+		link := to_jsonb(OLD);
+	ELSE
+		link := to_jsonb(NEW);
+	END IF;
+	INSERT INTO platform.events (action, entity_type, entity_id, related_type, related_id)
+	VALUES (
+		CASE TG_OP WHEN 'DELETE' THEN 'unlinked' ELSE 'linked' END,
+		TG_ARGV[0],
+		(link ->> (TG_ARGV[0] || '_id'))::int,
+		TG_ARGV[1],
+		(link ->> (TG_ARGV[1] || '_id'))::int
+	);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- sent when the transaction commits, so listeners never see a change that was rolled back
+CREATE FUNCTION platform.notify_event() RETURNS trigger AS $$
+BEGIN
+	PERFORM pg_notify('platform_events', row_to_json(NEW)::text);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_event AFTER INSERT ON platform.events
+	FOR EACH ROW EXECUTE FUNCTION platform.notify_event();
+
+CREATE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.topics
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('topic');
+CREATE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.terms
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('term');
+CREATE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.sources
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('source');
+CREATE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.questions
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('question');
+CREATE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.articles
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('article');
+
+CREATE TRIGGER record_event AFTER INSERT OR DELETE ON platform.topics_to_sources
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('topic', 'source');
+CREATE TRIGGER record_event AFTER INSERT OR DELETE ON platform.terms_to_sources
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('term', 'source');
+CREATE TRIGGER record_event AFTER INSERT OR DELETE ON platform.terms_to_topics
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('term', 'topic');
+CREATE TRIGGER record_event AFTER INSERT OR DELETE ON platform.articles_to_topics
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('article', 'topic');
+CREATE TRIGGER record_event AFTER INSERT OR DELETE ON platform.articles_to_terms
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('article', 'term');
+CREATE TRIGGER record_event AFTER INSERT OR DELETE ON platform.articles_to_questions
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('article', 'question');
+
+/*
+Webhooks
+
+Subscriptions to the lifecycle events sent by the API (term.created, topic.verified,
+source.linked, ...), see helpers/webhooks.rs. Every event a subscription receives is a row
+in webhook_deliveries, which is the queue the server sends from and the delivery log.
+*/
+-- two random uuids without the dashes, from the server's strong random source
+CREATE FUNCTION platform.new_webhook_secret() RETURNS text AS $$
+	SELECT replace(gen_random_uuid()::text || gen_random_uuid()::text, '-', '')
+$$ LANGUAGE sql;
+
+CREATE TABLE platform.webhooks (
+	id serial NOT NULL,
+	url text NOT NULL,
+	-- the key the payloads are signed with, generated when none is given
+	secret text NOT NULL DEFAULT platform.new_webhook_secret(),
+	-- the event types sent to the url, all of them when empty
+	event_types text[] NOT NULL DEFAULT '{}',
+	created_at timestamptz NOT NULL DEFAULT now(),
+	PRIMARY KEY (id)
+);
+
+CREATE TABLE platform.webhook_deliveries (
+	id bigserial NOT NULL,
+	webhook_id int NOT NULL,
+	event_type text NOT NULL,
+	-- the payload's `data`, the record the event is about
+	data jsonb NOT NULL,
+	-- pending until it is delivered, failed when every attempt failed
+	status text NOT NULL DEFAULT 'pending',
+	attempts int NOT NULL DEFAULT 0,
+	next_attempt_at timestamptz NOT NULL DEFAULT now(),
+	last_status_code int,
+	last_error text,
+	-- the delivery this one replays
+	replay_of bigint,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	delivered_at timestamptz,
+	PRIMARY KEY (id),
+	FOREIGN KEY (webhook_id) REFERENCES platform.webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_pending ON platform.webhook_deliveries (next_attempt_at)
+	WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_id ON platform.webhook_deliveries (webhook_id, id);
+
 ----------------- Insertion of Sample Data -----------------
 
 -- we don't need to specify the `id` column bc it is serial 
 -- so it will auto-increment
-INSERT INTO platform.sources (name, url, media_type, ai_generated) VALUES ('dictionary storm', 'https://www.merriam-webster.com/dictionary/storm', 'web', 'false');
-INSERT INTO platform.sources (name, url, media_type, ai_generated) VALUES ('wikipedia tropical cyclone', 'https://en.wikipedia.org/wiki/Tropical_cyclone', 'web', 'false');
-INSERT INTO platform.sources (name, url, media_type, ai_generated) VALUES ('wikipedia atlantic hurricane', 'https://en.wikipedia.org/wiki/Atlantic_hurricane', 'web', 'false');
+INSERT INTO platform.sources (name, slug, url, media_type, ai_generated) VALUES ('dictionary storm', 'dictionary-storm', 'https://www.merriam-webster.com/dictionary/storm', 'web', 'false');
+INSERT INTO platform.sources (name, slug, url, media_type, ai_generated) VALUES ('wikipedia tropical cyclone', 'wikipedia-tropical-cyclone', 'https://en.wikipedia.org/wiki/Tropical_cyclone', 'web', 'false');
+INSERT INTO platform.sources (name, slug, url, media_type, ai_generated) VALUES ('wikipedia atlantic hurricane', 'wikipedia-atlantic-hurricane', 'https://en.wikipedia.org/wiki/Atlantic_hurricane', 'web', 'false');
 
-INSERT INTO platform.topics (topic, brief_description) VALUES ('Hurricane', 'a tropical cyclone that forms in the Atlantic Ocean, primarily between the months of June and November.');
+INSERT INTO platform.topics (topic, slug, brief_description) VALUES ('Hurricane', 'hurricane', 'a tropical cyclone that forms in the Atlantic Ocean, primarily between the months of June and November.');
 
-INSERT INTO platform.terms (term, brief_description) VALUES ('Storm', 'a disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning');
-INSERT INTO platform.terms (term, brief_description) VALUES ('Tropical Cycle', 'a rapidly rotating storm system characterized by a low-pressure center, a closed low-level atmospheric circulation, strong winds, and a spiral arrangement of thunderstorms that produce heavy rain and squalls.');
+INSERT INTO platform.terms (term, slug, brief_description) VALUES ('Storm', 'storm', 'a disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning');
+INSERT INTO platform.terms (term, slug, brief_description) VALUES ('Tropical Cycle', 'tropical-cycle', 'a rapidly rotating storm system characterized by a low-pressure center, a closed low-level atmospheric circulation, strong winds, and a spiral arrangement of thunderstorms that produce heavy rain and squalls.');
 
 /*
 Manually updating the link tables, this can be done via methods in the code now.
@@ -0,0 +1,360 @@
+/*
+The SQLite version of init.sql, for running the API without Postgres, e.g.
+DATABASE_URL="sqlite://platform.db". The server runs it when it opens a database
+without the tables, see src/repository/sqlite.rs.
+
+Differences to the Postgres schema:
+- there are no schemas, the tables aren't prefixed with platform.
+- text[] columns hold a JSON array of strings
+- media_type and image_type are text columns checked against the values of the Postgres enums
+- booleans are 0 or 1
+- timestamps are UTC text like 2023-03-27T12:00:00.000Z, so they sort as text
+- the events are polled by the server instead of sent with NOTIFY
+*/
+CREATE TABLE sources (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	name text NOT NULL,
+	slug text NOT NULL, -- generated from the name on insert and never changed, used in URLs
+	url text,
+	author text,
+	author_url text,
+	media_type text CHECK (media_type IN ('audio', 'video', 'web', 'book', 'scientific article')),
+	image_url text,
+	image_type text CHECK (image_type IN ('pdf', 'png', 'tiff', 'jpeg', 'gif')),
+	ai_generated integer CHECK (ai_generated IN (0, 1)),
+	version integer NOT NULL DEFAULT 1, -- bumped on every UPDATE, used for optimistic concurrency control
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	CONSTRAINT unique_source_slug UNIQUE(slug)
+);
+
+CREATE TABLE topics (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	topic text NOT NULL,
+	slug text NOT NULL, -- generated from the topic on insert and never changed, used in URLs
+	is_verified integer NOT NULL DEFAULT 0 CHECK (is_verified IN (0, 1)),
+	brief_description text,
+	full_description text,
+	bullet_points text CHECK (json_type(bullet_points) = 'array'),
+	examples text CHECK (json_type(examples) = 'array'),
+	parallels text CHECK (json_type(parallels) = 'array'),
+	ai_brief_description text,
+	ai_full_description text,
+	ai_bullet_points text CHECK (json_type(ai_bullet_points) = 'array'),
+	ai_parallels text CHECK (json_type(ai_parallels) = 'array'),
+	ai_examples text CHECK (json_type(ai_examples) = 'array'),
+	version integer NOT NULL DEFAULT 1, -- bumped on every UPDATE, used for optimistic concurrency control
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	CONSTRAINT unique_topic UNIQUE(topic),
+	CONSTRAINT unique_topic_slug UNIQUE(slug)
+);
+
+CREATE TABLE terms (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	term text NOT NULL,
+	slug text NOT NULL, -- generated from the term on insert and never changed, used in URLs
+	is_verified integer NOT NULL DEFAULT 0 CHECK (is_verified IN (0, 1)),
+	brief_description text,
+	full_description text,
+	bullet_points text CHECK (json_type(bullet_points) = 'array'),
+	examples text CHECK (json_type(examples) = 'array'),
+	parallels text CHECK (json_type(parallels) = 'array'),
+	ai_brief_description text,
+	ai_full_description text,
+	ai_bullet_points text CHECK (json_type(ai_bullet_points) = 'array'),
+	ai_parallels text CHECK (json_type(ai_parallels) = 'array'),
+	ai_examples text CHECK (json_type(ai_examples) = 'array'),
+	version integer NOT NULL DEFAULT 1, -- bumped on every UPDATE, used for optimistic concurrency control
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	CONSTRAINT unique_term UNIQUE(term),
+	CONSTRAINT unique_term_slug UNIQUE(slug)
+);
+
+CREATE TABLE questions (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	question text NOT NULL,
+	topic_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (topic_id) REFERENCES topics(id),
+	CONSTRAINT unique_question UNIQUE(question)
+);
+
+CREATE TABLE articles (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	title text,
+	author text,
+	publish_date text, -- 2023-03-27
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+/*
+Bridge Table Definitions
+*/
+
+CREATE TABLE topics_to_sources (
+	topic_id integer NOT NULL,
+	source_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (topic_id) REFERENCES topics(id),
+	FOREIGN KEY (source_id) REFERENCES sources(id),
+	UNIQUE(topic_id, source_id)
+);
+
+CREATE TABLE terms_to_sources (
+	term_id integer NOT NULL,
+	source_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (term_id) REFERENCES terms(id),
+	FOREIGN KEY (source_id) REFERENCES sources(id),
+	UNIQUE(term_id, source_id)
+);
+
+CREATE TABLE terms_to_topics (
+	term_id integer NOT NULL,
+	topic_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (term_id) REFERENCES terms(id),
+	FOREIGN KEY (topic_id) REFERENCES topics(id),
+	UNIQUE(term_id, topic_id)
+);
+
+CREATE TABLE articles_to_topics (
+	article_id integer NOT NULL,
+	topic_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (article_id) REFERENCES articles(id),
+	FOREIGN KEY (topic_id) REFERENCES topics(id),
+	UNIQUE(article_id, topic_id)
+);
+
+CREATE TABLE articles_to_terms (
+	article_id integer NOT NULL,
+	term_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (article_id) REFERENCES articles(id),
+	FOREIGN KEY (term_id) REFERENCES terms(id),
+	UNIQUE(article_id, term_id)
+);
+
+CREATE TABLE articles_to_questions (
+	article_id integer NOT NULL,
+	question_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (article_id) REFERENCES articles(id),
+	FOREIGN KEY (question_id) REFERENCES questions(id),
+	UNIQUE(article_id, question_id)
+);
+
+/*
+Mind Map Tables
+*/
+CREATE TABLE related_topics (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	parent_id integer NOT NULL,
+	child_id integer NOT NULL,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	updated_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	FOREIGN KEY (parent_id) REFERENCES topics(id),
+	FOREIGN KEY (child_id) REFERENCES topics(id),
+	UNIQUE(parent_id, child_id)
+);
+
+/*
+Timestamps and row versions
+
+SQLite triggers can't change the row being updated, so these update it again when the
+statement didn't set updated_at (or, for topics, terms and sources, the version) itself.
+The second update doesn't fire the same trigger again since recursive triggers are off.
+*/
+CREATE TRIGGER bump_version_sources AFTER UPDATE ON sources
+	FOR EACH ROW WHEN NEW.version = OLD.version
+	BEGIN
+		UPDATE sources SET version = OLD.version + 1,
+			updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+	END;
+CREATE TRIGGER bump_version_topics AFTER UPDATE ON topics
+	FOR EACH ROW WHEN NEW.version = OLD.version
+	BEGIN
+		UPDATE topics SET version = OLD.version + 1,
+			updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+	END;
+CREATE TRIGGER bump_version_terms AFTER UPDATE ON terms
+	FOR EACH ROW WHEN NEW.version = OLD.version
+	BEGIN
+		UPDATE terms SET version = OLD.version + 1,
+			updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+	END;
+CREATE TRIGGER set_updated_at_questions AFTER UPDATE ON questions
+	FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+	BEGIN
+		UPDATE questions SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+	END;
+CREATE TRIGGER set_updated_at_articles AFTER UPDATE ON articles
+	FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+	BEGIN
+		UPDATE articles SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+	END;
+
+/*
+Change feed
+
+Same events as in init.sql. The updated triggers only fire for the update made by the
+triggers above, so a change is recorded once.
+*/
+CREATE TABLE events (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	-- created, updated, deleted, linked or unlinked
+	action text NOT NULL,
+	entity_type text NOT NULL,
+	entity_id integer NOT NULL,
+	-- the other side of a link
+	related_type text,
+	related_id integer,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE TRIGGER topic_created AFTER INSERT ON topics FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('created', 'topic', NEW.id); END;
+CREATE TRIGGER topic_updated AFTER UPDATE ON topics FOR EACH ROW WHEN NEW.version <> OLD.version
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('updated', 'topic', NEW.id); END;
+CREATE TRIGGER topic_deleted AFTER DELETE ON topics FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('deleted', 'topic', OLD.id); END;
+
+CREATE TRIGGER term_created AFTER INSERT ON terms FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('created', 'term', NEW.id); END;
+CREATE TRIGGER term_updated AFTER UPDATE ON terms FOR EACH ROW WHEN NEW.version <> OLD.version
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('updated', 'term', NEW.id); END;
+CREATE TRIGGER term_deleted AFTER DELETE ON terms FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('deleted', 'term', OLD.id); END;
+
+CREATE TRIGGER source_created AFTER INSERT ON sources FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('created', 'source', NEW.id); END;
+CREATE TRIGGER source_updated AFTER UPDATE ON sources FOR EACH ROW WHEN NEW.version <> OLD.version
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('updated', 'source', NEW.id); END;
+CREATE TRIGGER source_deleted AFTER DELETE ON sources FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('deleted', 'source', OLD.id); END;
+
+CREATE TRIGGER question_created AFTER INSERT ON questions FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('created', 'question', NEW.id); END;
+CREATE TRIGGER question_updated AFTER UPDATE ON questions FOR EACH ROW WHEN NEW.updated_at <> OLD.updated_at
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('updated', 'question', NEW.id); END;
+CREATE TRIGGER question_deleted AFTER DELETE ON questions FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('deleted', 'question', OLD.id); END;
+
+CREATE TRIGGER article_created AFTER INSERT ON articles FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('created', 'article', NEW.id); END;
+CREATE TRIGGER article_updated AFTER UPDATE ON articles FOR EACH ROW WHEN NEW.updated_at <> OLD.updated_at
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('updated', 'article', NEW.id); END;
+CREATE TRIGGER article_deleted AFTER DELETE ON articles FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id) VALUES ('deleted', 'article', OLD.id); END;
+
+CREATE TRIGGER topics_to_sources_linked AFTER INSERT ON topics_to_sources FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('linked', 'topic', NEW.topic_id, 'source', NEW.source_id); END;
+CREATE TRIGGER topics_to_sources_unlinked AFTER DELETE ON topics_to_sources FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('unlinked', 'topic', OLD.topic_id, 'source', OLD.source_id); END;
+
+CREATE TRIGGER terms_to_sources_linked AFTER INSERT ON terms_to_sources FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('linked', 'term', NEW.term_id, 'source', NEW.source_id); END;
+CREATE TRIGGER terms_to_sources_unlinked AFTER DELETE ON terms_to_sources FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('unlinked', 'term', OLD.term_id, 'source', OLD.source_id); END;
+
+CREATE TRIGGER terms_to_topics_linked AFTER INSERT ON terms_to_topics FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('linked', 'term', NEW.term_id, 'topic', NEW.topic_id); END;
+CREATE TRIGGER terms_to_topics_unlinked AFTER DELETE ON terms_to_topics FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('unlinked', 'term', OLD.term_id, 'topic', OLD.topic_id); END;
+
+CREATE TRIGGER articles_to_topics_linked AFTER INSERT ON articles_to_topics FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('linked', 'article', NEW.article_id, 'topic', NEW.topic_id); END;
+CREATE TRIGGER articles_to_topics_unlinked AFTER DELETE ON articles_to_topics FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('unlinked', 'article', OLD.article_id, 'topic', OLD.topic_id); END;
+
+CREATE TRIGGER articles_to_terms_linked AFTER INSERT ON articles_to_terms FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('linked', 'article', NEW.article_id, 'term', NEW.term_id); END;
+CREATE TRIGGER articles_to_terms_unlinked AFTER DELETE ON articles_to_terms FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('unlinked', 'article', OLD.article_id, 'term', OLD.term_id); END;
+
+CREATE TRIGGER articles_to_questions_linked AFTER INSERT ON articles_to_questions FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('linked', 'article', NEW.article_id, 'question', NEW.question_id); END;
+CREATE TRIGGER articles_to_questions_unlinked AFTER DELETE ON articles_to_questions FOR EACH ROW
+	BEGIN INSERT INTO events (action, entity_type, entity_id, related_type, related_id)
+		VALUES ('unlinked', 'article', OLD.article_id, 'question', OLD.question_id); END;
+
+/*
+Webhooks, see init.sql
+*/
+CREATE TABLE webhooks (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	url text NOT NULL,
+	-- the key the payloads are signed with, 64 random hex digits when none is given
+	secret text NOT NULL DEFAULT (lower(hex(randomblob(32)))),
+	-- the event types sent to the url as a JSON array, all of them when empty
+	event_types text NOT NULL DEFAULT '[]' CHECK (json_type(event_types) = 'array'),
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE TABLE webhook_deliveries (
+	id integer PRIMARY KEY AUTOINCREMENT,
+	webhook_id integer NOT NULL,
+	event_type text NOT NULL,
+	-- the payload's `data`, the record the event is about
+	data text NOT NULL CHECK (json_valid(data)),
+	-- pending until it is delivered, failed when every attempt failed
+	status text NOT NULL DEFAULT 'pending',
+	attempts integer NOT NULL DEFAULT 0,
+	next_attempt_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	last_status_code integer,
+	last_error text,
+	-- the delivery this one replays
+	replay_of integer,
+	created_at text NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+	delivered_at text,
+	FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_pending ON webhook_deliveries (next_attempt_at)
+	WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, id);
+
+----------------- Insertion of Sample Data -----------------
+
+INSERT INTO sources (name, slug, url, media_type, ai_generated) VALUES ('dictionary storm', 'dictionary-storm', 'https://www.merriam-webster.com/dictionary/storm', 'web', 0);
+INSERT INTO sources (name, slug, url, media_type, ai_generated) VALUES ('wikipedia tropical cyclone', 'wikipedia-tropical-cyclone', 'https://en.wikipedia.org/wiki/Tropical_cyclone', 'web', 0);
+INSERT INTO sources (name, slug, url, media_type, ai_generated) VALUES ('wikipedia atlantic hurricane', 'wikipedia-atlantic-hurricane', 'https://en.wikipedia.org/wiki/Atlantic_hurricane', 'web', 0);
+
+INSERT INTO topics (topic, slug, brief_description) VALUES ('Hurricane', 'hurricane', 'a tropical cyclone that forms in the Atlantic Ocean, primarily between the months of June and November.');
+
+INSERT INTO terms (term, slug, brief_description) VALUES ('Storm', 'storm', 'a disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning');
+INSERT INTO terms (term, slug, brief_description) VALUES ('Tropical Cycle', 'tropical-cycle', 'a rapidly rotating storm system characterized by a low-pressure center, a closed low-level atmospheric circulation, strong winds, and a spiral arrangement of thunderstorms that produce heavy rain and squalls.');
+
+INSERT INTO terms_to_sources (term_id, source_id) VALUES (1, 1), (2, 2);
+INSERT INTO topics_to_sources (topic_id, source_id) VALUES (1, 3);
+INSERT INTO terms_to_topics (term_id, topic_id) VALUES (1, 1), (2, 1);
+
+INSERT INTO questions (question, topic_id) VALUES ('What is a storm?', 1);
+
+INSERT INTO articles (title, author, publish_date) VALUES ('title1', 'author1', '2023-03-27');
+
+INSERT INTO articles_to_topics (article_id, topic_id) VALUES (1, 1);
+INSERT INTO articles_to_terms (article_id, term_id) VALUES (1, 1), (1, 2);
+INSERT INTO articles_to_questions (article_id, question_id) VALUES (1, 1);
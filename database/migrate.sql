@@ -0,0 +1,275 @@
+/*
+Brings a platform database created from an older init.sql up to date with the current one,
+without touching its data. New databases get all of this from init.sql and don't need it.
+
+Every statement can be run again, so the file can be applied to a database of any earlier
+version, or to a current one, which it leaves as it is:
+
+psql -U postgres -d platform -f migrate.sql
+
+It runs in one transaction, a migration that fails part way changes nothing.
+*/
+BEGIN;
+
+/*
+Timestamps
+
+The existing rows get the time of the migration as their created_at and updated_at.
+*/
+DO $$
+DECLARE
+	table_name text;
+BEGIN
+	FOREACH table_name IN ARRAY ARRAY[
+		'sources', 'topics', 'terms', 'questions', 'articles', 'topics_to_sources',
+		'terms_to_sources', 'terms_to_topics', 'articles_to_topics', 'articles_to_terms',
+		'articles_to_questions', 'related_topics'
+	]
+	LOOP
+		EXECUTE format(
+			'ALTER TABLE platform.%I
+			ADD COLUMN IF NOT EXISTS created_at timestamptz NOT NULL DEFAULT now(),
+			ADD COLUMN IF NOT EXISTS updated_at timestamptz NOT NULL DEFAULT now()',
+			table_name
+		);
+	END LOOP;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION platform.set_updated_at() RETURNS trigger AS $$
+BEGIN
+	NEW.updated_at = now();
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DO $$
+DECLARE
+	table_name text;
+BEGIN
+	FOR table_name IN
+		SELECT columns.table_name FROM information_schema.columns
+		WHERE table_schema = 'platform' AND column_name = 'updated_at'
+	LOOP
+		EXECUTE format(
+			'CREATE OR REPLACE TRIGGER set_updated_at BEFORE UPDATE ON platform.%I
+			FOR EACH ROW EXECUTE FUNCTION platform.set_updated_at()',
+			table_name
+		);
+	END LOOP;
+END;
+$$;
+
+/*
+Row versions
+
+The existing rows start at version 1.
+*/
+ALTER TABLE platform.sources ADD COLUMN IF NOT EXISTS version int NOT NULL DEFAULT 1;
+ALTER TABLE platform.topics ADD COLUMN IF NOT EXISTS version int NOT NULL DEFAULT 1;
+ALTER TABLE platform.terms ADD COLUMN IF NOT EXISTS version int NOT NULL DEFAULT 1;
+
+CREATE OR REPLACE FUNCTION platform.bump_version() RETURNS trigger AS $$
+BEGIN
+	NEW.version = OLD.version + 1;
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER bump_version BEFORE UPDATE ON platform.sources
+	FOR EACH ROW EXECUTE FUNCTION platform.bump_version();
+CREATE OR REPLACE TRIGGER bump_version BEFORE UPDATE ON platform.topics
+	FOR EACH ROW EXECUTE FUNCTION platform.bump_version();
+CREATE OR REPLACE TRIGGER bump_version BEFORE UPDATE ON platform.terms
+	FOR EACH ROW EXECUTE FUNCTION platform.bump_version();
+
+/*
+Slugs
+
+The existing rows get the slug the API would have given them (see slug_base and unique_slug
+in src/helpers/lookup.rs), in the order of their ids. The table's triggers are disabled
+meanwhile, so adding a slug doesn't count as an update of the record.
+*/
+DO $$
+DECLARE
+	entity record;
+	existing record;
+	base text;
+	slug text;
+	suffix int;
+	taken bool;
+	missing bool;
+BEGIN
+	FOR entity IN
+		SELECT * FROM (VALUES ('sources', 'name', 'source'), ('topics', 'topic', 'topic'), ('terms', 'term', 'term'))
+			AS entities (table_name, name_column, entity_type)
+	LOOP
+		EXECUTE format('ALTER TABLE platform.%I ADD COLUMN IF NOT EXISTS slug text', entity.table_name);
+		EXECUTE format('SELECT EXISTS (SELECT 1 FROM platform.%I WHERE slug IS NULL)', entity.table_name)
+			INTO missing;
+		IF missing THEN
+			EXECUTE format('ALTER TABLE platform.%I DISABLE TRIGGER USER', entity.table_name);
+			FOR existing IN EXECUTE format(
+				'SELECT id, %I AS name FROM platform.%I WHERE slug IS NULL ORDER BY id',
+				entity.name_column,
+				entity.table_name
+			)
+			LOOP
+				base := btrim(regexp_replace(lower(existing.name), '[^[:alnum:]]+', '-', 'g'), '-');
+				IF base = '' THEN
+					base := entity.entity_type;
+				ELSIF base ~ '^[0-9]+$' THEN
+					-- a numeric slug would be read as an id
+					base := entity.entity_type || '-' || base;
+				END IF;
+				slug := base;
+				suffix := 1;
+				LOOP
+					EXECUTE format('SELECT EXISTS (SELECT 1 FROM platform.%I WHERE slug = $1)', entity.table_name)
+						INTO taken USING slug;
+					EXIT WHEN NOT taken;
+					suffix := suffix + 1;
+					slug := base || '-' || suffix;
+				END LOOP;
+				EXECUTE format('UPDATE platform.%I SET slug = $1 WHERE id = $2', entity.table_name)
+					USING slug, existing.id;
+			END LOOP;
+			EXECUTE format('ALTER TABLE platform.%I ENABLE TRIGGER USER', entity.table_name);
+		END IF;
+		EXECUTE format('ALTER TABLE platform.%I ALTER COLUMN slug SET NOT NULL', entity.table_name);
+		IF NOT EXISTS (
+			SELECT 1 FROM pg_constraint
+			WHERE conname = 'unique_' || entity.entity_type || '_slug'
+				AND connamespace = 'platform'::regnamespace
+		) THEN
+			EXECUTE format(
+				'ALTER TABLE platform.%I ADD CONSTRAINT %I UNIQUE (slug)',
+				entity.table_name,
+				'unique_' || entity.entity_type || '_slug'
+			);
+		END IF;
+	END LOOP;
+END;
+$$;
+
+/*
+Change feed
+
+The events before the migration aren't recorded, the feed starts with it.
+*/
+CREATE TABLE IF NOT EXISTS platform.events (
+	id bigserial NOT NULL,
+	action text NOT NULL,
+	entity_type text NOT NULL,
+	entity_id int NOT NULL,
+	related_type text,
+	related_id int,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	PRIMARY KEY (id)
+);
+
+CREATE OR REPLACE FUNCTION platform.record_entity_event() RETURNS trigger AS $$
+BEGIN
+	INSERT INTO platform.events (action, entity_type, entity_id)
+	VALUES (
+		CASE TG_OP WHEN 'INSERT' THEN 'created' WHEN 'UPDATE' THEN 'updated' ELSE 'deleted' END,
+		TG_ARGV[0],
+		CASE TG_OP WHEN 'DELETE' THEN OLD.id ELSE NEW.id END
+	);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION platform.record_link_event() RETURNS trigger AS $$
+DECLARE
+	link jsonb;
+BEGIN
+	IF TG_OP = 'DELETE' THEN
+		link := to_jsonb(OLD);
+	ELSE
+		link := to_jsonb(NEW);
+	END IF;
+	INSERT INTO platform.events (action, entity_type, entity_id, related_type, related_id)
+	VALUES (
+		CASE TG_OP WHEN 'DELETE' THEN 'unlinked' ELSE 'linked' END,
+		TG_ARGV[0],
+		(link ->> (TG_ARGV[0] || '_id'))::int,
+		TG_ARGV[1],
+		(link ->> (TG_ARGV[1] || '_id'))::int
+	);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION platform.notify_event() RETURNS trigger AS $$
+BEGIN
+	PERFORM pg_notify('platform_events', row_to_json(NEW)::text);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER notify_event AFTER INSERT ON platform.events
+	FOR EACH ROW EXECUTE FUNCTION platform.notify_event();
+
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.topics
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('topic');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.terms
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('term');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.sources
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('source');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.questions
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('question');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR UPDATE OR DELETE ON platform.articles
+	FOR EACH ROW EXECUTE FUNCTION platform.record_entity_event('article');
+
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR DELETE ON platform.topics_to_sources
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('topic', 'source');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR DELETE ON platform.terms_to_sources
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('term', 'source');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR DELETE ON platform.terms_to_topics
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('term', 'topic');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR DELETE ON platform.articles_to_topics
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('article', 'topic');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR DELETE ON platform.articles_to_terms
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('article', 'term');
+CREATE OR REPLACE TRIGGER record_event AFTER INSERT OR DELETE ON platform.articles_to_questions
+	FOR EACH ROW EXECUTE FUNCTION platform.record_link_event('article', 'question');
+
+/*
+Webhooks
+*/
+CREATE OR REPLACE FUNCTION platform.new_webhook_secret() RETURNS text AS $$
+	SELECT replace(gen_random_uuid()::text || gen_random_uuid()::text, '-', '')
+$$ LANGUAGE sql;
+
+CREATE TABLE IF NOT EXISTS platform.webhooks (
+	id serial NOT NULL,
+	url text NOT NULL,
+	secret text NOT NULL DEFAULT platform.new_webhook_secret(),
+	event_types text[] NOT NULL DEFAULT '{}',
+	created_at timestamptz NOT NULL DEFAULT now(),
+	PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS platform.webhook_deliveries (
+	id bigserial NOT NULL,
+	webhook_id int NOT NULL,
+	event_type text NOT NULL,
+	data jsonb NOT NULL,
+	status text NOT NULL DEFAULT 'pending',
+	attempts int NOT NULL DEFAULT 0,
+	next_attempt_at timestamptz NOT NULL DEFAULT now(),
+	last_status_code int,
+	last_error text,
+	replay_of bigint,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	delivered_at timestamptz,
+	PRIMARY KEY (id),
+	FOREIGN KEY (webhook_id) REFERENCES platform.webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_pending ON platform.webhook_deliveries (next_attempt_at)
+	WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id ON platform.webhook_deliveries (webhook_id, id);
+
+COMMIT;
@@ -0,0 +1,131 @@
+/*
+`jd_crm_api export <file> [--format json|ndjson]`
+`jd_crm_api restore <file> [--format json|ndjson] [--on-conflict skip|overwrite|rename]`
+
+Export writes the archive of GET /v1/archive of the database of DATABASE_URL to a file,
+restore writes one back like POST /v1/archive/restore does and prints the report as JSON.
+The format is guessed from the file's extension when --format isn't given.
+ */
+use crate::helpers::archive::{export_archive, parse_archive, ArchiveFormat, OnConflict};
+use crate::{connect, Repository};
+use std::fs;
+use std::sync::Arc;
+
+const EXPORT_USAGE: &str = "usage: jd_crm_api export <file> [--format json|ndjson]";
+const RESTORE_USAGE: &str =
+    "usage: jd_crm_api restore <file> [--format json|ndjson] [--on-conflict skip|overwrite|rename]";
+
+struct ArchiveArgs {
+    path: String,
+    format: ArchiveFormat,
+    on_conflict: Option<OnConflict>,
+}
+
+// the arguments after `export` or `restore`, --on-conflict only for a restore
+fn parse_args(args: &[String], restoring: bool) -> Result<ArchiveArgs, String> {
+    let mut paths = vec![];
+    let mut format = None;
+    let mut on_conflict = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--format" => format = Some(value()?.parse()?),
+            "--on-conflict" if restoring => on_conflict = Some(value()?.parse()?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let [path] = <[String; 1]>::try_from(paths).map_err(|_| "pass one file".to_owned())?;
+    let format = format
+        .or_else(|| ArchiveFormat::from_extension(&path))
+        .ok_or_else(|| {
+            format!(
+                "pass --format, the extension of {} isn't .json or .ndjson",
+                path
+            )
+        })?;
+    Ok(ArchiveArgs {
+        path,
+        format,
+        on_conflict,
+    })
+}
+
+// the process's exit code: 0 when the archive was written, 2 when it couldn't be
+pub async fn run_export(db_uri: &str, args: &[String]) -> i32 {
+    let args = match parse_args(args, false) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, EXPORT_USAGE);
+            return 2;
+        }
+    };
+
+    let repository: Arc<dyn Repository> = connect(db_uri).await;
+    let archive = match export_archive(&*repository).await {
+        Ok(archive) => archive,
+        Err(error) => {
+            eprintln!("the archive can't be read: {}", error);
+            return 2;
+        }
+    };
+    let output = match args.format {
+        ArchiveFormat::Json => {
+            serde_json::to_string_pretty(&archive).expect("the archive can be serialized")
+        }
+        ArchiveFormat::Ndjson => archive.to_ndjson(),
+    };
+    if let Err(error) = fs::write(&args.path, output) {
+        eprintln!("{} can't be written: {}", args.path, error);
+        return 2;
+    }
+    0
+}
+
+// the process's exit code: 0 when everything was restored, 1 when some questions or links
+// weren't because their records are missing, 2 when nothing was
+pub async fn run_restore(db_uri: &str, args: &[String]) -> i32 {
+    let args = match parse_args(args, true) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, RESTORE_USAGE);
+            return 2;
+        }
+    };
+    let input = match fs::read_to_string(&args.path) {
+        Ok(input) => input,
+        Err(error) => {
+            eprintln!("{} can't be read: {}", args.path, error);
+            return 2;
+        }
+    };
+    let archive = match parse_archive(args.format, &input) {
+        Ok(archive) => archive,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    let repository: Arc<dyn Repository> = connect(db_uri).await;
+    let report = match repository
+        .restore_archive(&archive, args.on_conflict.unwrap_or_default())
+        .await
+    {
+        Ok(report) => report,
+        Err(error) => {
+            eprintln!("the restore failed, nothing was written: {}", error);
+            return 2;
+        }
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("the report can be serialized")
+    );
+    if report.unresolved.is_empty() {
+        0
+    } else {
+        1
+    }
+}
@@ -0,0 +1,804 @@
+/*
+Snapshots of the whole knowledge base. GET /v1/archive and the `export` command write every
+topic, term, source, question and article and every row of ARCHIVED_LINK_TABLES to one
+archive, POST /v1/archive/restore and the `restore` command write it back (see
+archive_command.rs), into an empty database or one that already has records.
+
+Records refer to each other by name instead of by id, so an archive can be restored into
+another database: a question names its topic, and a link names its two records. Names of
+sources and articles aren't unique, so each of them has a `key` that is only used within the
+archive, e.g. "source-3", and links refer to them by it. Ids, slugs, versions and timestamps
+aren't archived, restored records get new ones.
+
+An archive is a JSON object with its `format`, `version` and `exported_at`, the records of
+each type and the links. As NDJSON that header is the first line, followed by one record or
+link per line with its `type`, e.g. {"type":"term","term":"Storm",...}. Archives of a newer
+version than ARCHIVE_VERSION are refused.
+
+A restore writes everything in one transaction (see Repository::restore_archive), so a
+failed restore changes nothing. A record whose name is taken is skipped, overwritten, or
+restored under a free name like "Storm (2)", see OnConflict. A source only conflicts with one
+of the same name and url, an article with one of the same title, author and publish_date.
+Links are added to whichever record the name or key was restored as, or to the existing
+record with the name. Restores don't send webhooks.
+ */
+use crate::helpers::fieldsets::{
+    EntityFields, Field, FieldKind, SparseRecord, ARTICLE_FIELDS, QUESTION_FIELDS, SOURCE_FIELDS,
+    TERM_FIELDS, TOPIC_FIELDS,
+};
+use crate::helpers::ndjson::NDJSON_CONTENT_TYPE;
+use crate::helpers::shared_types::{ImageType, MediaType};
+use crate::repository::Repository;
+use axum::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+pub const ARCHIVE_FORMAT: &str = "jd_crm_api archive";
+
+// bumped when the archive changes in a way older servers can't restore
+pub const ARCHIVE_VERSION: u32 = 1;
+
+// in the order they are restored, topics before the questions that name them
+pub const ARCHIVED_ENTITIES: [&EntityFields; 5] = [
+    &TOPIC_FIELDS,
+    &TERM_FIELDS,
+    &SOURCE_FIELDS,
+    &QUESTION_FIELDS,
+    &ARTICLE_FIELDS,
+];
+
+// the columns that are generated when a record is inserted
+const GENERATED_FIELDS: [&str; 5] = ["id", "slug", "version", "created_at", "updated_at"];
+
+// a question's topic is archived as the topic's name instead of its topic_id
+const QUESTION_TOPIC_FIELD: &str = "topic";
+
+// the archive-local key of a source or an article, which links refer to them by
+const KEY_FIELD: &str = "key";
+
+// the entities that are archived with a key, and the fields that tell one of their records
+// from another of the same name when it's restored
+fn identity_fields(entity: &EntityFields) -> Option<&'static [&'static str]> {
+    match entity.entity_type {
+        "source" => Some(&["name", "url"]),
+        "article" => Some(&["title", "author", "publish_date"]),
+        _ => None,
+    }
+}
+
+// a table whose rows link two records, archived as the names or keys of both
+pub struct ArchiveLinkTable {
+    pub table: &'static str,
+    pub from: &'static EntityFields,
+    pub from_column: &'static str,
+    pub to: &'static EntityFields,
+    pub to_column: &'static str,
+}
+
+pub const ARCHIVED_LINK_TABLES: [ArchiveLinkTable; 7] = [
+    ArchiveLinkTable {
+        table: "terms_to_topics",
+        from: &TERM_FIELDS,
+        from_column: "term_id",
+        to: &TOPIC_FIELDS,
+        to_column: "topic_id",
+    },
+    ArchiveLinkTable {
+        table: "terms_to_sources",
+        from: &TERM_FIELDS,
+        from_column: "term_id",
+        to: &SOURCE_FIELDS,
+        to_column: "source_id",
+    },
+    ArchiveLinkTable {
+        table: "topics_to_sources",
+        from: &TOPIC_FIELDS,
+        from_column: "topic_id",
+        to: &SOURCE_FIELDS,
+        to_column: "source_id",
+    },
+    ArchiveLinkTable {
+        table: "articles_to_topics",
+        from: &ARTICLE_FIELDS,
+        from_column: "article_id",
+        to: &TOPIC_FIELDS,
+        to_column: "topic_id",
+    },
+    ArchiveLinkTable {
+        table: "articles_to_terms",
+        from: &ARTICLE_FIELDS,
+        from_column: "article_id",
+        to: &TERM_FIELDS,
+        to_column: "term_id",
+    },
+    ArchiveLinkTable {
+        table: "articles_to_questions",
+        from: &ARTICLE_FIELDS,
+        from_column: "article_id",
+        to: &QUESTION_FIELDS,
+        to_column: "question_id",
+    },
+    // the mind map of init.sql, a parent topic -> a child topic
+    ArchiveLinkTable {
+        table: "related_topics",
+        from: &TOPIC_FIELDS,
+        from_column: "parent_id",
+        to: &TOPIC_FIELDS,
+        to_column: "child_id",
+    },
+];
+
+pub fn archived_link_table(table: &str) -> Option<&'static ArchiveLinkTable> {
+    ARCHIVED_LINK_TABLES
+        .iter()
+        .find(|link_table| link_table.table == table)
+}
+
+// the fields of an entity's records in an archive
+pub fn archived_fields(entity: &EntityFields) -> Vec<Field> {
+    let mut fields: Vec<Field> = entity
+        .fields
+        .iter()
+        .filter(|(name, _)| !GENERATED_FIELDS.contains(name) && *name != "topic_id")
+        .copied()
+        .collect();
+    if entity.entity_type == "question" {
+        fields.push((QUESTION_TOPIC_FIELD, FieldKind::Text));
+    }
+    if identity_fields(entity).is_some() {
+        fields.push((KEY_FIELD, FieldKind::Text));
+    }
+    fields
+}
+
+// the fields a record can't be archived without, an article's title is optional
+fn required_fields(entity: &EntityFields) -> Vec<&'static str> {
+    match entity.entity_type {
+        "article" => vec![],
+        "question" => vec![entity.name_column, QUESTION_TOPIC_FIELD],
+        _ => vec![entity.name_column],
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ArchiveLink {
+    /// One of the link tables, e.g. terms_to_topics, or related_topics for the mind map
+    pub table: String,
+    /// The name of the record of the table's first column, e.g. the term, or the key of a
+    /// source or an article
+    pub from: String,
+    /// The name or key of the record of the table's second column, e.g. the topic
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Archive {
+    pub format: String,
+    pub version: u32,
+    #[serde(default)]
+    pub exported_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    pub topics: Vec<SparseRecord>,
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    pub terms: Vec<SparseRecord>,
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    pub sources: Vec<SparseRecord>,
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    pub questions: Vec<SparseRecord>,
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    pub articles: Vec<SparseRecord>,
+    #[serde(default)]
+    pub links: Vec<ArchiveLink>,
+}
+
+// the first line of an NDJSON archive
+#[derive(Serialize, Deserialize)]
+struct ArchiveHeader {
+    format: String,
+    version: u32,
+    #[serde(default)]
+    exported_at: Option<DateTime<Utc>>,
+}
+
+// the other lines of an NDJSON archive
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ArchiveLine {
+    Topic(SparseRecord),
+    Term(SparseRecord),
+    Source(SparseRecord),
+    Question(SparseRecord),
+    Article(SparseRecord),
+    Link(ArchiveLink),
+}
+
+impl Archive {
+    pub fn new(exported_at: Option<DateTime<Utc>>) -> Self {
+        Archive {
+            format: ARCHIVE_FORMAT.to_owned(),
+            version: ARCHIVE_VERSION,
+            exported_at,
+            topics: vec![],
+            terms: vec![],
+            sources: vec![],
+            questions: vec![],
+            articles: vec![],
+            links: vec![],
+        }
+    }
+
+    pub fn records(&self, entity: &EntityFields) -> &[SparseRecord] {
+        match entity.entity_type {
+            "topic" => &self.topics,
+            "term" => &self.terms,
+            "source" => &self.sources,
+            "question" => &self.questions,
+            "article" => &self.articles,
+            _ => &[],
+        }
+    }
+
+    fn records_mut(&mut self, entity_type: &str) -> Option<&mut Vec<SparseRecord>> {
+        match entity_type {
+            "topic" => Some(&mut self.topics),
+            "term" => Some(&mut self.terms),
+            "source" => Some(&mut self.sources),
+            "question" => Some(&mut self.questions),
+            "article" => Some(&mut self.articles),
+            _ => None,
+        }
+    }
+
+    // the header, then the records of ARCHIVED_ENTITIES in order, then the links
+    pub fn to_ndjson(&self) -> String {
+        let header = ArchiveHeader {
+            format: self.format.clone(),
+            version: self.version,
+            exported_at: self.exported_at,
+        };
+        let mut lines = vec![to_line(&header)];
+        for entity in ARCHIVED_ENTITIES {
+            for record in self.records(entity) {
+                let mut line = record.clone();
+                line.insert("type".to_owned(), json!(entity.entity_type));
+                lines.push(to_line(&line));
+            }
+        }
+        for link in &self.links {
+            lines.push(to_line(&json!({
+                "type": "link",
+                "table": link.table,
+                "from": link.from,
+                "to": link.to,
+            })));
+        }
+        lines.concat()
+    }
+}
+
+fn to_line<T: Serialize>(value: &T) -> String {
+    let mut line = serde_json::to_string(value).expect("archive lines can be serialized");
+    line.push('\n');
+    line
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    Json,
+    Ndjson,
+}
+
+impl ArchiveFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.split(';').next()?.trim() {
+            "application/json" => Some(ArchiveFormat::Json),
+            NDJSON_CONTENT_TYPE => Some(ArchiveFormat::Ndjson),
+            _ => None,
+        }
+    }
+
+    // "backup.ndjson" -> Ndjson
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_lowercase().as_str() {
+            "json" => Some(ArchiveFormat::Json),
+            "ndjson" | "jsonl" => Some(ArchiveFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(ArchiveFormat::Json),
+            "ndjson" => Ok(ArchiveFormat::Ndjson),
+            _ => Err(format!("unknown format {}, use json or ndjson", format)),
+        }
+    }
+}
+
+// what a restore does with a record whose name is already taken
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    // keep the existing record
+    #[default]
+    Skip,
+    // replace the existing record's fields with the archived ones
+    Overwrite,
+    // restore the record next to the existing one, as "<name> (2)"
+    Rename,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(on_conflict: &str) -> Result<Self, Self::Err> {
+        match on_conflict {
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "rename" => Ok(OnConflict::Rename),
+            _ => Err(format!(
+                "unknown conflict policy {}, use skip, overwrite or rename",
+                on_conflict
+            )),
+        }
+    }
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct RestoreCounts {
+    pub created: usize,
+    pub overwritten: usize,
+    pub renamed: usize,
+    pub skipped: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RenamedRecord {
+    pub entity_type: &'static str,
+    pub name: String,
+    pub restored_as: String,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct RestoreReport {
+    /// Entity type -> what was done with its records
+    pub records: BTreeMap<String, RestoreCounts>,
+    pub links_created: usize,
+    /// Links that were already there
+    pub links_existing: usize,
+    pub renamed: Vec<RenamedRecord>,
+    /// Questions and links naming a record that neither the archive nor the database has
+    pub unresolved: Vec<String>,
+}
+
+// a value of an archived field, typed for binding it to a query
+pub enum FieldValue {
+    Int(Option<i32>),
+    Text(Option<String>),
+    Bool(Option<bool>),
+    TextArray(Option<Vec<String>>),
+    Date(Option<NaiveDate>),
+    Timestamp(Option<DateTime<Utc>>),
+    MediaType(Option<MediaType>),
+    ImageType(Option<ImageType>),
+}
+
+fn typed<T: DeserializeOwned>(value: &Value) -> Option<T> {
+    serde_json::from_value(value.clone()).ok()
+}
+
+// the entity's `columns` with their values in `values`, null when they aren't there, for
+// ArchiveWriter::find_matching_id
+pub fn matching_values(
+    entity: &EntityFields,
+    values: &SparseRecord,
+    columns: &[&str],
+) -> SparseRecord {
+    entity
+        .fields
+        .iter()
+        .filter(|(name, _)| columns.contains(name))
+        .map(|(name, _)| {
+            let value = values.get(*name).cloned().unwrap_or(Value::Null);
+            ((*name).to_owned(), value)
+        })
+        .collect()
+}
+
+// None when the JSON value isn't of the field's kind
+pub fn field_value(kind: FieldKind, value: &Value) -> Option<FieldValue> {
+    let field_value = match kind {
+        FieldKind::Int => FieldValue::Int(typed(value)?),
+        FieldKind::Text => FieldValue::Text(typed(value)?),
+        FieldKind::Bool => FieldValue::Bool(typed(value)?),
+        FieldKind::TextArray => FieldValue::TextArray(typed(value)?),
+        FieldKind::Date => FieldValue::Date(typed(value)?),
+        FieldKind::Timestamp => FieldValue::Timestamp(typed(value)?),
+        FieldKind::MediaType => FieldValue::MediaType(typed(value)?),
+        FieldKind::ImageType => FieldValue::ImageType(typed(value)?),
+    };
+    Some(field_value)
+}
+
+fn kind_description(kind: FieldKind) -> &'static str {
+    match kind {
+        FieldKind::Int => "an integer",
+        FieldKind::Text => "a string",
+        FieldKind::Bool => "true or false",
+        FieldKind::TextArray => "a list of strings",
+        FieldKind::Date => "a date like 2023-03-27",
+        FieldKind::Timestamp => "an RFC 3339 timestamp",
+        FieldKind::MediaType => "one of Audio, Video, Web, Book, ScientificArticle",
+        FieldKind::ImageType => "one of PDF, PNG, TIFF, JPEG, GIF",
+    }
+}
+
+fn check_record(entity: &EntityFields, record: &SparseRecord) -> Result<(), String> {
+    let fields = archived_fields(entity);
+    for (name, value) in record {
+        let Some((_, kind)) = fields.iter().find(|(field, _)| field == name) else {
+            return Err(format!("unknown field {}", name));
+        };
+        if value.is_null() {
+            // the column is NOT NULL, leaving it out makes it false
+            if name == "is_verified" {
+                return Err("is_verified can't be null".to_owned());
+            }
+            continue;
+        }
+        if field_value(*kind, value).is_none() {
+            return Err(format!("{} must be {}", name, kind_description(*kind)));
+        }
+    }
+    for required in required_fields(entity) {
+        if record.get(required).is_none_or(Value::is_null) {
+            return Err(format!("{} is missing", required));
+        }
+    }
+    Ok(())
+}
+
+fn check_link(link: &ArchiveLink) -> Result<(), String> {
+    if archived_link_table(&link.table).is_none() {
+        let tables: Vec<&str> = ARCHIVED_LINK_TABLES
+            .iter()
+            .map(|link_table| link_table.table)
+            .collect();
+        return Err(format!(
+            "unknown link table {}, expected one of: {}",
+            link.table,
+            tables.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn check_header(format: &str, version: u32) -> Result<(), String> {
+    if format != ARCHIVE_FORMAT {
+        return Err(format!(
+            "not an archive of this API, its format is \"{}\" instead of \"{}\"",
+            format, ARCHIVE_FORMAT
+        ));
+    }
+    if version == 0 || version > ARCHIVE_VERSION {
+        return Err(format!(
+            "archive version {} can't be restored, this server restores versions up to {}",
+            version, ARCHIVE_VERSION
+        ));
+    }
+    Ok(())
+}
+
+// the archive, or what is wrong with it and where
+pub fn parse_archive(format: ArchiveFormat, input: &str) -> Result<Archive, String> {
+    let archive = match format {
+        ArchiveFormat::Json => {
+            let archive: Archive = serde_json::from_str(input)
+                .map_err(|error| format!("the archive isn't valid JSON: {}", error))?;
+            check_header(&archive.format, archive.version)?;
+            for entity in ARCHIVED_ENTITIES {
+                for (index, record) in archive.records(entity).iter().enumerate() {
+                    check_record(entity, record)
+                        .map_err(|error| format!("{}[{}]: {}", entity.table, index, error))?;
+                }
+            }
+            for (index, link) in archive.links.iter().enumerate() {
+                check_link(link).map_err(|error| format!("links[{}]: {}", index, error))?;
+            }
+            archive
+        }
+        ArchiveFormat::Ndjson => parse_ndjson(input)?,
+    };
+    Ok(archive)
+}
+
+fn parse_ndjson(input: &str) -> Result<Archive, String> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Err("the archive is empty".to_owned());
+    };
+    let header: ArchiveHeader = serde_json::from_str(header)
+        .map_err(|error| format!("line 1 isn't an archive header: {}", error))?;
+    check_header(&header.format, header.version)?;
+
+    let mut archive = Archive::new(header.exported_at);
+    archive.format = header.format;
+    archive.version = header.version;
+    for (line_number, line) in lines {
+        let line: ArchiveLine = serde_json::from_str(line)
+            .map_err(|error| format!("line {}: {}", line_number, error))?;
+        let (entity, record) = match line {
+            ArchiveLine::Link(link) => {
+                check_link(&link).map_err(|error| format!("line {}: {}", line_number, error))?;
+                archive.links.push(link);
+                continue;
+            }
+            ArchiveLine::Topic(record) => (&TOPIC_FIELDS, record),
+            ArchiveLine::Term(record) => (&TERM_FIELDS, record),
+            ArchiveLine::Source(record) => (&SOURCE_FIELDS, record),
+            ArchiveLine::Question(record) => (&QUESTION_FIELDS, record),
+            ArchiveLine::Article(record) => (&ARTICLE_FIELDS, record),
+        };
+        check_record(entity, &record)
+            .map_err(|error| format!("line {}: {}", line_number, error))?;
+        if let Some(records) = archive.records_mut(entity.entity_type) {
+            records.push(record);
+        }
+    }
+    Ok(archive)
+}
+
+// every record and link of the repository
+pub async fn export_archive(repository: &dyn Repository) -> sqlx::Result<Archive> {
+    let mut archive = Archive::new(Some(Utc::now()));
+    // entity type -> id -> name or key, for the references
+    let mut names: HashMap<&str, HashMap<i32, String>> = HashMap::new();
+    for entity in ARCHIVED_ENTITIES {
+        let fields = archived_fields(entity);
+        let records = repository.get_records(entity, entity.fields, None).await?;
+        let mut archived = vec![];
+        for record in records {
+            let id = record.get("id").and_then(Value::as_i64).unwrap_or_default() as i32;
+            let mut archived_record: SparseRecord = fields
+                .iter()
+                .filter_map(|(name, _)| Some(((*name).to_owned(), record.get(*name)?.clone())))
+                .collect();
+            let reference = if identity_fields(entity).is_some() {
+                let key = format!("{}-{}", entity.entity_type, id);
+                archived_record.insert(KEY_FIELD.to_owned(), json!(key));
+                Some(key)
+            } else {
+                record
+                    .get(entity.name_column)
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+            };
+            if let Some(reference) = reference {
+                names
+                    .entry(entity.entity_type)
+                    .or_default()
+                    .insert(id, reference);
+            }
+            if entity.entity_type == "question" {
+                let topic = record
+                    .get("topic_id")
+                    .and_then(Value::as_i64)
+                    .and_then(|topic_id| names.get("topic")?.get(&(topic_id as i32)));
+                archived_record.insert(QUESTION_TOPIC_FIELD.to_owned(), json!(topic));
+            }
+            archived.push(archived_record);
+        }
+        if let Some(records) = archive.records_mut(entity.entity_type) {
+            *records = archived;
+        }
+    }
+
+    let name = |entity: &EntityFields, id: i32| names.get(entity.entity_type)?.get(&id).cloned();
+    for link_table in &ARCHIVED_LINK_TABLES {
+        for (from_id, to_id) in repository.get_link_rows(link_table).await? {
+            // the rows of a record that was deleted in between
+            let (Some(from), Some(to)) =
+                (name(link_table.from, from_id), name(link_table.to, to_id))
+            else {
+                continue;
+            };
+            archive.links.push(ArchiveLink {
+                table: link_table.table.to_owned(),
+                from,
+                to,
+            });
+        }
+    }
+    Ok(archive)
+}
+
+/*
+The writes of a restore, on the transaction of Repository::restore_archive. `values` are
+columns of the entity's table, e.g. a question's topic_id, and inserted records get a slug
+generated from their name.
+ */
+#[async_trait]
+pub trait ArchiveWriter: Send {
+    // the record with the name, matched like EntityKey::Name
+    async fn find_id(&mut self, entity: &EntityFields, name: &str) -> sqlx::Result<Option<i32>>;
+
+    // the first record whose `columns` have the values in `values`, a missing value is null
+    async fn find_matching_id(
+        &mut self,
+        entity: &EntityFields,
+        values: &SparseRecord,
+        columns: &[&str],
+    ) -> sqlx::Result<Option<i32>>;
+
+    async fn insert_record(
+        &mut self,
+        entity: &EntityFields,
+        values: &SparseRecord,
+    ) -> sqlx::Result<i32>;
+
+    async fn update_record(
+        &mut self,
+        entity: &EntityFields,
+        id: i32,
+        values: &SparseRecord,
+    ) -> sqlx::Result<()>;
+
+    // false when the link already existed
+    async fn insert_link(
+        &mut self,
+        link_table: &ArchiveLinkTable,
+        from_id: i32,
+        to_id: i32,
+    ) -> sqlx::Result<bool>;
+}
+
+// the record restored under the name or key, or else the database's record with the name
+async fn resolve(
+    writer: &mut dyn ArchiveWriter,
+    restored: &HashMap<(&str, &str), i32>,
+    entity: &EntityFields,
+    reference: &str,
+) -> sqlx::Result<Option<i32>> {
+    if let Some(id) = restored.get(&(entity.entity_type, reference)) {
+        return Ok(Some(*id));
+    }
+    writer.find_id(entity, reference).await
+}
+
+// "Storm" -> "Storm (2)", or the first of (3), (4), ... that isn't taken either
+async fn free_name(
+    writer: &mut dyn ArchiveWriter,
+    entity: &EntityFields,
+    name: &str,
+) -> sqlx::Result<String> {
+    let mut number = 2;
+    loop {
+        let candidate = format!("{} ({})", name, number);
+        if writer.find_id(entity, &candidate).await?.is_none() {
+            return Ok(candidate);
+        }
+        number += 1;
+    }
+}
+
+// writes a checked archive (see parse_archive) with the writer
+pub async fn restore(
+    writer: &mut dyn ArchiveWriter,
+    archive: &Archive,
+    on_conflict: OnConflict,
+) -> sqlx::Result<RestoreReport> {
+    let mut report = RestoreReport::default();
+    // (entity type, archived name or key) -> the record it was restored as or skipped for
+    let mut restored: HashMap<(&str, &str), i32> = HashMap::new();
+    for entity in ARCHIVED_ENTITIES {
+        let mut counts = RestoreCounts::default();
+        for record in archive.records(entity) {
+            let name = record.get(entity.name_column).and_then(Value::as_str);
+            let key = match identity_fields(entity) {
+                Some(_) => record.get(KEY_FIELD).and_then(Value::as_str),
+                None => name,
+            };
+            let mut values = record.clone();
+            values.remove(KEY_FIELD);
+            // a topic's own name is in its "topic" field too
+            let topic = match entity.entity_type {
+                "question" => values.remove(QUESTION_TOPIC_FIELD),
+                _ => None,
+            };
+            if let Some(topic) = topic {
+                let topic = topic.as_str().unwrap_or_default();
+                let Some(topic_id) = resolve(writer, &restored, &TOPIC_FIELDS, topic).await? else {
+                    report.unresolved.push(format!(
+                        "question \"{}\": topic \"{}\"",
+                        name.unwrap_or_default(),
+                        topic
+                    ));
+                    continue;
+                };
+                values.insert("topic_id".to_owned(), json!(topic_id));
+            }
+
+            let existing = match (name, identity_fields(entity)) {
+                (Some(_), Some(columns)) => {
+                    writer.find_matching_id(entity, &values, columns).await?
+                }
+                (Some(name), None) => writer.find_id(entity, name).await?,
+                (None, _) => None,
+            };
+            let id = match (existing, name) {
+                (Some(id), Some(name)) => match on_conflict {
+                    OnConflict::Skip => {
+                        counts.skipped += 1;
+                        id
+                    }
+                    OnConflict::Overwrite => {
+                        writer.update_record(entity, id, &values).await?;
+                        counts.overwritten += 1;
+                        id
+                    }
+                    OnConflict::Rename => {
+                        let restored_as = free_name(writer, entity, name).await?;
+                        values.insert(entity.name_column.to_owned(), json!(restored_as));
+                        counts.renamed += 1;
+                        report.renamed.push(RenamedRecord {
+                            entity_type: entity.entity_type,
+                            name: name.to_owned(),
+                            restored_as,
+                        });
+                        writer.insert_record(entity, &values).await?
+                    }
+                },
+                _ => {
+                    counts.created += 1;
+                    writer.insert_record(entity, &values).await?
+                }
+            };
+            if let Some(key) = key {
+                restored.insert((entity.entity_type, key), id);
+            }
+        }
+        report.records.insert(entity.entity_type.to_owned(), counts);
+    }
+
+    for link in &archive.links {
+        // checked by parse_archive
+        let Some(link_table) = archived_link_table(&link.table) else {
+            continue;
+        };
+        let from_id = resolve(writer, &restored, link_table.from, &link.from).await?;
+        let to_id = resolve(writer, &restored, link_table.to, &link.to).await?;
+        let (Some(from_id), Some(to_id)) = (from_id, to_id) else {
+            report.unresolved.push(format!(
+                "{}: \"{}\" -> \"{}\"",
+                link.table, link.from, link.to
+            ));
+            continue;
+        };
+        if writer.insert_link(link_table, from_id, to_id).await? {
+            report.links_created += 1;
+        } else {
+            report.links_existing += 1;
+        }
+    }
+    Ok(report)
+}
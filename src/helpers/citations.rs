@@ -0,0 +1,413 @@
+/*
+Citations of sources in APA (7th edition), MLA (9th edition), Chicago (bibliography
+entries of the notes and bibliography system) and BibTeX, for
+GET /v1/sources/:id/citation and GET /v1/topics/:id/bibliography.
+
+A source's name is the title of the work. Its author is written as it's stored, e.g.
+"Smith, J." or "World Meteorological Organization", since it can't be told whether it's a
+person's name. BibTeX would split an author without a comma into first and last names, so
+it is braced there as a corporate author. Sources don't have a publication date, publisher
+or journal, so APA cites them as "n.d." and the other styles leave them out.
+
+The media type decides the form: books, videos and audio are italicised works of their
+own, web pages are italicised in APA and quoted in MLA and Chicago, and scientific
+articles are quoted (or plain in APA). Missing metadata isn't guessed: the citation is
+written without it and the missing fields are listed, a source without a media type has a
+plain title and nothing media-specific, and one without a url is cited as if it had none.
+ */
+use crate::helpers::fieldsets::{Field, FieldKind, SparseRecord};
+use crate::helpers::shared_types::MediaType;
+use crate::repository::Repository;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+// the source fields a citation is made of
+pub const CITATION_FIELDS: [Field; 6] = [
+    ("id", FieldKind::Int),
+    ("name", FieldKind::Text),
+    ("slug", FieldKind::Text),
+    ("url", FieldKind::Text),
+    ("author", FieldKind::Text),
+    ("media_type", FieldKind::MediaType),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CitationStyle {
+    #[default]
+    Apa,
+    Mla,
+    Chicago,
+    Bibtex,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Citation {
+    pub source_id: i32,
+    pub style: CitationStyle,
+    /// The citation as plain text, or the BibTeX entry
+    pub text: String,
+    /// The citation with its title in <i>, null for BibTeX
+    pub html: Option<String>,
+    /// The fields the citation needs that the source doesn't have: name, author, url or media_type
+    pub missing: Vec<&'static str>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Bibliography {
+    pub topic_id: i32,
+    pub style: CitationStyle,
+    /// The sources of the topic and of its terms, ordered by author, or by name when there's none
+    pub citations: Vec<Citation>,
+    /// The citations one per line, or the BibTeX entries separated by blank lines
+    pub text: String,
+}
+
+// a piece of a citation, only titles are italicised
+enum Part {
+    Plain(String),
+    Italic(String),
+}
+
+struct SourceMetadata<'a> {
+    id: i32,
+    slug: &'a str,
+    name: Option<&'a str>,
+    author: Option<&'a str>,
+    url: Option<&'a str>,
+    media_type: Option<MediaType>,
+}
+
+impl<'a> SourceMetadata<'a> {
+    fn from_record(source: &'a SparseRecord) -> Self {
+        // empty strings are as good as missing
+        let text = |field: &str| {
+            source
+                .get(field)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        SourceMetadata {
+            id: source.get("id").and_then(Value::as_i64).unwrap_or_default() as i32,
+            slug: text("slug").unwrap_or_default(),
+            name: text("name"),
+            author: text("author"),
+            url: text("url"),
+            media_type: source
+                .get("media_type")
+                .and_then(|media_type| serde_json::from_value(media_type.clone()).ok()),
+        }
+    }
+
+    fn missing(&self) -> Vec<&'static str> {
+        let mut missing = vec![];
+        if self.name.is_none() {
+            missing.push("name");
+        }
+        if self.author.is_none() {
+            missing.push("author");
+        }
+        // a book can be cited without one
+        if self.url.is_none() && self.media_type != Some(MediaType::Book) {
+            missing.push("url");
+        }
+        if self.media_type.is_none() {
+            missing.push("media_type");
+        }
+        missing
+    }
+
+    // what a bibliography is ordered by
+    fn sort_key(&self) -> String {
+        self.author.or(self.name).unwrap_or_default().to_lowercase()
+    }
+}
+
+pub fn cite(source: &SparseRecord, style: CitationStyle) -> Citation {
+    let metadata = SourceMetadata::from_record(source);
+    let (text, html) = match style {
+        CitationStyle::Bibtex => (bibtex(&metadata), None),
+        _ => {
+            let parts = match style {
+                CitationStyle::Apa => apa(&metadata),
+                CitationStyle::Mla => mla(&metadata),
+                _ => chicago(&metadata),
+            };
+            (render_text(&parts), Some(render_html(&parts)))
+        }
+    };
+    Citation {
+        source_id: metadata.id,
+        style,
+        text,
+        html,
+        missing: metadata.missing(),
+    }
+}
+
+pub fn bibliography(topic_id: i32, sources: &[SparseRecord], style: CitationStyle) -> Bibliography {
+    let mut sources: Vec<&SparseRecord> = sources.iter().collect();
+    sources.sort_by_cached_key(|source| {
+        let metadata = SourceMetadata::from_record(source);
+        (metadata.sort_key(), metadata.id)
+    });
+    let citations: Vec<Citation> = sources
+        .into_iter()
+        .map(|source| cite(source, style))
+        .collect();
+    let separator = match style {
+        CitationStyle::Bibtex => "\n\n",
+        _ => "\n",
+    };
+    let text = citations
+        .iter()
+        .map(|citation| citation.text.as_str())
+        .collect::<Vec<_>>()
+        .join(separator);
+    Bibliography {
+        topic_id,
+        style,
+        citations,
+        text,
+    }
+}
+
+// the sources of the topic and of its terms, a source of several of them once
+pub async fn topic_sources(
+    repository: &dyn Repository,
+    topic_id: i32,
+) -> sqlx::Result<Vec<SparseRecord>> {
+    let mut sources = repository
+        .get_related("topic", &[topic_id], "source", &CITATION_FIELDS)
+        .await?
+        .remove(&topic_id)
+        .unwrap_or_default();
+    let term_ids: Vec<i32> = repository
+        .get_related("topic", &[topic_id], "term", &[("id", FieldKind::Int)])
+        .await?
+        .remove(&topic_id)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|term| term["id"].as_i64())
+        .map(|id| id as i32)
+        .collect();
+    let term_sources = repository
+        .get_related("term", &term_ids, "source", &CITATION_FIELDS)
+        .await?;
+    sources.extend(term_sources.into_values().flatten());
+    let mut seen = HashSet::new();
+    sources.retain(|source| seen.insert(source["id"].as_i64()));
+    Ok(sources)
+}
+
+// Author. (n.d.). Title [Video]. https://...
+fn apa(source: &SourceMetadata) -> Vec<Part> {
+    let mut title = vec![];
+    if let Some(name) = source.name {
+        title.push(match source.media_type {
+            Some(MediaType::ScientificArticle) | None => Part::Plain(name.to_owned()),
+            _ => Part::Italic(name.to_owned()),
+        });
+    }
+    match source.media_type {
+        Some(MediaType::Video) => title.push(Part::Plain(" [Video]".to_owned())),
+        Some(MediaType::Audio) => title.push(Part::Plain(" [Audio]".to_owned())),
+        _ => {}
+    }
+
+    let mut parts = vec![];
+    // without an author the title takes its place
+    match source.author {
+        Some(author) => {
+            parts.push(Part::Plain(format!("{} (n.d.). ", sentence(author))));
+            if !title.is_empty() {
+                parts.extend(title);
+                parts.push(Part::Plain(". ".to_owned()));
+            }
+        }
+        None if title.is_empty() => parts.push(Part::Plain("(n.d.). ".to_owned())),
+        None => {
+            parts.extend(title);
+            parts.push(Part::Plain(". (n.d.). ".to_owned()));
+        }
+    }
+    if let Some(url) = source.url {
+        parts.push(Part::Plain(url.to_owned()));
+    }
+    trim_end(parts)
+}
+
+// Author. "Title." en.wikipedia.org/wiki/...
+fn mla(source: &SourceMetadata) -> Vec<Part> {
+    let mut parts = vec![];
+    if let Some(author) = source.author {
+        parts.push(Part::Plain(format!("{} ", sentence(author))));
+    }
+    if let Some(name) = source.name {
+        parts.extend(title(name, source.media_type));
+    }
+    // MLA leaves out the scheme of urls
+    if let Some(url) = source.url {
+        let url = url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        parts.push(Part::Plain(format!("{}.", url.trim_end_matches('/'))));
+    }
+    trim_end(parts)
+}
+
+// Author. "Title." https://... or Author. Title. Video. https://...
+fn chicago(source: &SourceMetadata) -> Vec<Part> {
+    let mut parts = vec![];
+    if let Some(author) = source.author {
+        parts.push(Part::Plain(format!("{} ", sentence(author))));
+    }
+    if let Some(name) = source.name {
+        parts.extend(title(name, source.media_type));
+    }
+    match source.media_type {
+        Some(MediaType::Video) => parts.push(Part::Plain("Video. ".to_owned())),
+        Some(MediaType::Audio) => parts.push(Part::Plain("Audio. ".to_owned())),
+        _ => {}
+    }
+    if let Some(url) = source.url {
+        parts.push(Part::Plain(format!("{}.", url)));
+    }
+    trim_end(parts)
+}
+
+// MLA and Chicago quote the titles of web pages and articles, and italicise the others
+fn title(name: &str, media_type: Option<MediaType>) -> Vec<Part> {
+    match media_type {
+        Some(MediaType::Book) | Some(MediaType::Video) | Some(MediaType::Audio) => vec![
+            Part::Italic(name.to_owned()),
+            Part::Plain(if ends_a_sentence(name) { " " } else { ". " }.to_owned()),
+        ],
+        Some(MediaType::Web) | Some(MediaType::ScientificArticle) => {
+            vec![Part::Plain(format!("\u{201c}{}\u{201d} ", sentence(name)))]
+        }
+        None => vec![Part::Plain(format!("{} ", sentence(name)))],
+    }
+}
+
+/*
+@misc{wikipedia-tropical-cyclone,
+  author = {{World Meteorological Organization}},
+  title = {wikipedia tropical cyclone},
+  url = {https://en.wikipedia.org/wiki/Tropical_cyclone},
+}
+ */
+fn bibtex(source: &SourceMetadata) -> String {
+    let entry_type = match source.media_type {
+        Some(MediaType::Book) => "book",
+        Some(MediaType::ScientificArticle) => "article",
+        _ => "misc",
+    };
+    let key = if source.slug.is_empty() {
+        format!("source{}", source.id)
+    } else {
+        source.slug.to_owned()
+    };
+    let mut fields = vec![];
+    if let Some(author) = source.author {
+        // "Smith, J." is a person, anything else is kept whole
+        let author = escape_bibtex(author);
+        if author.contains(',') {
+            fields.push(("author", author));
+        } else {
+            fields.push(("author", format!("{{{}}}", author)));
+        }
+    }
+    if let Some(name) = source.name {
+        fields.push(("title", escape_bibtex(name)));
+    }
+    if let Some(url) = source.url {
+        fields.push(("url", escape_bibtex_url(url)));
+    }
+    match source.media_type {
+        Some(MediaType::Video) => fields.push(("note", "Video".to_owned())),
+        Some(MediaType::Audio) => fields.push(("note", "Audio".to_owned())),
+        _ => {}
+    }
+    let mut entry = format!("@{}{{{},\n", entry_type, key);
+    for (field, value) in fields {
+        entry.push_str(&format!("  {} = {{{}}},\n", field, value));
+    }
+    entry.push('}');
+    entry
+}
+
+fn ends_a_sentence(text: &str) -> bool {
+    text.ends_with(['.', '?', '!'])
+}
+
+// the text with a full stop, unless it already ends a sentence
+fn sentence(text: &str) -> String {
+    if ends_a_sentence(text) {
+        text.to_owned()
+    } else {
+        format!("{}.", text)
+    }
+}
+
+// without the space after the last part
+fn trim_end(mut parts: Vec<Part>) -> Vec<Part> {
+    if let Some(Part::Plain(text)) = parts.last_mut() {
+        let trimmed = text.trim_end().len();
+        text.truncate(trimmed);
+    }
+    parts
+}
+
+fn render_text(parts: &[Part]) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Plain(text) | Part::Italic(text) => text.as_str(),
+        })
+        .collect()
+}
+
+fn render_html(parts: &[Part]) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Plain(text) => escape_html(text),
+            Part::Italic(text) => format!("<i>{}</i>", escape_html(text)),
+        })
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// LaTeX's special characters, braces included so they can't end the field
+fn escape_bibtex(text: &str) -> String {
+    let mut escaped = String::new();
+    for character in text.chars() {
+        match character {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(character);
+            }
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+// a url can't be escaped like text, its braces and backslashes are percent-encoded
+fn escape_bibtex_url(url: &str) -> String {
+    url.replace('\\', "%5C")
+        .replace('{', "%7B")
+        .replace('}', "%7D")
+}
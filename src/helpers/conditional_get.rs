@@ -0,0 +1,440 @@
+/*
+Conditional request support shared by the handlers.
+
+Every JSON response gets an `ETag` and, when the records carry timestamps, a
+`Last-Modified` header. A client that sends back a matching `If-None-Match`, or an
+`If-Modified-Since` that is not older than the data, gets an empty 304 Not Modified
+instead of the full body.
+
+Lists are tagged with a sha256 of the serialized body. Single records are tagged with
+their row version, which is what the update handlers expect back in `If-Match`.
+ */
+use crate::helpers::handler_utils::UpdateOutcome;
+use axum::{
+    http::{
+        header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+
+// how often `If-Match: *` retries an update that lost to another one
+const ANY_VERSION_ATTEMPTS: usize = 3;
+
+// Format used by HTTP dates, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+pub trait LastModified {
+    fn last_modified(&self) -> Option<DateTime<Utc>>;
+}
+
+pub trait RowVersion {
+    fn version(&self) -> i32;
+}
+
+impl<T: LastModified> LastModified for Vec<T> {
+    fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.iter().filter_map(|item| item.last_modified()).max()
+    }
+}
+
+pub fn conditional_json_response<T: Serialize + LastModified>(
+    headers: &HeaderMap,
+    body: &T,
+) -> Response {
+    conditional_json_response_with_last_modified(headers, body, body.last_modified())
+}
+
+pub fn conditional_versioned_json_response<T: Serialize + LastModified + RowVersion>(
+    headers: &HeaderMap,
+    body: &T,
+) -> Response {
+    match serde_json::to_vec(body) {
+        Ok(serialized_body) => build_conditional_response(
+            headers,
+            serialized_body,
+            version_etag(body.version()),
+            body.last_modified(),
+        ),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+/*
+Used when the last modification time is not only determined by the records in the body,
+e.g. /terms-from-topic also changes when a new term is linked to the topic.
+ */
+pub fn conditional_json_response_with_last_modified<T: Serialize>(
+    headers: &HeaderMap,
+    body: &T,
+    last_modified: Option<DateTime<Utc>>,
+) -> Response {
+    match serde_json::to_vec(body) {
+        Ok(serialized_body) => {
+            let etag = format!("\"{}\"", hex::encode(Sha256::digest(&serialized_body)));
+            build_conditional_response(headers, serialized_body, etag, last_modified)
+        }
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+fn build_conditional_response(
+    headers: &HeaderMap,
+    serialized_body: Vec<u8>,
+    etag: String,
+    last_modified: Option<DateTime<Utc>>,
+) -> Response {
+    // HTTP dates only have second precision
+    let last_modified =
+        last_modified.and_then(|time| Utc.timestamp_opt(time.timestamp(), 0).single());
+
+    let mut response_headers = HeaderMap::new();
+    if let Ok(etag_value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(ETAG, etag_value);
+    }
+    if let Some(last_modified) = last_modified {
+        if let Ok(last_modified_value) =
+            HeaderValue::from_str(&last_modified.format(HTTP_DATE_FORMAT).to_string())
+        {
+            response_headers.insert(LAST_MODIFIED, last_modified_value);
+        }
+    }
+
+    if is_not_modified(headers, &etag, last_modified) {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    (StatusCode::OK, response_headers, serialized_body).into_response()
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since (RFC 7232 section 6)
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        return if_none_match
+            .to_str()
+            .map(|value| etag_matches(value, etag))
+            .unwrap_or(false);
+    }
+    if let (Some(if_modified_since), Some(last_modified)) =
+        (headers.get(IF_MODIFIED_SINCE), last_modified)
+    {
+        if let Some(if_modified_since) = if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        {
+            return last_modified <= if_modified_since;
+        }
+    }
+    false
+}
+
+// weak comparison, so `W/"<hash>"` from a proxy still matches
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+pub fn version_etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+// the versions an update may be applied to
+#[derive(Debug, PartialEq)]
+pub enum ExpectedVersion {
+    // `If-Match: *`, whatever the record's version is
+    Any,
+    // empty when no tag of If-Match can match, the update then always fails with 412
+    OneOf(Vec<i32>),
+}
+
+/*
+The version an update is based on, taken from `If-Match: "<version>"` or, for clients
+that can't set headers, from the `version` field of the body.
+
+If-Match uses the strong comparison (RFC 7232 section 3.1): `*` matches any record, each
+tag of a list is tried, and a weak `W/"<version>"` tag never matches.
+ */
+pub fn expected_version(headers: &HeaderMap, body_version: Option<i32>) -> Option<ExpectedVersion> {
+    if let Some(if_match) = headers.get(IF_MATCH) {
+        let if_match = if_match.to_str().unwrap_or_default().trim();
+        if if_match == "*" {
+            return Some(ExpectedVersion::Any);
+        }
+        let versions = if_match
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.starts_with("W/"))
+            .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+            .collect();
+        return Some(ExpectedVersion::OneOf(versions));
+    }
+    body_version.map(|version| ExpectedVersion::OneOf(vec![version]))
+}
+
+/*
+Runs `update(version)`, the conditional update of a record, for the versions in
+`expected`, until one matches or the record is missing. Versions start at 1, so version 0
+never matches and only reads the current version, for the 412 or for `*`.
+ */
+pub async fn update_if_match<F, U>(
+    expected: &ExpectedVersion,
+    mut update: F,
+) -> sqlx::Result<UpdateOutcome>
+where
+    F: FnMut(i32) -> U,
+    U: Future<Output = sqlx::Result<UpdateOutcome>>,
+{
+    match expected {
+        ExpectedVersion::Any => {
+            let mut outcome = update(0).await?;
+            for _ in 0..ANY_VERSION_ATTEMPTS {
+                let UpdateOutcome::VersionConflict { current_version } = outcome else {
+                    break;
+                };
+                outcome = update(current_version).await?;
+            }
+            Ok(outcome)
+        }
+        ExpectedVersion::OneOf(versions) => {
+            let mut conflict = None;
+            for version in versions {
+                let outcome = update(*version).await?;
+                if !matches!(outcome, UpdateOutcome::VersionConflict { .. }) {
+                    return Ok(outcome);
+                }
+                conflict = Some(outcome);
+            }
+            match conflict {
+                Some(conflict) => Ok(conflict),
+                None => update(0).await,
+            }
+        }
+    }
+}
+
+pub fn precondition_required_response() -> Response {
+    (
+        StatusCode::PRECONDITION_REQUIRED,
+        "an If-Match header or a version field is required to update a record",
+    )
+        .into_response()
+}
+
+// 412 with the current version, so the client can refetch, merge and retry
+pub fn precondition_failed_response(current_version: i32) -> Response {
+    let mut response = (
+        StatusCode::PRECONDITION_FAILED,
+        axum::Json(json!({
+            "error": "the record was modified by someone else",
+            "current_version": current_version,
+        })),
+    )
+        .into_response();
+    if let Ok(etag_value) = HeaderValue::from_str(&version_etag(current_version)) {
+        response.headers_mut().insert(ETAG, etag_value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Record {
+        version: i32,
+        updated_at: DateTime<Utc>,
+    }
+
+    impl LastModified for Record {
+        fn last_modified(&self) -> Option<DateTime<Utc>> {
+            Some(self.updated_at)
+        }
+    }
+
+    impl RowVersion for Record {
+        fn version(&self) -> i32 {
+            self.version
+        }
+    }
+
+    fn record() -> Record {
+        Record {
+            version: 3,
+            // the milliseconds are dropped from Last-Modified
+            updated_at: Utc.timestamp_opt(784111777, 500_000_000).unwrap(),
+        }
+    }
+
+    fn request(headers: &[(&'static str, &str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    axum::http::HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tags_records_with_their_version_and_lists_with_a_hash() {
+        let response = conditional_versioned_json_response(&request(&[]), &record());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], "\"3\"");
+        assert_eq!(
+            response.headers()[LAST_MODIFIED],
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+        let list = vec![record()];
+        let response = conditional_json_response(&request(&[]), &list);
+        let etag = response.headers()[ETAG].to_str().unwrap().to_owned();
+        assert_eq!(etag.len(), 64 + 2);
+        // the same body has the same tag
+        let response = conditional_json_response(&request(&[]), &list);
+        assert_eq!(response.headers()[ETAG], etag.as_str());
+    }
+
+    #[test]
+    fn answers_304_to_a_matching_if_none_match() {
+        for if_none_match in ["\"3\"", "W/\"3\"", "\"1\", \"3\"", "*"] {
+            let headers = request(&[("if-none-match", if_none_match)]);
+            let response = conditional_versioned_json_response(&headers, &record());
+            assert_eq!(
+                response.status(),
+                StatusCode::NOT_MODIFIED,
+                "{}",
+                if_none_match
+            );
+            assert_eq!(response.headers()[ETAG], "\"3\"");
+        }
+        let headers = request(&[("if-none-match", "\"2\"")]);
+        let response = conditional_versioned_json_response(&headers, &record());
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn answers_304_when_not_modified_since() {
+        for (if_modified_since, status) in [
+            ("Sun, 06 Nov 1994 08:49:37 GMT", StatusCode::NOT_MODIFIED),
+            ("Mon, 07 Nov 1994 08:49:37 GMT", StatusCode::NOT_MODIFIED),
+            ("Sun, 06 Nov 1994 08:49:36 GMT", StatusCode::OK),
+            ("not a date", StatusCode::OK),
+        ] {
+            let headers = request(&[("if-modified-since", if_modified_since)]);
+            let response = conditional_versioned_json_response(&headers, &record());
+            assert_eq!(response.status(), status, "{}", if_modified_since);
+        }
+
+        // If-None-Match wins when both are sent
+        let headers = request(&[
+            ("if-none-match", "\"2\""),
+            ("if-modified-since", "Mon, 07 Nov 1994 08:49:37 GMT"),
+        ]);
+        let response = conditional_versioned_json_response(&headers, &record());
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn reads_the_expected_version_from_if_match_or_the_body() {
+        assert_eq!(expected_version(&request(&[]), None), None);
+        assert_eq!(
+            expected_version(&request(&[]), Some(2)),
+            Some(ExpectedVersion::OneOf(vec![2]))
+        );
+        // the header wins over the body
+        for (if_match, expected) in [
+            ("\"3\"", ExpectedVersion::OneOf(vec![3])),
+            ("*", ExpectedVersion::Any),
+            ("\"1\", W/\"2\", \"3\"", ExpectedVersion::OneOf(vec![1, 3])),
+            ("W/\"3\"", ExpectedVersion::OneOf(vec![])),
+            ("3", ExpectedVersion::OneOf(vec![])),
+        ] {
+            let headers = request(&[("if-match", if_match)]);
+            assert_eq!(
+                expected_version(&headers, Some(2)),
+                Some(expected),
+                "{}",
+                if_match
+            );
+        }
+    }
+
+    // an update of a record at `current_version`, recording the versions it was tried with
+    async fn update(
+        expected: ExpectedVersion,
+        current_version: Option<i32>,
+    ) -> (UpdateOutcome, Vec<i32>) {
+        let mut tried = vec![];
+        let outcome = update_if_match(&expected, |version| {
+            tried.push(version);
+            async move {
+                Ok(match current_version {
+                    None => UpdateOutcome::NotFound,
+                    Some(current) if current == version => {
+                        UpdateOutcome::Updated { verified: false }
+                    }
+                    Some(current_version) => UpdateOutcome::VersionConflict { current_version },
+                })
+            }
+        })
+        .await
+        .unwrap();
+        (outcome, tried)
+    }
+
+    #[tokio::test]
+    async fn updates_when_a_version_of_if_match_is_current() {
+        let (outcome, tried) = update(ExpectedVersion::OneOf(vec![1, 4, 6]), Some(4)).await;
+        assert!(matches!(outcome, UpdateOutcome::Updated { .. }));
+        assert_eq!(tried, [1, 4]);
+
+        let (outcome, tried) = update(ExpectedVersion::Any, Some(4)).await;
+        assert!(matches!(outcome, UpdateOutcome::Updated { .. }));
+        assert_eq!(tried, [0, 4]);
+
+        let (outcome, _) = update(ExpectedVersion::Any, None).await;
+        assert!(matches!(outcome, UpdateOutcome::NotFound));
+    }
+
+    #[tokio::test]
+    async fn reports_the_current_version_when_none_matches() {
+        let (outcome, tried) = update(ExpectedVersion::OneOf(vec![1, 2]), Some(4)).await;
+        assert!(matches!(
+            outcome,
+            UpdateOutcome::VersionConflict { current_version: 4 }
+        ));
+        assert_eq!(tried, [1, 2]);
+
+        // only weak tags
+        let (outcome, tried) = update(ExpectedVersion::OneOf(vec![]), Some(4)).await;
+        assert!(matches!(
+            outcome,
+            UpdateOutcome::VersionConflict { current_version: 4 }
+        ));
+        assert_eq!(tried, [0]);
+    }
+
+    #[test]
+    fn answers_412_with_the_current_version_and_428_without_one() {
+        let response = precondition_failed_response(4);
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers()[ETAG], "\"4\"");
+        assert_eq!(
+            precondition_required_response().status(),
+            StatusCode::PRECONDITION_REQUIRED
+        );
+    }
+}
@@ -0,0 +1,106 @@
+/*
+Headers for the legacy routes, which are kept as aliases of the /v1 routes until the
+clients have moved.
+
+Every response from a legacy route gets a `Deprecation` header with the date the route
+was deprecated (RFC 9745) and a `Link` header pointing at the /v1 resource replacing it.
+ */
+use axum::{
+    extract::MatchedPath,
+    http::{header::LINK, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+
+pub const DEPRECATION_HEADER: &str = "deprecation";
+
+// 2026-10-19T00:00:00Z, the legacy routes were deprecated when /v1 was added
+const DEPRECATED_SINCE: &str = "@1792368000";
+
+// e.g. "/term" and "/sources-from-term" -> "/v1/terms"
+fn successor_route(legacy_route: &str) -> Option<String> {
+    let successor = match legacy_route {
+        "/topics" | "/topic" | "/new-topic" => "/v1/topics".to_owned(),
+        "/terms" | "/term" | "/new-term" => "/v1/terms".to_owned(),
+        "/sources" | "/source" | "/new-source" => "/v1/sources".to_owned(),
+        "/link-entities" => "/v1/links".to_owned(),
+        _ => {
+            let (_, parent_type) = legacy_route.split_once("-from-")?;
+            format!("/v1/{}s", parent_type)
+        }
+    };
+    Some(successor)
+}
+
+pub async fn deprecated_alias<B>(request: Request<B>, next: Next<B>) -> Response {
+    let successor = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|route| successor_route(route.as_str()));
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static(DEPRECATION_HEADER),
+        HeaderValue::from_static(DEPRECATED_SINCE),
+    );
+    if let Some(successor) = successor {
+        let link = format!("<{}>; rel=\"successor-version\"", successor);
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.insert(LINK, link);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn finds_the_v1_successor_of_a_legacy_route() {
+        for (legacy_route, successor) in [
+            ("/topic", Some("/v1/topics")),
+            ("/new-term", Some("/v1/terms")),
+            ("/link-entities", Some("/v1/links")),
+            ("/sources-from-term", Some("/v1/terms")),
+            ("/metrics", None),
+        ] {
+            assert_eq!(
+                successor_route(legacy_route).as_deref(),
+                successor,
+                "{}",
+                legacy_route
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn adds_the_deprecation_and_link_headers() {
+        let router = Router::new()
+            .route("/term", get(|| async { "storm" }))
+            .route("/unknown", get(|| async { "" }))
+            .route_layer(middleware::from_fn(deprecated_alias));
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/term?id=1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[DEPRECATION_HEADER], DEPRECATED_SINCE);
+        assert_eq!(
+            response.headers()[LINK],
+            "</v1/terms>; rel=\"successor-version\""
+        );
+
+        // a route without a successor is still deprecated
+        let response = router
+            .oneshot(Request::get("/unknown").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[DEPRECATION_HEADER], DEPRECATED_SINCE);
+        assert!(!response.headers().contains_key(LINK));
+    }
+}
@@ -0,0 +1,86 @@
+/*
+The change feed behind /events.
+
+Every change to a topic, term, source, question or article, and every link added or removed,
+is recorded as an Event by the repository and broadcast on its EventFeed. On Postgres the
+triggers in init.sql record the events in platform.events and NOTIFY the platform_events
+channel, and each server instance holds one LISTEN connection that broadcasts them to its
+/events streams, so changes made by other instances or in psql are sent too. On SQLite the
+triggers in init_sqlite.sql record them and the repository polls the events table instead.
+
+A stream reads the events it can't get from the broadcast from the repository instead: the
+ones since the Last-Event-ID it resumed from, the ones it missed when it fell behind, and
+the ones sent while the LISTEN connection was down.
+ */
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+// the entity types events are recorded for
+pub const EVENT_ENTITY_TYPES: [&str; 5] = ["topic", "term", "source", "question", "article"];
+
+// events a stream can fall behind by before it has to read them from the table
+const BROADCAST_CAPACITY: usize = 1024;
+// events read from the repository per query
+pub const EVENTS_PAGE_SIZE: i64 = 500;
+
+#[derive(Serialize, Deserialize, FromRow, Clone)]
+pub struct Event {
+    pub id: i64,
+    // created, updated, deleted, linked or unlinked
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: i32,
+    // the other side of a link
+    pub related_type: Option<String>,
+    pub related_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Event {
+    // no entity types means every event, a link matches either of its types
+    pub fn matches(&self, entity_types: &[String]) -> bool {
+        entity_types.is_empty()
+            || entity_types.contains(&self.entity_type)
+            || self
+                .related_type
+                .as_ref()
+                .is_some_and(|related_type| entity_types.contains(related_type))
+    }
+}
+
+#[derive(Clone)]
+pub enum FeedMessage {
+    Event(Arc<Event>),
+    // the LISTEN connection was (re)established, events may have been missed before it
+    Resync,
+}
+
+#[derive(Clone)]
+pub struct EventFeed {
+    sender: broadcast::Sender<FeedMessage>,
+}
+
+impl EventFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        EventFeed { sender }
+    }
+
+    // sending fails when there are no streams, which is fine
+    pub fn send(&self, message: FeedMessage) {
+        let _ = self.sender.send(message);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FeedMessage> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventFeed {
+    fn default() -> Self {
+        EventFeed::new()
+    }
+}
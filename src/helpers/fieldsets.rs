@@ -0,0 +1,320 @@
+/*
+Sparse fieldsets: `?fields=id,term,brief_description` on the GET endpoints.
+
+Only the requested columns are read by the repository, and each record is a JSON
+object with just those keys. Field names are checked against the entity's columns
+before they are put into a query, so unknown names are rejected with a 400.
+ */
+use crate::helpers::conditional_get::conditional_json_response_with_last_modified;
+use crate::helpers::ndjson::{ndjson_response, wants_ndjson};
+use crate::helpers::read_cache::ReadCache;
+use crate::repository::Repository;
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+pub type SparseRecord = Map<String, Value>;
+
+#[derive(Clone, Copy, Debug)]
+pub enum FieldKind {
+    Int,
+    Text,
+    Bool,
+    TextArray,
+    Date,
+    Timestamp,
+    MediaType,
+    ImageType,
+}
+
+pub type Field = (&'static str, FieldKind);
+
+pub struct EntityFields {
+    pub entity_type: &'static str,
+    pub table: &'static str,
+    // the column records are looked up by name with
+    pub name_column: &'static str,
+    pub fields: &'static [Field],
+}
+
+const TOPIC_OR_TERM_CONTENT_FIELDS: [Field; 12] = [
+    ("is_verified", FieldKind::Bool),
+    ("brief_description", FieldKind::Text),
+    ("full_description", FieldKind::Text),
+    ("bullet_points", FieldKind::TextArray),
+    ("examples", FieldKind::TextArray),
+    ("parallels", FieldKind::TextArray),
+    ("ai_brief_description", FieldKind::Text),
+    ("ai_full_description", FieldKind::Text),
+    ("ai_bullet_points", FieldKind::TextArray),
+    ("ai_parallels", FieldKind::TextArray),
+    ("ai_examples", FieldKind::TextArray),
+    ("version", FieldKind::Int),
+];
+
+const fn topic_or_term_fields(name_column: &'static str) -> [Field; 17] {
+    let content = TOPIC_OR_TERM_CONTENT_FIELDS;
+    [
+        ("id", FieldKind::Int),
+        (name_column, FieldKind::Text),
+        ("slug", FieldKind::Text),
+        content[0],
+        content[1],
+        content[2],
+        content[3],
+        content[4],
+        content[5],
+        content[6],
+        content[7],
+        content[8],
+        content[9],
+        content[10],
+        content[11],
+        ("created_at", FieldKind::Timestamp),
+        ("updated_at", FieldKind::Timestamp),
+    ]
+}
+
+pub const TOPIC_FIELDS: EntityFields = EntityFields {
+    entity_type: "topic",
+    table: "topics",
+    name_column: "topic",
+    fields: &topic_or_term_fields("topic"),
+};
+
+pub const TERM_FIELDS: EntityFields = EntityFields {
+    entity_type: "term",
+    table: "terms",
+    name_column: "term",
+    fields: &topic_or_term_fields("term"),
+};
+
+pub const SOURCE_FIELDS: EntityFields = EntityFields {
+    entity_type: "source",
+    table: "sources",
+    name_column: "name",
+    fields: &[
+        ("id", FieldKind::Int),
+        ("name", FieldKind::Text),
+        ("slug", FieldKind::Text),
+        ("url", FieldKind::Text),
+        ("author", FieldKind::Text),
+        ("author_url", FieldKind::Text),
+        ("media_type", FieldKind::MediaType),
+        ("image_url", FieldKind::Text),
+        ("image_type", FieldKind::ImageType),
+        ("ai_generated", FieldKind::Bool),
+        ("version", FieldKind::Int),
+        ("created_at", FieldKind::Timestamp),
+        ("updated_at", FieldKind::Timestamp),
+    ],
+};
+
+pub const QUESTION_FIELDS: EntityFields = EntityFields {
+    entity_type: "question",
+    table: "questions",
+    name_column: "question",
+    fields: &[
+        ("id", FieldKind::Int),
+        ("question", FieldKind::Text),
+        ("topic_id", FieldKind::Int),
+        ("created_at", FieldKind::Timestamp),
+        ("updated_at", FieldKind::Timestamp),
+    ],
+};
+
+pub const ARTICLE_FIELDS: EntityFields = EntityFields {
+    entity_type: "article",
+    table: "articles",
+    name_column: "title",
+    fields: &[
+        ("id", FieldKind::Int),
+        ("title", FieldKind::Text),
+        ("author", FieldKind::Text),
+        ("publish_date", FieldKind::Date),
+        ("created_at", FieldKind::Timestamp),
+        ("updated_at", FieldKind::Timestamp),
+    ],
+};
+
+// "topic" -> TOPIC_FIELDS etc.
+pub fn entity_fields(entity_type: &str) -> Option<&'static EntityFields> {
+    match entity_type {
+        "topic" => Some(&TOPIC_FIELDS),
+        "term" => Some(&TERM_FIELDS),
+        "source" => Some(&SOURCE_FIELDS),
+        "question" => Some(&QUESTION_FIELDS),
+        "article" => Some(&ARTICLE_FIELDS),
+        _ => None,
+    }
+}
+
+// comma separated field names -> the entity's fields, in the requested order
+pub fn parse_fields(
+    fields: &str,
+    entity: &EntityFields,
+) -> std::result::Result<Vec<Field>, String> {
+    let mut requested: Vec<Field> = vec![];
+    for field_name in fields
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let Some(field) = entity.fields.iter().find(|(name, _)| *name == field_name) else {
+            let known: Vec<&str> = entity.fields.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "unknown field \"{}\" for {}, expected one of: {}",
+                field_name,
+                entity.table,
+                known.join(", ")
+            ));
+        };
+        if !requested.iter().any(|(name, _)| *name == field_name) {
+            requested.push(*field);
+        }
+    }
+    if requested.is_empty() {
+        return Err("fields must name at least one field".to_owned());
+    }
+    Ok(requested)
+}
+
+// e.g. "terms.id, terms.term", the names are from the allowlists above
+pub fn select_list(fields: &[Field], table_alias: &str) -> String {
+    fields
+        .iter()
+        .map(|(name, _)| format!("{}.{}", table_alias, name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// a complete record with only the given fields
+pub fn select_fields(record: &SparseRecord, fields: &[Field]) -> SparseRecord {
+    fields
+        .iter()
+        .map(|(name, _)| {
+            let value = record.get(*name).cloned().unwrap_or(Value::Null);
+            ((*name).to_owned(), value)
+        })
+        .collect()
+}
+
+/*
+Response for a list endpoint called with ?fields=, with the same NDJSON, caching and
+ETag behaviour as the full records. There is no Last-Modified since updated_at may not
+have been selected.
+ */
+pub async fn sparse_list_response(
+    repository: Arc<dyn Repository>,
+    read_cache: &ReadCache,
+    headers: &HeaderMap,
+    entity: &'static EntityFields,
+    fields: &str,
+    updated_since: Option<DateTime<Utc>>,
+) -> Response {
+    let fields = match parse_fields(fields, entity) {
+        Ok(fields) => fields,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    if wants_ndjson(headers) {
+        return ndjson_response(repository.stream_records(entity, fields, updated_since));
+    }
+
+    let cache_key = format!(
+        "{}?fields={}&updated_since={:?}",
+        entity.table,
+        select_list(&fields, entity.table),
+        updated_since
+    );
+    let records = read_cache
+        .get_or_load(cache_key, || {
+            repository.get_records(entity, &fields, updated_since)
+        })
+        .await;
+    match records {
+        Ok(records) => conditional_json_response_with_last_modified(headers, &*records, None),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+// see sparse_list_response
+pub async fn sparse_record_response(
+    repository: Arc<dyn Repository>,
+    read_cache: &ReadCache,
+    headers: &HeaderMap,
+    entity: &EntityFields,
+    fields: &str,
+    id: i32,
+) -> Response {
+    let fields = match parse_fields(fields, entity) {
+        Ok(fields) => fields,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let cache_key = format!(
+        "{}?id={}&fields={}",
+        entity.table,
+        id,
+        select_list(&fields, entity.table)
+    );
+    let record = read_cache
+        .get_or_load(cache_key, || async {
+            repository
+                .get_record(entity, &fields, id)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)
+        })
+        .await;
+    match record {
+        Ok(record) => conditional_json_response_with_last_modified(headers, &*record, None),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn names(fields: &[Field]) -> Vec<&'static str> {
+        fields.iter().map(|(name, _)| *name).collect()
+    }
+
+    #[test]
+    fn parses_fields_in_the_requested_order() {
+        let fields = parse_fields(" term, id ,,term,slug", &TERM_FIELDS).unwrap();
+        assert_eq!(names(&fields), ["term", "id", "slug"]);
+        assert_eq!(
+            select_list(&fields, "terms"),
+            "terms.term, terms.id, terms.slug"
+        );
+    }
+
+    #[test]
+    fn refuses_unknown_and_missing_fields() {
+        let error = parse_fields("id,color", &TOPIC_FIELDS).unwrap_err();
+        assert!(error.starts_with("unknown field \"color\" for topics, expected one of: id, topic"));
+        // a column of another entity
+        assert!(parse_fields("term", &TOPIC_FIELDS).is_err());
+        // a name can't sneak SQL into the select list
+        assert!(parse_fields("id; DROP TABLE topics", &TOPIC_FIELDS).is_err());
+        assert_eq!(
+            parse_fields(" , ", &TOPIC_FIELDS).unwrap_err(),
+            "fields must name at least one field"
+        );
+    }
+
+    #[test]
+    fn selects_the_fields_of_a_record() {
+        let record = json!({"id": 1, "term": "Storm", "slug": "storm"});
+        let fields = parse_fields("slug,id,brief_description", &TERM_FIELDS).unwrap();
+        let selected = select_fields(record.as_object().unwrap(), &fields);
+        assert_eq!(
+            Value::Object(selected),
+            json!({"slug": "storm", "id": 1, "brief_description": null})
+        );
+    }
+}
@@ -0,0 +1,199 @@
+/*
+Flashcards of the terms for GET /v1/export/flashcards, one card per term with the term on
+the front and its description, bullet points and examples on the back.
+
+Anki imports the tab separated file with the directives of its first lines: the back is
+HTML, and the third column has the card's tags, the names of the term's topics with their
+spaces replaced by underscores since Anki separates tags with spaces. Quizlet imports the
+CSV with "term" and "definition" columns, the lines of the back are separated by newlines.
+ */
+use crate::helpers::fieldsets::{Field, FieldKind, SparseRecord};
+use serde::Deserialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashcardFormat {
+    // tab separated values for Anki
+    #[default]
+    Anki,
+    // CSV for Quizlet
+    Quizlet,
+}
+
+impl FlashcardFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FlashcardFormat::Anki => "text/tab-separated-values; charset=utf-8",
+            FlashcardFormat::Quizlet => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            FlashcardFormat::Anki => "flashcards.txt",
+            FlashcardFormat::Quizlet => "flashcards.csv",
+        }
+    }
+}
+
+// which of a term's descriptions go on the back of its card
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CardFields {
+    // brief_description, bullet_points and examples
+    #[default]
+    Human,
+    // ai_brief_description, ai_bullet_points and ai_examples
+    Ai,
+}
+
+impl CardFields {
+    // the term fields a card is made of, besides the term itself
+    pub fn fields(self) -> [Field; 4] {
+        let [brief_description, bullet_points, examples] = self.columns();
+        [
+            ("is_verified", FieldKind::Bool),
+            (brief_description, FieldKind::Text),
+            (bullet_points, FieldKind::TextArray),
+            (examples, FieldKind::TextArray),
+        ]
+    }
+
+    fn columns(self) -> [&'static str; 3] {
+        match self {
+            CardFields::Human => ["brief_description", "bullet_points", "examples"],
+            CardFields::Ai => ["ai_brief_description", "ai_bullet_points", "ai_examples"],
+        }
+    }
+}
+
+pub struct Flashcard {
+    pub front: String,
+    pub description: Option<String>,
+    pub bullet_points: Vec<String>,
+    pub examples: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+fn text(record: &SparseRecord, field: &str) -> Option<String> {
+    record
+        .get(field)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_owned)
+}
+
+fn texts(record: &SparseRecord, field: &str) -> Vec<String> {
+    record
+        .get(field)
+        .and_then(Value::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl Flashcard {
+    // None when the term has nothing for the back of the card
+    pub fn from_term(term: &SparseRecord, fields: CardFields, topics: &[String]) -> Option<Self> {
+        let [brief_description, bullet_points, examples] = fields.columns();
+        let card = Flashcard {
+            front: text(term, "term")?,
+            description: text(term, brief_description),
+            bullet_points: texts(term, bullet_points),
+            examples: texts(term, examples),
+            tags: topics
+                .iter()
+                .map(|topic| topic.split_whitespace().collect::<Vec<_>>().join("_"))
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+        };
+        if card.description.is_none() && card.bullet_points.is_empty() && card.examples.is_empty() {
+            return None;
+        }
+        Some(card)
+    }
+
+    // the back as plain text lines, for Quizlet
+    fn back_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.description.iter().cloned().collect();
+        lines.extend(
+            self.bullet_points
+                .iter()
+                .map(|point| format!("• {}", point)),
+        );
+        lines.extend(
+            self.examples
+                .iter()
+                .map(|example| format!("Example: {}", example)),
+        );
+        lines
+    }
+
+    // the back as HTML, for Anki
+    fn back_html(&self) -> String {
+        let mut html = String::new();
+        if let Some(description) = &self.description {
+            html.push_str(&escape_html(description));
+        }
+        if !self.bullet_points.is_empty() {
+            html.push_str("<ul>");
+            for point in &self.bullet_points {
+                html.push_str(&format!("<li>{}</li>", escape_html(point)));
+            }
+            html.push_str("</ul>");
+        }
+        for example in &self.examples {
+            if !html.is_empty() && !html.ends_with("</ul>") {
+                html.push_str("<br>");
+            }
+            html.push_str(&format!("<i>Example:</i> {}", escape_html(example)));
+        }
+        html
+    }
+}
+
+// the text of an Anki field, where a tab or a newline would end the field or the card
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace(['\t', '\r'], " ")
+        .replace('\n', "<br>")
+}
+
+pub fn render_anki(cards: &[Flashcard]) -> String {
+    let mut file = String::from("#separator:tab\n#html:true\n#tags column:3\n");
+    for card in cards {
+        file.push_str(&format!(
+            "{}\t{}\t{}\n",
+            escape_html(&card.front),
+            card.back_html(),
+            card.tags.join(" ")
+        ));
+    }
+    file
+}
+
+pub fn render_quizlet(cards: &[Flashcard]) -> String {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record(["term", "definition"])
+        .expect("writing to a Vec can't fail");
+    for card in cards {
+        writer
+            .write_record([card.front.as_str(), card.back_lines().join("\n").as_str()])
+            .expect("writing to a Vec can't fail");
+    }
+    let file = writer.into_inner().expect("writing to a Vec can't fail");
+    String::from_utf8(file).expect("the cards are UTF-8")
+}
@@ -0,0 +1,238 @@
+/*
+The knowledge graph for GET /v1/export/graph: a node per topic, term and source, and an
+edge per row of the link tables between them, labelled with the table. An edge goes from
+the record of the table's first column to the one of its second, e.g. term -> topic, and
+parent -> child for the mind map, related_topics. Node ids are "<entity type>:<id>".
+
+The graph is written as Graphviz DOT, as GraphML, or as node-link JSON for d3, which
+takes the `links` with their `source` and `target` as they are.
+ */
+use crate::helpers::archive::archived_link_table;
+use crate::helpers::fieldsets::{
+    EntityFields, FieldKind, SOURCE_FIELDS, TERM_FIELDS, TOPIC_FIELDS,
+};
+use crate::repository::Repository;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use utoipa::ToSchema;
+
+pub const GRAPH_ENTITIES: [&EntityFields; 3] = [&TOPIC_FIELDS, &TERM_FIELDS, &SOURCE_FIELDS];
+
+// the link tables between the graph's entities, in the order their edges are written
+const GRAPH_LINK_TABLES: [&str; 4] = [
+    "terms_to_topics",
+    "terms_to_sources",
+    "topics_to_sources",
+    "related_topics",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    // node-link JSON for d3
+    #[default]
+    Json,
+    Dot,
+    Graphml,
+}
+
+impl GraphFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            GraphFormat::Json => "application/json",
+            GraphFormat::Dot => "text/vnd.graphviz; charset=utf-8",
+            GraphFormat::Graphml => "application/graphml+xml; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GraphNode {
+    /// "<entity type>:<id>", e.g. "term:1"
+    pub id: String,
+    /// topic, term or source
+    #[serde(rename = "type")]
+    pub entity_type: &'static str,
+    /// The record's name
+    pub label: String,
+    pub slug: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GraphLink {
+    /// The id of the node of the table's first column
+    pub source: String,
+    /// The id of the node of the table's second column
+    pub target: String,
+    /// The link table of the edge, e.g. terms_to_topics
+    pub table: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct KnowledgeGraph {
+    /// Always true, an edge goes from the record of its table's first column to the one of its second
+    pub directed: bool,
+    pub nodes: Vec<GraphNode>,
+    pub links: Vec<GraphLink>,
+}
+
+pub fn node_id(entity_type: &str, id: i32) -> String {
+    format!("{}:{}", entity_type, id)
+}
+
+// every topic, term and source, ordered by id, and every link between them
+pub async fn load_graph(repository: &dyn Repository) -> sqlx::Result<KnowledgeGraph> {
+    let mut nodes = vec![];
+    for entity in GRAPH_ENTITIES {
+        let fields = [
+            ("id", FieldKind::Int),
+            (entity.name_column, FieldKind::Text),
+            ("slug", FieldKind::Text),
+        ];
+        for record in repository.get_records(entity, &fields, None).await? {
+            let Some(id) = record.get("id").and_then(|id| id.as_i64()) else {
+                continue;
+            };
+            nodes.push(GraphNode {
+                id: node_id(entity.entity_type, id as i32),
+                entity_type: entity.entity_type,
+                label: record
+                    .get(entity.name_column)
+                    .and_then(|name| name.as_str())
+                    .unwrap_or_default()
+                    .to_owned(),
+                slug: record
+                    .get("slug")
+                    .and_then(|slug| slug.as_str())
+                    .map(str::to_owned),
+            });
+        }
+    }
+
+    let mut links = vec![];
+    for table in GRAPH_LINK_TABLES {
+        let link_table = archived_link_table(table).expect("an archived table");
+        for (from_id, to_id) in repository.get_link_rows(link_table).await? {
+            links.push(GraphLink {
+                source: node_id(link_table.from.entity_type, from_id),
+                target: node_id(link_table.to.entity_type, to_id),
+                table: link_table.table,
+            });
+        }
+    }
+    Ok(KnowledgeGraph {
+        directed: true,
+        nodes,
+        links,
+    })
+}
+
+impl KnowledgeGraph {
+    // the nodes at most `depth` edges away from the start node, either way, and the edges between them
+    pub fn neighbourhood(self, start: &str, depth: usize) -> Self {
+        let mut adjacent: HashMap<&str, Vec<&str>> = HashMap::new();
+        for link in &self.links {
+            adjacent.entry(&link.source).or_default().push(&link.target);
+            adjacent.entry(&link.target).or_default().push(&link.source);
+        }
+        let mut reached: HashSet<String> = HashSet::from([start.to_owned()]);
+        let mut queue = VecDeque::from([(start, 0)]);
+        while let Some((node, distance)) = queue.pop_front() {
+            if distance == depth {
+                continue;
+            }
+            for next in adjacent.get(node).into_iter().flatten() {
+                if reached.insert((*next).to_owned()) {
+                    queue.push_back((next, distance + 1));
+                }
+            }
+        }
+        KnowledgeGraph {
+            directed: self.directed,
+            nodes: self
+                .nodes
+                .into_iter()
+                .filter(|node| reached.contains(&node.id))
+                .collect(),
+            links: self
+                .links
+                .into_iter()
+                .filter(|link| reached.contains(&link.source) && reached.contains(&link.target))
+                .collect(),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph knowledge {\n");
+        for node in &self.nodes {
+            let shape = match node.entity_type {
+                "topic" => "box",
+                "term" => "ellipse",
+                _ => "note",
+            };
+            dot.push_str(&format!(
+                "  {} [label={}, type={}, shape={}];\n",
+                dot_string(&node.id),
+                dot_string(&node.label),
+                dot_string(node.entity_type),
+                shape
+            ));
+        }
+        for link in &self.links {
+            dot.push_str(&format!(
+                "  {} -> {} [label={}];\n",
+                dot_string(&link.source),
+                dot_string(&link.target),
+                dot_string(link.table)
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_graphml(&self) -> String {
+        let mut graphml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"type\" for=\"node\" attr.name=\"type\" attr.type=\"string\"/>\n",
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+            "  <key id=\"slug\" for=\"node\" attr.name=\"slug\" attr.type=\"string\"/>\n",
+            "  <key id=\"table\" for=\"edge\" attr.name=\"table\" attr.type=\"string\"/>\n",
+            "  <graph id=\"knowledge\" edgedefault=\"directed\">\n",
+        ));
+        for node in &self.nodes {
+            graphml.push_str(&format!(
+                "    <node id=\"{}\"><data key=\"type\">{}</data><data key=\"label\">{}</data>",
+                escape_xml(&node.id),
+                node.entity_type,
+                escape_xml(&node.label)
+            ));
+            if let Some(slug) = &node.slug {
+                graphml.push_str(&format!("<data key=\"slug\">{}</data>", escape_xml(slug)));
+            }
+            graphml.push_str("</node>\n");
+        }
+        for link in &self.links {
+            graphml.push_str(&format!(
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"table\">{}</data></edge>\n",
+                escape_xml(&link.source),
+                escape_xml(&link.target),
+                link.table
+            ));
+        }
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+}
+
+// a quoted DOT id
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,100 @@
+/*
+Batched reads of related records for the relationship fields of the GraphQL schema.
+
+Resolving `topics { terms { sources } }` field by field would run a query per topic and
+then per term. The fields ask a DataLoader instead, which collects the keys requested
+while a level of the query is resolved and loads them with one repository call per parent type.
+ */
+use crate::helpers::fieldsets::{entity_fields, EntityFields};
+use crate::repository::{from_record, Repository};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::Context;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+// a record type the relationship fields return, read with the fields of ENTITY_TYPE
+pub trait GraphNode: DeserializeOwned + Clone + Send + Sync + 'static {
+    const ENTITY_TYPE: &'static str;
+
+    fn entity() -> &'static EntityFields {
+        entity_fields(Self::ENTITY_TYPE).expect("every GraphNode has its EntityFields")
+    }
+}
+
+// a struct rather than a tuple, the compiler can't prove a future holding a
+// (&'static str, i32) key is Send for every lifetime the loader is used with
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParentKey {
+    parent_type: &'static str,
+    parent_id: i32,
+}
+
+// loads the T records related to each parent
+pub struct RelatedLoader<T> {
+    repository: Arc<dyn Repository>,
+    node: PhantomData<fn() -> T>,
+}
+
+impl<T> RelatedLoader<T> {
+    pub fn new(repository: Arc<dyn Repository>) -> Self {
+        RelatedLoader {
+            repository,
+            node: PhantomData,
+        }
+    }
+}
+
+impl<T: GraphNode> Loader<ParentKey> for RelatedLoader<T> {
+    type Value = Vec<T>;
+    // the error is handed to every field waiting on the batch, so it has to be cloneable
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[ParentKey]) -> Result<HashMap<ParentKey, Vec<T>>, Self::Error> {
+        let mut parent_ids: HashMap<&'static str, Vec<i32>> = HashMap::new();
+        for key in keys {
+            parent_ids
+                .entry(key.parent_type)
+                .or_default()
+                .push(key.parent_id);
+        }
+
+        let mut related: HashMap<ParentKey, Vec<T>> = HashMap::new();
+        for (parent_type, ids) in parent_ids {
+            let records = self
+                .repository
+                .get_related(parent_type, &ids, T::ENTITY_TYPE, T::entity().fields)
+                .await?;
+            for (parent_id, records) in records {
+                let records = records
+                    .into_iter()
+                    .map(from_record)
+                    .collect::<sqlx::Result<Vec<T>>>()?;
+                related.insert(
+                    ParentKey {
+                        parent_type,
+                        parent_id,
+                    },
+                    records,
+                );
+            }
+        }
+        Ok(related)
+    }
+}
+
+// the T records related to a parent, for a relationship field's resolver
+pub async fn load_related<T: GraphNode>(
+    ctx: &Context<'_>,
+    parent_type: &'static str,
+    parent_id: i32,
+) -> async_graphql::Result<Vec<T>> {
+    let loader = ctx.data::<DataLoader<RelatedLoader<T>>>()?;
+    let key = ParentKey {
+        parent_type,
+        parent_id,
+    };
+    let related = loader.load_one(key).await?;
+    Ok(related.unwrap_or_default())
+}
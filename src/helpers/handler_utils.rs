@@ -1,39 +1,65 @@
+use crate::helpers::fieldsets::entity_fields;
+use crate::helpers::lookup::EntityKey;
+use crate::helpers::metrics::{ENTITIES_CREATED_TOTAL, LINK_ROWS_INSERTED_TOTAL};
 use crate::helpers::shared_types::CreateSource;
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::Repository;
 use lazy_static::lazy_static;
 use serde::Deserialize;
-use sqlx::{FromRow, PgPool, Result};
+use sqlx::{FromRow, Result};
 use std::collections::HashMap;
+use utoipa::ToSchema;
 
-#[derive(Deserialize, FromRow)]
+#[derive(Deserialize, FromRow, ToSchema)]
 pub struct CreateTopicOrTerm {
-    name: String,
-    is_verified: bool,
-    brief_description: Option<String>,
-    full_description: Option<String>,
-    bullet_points: Option<Vec<String>>,
-    examples: Option<Vec<String>>,
-    parallels: Option<Vec<String>>,
-    ai_brief_description: Option<String>,
-    ai_full_description: Option<String>,
-    ai_bullet_points: Option<Vec<String>>,
-    ai_parallels: Option<Vec<String>>,
-    ai_examples: Option<Vec<String>>,
-    related_terms: Option<Vec<String>>,
-    related_topics: Option<Vec<String>>,
-    related_sources: Option<Vec<String>>,
+    pub name: String,
+    pub is_verified: bool,
+    pub brief_description: Option<String>,
+    pub full_description: Option<String>,
+    pub bullet_points: Option<Vec<String>>,
+    pub examples: Option<Vec<String>>,
+    pub parallels: Option<Vec<String>>,
+    pub ai_brief_description: Option<String>,
+    pub ai_full_description: Option<String>,
+    pub ai_bullet_points: Option<Vec<String>>,
+    pub ai_parallels: Option<Vec<String>>,
+    pub ai_examples: Option<Vec<String>>,
+    pub related_terms: Option<Vec<String>>,
+    pub related_topics: Option<Vec<String>>,
+    pub related_sources: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, FromRow)]
-pub struct IdRow {
-    id: i32,
+/*
+Body of the update endpoints: the same fields as the create payload, plus the version
+the update is based on when the client doesn't send an If-Match header.
+ */
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateTopicOrTerm {
+    #[serde(flatten)]
+    pub fields: CreateTopicOrTerm,
+    pub version: Option<i32>,
 }
 
+pub enum UpdateOutcome {
+    // `verified` when the update set is_verified on a record that wasn't verified
+    Updated { verified: bool },
+    VersionConflict { current_version: i32 },
+    NotFound,
+}
 
 pub trait CreateEntity {
     fn name(&self) -> &String;
     fn related_terms(&self) -> &Option<Vec<String>>;
     fn related_topics(&self) -> &Option<Vec<String>>;
     fn related_sources(&self) -> &Option<Vec<String>>;
+    // every array field of the payload, checked against the max_array_length limit
+    fn array_fields(&self) -> Vec<(&'static str, &Option<Vec<String>>)>;
+}
+
+impl CreateTopicOrTerm {
+    pub fn is_verified(&self) -> bool {
+        self.is_verified
+    }
 }
 
 impl CreateEntity for CreateTopicOrTerm {
@@ -52,6 +78,20 @@ impl CreateEntity for CreateTopicOrTerm {
     fn related_sources(&self) -> &Option<Vec<String>> {
         &self.related_sources
     }
+
+    fn array_fields(&self) -> Vec<(&'static str, &Option<Vec<String>>)> {
+        vec![
+            ("bullet_points", &self.bullet_points),
+            ("examples", &self.examples),
+            ("parallels", &self.parallels),
+            ("ai_bullet_points", &self.ai_bullet_points),
+            ("ai_parallels", &self.ai_parallels),
+            ("ai_examples", &self.ai_examples),
+            ("related_terms", &self.related_terms),
+            ("related_topics", &self.related_topics),
+            ("related_sources", &self.related_sources),
+        ]
+    }
 }
 
 impl CreateEntity for CreateSource {
@@ -70,6 +110,14 @@ impl CreateEntity for CreateSource {
     fn related_sources(&self) -> &Option<Vec<String>> {
         &self.related_sources
     }
+
+    fn array_fields(&self) -> Vec<(&'static str, &Option<Vec<String>>)> {
+        vec![
+            ("related_terms", &self.related_terms),
+            ("related_topics", &self.related_topics),
+            ("related_sources", &self.related_sources),
+        ]
+    }
 }
 
 pub fn process_optional_vec(param: &Option<Vec<String>>) -> Vec<String> {
@@ -83,7 +131,7 @@ pub fn process_optional_vec(param: &Option<Vec<String>>) -> Vec<String> {
 
 // The lazy_static macro ensures that the HashMap is initialized lazily at runtime, which means that it's only created when it's first accessed.
 lazy_static! {
-    static ref LINK_TABLES: HashMap<&'static str, HashMap<&'static str, &'static str>> = {
+    pub static ref LINK_TABLES: HashMap<&'static str, HashMap<&'static str, &'static str>> = {
         let mut data = HashMap::new();
         data.insert(
             "topic",
@@ -101,139 +149,111 @@ lazy_static! {
     };
 }
 
+// returns the new topic's or term's id
 pub async fn insert_topic_or_term(
+    repository: &dyn Repository,
     payload: &CreateTopicOrTerm,
     topic_or_term: &str,
-    db_pool: &PgPool,
-) -> Result<()> {
-    let bullet_points = process_optional_vec(&payload.bullet_points);
-    let examples = process_optional_vec(&payload.examples);
-    let parallels = process_optional_vec(&payload.parallels);
-    let ai_bullet_points = process_optional_vec(&payload.ai_bullet_points);
-    let ai_parallels = process_optional_vec(&payload.ai_parallels);
-    let ai_examples = process_optional_vec(&payload.ai_examples);
-
-    let query_string = format!("INSERT INTO platform.{}s ({}, is_verified, brief_description, full_description, 
-        bullet_points, examples, parallels, ai_brief_description, ai_full_description, ai_bullet_points, ai_parallels, 
-        ai_examples) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)", topic_or_term, topic_or_term);
-
-    let _insert_result = sqlx::query(&query_string)
-        .bind(&payload.name)
-        .bind(&payload.is_verified)
-        .bind(&payload.brief_description)
-        .bind(&payload.full_description)
-        .bind(bullet_points.as_slice())
-        .bind(examples.as_slice())
-        .bind(parallels.as_slice())
-        .bind(&payload.ai_brief_description)
-        .bind(&payload.ai_full_description)
-        .bind(ai_bullet_points.as_slice())
-        .bind(ai_parallels.as_slice())
-        .bind(ai_examples.as_slice())
-        .execute(db_pool)
-        .await;
-
-    Ok(())
+) -> Result<i32> {
+    let id = repository
+        .insert_topic_or_term(payload, topic_or_term)
+        .await?;
+    ENTITIES_CREATED_TOTAL
+        .with_label_values(&[topic_or_term])
+        .inc();
+    Ok(id)
 }
 
+// links the parent to the children, and sends <type>.linked with the links that are new
 pub async fn update_link_table(
+    repository: &dyn Repository,
+    webhooks: &Webhooks,
     parent_entity_type: &str,
     child_entity_type: &str,
-    parent_id: &i32,
-    child_ids: &Vec<i32>,
-    db_pool: &PgPool,
+    parent_id: i32,
+    child_ids: &[i32],
 ) -> Result<()> {
-    let link_table: &str;
-    if let Some(inner_hashmap) = LINK_TABLES.get(parent_entity_type) {
-        if let Some(table_name) = inner_hashmap.get(child_entity_type) {
-            link_table = table_name;
-            let insert_query_str = format!(
-                "INSERT INTO platform.{} ({}_id, {}_id) VALUES ($1, {})",
-                link_table, child_entity_type, parent_entity_type, parent_id
-            );
-            for child_id in child_ids {
-                sqlx::query(&insert_query_str)
-                    .bind(child_id)
-                    .execute(db_pool)
-                    .await?;
-            }
-        }
-    }
+    let Some(link_table) = LINK_TABLES
+        .get(parent_entity_type)
+        .and_then(|children| children.get(child_entity_type))
+    else {
+        return Ok(());
+    };
+    let inserted = repository
+        .insert_links(
+            link_table,
+            parent_entity_type,
+            parent_id,
+            child_entity_type,
+            child_ids,
+        )
+        .await?;
+    // links that already existed aren't counted or sent
+    LINK_ROWS_INSERTED_TOTAL
+        .with_label_values(&[link_table])
+        .inc_by(inserted.len() as u64);
+    webhooks
+        .links_created(parent_entity_type, parent_id, child_entity_type, &inserted)
+        .await;
     Ok(())
 }
 
+/*
+Links the new or updated record to the related_* names of its payload. Names that aren't
+found are skipped, and a record isn't linked to records of its own type.
+ */
 pub async fn build_link_tables<T: CreateEntity>(
+    repository: &dyn Repository,
+    webhooks: &Webhooks,
     payload: &T,
     entity_type: &str,
-    db_pool: &PgPool,
+    id: i32,
 ) -> Result<()> {
-    // first use payload.value to query that table and get the id for the value
-    let get_id_query_str: String;
-    if entity_type == "source" {
-        get_id_query_str = format!("SELECT id from platform.{}s where name = $1", entity_type);
-    } else {
-        get_id_query_str = format!(
-            "SELECT id from platform.{}s where {} = $1",
-            entity_type, entity_type
-        );
-    }
-    let entity_row = sqlx::query_as::<_, IdRow>(&get_id_query_str)
-        .bind(&payload.name())
-        .fetch_one(db_pool)
-        .await?;
+    link_related_names(repository, webhooks, payload, entity_type, id).await?;
+    Ok(())
+}
 
-    let related_terms = process_optional_vec(&payload.related_terms());
-    let related_terms_str = related_terms.join(",");
-    let related_topics = process_optional_vec(&payload.related_topics());
-    let related_topics_str = related_topics.join(",");
-    let related_sources = process_optional_vec(&payload.related_sources());
-    let related_sources_str = related_sources.join(",");
-    let term_ids: Vec<i32>;
-    let topic_ids: Vec<i32>;
-    let source_ids: Vec<i32>;
-
-    // self-referential data currently not supported for terms
-    if !related_terms.is_empty() && entity_type != "term" {
-        // term_id_rows is of type Vec<IdRow>
-        if let Ok(term_id_rows) = sqlx::query_as!(
-            IdRow,
-            "SELECT id from platform.terms where term in ($1)",
-            related_terms_str
-        )
-        .fetch_all(db_pool)
-        .await
-        {
-            term_ids = term_id_rows.iter().map(|row| row.id).collect();
-            update_link_table(entity_type, "term", &entity_row.id, &term_ids, db_pool).await?;
+// same as build_link_tables, returns the names that weren't found
+#[tracing::instrument(skip_all, fields(entity_type = entity_type, entity_name = %payload.name()), err)]
+pub async fn link_related_names<T: CreateEntity>(
+    repository: &dyn Repository,
+    webhooks: &Webhooks,
+    payload: &T,
+    entity_type: &str,
+    id: i32,
+) -> Result<Vec<String>> {
+    let related_names = [
+        ("term", payload.related_terms()),
+        ("topic", payload.related_topics()),
+        ("source", payload.related_sources()),
+    ];
+    let mut unresolved = vec![];
+    for (related_type, names) in related_names {
+        if related_type == entity_type {
+            continue;
         }
-    }
-    // TODO: add support for adding self-referential topics
-    if !related_topics.is_empty() && entity_type != "topic" {
-        if let Ok(topic_id_rows) = sqlx::query_as!(
-            IdRow,
-            "SELECT id from platform.topics where topic in ($1)",
-            related_topics_str
-        )
-        .fetch_all(db_pool)
-        .await
-        {
-            topic_ids = topic_id_rows.iter().map(|row| row.id).collect();
-            update_link_table(entity_type, "topic", &entity_row.id, &topic_ids, db_pool).await?;
+        let Some(related) = entity_fields(related_type) else {
+            continue;
+        };
+        let mut related_ids = vec![];
+        for name in process_optional_vec(names) {
+            match repository
+                .find_entity_id(related, &EntityKey::Name(&name))
+                .await?
+            {
+                Some(related_id) => related_ids.push(related_id),
+                None => unresolved.push(name),
+            }
         }
-    }
-    if !related_sources.is_empty() && entity_type != "source" {
-        if let Ok(source_id_rows) = sqlx::query_as!(
-            IdRow,
-            "SELECT id from platform.sources where name in ($1)",
-            related_sources_str
+        update_link_table(
+            repository,
+            webhooks,
+            entity_type,
+            related_type,
+            id,
+            &related_ids,
         )
-        .fetch_all(db_pool)
-        .await
-        {
-            source_ids = source_id_rows.iter().map(|row| row.id).collect();
-            update_link_table(entity_type, "source", &entity_row.id, &source_ids, db_pool)
-                .await?;
-        }
+        .await?;
     }
-    Ok(())
+    Ok(unresolved)
 }
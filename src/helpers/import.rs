@@ -0,0 +1,629 @@
+/*
+Bulk import of topics, terms and sources, from POST /v1/import or the `import` command
+(see import_command.rs).
+
+The records are the create payloads, CreateTopicOrTerm or CreateSource, given as a JSON
+array, as NDJSON (one record per line) or as CSV. A record's `entity_type` (topic, term or
+source) says which one it is, records without one are of the type given for the whole
+input, so one file can hold a glossary's topics, terms and sources. is_verified can be
+left out and is then false. CSV columns named like a payload field are that field, other columns have to be
+mapped with a ColumnMapping, e.g. `Word:name,Definition:brief_description,Notes:-` where
+`-` ignores the column. In CSV cells the array fields (bullet_points, related_terms, ...)
+are split on `|`, and an empty cell leaves the field out.
+
+The records of each type are written IMPORT_BATCH_SIZE at a time, each batch in a
+transaction (see Repository::import_records). A record whose name is taken is skipped, or
+updated when OnExisting::Update is asked for. Sources don't have unique names, a source is
+only taken by one with the same name and url. The related_* names are only linked once
+every batch is written, so a term can name a topic that comes later in the file.
+ */
+use crate::helpers::fieldsets::{entity_fields, EntityFields};
+use crate::helpers::handler_utils::{
+    link_related_names, CreateEntity, CreateTopicOrTerm, UpdateOutcome,
+};
+use crate::helpers::limits::{check_array_lengths, RequestLimits};
+use crate::helpers::metrics::ENTITIES_CREATED_TOTAL;
+use crate::helpers::ndjson::NDJSON_CONTENT_TYPE;
+use crate::helpers::shared_types::CreateSource;
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::Repository;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+// records written per transaction
+pub const IMPORT_BATCH_SIZE: usize = 100;
+
+// the entity types that can be imported, in the order their batches are written
+const IMPORTABLE_ENTITY_TYPES: [&str; 3] = ["topic", "term", "source"];
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+
+// the separator of the values of an array field in a CSV cell
+const CSV_ARRAY_SEPARATOR: char = '|';
+
+const ARRAY_FIELDS: [&str; 9] = [
+    "bullet_points",
+    "examples",
+    "parallels",
+    "ai_bullet_points",
+    "ai_parallels",
+    "ai_examples",
+    "related_terms",
+    "related_topics",
+    "related_sources",
+];
+
+const BOOL_FIELDS: [&str; 2] = ["is_verified", "ai_generated"];
+
+const TOPIC_OR_TERM_PAYLOAD_FIELDS: [&str; 15] = [
+    "name",
+    "is_verified",
+    "brief_description",
+    "full_description",
+    "bullet_points",
+    "examples",
+    "parallels",
+    "ai_brief_description",
+    "ai_full_description",
+    "ai_bullet_points",
+    "ai_parallels",
+    "ai_examples",
+    "related_terms",
+    "related_topics",
+    "related_sources",
+];
+
+const SOURCE_PAYLOAD_FIELDS: [&str; 11] = [
+    "name",
+    "url",
+    "author",
+    "author_url",
+    "media_type",
+    "image_url",
+    "image_type",
+    "ai_generated",
+    "related_terms",
+    "related_topics",
+    "related_sources",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl ImportFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next()?.trim();
+        match media_type {
+            "application/json" => Some(ImportFormat::Json),
+            NDJSON_CONTENT_TYPE => Some(ImportFormat::Ndjson),
+            CSV_CONTENT_TYPE => Some(ImportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    // "glossary.csv" -> Csv
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_lowercase().as_str() {
+            "json" => Some(ImportFormat::Json),
+            "ndjson" | "jsonl" => Some(ImportFormat::Ndjson),
+            "csv" => Some(ImportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(ImportFormat::Json),
+            "ndjson" => Ok(ImportFormat::Ndjson),
+            "csv" => Ok(ImportFormat::Csv),
+            _ => Err(format!(
+                "unknown format {}, use json, ndjson or csv",
+                format
+            )),
+        }
+    }
+}
+
+// what happens to a record whose name is already taken
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnExisting {
+    #[default]
+    Skip,
+    Update,
+}
+
+impl FromStr for OnExisting {
+    type Err = String;
+
+    fn from_str(on_existing: &str) -> Result<Self, Self::Err> {
+        match on_existing {
+            "skip" => Ok(OnExisting::Skip),
+            "update" => Ok(OnExisting::Update),
+            _ => Err(format!(
+                "unknown on_existing {}, use skip or update",
+                on_existing
+            )),
+        }
+    }
+}
+
+/*
+CSV column -> payload field, parsed from "Column:field,Other column:-". Columns that aren't
+in the mapping are the field of the same name.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct ColumnMapping(HashMap<String, String>);
+
+impl FromStr for ColumnMapping {
+    type Err = String;
+
+    fn from_str(mapping: &str) -> Result<Self, Self::Err> {
+        let mut columns = HashMap::new();
+        for entry in mapping.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let Some((column, field)) = entry.rsplit_once(':') else {
+                return Err(format!(
+                    "{} isn't a column mapping, use <column>:<field> or <column>:-",
+                    entry
+                ));
+            };
+            columns.insert(column.trim().to_owned(), field.trim().to_owned());
+        }
+        Ok(ColumnMapping(columns))
+    }
+}
+
+impl ColumnMapping {
+    // the payload field of the column, None when the column is ignored
+    fn field<'a>(&'a self, column: &'a str) -> Option<&'a str> {
+        match self.0.get(column).map(String::as_str) {
+            Some("-") => None,
+            Some(field) => Some(field),
+            None => Some(column),
+        }
+    }
+}
+
+pub enum ImportRecord {
+    TopicOrTerm(CreateTopicOrTerm),
+    Source(CreateSource),
+}
+
+impl ImportRecord {
+    pub fn name(&self) -> &str {
+        match self {
+            ImportRecord::TopicOrTerm(payload) => &payload.name,
+            ImportRecord::Source(payload) => &payload.name,
+        }
+    }
+
+    fn array_fields(&self) -> Vec<(&'static str, &Option<Vec<String>>)> {
+        match self {
+            ImportRecord::TopicOrTerm(payload) => payload.array_fields(),
+            ImportRecord::Source(payload) => payload.array_fields(),
+        }
+    }
+
+    fn is_verified(&self) -> bool {
+        match self {
+            ImportRecord::TopicOrTerm(payload) => payload.is_verified(),
+            ImportRecord::Source(_) => false,
+        }
+    }
+}
+
+// what Repository::import_records did with a record
+pub enum ImportOutcome {
+    Created { id: i32 },
+    // `verified` as in UpdateOutcome::Updated
+    Updated { id: i32, verified: bool },
+    // the name is taken and existing records aren't updated
+    Skipped { id: i32 },
+    Failed { error: String },
+}
+
+impl ImportOutcome {
+    // the update is based on the version read just before it, so it only conflicts when
+    // another writer got in between
+    pub fn from_update(id: i32, outcome: UpdateOutcome) -> Self {
+        match outcome {
+            UpdateOutcome::Updated { verified } => ImportOutcome::Updated { id, verified },
+            UpdateOutcome::VersionConflict { .. } => ImportOutcome::Failed {
+                error: "the record was changed while it was imported".to_owned(),
+            },
+            UpdateOutcome::NotFound => ImportOutcome::Failed {
+                error: "the record was deleted while it was imported".to_owned(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    Updated,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportRow {
+    /// The record's line in NDJSON and CSV files, its position (from 1) in a JSON array
+    pub row: usize,
+    pub entity_type: Option<&'static str>,
+    pub name: Option<String>,
+    pub status: ImportStatus,
+    /// The created, updated or existing record's id
+    pub id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// related_* names that weren't found, so weren't linked
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRow>,
+}
+
+// a record read from the input with its entity, or why it couldn't be read
+pub struct ParsedRecord {
+    pub row: usize,
+    pub name: Option<String>,
+    pub record: Result<(&'static EntityFields, ImportRecord), String>,
+}
+
+impl ParsedRecord {
+    fn failed(row: usize, error: String) -> Self {
+        ParsedRecord {
+            row,
+            name: None,
+            record: Err(error),
+        }
+    }
+}
+
+// the fields of a topic, term or source, None for the entities that can't be imported
+pub fn importable_entity(entity_type: &str) -> Option<&'static EntityFields> {
+    IMPORTABLE_ENTITY_TYPES
+        .contains(&entity_type)
+        .then(|| entity_fields(entity_type))
+        .flatten()
+}
+
+fn payload_fields(entity_type: &str) -> &'static [&'static str] {
+    match entity_type {
+        "source" => &SOURCE_PAYLOAD_FIELDS,
+        _ => &TOPIC_OR_TERM_PAYLOAD_FIELDS,
+    }
+}
+
+// the record's own entity_type, or the default one
+fn record_entity(
+    object: &mut Map<String, Value>,
+    default_entity: Option<&'static EntityFields>,
+) -> Result<&'static EntityFields, String> {
+    match object.remove("entity_type") {
+        Some(Value::String(entity_type)) => importable_entity(&entity_type).ok_or_else(|| {
+            format!(
+                "unknown entity_type {}, use topic, term or source",
+                entity_type
+            )
+        }),
+        Some(_) => Err("entity_type must be topic, term or source".to_owned()),
+        None => default_entity
+            .ok_or_else(|| "the record has no entity_type and there is no default".to_owned()),
+    }
+}
+
+// a JSON object -> the create payload of its entity
+fn parse_record(
+    default_entity: Option<&'static EntityFields>,
+    row: usize,
+    mut value: Value,
+) -> ParsedRecord {
+    let name = value.get("name").and_then(Value::as_str).map(str::to_owned);
+    let Value::Object(object) = &mut value else {
+        return ParsedRecord::failed(row, "the record isn't a JSON object".to_owned());
+    };
+    let entity = match record_entity(object, default_entity) {
+        Ok(entity) => entity,
+        Err(error) => {
+            return ParsedRecord {
+                row,
+                name,
+                record: Err(error),
+            }
+        }
+    };
+    if entity.entity_type != "source" && !object.contains_key("is_verified") {
+        object.insert("is_verified".to_owned(), Value::Bool(false));
+    }
+    let record = match entity.entity_type {
+        "source" => serde_json::from_value(value).map(ImportRecord::Source),
+        _ => serde_json::from_value(value).map(ImportRecord::TopicOrTerm),
+    };
+    ParsedRecord {
+        row,
+        name,
+        record: record
+            .map(|record| (entity, record))
+            .map_err(|error| error.to_string()),
+    }
+}
+
+fn parse_bool(field: &str, cell: &str) -> Result<bool, String> {
+    match cell.to_lowercase().as_str() {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("{} must be true or false, not {}", field, cell)),
+    }
+}
+
+// a CSV row -> the JSON object of its non-empty cells
+fn csv_cells_to_object(
+    fields: &[Option<&str>],
+    cells: &csv::StringRecord,
+) -> Result<Value, String> {
+    let mut object = Map::new();
+    for (field, cell) in fields.iter().zip(cells.iter()) {
+        let (Some(field), cell) = (field, cell.trim()) else {
+            continue;
+        };
+        if cell.is_empty() {
+            continue;
+        }
+        let value = if ARRAY_FIELDS.contains(field) {
+            cell.split(CSV_ARRAY_SEPARATOR)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| Value::String(value.to_owned()))
+                .collect()
+        } else if BOOL_FIELDS.contains(field) {
+            Value::Bool(parse_bool(field, cell)?)
+        } else {
+            Value::String(cell.to_owned())
+        };
+        object.insert((*field).to_owned(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+/*
+The columns have to be fields of the default entity, or of any of them when there is an
+entity_type column.
+ */
+fn parse_csv(
+    default_entity: Option<&'static EntityFields>,
+    input: &str,
+    mapping: &ColumnMapping,
+) -> Result<Vec<ParsedRecord>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|error| format!("the CSV header can't be read: {}", error))?
+        .clone();
+    let fields: Vec<Option<&str>> = headers
+        .iter()
+        .map(|column| mapping.field(column.trim()))
+        .collect();
+    let allowed: Vec<&str> = match (fields.contains(&Some("entity_type")), default_entity) {
+        (true, _) => TOPIC_OR_TERM_PAYLOAD_FIELDS
+            .iter()
+            .chain(&SOURCE_PAYLOAD_FIELDS)
+            .chain(&["entity_type"])
+            .copied()
+            .collect(),
+        (false, Some(entity)) => payload_fields(entity.entity_type).to_vec(),
+        (false, None) => {
+            return Err(
+                "pass the entity_type of the records or add an entity_type column".to_owned(),
+            )
+        }
+    };
+    for (column, field) in headers.iter().zip(&fields) {
+        if field.is_some_and(|field| !allowed.contains(&field)) {
+            return Err(format!(
+                "column {} isn't a field, map it with <column>:<field> or ignore it with <column>:-",
+                column
+            ));
+        }
+    }
+    if !fields.contains(&Some("name")) {
+        return Err("no column is mapped to name".to_owned());
+    }
+
+    let mut records = vec![];
+    for cells in reader.records() {
+        let record = match cells {
+            Ok(cells) => {
+                let row = cells
+                    .position()
+                    .map_or(0, |position| position.line() as usize);
+                match csv_cells_to_object(&fields, &cells) {
+                    Ok(object) => parse_record(default_entity, row, object),
+                    Err(error) => ParsedRecord::failed(row, error),
+                }
+            }
+            Err(error) => ParsedRecord::failed(
+                error
+                    .position()
+                    .map_or(0, |position| position.line() as usize),
+                error.to_string(),
+            ),
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/*
+The records of the input. An input that can't be read at all, e.g. a JSON body that isn't
+an array or a CSV column that isn't a field, is an error, a record that can't be read is
+reported as failed. Records without an entity_type are of `default_entity`.
+ */
+pub fn parse_records(
+    default_entity: Option<&'static EntityFields>,
+    format: ImportFormat,
+    input: &str,
+    mapping: &ColumnMapping,
+) -> Result<Vec<ParsedRecord>, String> {
+    match format {
+        ImportFormat::Json => {
+            let values: Vec<Value> = serde_json::from_str(input)
+                .map_err(|error| format!("the body isn't a JSON array: {}", error))?;
+            Ok(values
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| parse_record(default_entity, index + 1, value))
+                .collect())
+        }
+        ImportFormat::Ndjson => Ok(input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| match serde_json::from_str(line) {
+                Ok(value) => parse_record(default_entity, index + 1, value),
+                Err(error) => ParsedRecord::failed(index + 1, error.to_string()),
+            })
+            .collect()),
+        ImportFormat::Csv => parse_csv(default_entity, input, mapping),
+    }
+}
+
+/*
+Writes the records, in batches of one entity type, and links their related_* names once
+all of them are written. Then queues the webhook events of the created and verified
+records. The caller invalidates the read cache.
+ */
+#[tracing::instrument(skip_all, fields(records = records.len()))]
+pub async fn import(
+    repository: &dyn Repository,
+    webhooks: &Webhooks,
+    limits: &RequestLimits,
+    records: Vec<ParsedRecord>,
+    on_existing: OnExisting,
+) -> ImportReport {
+    let mut rows = vec![];
+    // (index in rows, entity, record) of the records that can be written
+    let mut valid = vec![];
+    for parsed in records {
+        let record = parsed.record.and_then(|(entity, record)| {
+            check_array_lengths(&record.array_fields(), limits)?;
+            Ok((entity, record))
+        });
+        rows.push(ImportRow {
+            row: parsed.row,
+            entity_type: record.as_ref().ok().map(|(entity, _)| entity.entity_type),
+            name: parsed.name,
+            status: ImportStatus::Failed,
+            id: None,
+            error: record.as_ref().err().cloned(),
+            unresolved: vec![],
+        });
+        if let Ok((entity, record)) = record {
+            valid.push((rows.len() - 1, entity, record));
+        }
+    }
+
+    for entity_type in IMPORTABLE_ENTITY_TYPES {
+        let of_type: Vec<_> = valid
+            .iter()
+            .filter(|(_, entity, _)| entity.entity_type == entity_type)
+            .collect();
+        for batch in of_type.chunks(IMPORT_BATCH_SIZE) {
+            let entity = batch[0].1;
+            let batch_records: Vec<&ImportRecord> =
+                batch.iter().map(|(_, _, record)| record).collect();
+            let outcomes = match repository
+                .import_records(entity, &batch_records, on_existing == OnExisting::Update)
+                .await
+            {
+                Ok(outcomes) => outcomes,
+                // the batch was rolled back
+                Err(error) => batch
+                    .iter()
+                    .map(|_| ImportOutcome::Failed {
+                        error: error.to_string(),
+                    })
+                    .collect(),
+            };
+            for ((index, _, _), outcome) in batch.iter().zip(outcomes) {
+                let row = &mut rows[*index];
+                let (status, id, error) = match outcome {
+                    ImportOutcome::Created { id } => (ImportStatus::Created, Some(id), None),
+                    ImportOutcome::Updated { id, verified } => {
+                        if verified {
+                            webhooks.record_event(entity_type, "verified", id).await;
+                        }
+                        (ImportStatus::Updated, Some(id), None)
+                    }
+                    ImportOutcome::Skipped { id } => (ImportStatus::Skipped, Some(id), None),
+                    ImportOutcome::Failed { error } => (ImportStatus::Failed, None, Some(error)),
+                };
+                row.status = status;
+                row.id = id;
+                row.error = error;
+            }
+        }
+    }
+
+    // every record is written, so the related names can refer to records later in the input
+    for (index, entity, record) in &valid {
+        let row = &mut rows[*index];
+        let (Some(id), ImportStatus::Created | ImportStatus::Updated) = (row.id, row.status) else {
+            continue;
+        };
+        // created goes out before the linked events of the related names
+        if row.status == ImportStatus::Created {
+            ENTITIES_CREATED_TOTAL
+                .with_label_values(&[entity.entity_type])
+                .inc();
+            webhooks
+                .record_created(entity.entity_type, id, record.is_verified())
+                .await;
+        }
+        let linked = match record {
+            ImportRecord::TopicOrTerm(payload) => {
+                link_related_names(repository, webhooks, payload, entity.entity_type, id).await
+            }
+            ImportRecord::Source(payload) => {
+                link_related_names(repository, webhooks, payload, entity.entity_type, id).await
+            }
+        };
+        match linked {
+            Ok(unresolved) => row.unresolved = unresolved,
+            Err(error) => row.error = Some(format!("linking failed: {}", error)),
+        }
+    }
+
+    let count = |status| rows.iter().filter(|row| row.status == status).count();
+    ImportReport {
+        created: count(ImportStatus::Created),
+        updated: count(ImportStatus::Updated),
+        skipped: count(ImportStatus::Skipped),
+        failed: count(ImportStatus::Failed),
+        rows,
+    }
+}
@@ -0,0 +1,167 @@
+/*
+Embedded related entities: `?include=terms,sources` on /topic, /term and /source.
+
+Terms, topics and sources are related through the bridge tables in LINK_TABLES.
+Questions belong to a topic, so the questions of a term or source are the questions of
+the topics it is linked to (see repository::relation). Each include is a single query for
+all of the parent ids, so the number of queries doesn't grow with the number of related rows.
+ */
+use crate::helpers::conditional_get::conditional_json_response_with_last_modified;
+use crate::helpers::fieldsets::{
+    entity_fields, parse_fields, select_list, EntityFields, Field, SparseRecord,
+};
+use crate::helpers::read_cache::ReadCache;
+use crate::repository::{relation, Repository};
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use sqlx::Result;
+use std::sync::Arc;
+
+// include name -> entity type
+const INCLUDE_NAMES: [(&str, &str); 4] = [
+    ("terms", "term"),
+    ("topics", "topic"),
+    ("sources", "source"),
+    ("questions", "question"),
+];
+
+// comma separated include names -> the related entity types, e.g. "terms,sources" -> ["term", "source"]
+pub fn parse_includes(
+    include: &str,
+    entity_type: &str,
+) -> std::result::Result<Vec<&'static str>, String> {
+    let mut includes: Vec<&'static str> = vec![];
+    for include_name in include
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let Some((_, child_type)) = INCLUDE_NAMES.iter().find(|(name, _)| *name == include_name)
+        else {
+            let known: Vec<&str> = INCLUDE_NAMES.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "unknown include \"{}\", expected one of: {}",
+                include_name,
+                known.join(", ")
+            ));
+        };
+        if !is_related(entity_type, child_type) {
+            return Err(format!("a {} has no related {}", entity_type, include_name));
+        }
+        if !includes.contains(child_type) {
+            includes.push(child_type);
+        }
+    }
+    if includes.is_empty() {
+        return Err("include must name at least one entity".to_owned());
+    }
+    Ok(includes)
+}
+
+pub fn is_related(entity_type: &str, child_type: &str) -> bool {
+    entity_fields(child_type).is_some() && relation(entity_type, child_type).is_some()
+}
+
+pub async fn get_record_with_includes(
+    repository: &dyn Repository,
+    entity: &EntityFields,
+    fields: &[Field],
+    id: i32,
+    includes: &[&str],
+) -> Result<SparseRecord> {
+    let mut record = repository
+        .get_record(entity, fields, id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    for child_type in includes {
+        let Some(child) = entity_fields(child_type) else {
+            continue;
+        };
+        let mut related = repository
+            .get_related(entity.entity_type, &[id], child_type, child.fields)
+            .await?;
+        let related = related.remove(&id).unwrap_or_default();
+        record.insert(
+            format!("{}s", child_type),
+            Value::Array(related.into_iter().map(Value::Object).collect()),
+        );
+    }
+    Ok(record)
+}
+
+/*
+Response for /topic, /term or /source called with ?include=, optionally combined with ?fields=.
+The ETag is a hash of the body rather than the record's version, since linking a new
+related entity changes the response without changing the record.
+ */
+pub async fn record_with_includes_response(
+    repository: Arc<dyn Repository>,
+    read_cache: &ReadCache,
+    headers: &HeaderMap,
+    entity: &EntityFields,
+    id: i32,
+    fields: Option<&str>,
+    include: &str,
+) -> Response {
+    let includes = match parse_includes(include, entity.entity_type) {
+        Ok(includes) => includes,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let fields = match fields.map(|fields| parse_fields(fields, entity)) {
+        None => entity.fields.to_vec(),
+        Some(Ok(fields)) => fields,
+        Some(Err(message)) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let cache_key = format!(
+        "{}?id={}&fields={}&include={}",
+        entity.entity_type,
+        id,
+        select_list(&fields, entity.table),
+        includes.join(",")
+    );
+    let record = read_cache
+        .get_or_load(cache_key, || {
+            get_record_with_includes(&*repository, entity, &fields, id, &includes)
+        })
+        .await;
+    match record {
+        Ok(record) => conditional_json_response_with_last_modified(headers, &*record, None),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_includes_to_entity_types() {
+        assert_eq!(
+            parse_includes("terms, sources,terms", "topic").unwrap(),
+            ["term", "source"]
+        );
+        // through the topics the term is linked to
+        assert_eq!(parse_includes("questions", "term").unwrap(), ["question"]);
+        assert_eq!(parse_includes("topics", "question").unwrap(), ["topic"]);
+    }
+
+    #[test]
+    fn refuses_unknown_and_unrelated_includes() {
+        assert_eq!(
+            parse_includes("terms,authors", "topic").unwrap_err(),
+            "unknown include \"authors\", expected one of: terms, topics, sources, questions"
+        );
+        assert_eq!(
+            parse_includes("terms", "question").unwrap_err(),
+            "a question has no related terms"
+        );
+        assert_eq!(
+            parse_includes(",", "topic").unwrap_err(),
+            "include must name at least one entity"
+        );
+    }
+}
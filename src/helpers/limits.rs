@@ -0,0 +1,418 @@
+/*
+Request limits: per client rate limiting, the maximum body size (larger for imports),
+the maximum length of the arrays in the create and update payloads and the maximum
+depth and complexity of a GraphQL query.
+
+All of them are configured with environment variables, see RequestLimits::from_env.
+ */
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::RETRY_AFTER, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+#[derive(Clone)]
+pub struct RequestLimits {
+    // tokens added to a client's bucket per second, 0 disables rate limiting
+    pub rate_limit_per_second: f64,
+    // size of a client's bucket, i.e. how many requests it can send in a burst
+    pub rate_limit_burst: f64,
+    // how many clients have a bucket at most, the least recently seen one is evicted
+    pub rate_limit_max_clients: usize,
+    // the X-Api-Key values that get a bucket of their own, other clients are limited by IP
+    pub api_keys: HashSet<String>,
+    // the reverse proxies whose X-Forwarded-For header names the client, see client_key
+    pub trusted_proxies: HashSet<IpAddr>,
+    pub max_body_bytes: usize,
+    // POST /v1/import takes whole files
+    pub max_import_bytes: usize,
+    pub max_array_length: usize,
+    // nesting of a GraphQL query, e.g. `topics { terms { sources } }` is 3 deep
+    pub graphql_max_depth: usize,
+    // see routes/graphql.rs for how the complexity of a query is counted
+    pub graphql_max_complexity: usize,
+}
+
+impl RequestLimits {
+    pub fn from_env() -> Self {
+        RequestLimits {
+            rate_limit_per_second: env_or("RATE_LIMIT_PER_SECOND", 10.0),
+            rate_limit_burst: env_or("RATE_LIMIT_BURST", 20.0),
+            rate_limit_max_clients: env_or("RATE_LIMIT_MAX_CLIENTS", 10_000),
+            api_keys: env::var("API_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            trusted_proxies: env::var("TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|proxy| proxy.trim().parse().ok())
+                .collect(),
+            max_body_bytes: env_or("MAX_BODY_BYTES", 256 * 1024),
+            max_import_bytes: env_or("MAX_IMPORT_BYTES", 16 * 1024 * 1024),
+            max_array_length: env_or("MAX_ARRAY_LENGTH", 100),
+            graphql_max_depth: env_or("GRAPHQL_MAX_DEPTH", 6),
+            graphql_max_complexity: env_or("GRAPHQL_MAX_COMPLEXITY", 5000),
+        }
+    }
+}
+
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// names the first array field that is longer than max_array_length
+pub fn check_array_lengths<T>(
+    fields: &[(&str, &Option<Vec<T>>)],
+    limits: &RequestLimits,
+) -> Result<(), String> {
+    for (field_name, field) in fields {
+        if let Some(values) = field {
+            if values.len() > limits.max_array_length {
+                return Err(format!(
+                    "{} has {} entries, at most {} are allowed",
+                    field_name,
+                    values.len(),
+                    limits.max_array_length
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    per_second: f64,
+    burst: f64,
+    max_clients: usize,
+    api_keys: HashSet<String>,
+    trusted_proxies: HashSet<IpAddr>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &RequestLimits) -> Self {
+        RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            per_second: limits.rate_limit_per_second,
+            burst: limits.rate_limit_burst.max(1.0),
+            max_clients: limits.rate_limit_max_clients.max(1),
+            api_keys: limits.api_keys.clone(),
+            trusted_proxies: limits.trusted_proxies.clone(),
+        }
+    }
+
+    // takes a token from the client's bucket, or returns how long until one is available
+    pub fn check(&self, client_key: &str) -> Result<(), Duration> {
+        self.check_at(client_key, Instant::now())
+    }
+
+    fn check_at(&self, client_key: &str, now: Instant) -> Result<(), Duration> {
+        if self.per_second <= 0.0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if !buckets.contains_key(client_key) && buckets.len() >= self.max_clients {
+            // buckets that have been idle long enough to be full again carry no state
+            let refill_time = Duration::from_secs_f64(self.burst / self.per_second);
+            buckets.retain(|_, bucket| now.duration_since(bucket.last_refill) < refill_time);
+            // still full: the least recently seen client starts over with a full bucket
+            if buckets.len() >= self.max_clients {
+                let least_recent = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.last_refill)
+                    .map(|(key, _)| key.clone());
+                if let Some(key) = least_recent {
+                    buckets.remove(&key);
+                }
+            }
+        }
+
+        let bucket = buckets.entry(client_key.to_owned()).or_insert(TokenBucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    /*
+    A client is identified by its X-Api-Key header when the key is one of API_KEYS, and by
+    its IP address otherwise. Unknown keys aren't trusted, a client sending a new key with
+    every request would get a new bucket every time.
+
+    The IP address is the peer's, unless the peer is one of TRUSTED_PROXIES: then it is the
+    last address in X-Forwarded-For that isn't a trusted proxy, as the ones before it were
+    sent by the client and can be anything. None when the server wasn't started with the
+    peer addresses (into_make_service_with_connect_info), the request isn't limited then
+    rather than sharing one bucket with every other client.
+     */
+    fn client_key<B>(&self, request: &Request<B>) -> Option<String> {
+        let api_key = request
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|api_key| self.api_keys.contains(*api_key));
+        if let Some(api_key) = api_key {
+            return Some(format!("key:{}", api_key));
+        }
+        let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+        let mut client = peer.ip();
+        if self.trusted_proxies.contains(&client) {
+            let forwarded_for = request
+                .headers()
+                .get_all(FORWARDED_FOR_HEADER)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(|address| address.trim().parse::<IpAddr>());
+            for address in forwarded_for.collect::<Vec<_>>().into_iter().rev() {
+                // an address that isn't one can't be told apart from a forged one
+                let Ok(address) = address else {
+                    break;
+                };
+                client = address;
+                if !self.trusted_proxies.contains(&address) {
+                    break;
+                }
+            }
+        }
+        Some(format!("ip:{}", client))
+    }
+}
+
+pub async fn rate_limit<B>(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(client_key) = rate_limiter.client_key(&request) else {
+        return next.run(request).await;
+    };
+    match rate_limiter.check(&client_key) {
+        Ok(_) => next.run(request).await,
+        Err(retry_after) => {
+            // Retry-After is in whole seconds
+            let retry_after_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after_secs.to_string())],
+                "rate limit exceeded",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn rate_limiter(per_second: f64, burst: f64, max_clients: usize) -> RateLimiter {
+        let mut limits = RequestLimits::from_env();
+        limits.rate_limit_per_second = per_second;
+        limits.rate_limit_burst = burst;
+        limits.rate_limit_max_clients = max_clients;
+        limits.api_keys = HashSet::from(["known-key".to_owned()]);
+        limits.trusted_proxies = HashSet::from(["10.0.0.1".parse().unwrap()]);
+        RateLimiter::new(&limits)
+    }
+
+    fn request_from(peer: Option<&str>, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut request = Request::builder().uri("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some(peer) = peer {
+            request = request.extension(ConnectInfo::<SocketAddr>(peer.parse().unwrap()));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn buckets_refill_at_the_configured_rate() {
+        let rate_limiter = rate_limiter(2.0, 3.0, 10);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(rate_limiter.check_at("a", start), Ok(()));
+        }
+        assert_eq!(
+            rate_limiter.check_at("a", start),
+            Err(Duration::from_millis(500))
+        );
+        // another client has a bucket of its own
+        assert_eq!(rate_limiter.check_at("b", start), Ok(()));
+
+        let refilled = start + Duration::from_millis(500);
+        assert_eq!(rate_limiter.check_at("a", refilled), Ok(()));
+        assert!(rate_limiter.check_at("a", refilled).is_err());
+
+        // a bucket never holds more than the burst
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(rate_limiter.check_at("a", much_later), Ok(()));
+        }
+        assert!(rate_limiter.check_at("a", much_later).is_err());
+    }
+
+    #[test]
+    fn the_number_of_buckets_is_capped() {
+        let rate_limiter = rate_limiter(1.0, 5.0, 3);
+        let start = Instant::now();
+        for (offset, client) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            let now = start + Duration::from_millis(offset as u64);
+            assert_eq!(rate_limiter.check_at(client, now), Ok(()));
+        }
+        // none of the buckets was idle, so the least recently seen one was evicted
+        let buckets = rate_limiter.buckets.lock().unwrap();
+        let mut clients: Vec<&String> = buckets.keys().collect();
+        clients.sort();
+        assert_eq!(clients, ["b", "c", "d"]);
+        drop(buckets);
+
+        // once they are full again, idle buckets are pruned before anything is evicted
+        let idle = start + Duration::from_secs(10);
+        assert_eq!(rate_limiter.check_at("e", idle), Ok(()));
+        assert_eq!(rate_limiter.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rate_limiting_can_be_disabled() {
+        let rate_limiter = rate_limiter(0.0, 1.0, 10);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(rate_limiter.check_at("a", now), Ok(()));
+        }
+        assert!(rate_limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    const PEER: Option<&str> = Some("192.0.2.1:50000");
+
+    async fn status_and_retry_after(
+        app: &Router,
+        peer: Option<&str>,
+        api_key: &str,
+    ) -> (StatusCode, Option<String>) {
+        let request = request_from(peer, &[(API_KEY_HEADER, api_key)]);
+        let response = app.clone().oneshot(request).await.unwrap();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().to_owned());
+        (response.status(), retry_after)
+    }
+
+    #[tokio::test]
+    async fn only_configured_api_keys_get_a_bucket_of_their_own() {
+        let rate_limiter = Arc::new(rate_limiter(0.5, 2.0, 10));
+        let app =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(middleware::from_fn_with_state(
+                    rate_limiter.clone(),
+                    rate_limit,
+                ));
+
+        // a new key with every request doesn't get around the limit
+        for api_key in ["random-1", "random-2"] {
+            assert_eq!(
+                status_and_retry_after(&app, PEER, api_key).await,
+                (StatusCode::OK, None)
+            );
+        }
+        assert_eq!(
+            status_and_retry_after(&app, PEER, "random-3").await,
+            (StatusCode::TOO_MANY_REQUESTS, Some("2".to_owned()))
+        );
+        assert_eq!(
+            status_and_retry_after(&app, PEER, "known-key").await,
+            (StatusCode::OK, None)
+        );
+        assert_eq!(rate_limiter.buckets.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_peer_address_are_not_limited() {
+        let rate_limiter = Arc::new(rate_limiter(0.5, 1.0, 10));
+        let app =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(middleware::from_fn_with_state(
+                    rate_limiter.clone(),
+                    rate_limit,
+                ));
+        for _ in 0..3 {
+            assert_eq!(
+                status_and_retry_after(&app, None, "random").await,
+                (StatusCode::OK, None)
+            );
+        }
+        assert!(rate_limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn forwarded_for_is_only_read_from_trusted_proxies() {
+        let rate_limiter = rate_limiter(1.0, 1.0, 10);
+        let client_key = |peer, forwarded_for| {
+            rate_limiter.client_key(&request_from(
+                Some(peer),
+                &[("x-forwarded-for", forwarded_for)],
+            ))
+        };
+        // anyone else can send the header
+        assert_eq!(
+            client_key("192.0.2.1:1", "198.51.100.7"),
+            Some("ip:192.0.2.1".to_owned())
+        );
+        // the last address the trusted proxy added, not the ones the client sent
+        assert_eq!(
+            client_key("10.0.0.1:1", "203.0.113.9, 198.51.100.7"),
+            Some("ip:198.51.100.7".to_owned())
+        );
+        assert_eq!(
+            client_key("10.0.0.1:1", "198.51.100.7, 10.0.0.1"),
+            Some("ip:198.51.100.7".to_owned())
+        );
+        assert_eq!(
+            client_key("10.0.0.1:1", "198.51.100.7, garbage"),
+            Some("ip:10.0.0.1".to_owned())
+        );
+        assert_eq!(rate_limiter.client_key(&request_from(None, &[])), None);
+    }
+}
@@ -0,0 +1,207 @@
+/*
+Slugs, and looking up topics, terms and sources by id, name or slug.
+
+Slugs are generated from the name when a record is created and are never changed, so
+links built from them keep working after a rename or after the database is reloaded
+with different ids.
+ */
+use crate::helpers::fieldsets::EntityFields;
+use crate::repository::Repository;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use utoipa::IntoParams;
+
+// "Tropical Cyclone (Atlantic)" -> "tropical-cyclone-atlantic"
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for character in name.chars().flat_map(char::to_lowercase) {
+        if character.is_alphanumeric() {
+            slug.push(character);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+/*
+The slug for a new record: the slugified name, with -2, -3, ... added when another
+record already has it (e.g. "C++" and "C" are both "c"). `taken` are the slugs that start
+with slug_base(..), the repository reads them.
+ */
+pub fn unique_slug(base: &str, taken: &HashSet<String>) -> String {
+    if !taken.contains(base) {
+        return base.to_owned();
+    }
+    let suffix = (2..)
+        .find(|suffix| !taken.contains(&format!("{}-{}", base, suffix)))
+        .unwrap_or_default();
+    format!("{}-{}", base, suffix)
+}
+
+// the slugified name, or the entity type when no character of the name is left.
+// A numeric slug would be read as an id (see from_path_segment), so it gets the entity
+// type in front, e.g. "term-1984"
+pub fn slug_base(entity_type: &str, name: &str) -> String {
+    let base = slugify(name);
+    if base.is_empty() {
+        return entity_type.to_owned();
+    }
+    if base.chars().all(|character| character.is_ascii_digit()) {
+        return format!("{}-{}", entity_type, base);
+    }
+    base
+}
+
+pub enum EntityKey<'a> {
+    Id(i32),
+    // matched case-insensitively
+    Name(&'a str),
+    Slug(&'a str),
+}
+
+/*
+The record a request is about, exactly one of the three has to be given.
+
+As an extractor it is read from the `:id` path segment on the /v1 routes, which is
+an id when it is numeric and a slug otherwise, and from the id, name and slug query
+parameters on the legacy routes.
+ */
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LookupParams {
+    /// The record's id
+    pub id: Option<i32>,
+    /// The record's name, matched case-insensitively
+    pub name: Option<String>,
+    /// The record's slug
+    pub slug: Option<String>,
+}
+
+impl LookupParams {
+    pub fn from_path_segment(segment: &str) -> Self {
+        match segment.parse() {
+            Ok(id) => LookupParams {
+                id: Some(id),
+                ..Default::default()
+            },
+            Err(_) => LookupParams {
+                slug: Some(segment.to_owned()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for LookupParams {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> std::result::Result<Self, Response> {
+        if let Ok(Path(segments)) =
+            Path::<HashMap<String, String>>::from_request_parts(parts, state).await
+        {
+            if let Some(segment) = segments.get("id") {
+                return Ok(LookupParams::from_path_segment(segment));
+            }
+        }
+        let Query(params) = Query::<LookupParams>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(params)
+    }
+}
+
+impl<'a> EntityKey<'a> {
+    pub fn from_params(params: &'a LookupParams) -> std::result::Result<Self, String> {
+        match (params.id, &params.name, &params.slug) {
+            (Some(id), None, None) => Ok(EntityKey::Id(id)),
+            (None, Some(name), None) => Ok(EntityKey::Name(name)),
+            (None, None, Some(slug)) => Ok(EntityKey::Slug(slug)),
+            _ => Err("pass exactly one of id, name or slug".to_owned()),
+        }
+    }
+}
+
+pub enum LookupError {
+    BadRequest(String),
+    NotFound(&'static str),
+    Database(sqlx::Error),
+}
+
+impl IntoResponse for LookupError {
+    fn into_response(self) -> Response {
+        match self {
+            LookupError::BadRequest(message) => (StatusCode::BAD_REQUEST, message).into_response(),
+            LookupError::NotFound(entity_type) => {
+                (StatusCode::NOT_FOUND, format!("{} not found", entity_type)).into_response()
+            }
+            LookupError::Database(error) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+            }
+        }
+    }
+}
+
+pub async fn resolve_entity_id(
+    repository: &dyn Repository,
+    entity: &EntityFields,
+    params: &LookupParams,
+) -> std::result::Result<i32, LookupError> {
+    let key = EntityKey::from_params(params).map_err(LookupError::BadRequest)?;
+    match repository.find_entity_id(entity, &key).await {
+        Ok(Some(id)) => Ok(id),
+        Ok(None) => Err(LookupError::NotFound(entity.entity_type)),
+        Err(error) => Err(LookupError::Database(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugifies_names() {
+        for (name, slug) in [
+            ("Tropical Cyclone (Atlantic)", "tropical-cyclone-atlantic"),
+            ("  Storm  Surge ", "storm-surge"),
+            ("C++", "c"),
+            ("El Niño", "el-niño"),
+            ("--a--b--", "a-b"),
+            ("!!!", ""),
+        ] {
+            assert_eq!(slugify(name), slug, "{}", name);
+        }
+        assert_eq!(slug_base("topic", "!!!"), "topic");
+        assert_eq!(slug_base("topic", "Storm"), "storm");
+    }
+
+    #[test]
+    fn never_makes_a_slug_that_reads_as_an_id() {
+        for (name, slug) in [("1984", "term-1984"), ("-42", "term-42"), ("1.5", "1-5")] {
+            assert_eq!(slug_base("term", name), slug, "{}", name);
+            let params = LookupParams::from_path_segment(slug);
+            assert_eq!(params.slug.as_deref(), Some(slug));
+            assert_eq!(params.id, None);
+        }
+    }
+
+    #[test]
+    fn numbers_taken_slugs() {
+        let taken = |slugs: &[&str]| slugs.iter().map(|slug| slug.to_string()).collect();
+        assert_eq!(unique_slug("c", &taken(&[])), "c");
+        assert_eq!(unique_slug("c", &taken(&["c"])), "c-2");
+        assert_eq!(unique_slug("c", &taken(&["c", "c-2", "c-4"])), "c-3");
+        // a slug that only starts with the base isn't taken
+        assert_eq!(unique_slug("c", &taken(&["c-sharp"])), "c");
+    }
+}
@@ -0,0 +1,96 @@
+/*
+Prometheus metrics, served in the text format by /metrics.
+
+Request metrics are recorded by the track_metrics middleware for every route,
+domain counters are incremented by the handlers and handler_utils.
+ */
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec_with_registry, register_histogram_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry, Histogram, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Registry,
+};
+use std::time::Instant;
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+    static ref HTTP_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "http_requests_total",
+        "Number of HTTP requests handled",
+        &["method", "route", "status"],
+        REGISTRY
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION_SECONDS: HistogramVec = register_histogram_vec_with_registry!(
+        "http_request_duration_seconds",
+        "HTTP request latency in seconds",
+        &["method", "route", "status"],
+        REGISTRY
+    )
+    .unwrap();
+    pub static ref DB_POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec_with_registry!(
+        "db_pool_connections",
+        "Connections in the Postgres pool by state (in_use, idle)",
+        &["state"],
+        REGISTRY
+    )
+    .unwrap();
+    pub static ref DB_POOL_ACQUIRE_WAIT_SECONDS: Histogram = register_histogram_with_registry!(
+        "db_pool_acquire_wait_seconds",
+        "Time the repository waited for a connection from the pool",
+        REGISTRY
+    )
+    .unwrap();
+    pub static ref ENTITIES_CREATED_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "entities_created_total",
+        "Number of topics, terms and sources created",
+        &["entity_type"],
+        REGISTRY
+    )
+    .unwrap();
+    pub static ref LINK_ROWS_INSERTED_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "link_rows_inserted_total",
+            "Number of rows inserted into the bridge tables",
+            &["link_table"],
+            REGISTRY
+        )
+        .unwrap();
+    pub static ref READ_CACHE_REQUESTS_TOTAL: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "read_cache_requests_total",
+            "Read cache lookups by result (hit, miss)",
+            &["result"],
+            REGISTRY
+        )
+        .unwrap();
+    pub static ref READ_CACHE_ENTRIES: IntGauge = register_int_gauge_with_registry!(
+        "read_cache_entries",
+        "Number of entries in the read cache",
+        REGISTRY
+    )
+    .unwrap();
+}
+
+pub async fn track_metrics<B>(request: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    // requests that match no route are grouped, so random paths can't create new series
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned());
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS_TOTAL.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
@@ -1,2 +1,21 @@
+pub mod archive;
+pub mod citations;
+pub mod conditional_get;
+pub mod deprecation;
+pub mod events;
+pub mod fieldsets;
+pub mod flashcards;
+pub mod graph;
+pub mod graphql_loader;
 pub mod handler_utils;
+pub mod import;
+pub mod includes;
+pub mod limits;
+pub mod lookup;
+pub mod metrics;
+pub mod ndjson;
+pub mod obsidian;
+pub mod read_cache;
+pub mod request_tracing;
 pub mod shared_types;
+pub mod webhooks;
@@ -0,0 +1,116 @@
+/*
+Streaming responses for the list endpoints.
+
+Clients that send `Accept: application/x-ndjson` get one JSON record per line, written
+to the response as the rows arrive from Postgres instead of being collected first, so
+full dumps use constant memory on the server.
+ */
+use axum::{
+    body::StreamBody,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderValue,
+    },
+    response::{IntoResponse, Response},
+};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/*
+Whether the Accept header prefers NDJSON to JSON. Every media range is weighed by its q
+(1 when it has none, RFC 9110 section 12.5.1): NDJSON is sent when it is listed with a q
+above 0 and no range that covers application/json has a higher one, so
+`application/x-ndjson;q=0, application/json` gets JSON.
+ */
+pub fn wants_ndjson(headers: &HeaderMap) -> bool {
+    let mut ndjson_quality: f32 = 0.0;
+    let mut json_quality: f32 = 0.0;
+    for media_range in headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut parameters = media_range.split(';').map(str::trim);
+        let media_type = parameters.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parameters
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(1.0, |(_, quality)| quality.trim().parse().unwrap_or(0.0));
+        match media_type.as_str() {
+            NDJSON_CONTENT_TYPE => ndjson_quality = ndjson_quality.max(quality),
+            "application/json" | "application/*" | "*/*" => {
+                json_quality = json_quality.max(quality)
+            }
+            _ => {}
+        }
+    }
+    ndjson_quality > 0.0 && ndjson_quality >= json_quality
+}
+
+/*
+The status is sent before the first row is read, so an error part way through can't
+become a 500. Instead the body is cut off, which clients see as an incomplete response.
+ */
+pub fn ndjson_response<S, T>(rows: S) -> Response
+where
+    S: Stream<Item = Result<T, sqlx::Error>> + Send + 'static,
+    T: Serialize,
+{
+    let lines = rows.map(|row| {
+        let row = row.map_err(|error| {
+            tracing::error!(%error, "streaming response failed");
+            error
+        })?;
+        let mut line =
+            serde_json::to_vec(&row).map_err(|error| sqlx::Error::Decode(error.into()))?;
+        line.push(b'\n');
+        Ok::<_, sqlx::Error>(line)
+    });
+
+    (
+        [(CONTENT_TYPE, HeaderValue::from_static(NDJSON_CONTENT_TYPE))],
+        StreamBody::new(lines),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ACCEPT, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn weighs_the_media_ranges_by_quality() {
+        for (values, ndjson) in [
+            (vec!["application/x-ndjson"], true),
+            (vec!["application/json, application/x-ndjson"], true),
+            (vec!["Application/X-NDJSON; charset=utf-8"], true),
+            (vec!["application/x-ndjson;q=0, application/json"], false),
+            (vec!["application/x-ndjson; q=0.0"], false),
+            (
+                vec!["application/json", "application/x-ndjson;q=0.5"],
+                false,
+            ),
+            (
+                vec!["application/json;q=0.2, application/x-ndjson;q=0.5"],
+                true,
+            ),
+            (vec!["*/*, application/x-ndjson;q=0.9"], false),
+            (vec!["application/x-ndjson-seq"], false),
+            (vec!["application/json"], false),
+            (vec![], false),
+        ] {
+            assert_eq!(wants_ndjson(&accept(&values)), ndjson, "{:?}", values);
+        }
+    }
+}
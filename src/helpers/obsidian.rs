@@ -0,0 +1,609 @@
+/*
+Obsidian vaults of the topics and terms, written by the `export-vault` command and read
+back by `import-vault` (see vault_command.rs).
+
+Every topic is a note in Topics/ and every term a note in Terms/, named after the record.
+A note has YAML front matter with the record's id, is_verified and the names of its
+sources, the record's name as its title, and a section per description, e.g.
+
+---
+id: 1
+is_verified: false
+sources:
+  - "dictionary storm"
+---
+# Storm
+
+## Brief description
+
+a disturbance of the atmosphere
+
+## Topics
+
+- [[Hurricane]]
+
+The relationships are sections of [[wikilinks]]: a term's Topics and a topic's Terms are
+rows of terms_to_topics, and a topic's Subtopics are rows of the mind map, related_topics.
+Names that can't be file names, e.g. "Wind/Rain", are linked as [[Wind-Rain|Wind/Rain]].
+
+A vault is read back into an Archive and restored like one (see helpers/archive.rs), so
+notes are matched to records by their title, not their id, which is only the id of the
+exported database. A note without a title is named after its file. Notes outside Topics/
+and Terms/, other front matter keys and other sections are left out.
+ */
+use crate::helpers::archive::{archived_link_table, Archive, ArchiveLink};
+use crate::helpers::fieldsets::{
+    EntityFields, Field, FieldKind, SparseRecord, SOURCE_FIELDS, TERM_FIELDS, TOPIC_FIELDS,
+};
+use crate::repository::Repository;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+pub const TOPICS_FOLDER: &str = "Topics";
+pub const TERMS_FOLDER: &str = "Terms";
+
+// heading -> field of the description sections, in the order they are written
+const DESCRIPTION_SECTIONS: [(&str, &str, FieldKind); 5] = [
+    ("Brief description", "brief_description", FieldKind::Text),
+    ("Full description", "full_description", FieldKind::Text),
+    ("Bullet points", "bullet_points", FieldKind::TextArray),
+    ("Examples", "examples", FieldKind::TextArray),
+    ("Parallels", "parallels", FieldKind::TextArray),
+];
+
+// a section of wikilinks, the rows of a link table the note's record is in
+struct LinkSection {
+    entity: &'static EntityFields,
+    heading: &'static str,
+    table: &'static str,
+    // whether the note's record is in the table's first column
+    note_is_from: bool,
+    linked: &'static EntityFields,
+}
+
+const LINK_SECTIONS: [LinkSection; 3] = [
+    LinkSection {
+        entity: &TERM_FIELDS,
+        heading: "Topics",
+        table: "terms_to_topics",
+        note_is_from: true,
+        linked: &TOPIC_FIELDS,
+    },
+    LinkSection {
+        entity: &TOPIC_FIELDS,
+        heading: "Terms",
+        table: "terms_to_topics",
+        note_is_from: false,
+        linked: &TERM_FIELDS,
+    },
+    LinkSection {
+        entity: &TOPIC_FIELDS,
+        heading: "Subtopics",
+        table: "related_topics",
+        note_is_from: true,
+        linked: &TOPIC_FIELDS,
+    },
+];
+
+// the link table of the `sources` front matter key
+fn sources_table(entity: &EntityFields) -> &'static str {
+    match entity.entity_type {
+        "topic" => "topics_to_sources",
+        _ => "terms_to_sources",
+    }
+}
+
+fn folder(entity: &EntityFields) -> &'static str {
+    match entity.entity_type {
+        "topic" => TOPICS_FOLDER,
+        _ => TERMS_FOLDER,
+    }
+}
+
+// a note of the vault, its path is relative to the vault, e.g. "Terms/Storm.md"
+pub struct VaultNote {
+    pub path: String,
+    pub contents: String,
+}
+
+// the characters Obsidian doesn't allow in note names
+const FORBIDDEN_CHARACTERS: [char; 13] = [
+    '\\', '/', ':', '*', '?', '"', '<', '>', '|', '#', '^', '[', ']',
+];
+
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|character| {
+            if FORBIDDEN_CHARACTERS.contains(&character) || character.is_control() {
+                '-'
+            } else {
+                character
+            }
+        })
+        .collect();
+    // hidden files and trailing dots aren't notes on every platform
+    let stem = stem.trim().trim_matches('.').to_owned();
+    if stem.is_empty() {
+        "Untitled".to_owned()
+    } else {
+        stem
+    }
+}
+
+// a record of the exported vault
+struct NoteRecord {
+    id: i32,
+    name: String,
+    stem: String,
+    record: SparseRecord,
+}
+
+fn wikilink(note: &NoteRecord) -> String {
+    if note.stem == note.name {
+        format!("[[{}]]", note.name)
+    } else {
+        format!(
+            "[[{}|{}]]",
+            note.stem,
+            note.name.replace(['[', ']', '|'], "-")
+        )
+    }
+}
+
+// a YAML scalar, strings are double quoted like JSON strings
+fn yaml_string(text: &str) -> String {
+    Value::from(text).to_string()
+}
+
+// the text of a list item or paragraph, without the newlines that would end it
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// the records of the entity with a unique file name each, ordered by id
+async fn note_records(
+    repository: &dyn Repository,
+    entity: &EntityFields,
+) -> sqlx::Result<Vec<NoteRecord>> {
+    let mut fields: Vec<Field> = vec![
+        ("id", FieldKind::Int),
+        (entity.name_column, FieldKind::Text),
+        ("is_verified", FieldKind::Bool),
+    ];
+    fields.extend(
+        DESCRIPTION_SECTIONS
+            .iter()
+            .map(|(_, field, kind)| (*field, *kind)),
+    );
+    let records = repository.get_records(entity, &fields, None).await?;
+    // file names are case-insensitive on macOS and Windows
+    let mut taken = HashSet::new();
+    Ok(records
+        .into_iter()
+        .filter_map(|record| {
+            let id = record.get("id")?.as_i64()? as i32;
+            let name = record.get(entity.name_column)?.as_str()?.to_owned();
+            let base = file_stem(&name);
+            let mut stem = base.clone();
+            let mut number = 2;
+            while !taken.insert(stem.to_lowercase()) {
+                stem = format!("{} ({})", base, number);
+                number += 1;
+            }
+            Some(NoteRecord {
+                id,
+                name,
+                stem,
+                record,
+            })
+        })
+        .collect())
+}
+
+// the vault's notes, Topics/ before Terms/, each ordered by id
+pub async fn export_vault(repository: &dyn Repository) -> sqlx::Result<Vec<VaultNote>> {
+    let topics = note_records(repository, &TOPIC_FIELDS).await?;
+    let terms = note_records(repository, &TERM_FIELDS).await?;
+    let source_names: HashMap<i32, String> = repository
+        .get_records(
+            &SOURCE_FIELDS,
+            &[("id", FieldKind::Int), ("name", FieldKind::Text)],
+            None,
+        )
+        .await?
+        .into_iter()
+        .filter_map(|source| {
+            let id = source.get("id")?.as_i64()? as i32;
+            Some((id, source.get("name")?.as_str()?.to_owned()))
+        })
+        .collect();
+    let by_id = |records: &[NoteRecord]| -> HashMap<i32, usize> {
+        records
+            .iter()
+            .enumerate()
+            .map(|(index, record)| (record.id, index))
+            .collect()
+    };
+    let topic_index = by_id(&topics);
+    let term_index = by_id(&terms);
+    let records_of = |entity: &EntityFields| -> (&[NoteRecord], &HashMap<i32, usize>) {
+        match entity.entity_type {
+            "topic" => (&topics, &topic_index),
+            _ => (&terms, &term_index),
+        }
+    };
+
+    let mut notes = vec![];
+    for entity in [&TOPIC_FIELDS, &TERM_FIELDS] {
+        let (records, _) = records_of(entity);
+        // note id -> (source names, the wikilinks of each of the entity's link sections)
+        let mut sources: HashMap<i32, Vec<String>> = HashMap::new();
+        let link_table = archived_link_table(sources_table(entity)).expect("an archived table");
+        for (from_id, source_id) in repository.get_link_rows(link_table).await? {
+            if let Some(name) = source_names.get(&source_id) {
+                sources.entry(from_id).or_default().push(name.clone());
+            }
+        }
+        let mut sections: Vec<(&str, HashMap<i32, Vec<String>>)> = vec![];
+        for section in LINK_SECTIONS
+            .iter()
+            .filter(|section| section.entity.entity_type == entity.entity_type)
+        {
+            let link_table = archived_link_table(section.table).expect("an archived table");
+            let (linked_records, linked_index) = records_of(section.linked);
+            let mut links: HashMap<i32, Vec<String>> = HashMap::new();
+            for (from_id, to_id) in repository.get_link_rows(link_table).await? {
+                let (note_id, linked_id) = if section.note_is_from {
+                    (from_id, to_id)
+                } else {
+                    (to_id, from_id)
+                };
+                if let Some(index) = linked_index.get(&linked_id) {
+                    links
+                        .entry(note_id)
+                        .or_default()
+                        .push(wikilink(&linked_records[*index]));
+                }
+            }
+            sections.push((section.heading, links));
+        }
+
+        for note in records {
+            let mut contents = format!(
+                "---\nid: {}\nis_verified: {}\n",
+                note.id,
+                note.record.get("is_verified") == Some(&Value::Bool(true))
+            );
+            match sources.get(&note.id) {
+                Some(names) => {
+                    contents.push_str("sources:\n");
+                    for name in names {
+                        contents.push_str(&format!("  - {}\n", yaml_string(name)));
+                    }
+                }
+                None => contents.push_str("sources: []\n"),
+            }
+            contents.push_str(&format!("---\n# {}\n", one_line(&note.name)));
+
+            for (heading, field, kind) in DESCRIPTION_SECTIONS {
+                let body = match (kind, note.record.get(field)) {
+                    (FieldKind::TextArray, Some(Value::Array(items))) => items
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|item| format!("- {}\n", one_line(item)))
+                        .collect::<String>(),
+                    (_, Some(Value::String(text))) => format!("{}\n", text.trim()),
+                    _ => String::new(),
+                };
+                if !body.trim().is_empty() {
+                    contents.push_str(&format!("\n## {}\n\n{}", heading, body));
+                }
+            }
+            for (heading, links) in &sections {
+                if let Some(links) = links.get(&note.id) {
+                    contents.push_str(&format!("\n## {}\n\n", heading));
+                    for link in links {
+                        contents.push_str(&format!("- {}\n", link));
+                    }
+                }
+            }
+            notes.push(VaultNote {
+                path: format!("{}/{}.md", folder(entity), note.stem),
+                contents,
+            });
+        }
+    }
+    Ok(notes)
+}
+
+// a note read back, before its wikilinks are resolved
+struct ParsedNote<'a> {
+    path: &'a str,
+    entity: &'static EntityFields,
+    name: String,
+    record: SparseRecord,
+    sources: Vec<String>,
+    // heading -> the link targets of the section, e.g. "Storm" of [[Storm|the storm]]
+    sections: HashMap<String, Vec<String>>,
+}
+
+// a scalar of the front matter, quoted or not
+fn yaml_scalar(value: &str) -> String {
+    let value = value.trim();
+    if value.starts_with('"') {
+        if let Ok(text) = serde_json::from_str::<String>(value) {
+            return text;
+        }
+    }
+    if let Some(text) = value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        return text.replace("''", "'");
+    }
+    value.to_owned()
+}
+
+/*
+The front matter's keys with their values, a list for `key:` followed by `- item` lines
+or for a flow list like `[a, b]`. Only the YAML Obsidian writes for its properties is
+understood.
+ */
+fn parse_front_matter(lines: &[&str]) -> Result<HashMap<String, Vec<String>>, String> {
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+    let mut current = None;
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(item) = trimmed.strip_prefix('-') {
+            let Some(key) = &current else {
+                return Err(format!("the list item {} has no key", trimmed));
+            };
+            values
+                .entry(String::clone(key))
+                .or_default()
+                .push(yaml_scalar(item));
+            continue;
+        }
+        let Some((key, value)) = trimmed.split_once(':') else {
+            return Err(format!("{} isn't a key: value line", trimmed));
+        };
+        let key = key.trim().to_owned();
+        let value = value.trim();
+        let items = match value
+            .strip_prefix('[')
+            .and_then(|value| value.strip_suffix(']'))
+        {
+            Some(list) => list
+                .split(',')
+                .map(yaml_scalar)
+                .filter(|item| !item.is_empty())
+                .collect(),
+            None if value.is_empty() => vec![],
+            None => vec![yaml_scalar(value)],
+        };
+        values.insert(key.clone(), items);
+        current = Some(key);
+    }
+    Ok(values)
+}
+
+// the targets of the section's [[wikilinks]], without their display text or heading
+fn wikilink_targets(text: &str) -> Vec<String> {
+    let mut targets = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let Some(end) = rest[start + 2..].find("]]") else {
+            break;
+        };
+        let link = &rest[start + 2..start + 2 + end];
+        let target = link.split(['|', '#']).next().unwrap_or_default();
+        // [[Terms/Storm]] links the note by its path
+        let target = target.rsplit('/').next().unwrap_or_default().trim();
+        if !target.is_empty() {
+            targets.push(target.to_owned());
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+    targets
+}
+
+fn list_items(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            line.strip_prefix("- ")
+                .or_else(|| line.strip_prefix("* "))
+                .map(str::trim)
+        })
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn parse_note<'a>(
+    path: &'a str,
+    entity: &'static EntityFields,
+    contents: &str,
+) -> Result<ParsedNote<'a>, String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let (mut front_matter, body) = match lines.first().map(|line| line.trim_end()) {
+        Some("---") => {
+            let end = lines[1..]
+                .iter()
+                .position(|line| line.trim_end() == "---")
+                .ok_or("the front matter isn't closed with ---")?;
+            (parse_front_matter(&lines[1..end + 1])?, &lines[end + 2..])
+        }
+        _ => (HashMap::new(), &lines[..]),
+    };
+
+    let is_verified = match front_matter
+        .get("is_verified")
+        .and_then(|values| values.first())
+        .map(String::as_str)
+    {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(other) => return Err(format!("is_verified must be true or false, not {}", other)),
+    };
+    let stem = path
+        .rsplit('/')
+        .next()
+        .and_then(|file| file.strip_suffix(".md"))
+        .unwrap_or(path);
+    let mut name = None;
+    // heading -> the lines under it
+    let mut section_lines: Vec<(String, Vec<&str>)> = vec![];
+    for line in body {
+        if let Some(title) = line.strip_prefix("# ") {
+            if name.is_none() && section_lines.is_empty() {
+                name = Some(title.trim().to_owned());
+                continue;
+            }
+        }
+        if let Some(heading) = line.strip_prefix("## ") {
+            section_lines.push((heading.trim().to_lowercase(), vec![]));
+        } else if let Some((_, lines)) = section_lines.last_mut() {
+            lines.push(line);
+        }
+    }
+    let name = name
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| stem.to_owned());
+    let sections: HashMap<String, String> = section_lines
+        .into_iter()
+        .map(|(heading, lines)| (heading, lines.join("\n").trim().to_owned()))
+        .collect();
+
+    let mut record = SparseRecord::new();
+    record.insert(entity.name_column.to_owned(), json!(name));
+    record.insert("is_verified".to_owned(), json!(is_verified));
+    // a section that was removed in Obsidian clears the field
+    for (heading, field, kind) in DESCRIPTION_SECTIONS {
+        let value = match (kind, sections.get(&heading.to_lowercase())) {
+            (_, None) => Value::Null,
+            (FieldKind::TextArray, Some(text)) => {
+                let items = list_items(text);
+                if items.is_empty() {
+                    Value::Null
+                } else {
+                    json!(items)
+                }
+            }
+            (_, Some(text)) if text.is_empty() => Value::Null,
+            (_, Some(text)) => json!(text),
+        };
+        record.insert(field.to_owned(), value);
+    }
+    Ok(ParsedNote {
+        path,
+        entity,
+        name,
+        record,
+        sources: front_matter.remove("sources").unwrap_or_default(),
+        sections: LINK_SECTIONS
+            .iter()
+            .filter(|section| section.entity.entity_type == entity.entity_type)
+            .map(|section| {
+                let targets = sections
+                    .get(&section.heading.to_lowercase())
+                    .map(|text| wikilink_targets(text))
+                    .unwrap_or_default();
+                (section.heading.to_owned(), targets)
+            })
+            .collect(),
+    })
+}
+
+/*
+The archive of the vault's notes, given as (path relative to the vault, contents). A
+wikilink names a note of the vault by its file name, or else a record by its name.
+ */
+pub fn parse_vault(notes: &[(String, String)]) -> Result<Archive, String> {
+    let mut parsed = vec![];
+    for (path, contents) in notes {
+        let entity = match path.split('/').next() {
+            Some(TOPICS_FOLDER) => &TOPIC_FIELDS,
+            Some(TERMS_FOLDER) => &TERM_FIELDS,
+            _ => continue,
+        };
+        if !path.ends_with(".md") {
+            continue;
+        }
+        parsed.push(
+            parse_note(path, entity, contents).map_err(|error| format!("{}: {}", path, error))?,
+        );
+    }
+
+    // (entity type, lowercased file name or name) -> the note's name
+    let mut names: HashMap<(&str, String), String> = HashMap::new();
+    let mut seen = HashSet::new();
+    for note in &parsed {
+        if !seen.insert((note.entity.entity_type, note.name.to_lowercase())) {
+            return Err(format!(
+                "{}: another {} note is titled {}",
+                note.path, note.entity.entity_type, note.name
+            ));
+        }
+        let stem = note
+            .path
+            .rsplit('/')
+            .next()
+            .and_then(|file| file.strip_suffix(".md"))
+            .unwrap_or_default();
+        names.insert(
+            (note.entity.entity_type, stem.to_lowercase()),
+            note.name.clone(),
+        );
+    }
+    let resolve = |entity: &EntityFields, target: &str| -> String {
+        names
+            .get(&(entity.entity_type, target.to_lowercase()))
+            .cloned()
+            .unwrap_or_else(|| target.to_owned())
+    };
+
+    let mut archive = Archive::new(None);
+    let mut links = HashSet::new();
+    for note in parsed {
+        let mut add_link = |table: &str, from: String, to: String| {
+            if links.insert((table.to_owned(), from.clone(), to.clone())) {
+                archive.links.push(ArchiveLink {
+                    table: table.to_owned(),
+                    from,
+                    to,
+                });
+            }
+        };
+        for source in &note.sources {
+            add_link(
+                sources_table(note.entity),
+                note.name.clone(),
+                source.clone(),
+            );
+        }
+        for section in LINK_SECTIONS
+            .iter()
+            .filter(|section| section.entity.entity_type == note.entity.entity_type)
+        {
+            for target in &note.sections[section.heading] {
+                let linked = resolve(section.linked, target);
+                let (from, to) = if section.note_is_from {
+                    (note.name.clone(), linked)
+                } else {
+                    (linked, note.name.clone())
+                };
+                add_link(section.table, from, to);
+            }
+        }
+        match note.entity.entity_type {
+            "topic" => archive.topics.push(note.record),
+            _ => archive.terms.push(note.record),
+        }
+    }
+    Ok(archive)
+}
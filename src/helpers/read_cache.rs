@@ -0,0 +1,230 @@
+/*
+Optional in-process cache for the list and by-id reads.
+
+Entries expire after READ_CACHE_TTL_SECONDS and at most READ_CACHE_MAX_ENTRIES are kept.
+Every write through this server (create, update, link) clears the whole cache, since a
+single write can change many cached responses (e.g. a new link changes /terms-from-topic).
+Writes made by other server instances or directly in psql are only picked up once the
+entries expire, so keep the TTL short when that matters.
+
+The cache is disabled when either setting is 0, which is the default.
+ */
+use crate::helpers::metrics::{READ_CACHE_ENTRIES, READ_CACHE_REQUESTS_TOTAL};
+use std::{
+    any::Any,
+    collections::HashMap,
+    env,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+struct CacheEntry {
+    value: Arc<dyn Any + Send + Sync>,
+    inserted_at: Instant,
+}
+
+pub struct ReadCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    ttl: Duration,
+    max_entries: usize,
+    // bumped by every invalidation, so a load that raced with a write isn't cached
+    generation: AtomicU64,
+}
+
+impl ReadCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        ReadCache {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            max_entries,
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let ttl_seconds = env::var("READ_CACHE_TTL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        let max_entries = env::var("READ_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        ReadCache::new(Duration::from_secs(ttl_seconds), max_entries)
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.max_entries > 0
+    }
+
+    /*
+    Returns the cached value for `key`, or runs `load` and caches its result.
+    Errors are never cached.
+     */
+    pub async fn get_or_load<T, F, Fut, E>(&self, key: String, load: F) -> Result<Arc<T>, E>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if !self.is_enabled() {
+            return load().await.map(Arc::new);
+        }
+
+        if let Some(value) = self.get(&key) {
+            READ_CACHE_REQUESTS_TOTAL.with_label_values(&["hit"]).inc();
+            return Ok(value);
+        }
+        READ_CACHE_REQUESTS_TOTAL.with_label_values(&["miss"]).inc();
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        let value = Arc::new(load().await?);
+        self.insert(key, value.clone(), generation);
+        Ok(value)
+    }
+
+    fn get<T: Send + Sync + 'static>(&self, key: &str) -> Option<Arc<T>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.inserted_at.elapsed() >= self.ttl {
+            return None;
+        }
+        entry.value.clone().downcast::<T>().ok()
+    }
+
+    fn insert<T: Send + Sync + 'static>(&self, key: String, value: Arc<T>, generation: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.inserted_at.elapsed() < ttl);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest_key = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest_key) = oldest_key {
+                entries.remove(&oldest_key);
+            }
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                value,
+                inserted_at: Instant::now(),
+            },
+        );
+        READ_CACHE_ENTRIES.set(entries.len() as i64);
+    }
+
+    pub fn invalidate_all(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.clear();
+        READ_CACHE_ENTRIES.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn cache() -> ReadCache {
+        ReadCache::new(Duration::from_secs(60), 2)
+    }
+
+    // a load that counts how often it ran
+    async fn load(cache: &ReadCache, key: &str, loads: &AtomicUsize) -> Arc<usize> {
+        cache
+            .get_or_load(key.to_owned(), || async {
+                Ok::<_, ()>(loads.fetch_add(1, Ordering::SeqCst) + 1)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn loads_once_until_invalidated() {
+        let cache = cache();
+        let loads = AtomicUsize::new(0);
+        assert_eq!(*load(&cache, "/topics", &loads).await, 1);
+        assert_eq!(*load(&cache, "/topics", &loads).await, 1);
+        cache.invalidate_all();
+        assert_eq!(*load(&cache, "/topics", &loads).await, 2);
+    }
+
+    #[tokio::test]
+    async fn is_off_without_a_ttl_or_entries() {
+        for cache in [
+            ReadCache::new(Duration::ZERO, 2),
+            ReadCache::new(Duration::from_secs(60), 0),
+        ] {
+            let loads = AtomicUsize::new(0);
+            load(&cache, "/topics", &loads).await;
+            load(&cache, "/topics", &loads).await;
+            assert_eq!(loads.load(Ordering::SeqCst), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_errors() {
+        let cache = cache();
+        let failed: Result<Arc<usize>, &str> = cache
+            .get_or_load("/topics".to_owned(), || async { Err("no database") })
+            .await;
+        assert!(failed.is_err());
+        let loads = AtomicUsize::new(0);
+        assert_eq!(*load(&cache, "/topics", &loads).await, 1);
+    }
+
+    #[tokio::test]
+    async fn drops_a_load_that_raced_with_a_write() {
+        let cache = cache();
+        // the write lands after the load read the old data but before it is cached
+        let stale = cache
+            .get_or_load("/topics".to_owned(), || async {
+                cache.invalidate_all();
+                Ok::<_, ()>(0)
+            })
+            .await
+            .unwrap();
+        assert_eq!(*stale, 0);
+        let loads = AtomicUsize::new(0);
+        assert_eq!(*load(&cache, "/topics", &loads).await, 1);
+    }
+
+    #[tokio::test]
+    async fn evicts_the_oldest_entry_when_full() {
+        let cache = cache();
+        let loads = AtomicUsize::new(0);
+        load(&cache, "/topics", &loads).await;
+        load(&cache, "/terms", &loads).await;
+        load(&cache, "/sources", &loads).await;
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+        assert_eq!(*load(&cache, "/sources", &loads).await, 3);
+        assert_eq!(*load(&cache, "/terms", &loads).await, 2);
+        assert_eq!(*load(&cache, "/topics", &loads).await, 4);
+    }
+
+    #[tokio::test]
+    async fn expires_entries_after_the_ttl() {
+        let cache = ReadCache::new(Duration::from_millis(10), 2);
+        let loads = AtomicUsize::new(0);
+        load(&cache, "/topics", &loads).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(*load(&cache, "/topics", &loads).await, 2);
+    }
+}
@@ -0,0 +1,51 @@
+/*
+Per request tracing.
+
+Every request runs inside a `request` span carrying its X-Request-Id, method and matched
+route, so any event logged while handling it (e.g. a sqlx error from handler_utils) can be
+tied back to the request id the client received in the response headers.
+ */
+use axum::{
+    extract::MatchedPath,
+    http::{Request, Response},
+};
+use std::time::Duration;
+use tracing::{field::Empty, Span};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    // the route template, e.g. /term instead of /term?id=1, keeps the logs groupable
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| request.uri().path());
+
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        route,
+        status = Empty,
+        latency_ms = Empty,
+    )
+}
+
+pub fn on_request_end<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    let status = response.status().as_u16();
+    let latency_ms = latency.as_millis() as u64;
+    span.record("status", status);
+    span.record("latency_ms", latency_ms);
+
+    if response.status().is_server_error() {
+        tracing::error!(status, latency_ms, "request failed");
+    } else {
+        tracing::info!(status, latency_ms, "request finished");
+    }
+}
@@ -1,18 +1,23 @@
+use async_graphql::Enum;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Type};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Type, Serialize, Deserialize)]
+#[derive(Type, Serialize, Deserialize, ToSchema, Enum, Clone, Copy, PartialEq, Eq)]
 #[sqlx(type_name = "media_type", rename_all = "lowercase")]
 pub enum MediaType {
     Audio,
     Video,
     Web,
     Book,
+    #[sqlx(rename = "scientific article")]
     ScientificArticle,
 }
 
-#[derive(Type, Serialize, Deserialize)]
+#[derive(Type, Serialize, Deserialize, ToSchema, Enum, Clone, Copy, PartialEq, Eq)]
 #[sqlx(type_name = "image_type", rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ImageType {
     PDF,
     PNG,
@@ -21,7 +26,7 @@ pub enum ImageType {
     GIF,
 }
 
-#[derive(Deserialize, FromRow)]
+#[derive(Deserialize, FromRow, ToSchema)]
 pub struct CreateSource {
     pub name: String,
     pub url: Option<String>,
@@ -36,6 +41,40 @@ pub struct CreateSource {
     pub related_sources: Option<Vec<String>>,
 }
 
+// see UpdateTopicOrTerm
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateSource {
+    #[serde(flatten)]
+    pub fields: CreateSource,
+    pub version: Option<i32>,
+}
+
+/*
+Query parameters shared by the list endpoints.
+`updated_since` is an RFC 3339 timestamp, e.g. /terms?updated_since=2023-04-01T00:00:00Z
+ */
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQueryParams {
+    /// Only return records created or updated at or after this time (RFC 3339)
+    pub updated_since: Option<DateTime<Utc>>,
+    /// Comma separated columns to return, see helpers/fieldsets.rs
+    pub fields: Option<String>,
+}
+
+/*
+Query parameters of the single record endpoints, next to the record's id, name or slug
+(see helpers/lookup.rs).
+ */
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecordQueryParams {
+    /// Comma separated columns to return, see helpers/fieldsets.rs
+    pub fields: Option<String>,
+    /// Comma separated related entities to embed (terms, topics, sources, questions), see helpers/includes.rs
+    pub include: Option<String>,
+}
+
 /*
 I need to import the above into handler_utils as well as sources.rs
  */
@@ -0,0 +1,555 @@
+/*
+Outbound webhooks for the lifecycle events of topics, terms and sources.
+
+The create, update and link handlers (and the GraphQL mutations that share them) queue an
+event with Webhooks, which adds a delivery for every subscription to its type to the
+repository (platform.webhook_deliveries on Postgres). A background task sends the due
+deliveries as signed JSON POSTs and retries the failed ones with exponential backoff until
+WEBHOOK_MAX_ATTEMPTS is reached.
+
+Webhooks aren't sent to loopback, link-local or private (RFC 1918) addresses, so a
+subscription can't be used to reach services that are only meant for this network. Hosts in
+WEBHOOK_ALLOWED_HOSTS are exempt. The url is checked when the webhook is created and its host
+is resolved and checked again before every request, which is then sent to the address that was
+checked. Redirects aren't followed, a 3xx response is a failed delivery.
+
+A claimed delivery isn't due again until its request timed out, so the ones a stopped
+instance was sending are retried. On Postgres the rows are claimed with FOR UPDATE SKIP
+LOCKED, so several server instances can send from the same table without sending a
+delivery twice.
+
+Every request has the headers
+- webhook-id: the delivery id, a replayed delivery gets a new one
+- webhook-event: the event type, e.g. term.created
+- webhook-timestamp: unix seconds when the request was signed
+- webhook-signature: sha256=<hex HMAC-SHA256 of "<timestamp>.<body>" keyed with the secret>
+ */
+use crate::helpers::fieldsets::entity_fields;
+use crate::helpers::limits::env_or;
+use crate::repository::Repository;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{FromRow, Result};
+use std::collections::HashSet;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+pub const WEBHOOK_EVENT_TYPES: [&str; 8] = [
+    "topic.created",
+    "topic.verified",
+    "topic.linked",
+    "term.created",
+    "term.verified",
+    "term.linked",
+    "source.created",
+    "source.linked",
+];
+
+pub const WEBHOOK_ID_HEADER: &str = "webhook-id";
+pub const WEBHOOK_EVENT_HEADER: &str = "webhook-event";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "webhook-timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "webhook-signature";
+
+// how often due retries are looked for when no new event wakes the sender
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// deliveries claimed and sent at once
+const BATCH_SIZE: i64 = 20;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+// the part of an error response kept in the delivery log
+const MAX_ERROR_LENGTH: usize = 1000;
+
+#[derive(Serialize, FromRow, ToSchema, Clone)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    // empty when every event type is sent
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// the secret is only returned when the webhook is created
+#[derive(Serialize, FromRow, ToSchema, Clone)]
+pub struct NewWebhook {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhook {
+    /// The http(s) URL the events are POSTed to
+    pub url: String,
+    /// The key the payloads are signed with, a random one is generated when it is left out
+    pub secret: Option<String>,
+    /// The event types to send, e.g. ["term.created", "term.linked"], all of them when left out or empty
+    pub event_types: Option<Vec<String>>,
+}
+
+#[derive(Serialize, FromRow, ToSchema, Clone)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i32,
+    pub event_type: String,
+    /// The payload's `data`
+    #[schema(value_type = Object)]
+    pub data: Value,
+    /// pending, delivered or failed
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    /// The delivery this one replays
+    pub replay_of: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+// a delivery claimed for sending, with where to send it
+#[derive(FromRow)]
+pub struct DueDelivery {
+    pub id: i64,
+    pub event_type: String,
+    pub data: Value,
+    // the attempts before this one
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+    pub url: String,
+    pub secret: String,
+}
+
+#[derive(Clone)]
+pub struct WebhookSettings {
+    // a delivery is failed after this many attempts
+    pub max_attempts: i32,
+    // the delay before the first retry, doubled for every retry after it, at most an hour
+    pub retry_base_delay: Duration,
+    pub timeout: Duration,
+    // lowercase hosts that can be internal addresses, see check_webhook_url
+    pub allowed_hosts: Arc<HashSet<String>>,
+}
+
+impl WebhookSettings {
+    pub fn from_env() -> Self {
+        WebhookSettings {
+            max_attempts: env_or("WEBHOOK_MAX_ATTEMPTS", 8),
+            retry_base_delay: Duration::from_millis(env_or("WEBHOOK_RETRY_BASE_MS", 30_000)),
+            timeout: Duration::from_secs(env_or("WEBHOOK_TIMEOUT_SECONDS", 10)),
+            allowed_hosts: Arc::new(
+                env::var("WEBHOOK_ALLOWED_HOSTS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|host| host.trim().to_lowercase())
+                    .filter(|host| !host.is_empty())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Webhooks {
+    repository: Arc<dyn Repository>,
+    // wakes the sender when a delivery is queued
+    queued: Arc<Notify>,
+    allowed_hosts: Arc<HashSet<String>>,
+}
+
+impl Webhooks {
+    // starts sending the queued deliveries in the background
+    pub fn start(repository: Arc<dyn Repository>, settings: WebhookSettings) -> Self {
+        let queued = Arc::new(Notify::new());
+        let allowed_hosts = settings.allowed_hosts.clone();
+        tokio::spawn(send_deliveries(
+            repository.clone(),
+            settings,
+            queued.clone(),
+        ));
+        Webhooks {
+            repository,
+            queued,
+            allowed_hosts,
+        }
+    }
+
+    // only queues the deliveries, for the import command: a running server sends them
+    pub fn queue_only(repository: Arc<dyn Repository>) -> Self {
+        Webhooks {
+            repository,
+            queued: Arc::new(Notify::new()),
+            allowed_hosts: Arc::default(),
+        }
+    }
+
+    // see check_webhook_url
+    pub async fn check_url(&self, url: &reqwest::Url) -> std::result::Result<(), String> {
+        check_webhook_url(url, &self.allowed_hosts)
+            .await
+            .map(|_| ())
+    }
+
+    // the event's payload `data` is the record as it is now, e.g. the new term
+    pub async fn record_event(&self, entity_type: &str, action: &str, id: i32) {
+        let event_type = format!("{}.{}", entity_type, action);
+        let Some(entity) = entity_fields(entity_type) else {
+            return;
+        };
+        let queue_result = match self.repository.get_record(entity, entity.fields, id).await {
+            Ok(Some(record)) => {
+                self.repository
+                    .queue_webhook_deliveries(&event_type, &Value::Object(record))
+                    .await
+            }
+            Ok(None) => Ok(0),
+            Err(error) => Err(error),
+        };
+        self.wake_sender(&event_type, queue_result);
+    }
+
+    // created, and verified too when the record was created verified
+    pub async fn record_created(&self, entity_type: &str, id: i32, is_verified: bool) {
+        self.record_event(entity_type, "created", id).await;
+        if is_verified {
+            self.record_event(entity_type, "verified", id).await;
+        }
+    }
+
+    // sent as <type>.linked for both entity types of the link
+    pub async fn links_created(
+        &self,
+        parent_entity_type: &str,
+        parent_id: i32,
+        child_entity_type: &str,
+        child_ids: &[i32],
+    ) {
+        if child_ids.is_empty() {
+            return;
+        }
+        let data = json!({
+            "parent_entity_type": parent_entity_type,
+            "parent_id": parent_id,
+            "child_entity_type": child_entity_type,
+            "child_ids": child_ids,
+        });
+        for entity_type in [parent_entity_type, child_entity_type] {
+            let event_type = format!("{}.linked", entity_type);
+            if !WEBHOOK_EVENT_TYPES.contains(&event_type.as_str()) {
+                continue;
+            }
+            let queue_result = self
+                .repository
+                .queue_webhook_deliveries(&event_type, &data)
+                .await;
+            self.wake_sender(&event_type, queue_result);
+        }
+    }
+
+    // queues the delivery's payload again as a new delivery, None when there is no such delivery
+    pub async fn replay(&self, webhook_id: i32, delivery_id: i64) -> Result<Option<i64>> {
+        let replay_id = self
+            .repository
+            .replay_webhook_delivery(webhook_id, delivery_id)
+            .await?;
+        if replay_id.is_some() {
+            self.queued.notify_one();
+        }
+        Ok(replay_id)
+    }
+
+    // the change the event is about is already saved, so failing to queue it is only logged
+    fn wake_sender(&self, event_type: &str, queue_result: Result<u64>) {
+        match queue_result {
+            Ok(0) => {}
+            Ok(_) => self.queued.notify_one(),
+            Err(error) => {
+                tracing::error!(%error, event_type, "queueing the webhook deliveries failed")
+            }
+        }
+    }
+}
+
+// loopback, link-local, private and unspecified addresses, IPv4 ones mapped to IPv6 too
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_unspecified()
+            }
+        },
+    }
+}
+
+// refuses a url whose host is, or resolves to, an internal address unless it's allowed.
+// Returns the address a host name was resolved to, the request has to be sent there and
+// not to whatever the name resolves to next time
+pub async fn check_webhook_url(
+    url: &reqwest::Url,
+    allowed_hosts: &HashSet<String>,
+) -> std::result::Result<Option<SocketAddr>, String> {
+    let Some(host) = url.host_str() else {
+        return Err("url has no host".to_owned());
+    };
+    if allowed_hosts.contains(&host.to_lowercase()) {
+        return Ok(None);
+    }
+    // IPv6 hosts are in brackets, an address is connected to as it is
+    if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        if is_internal(ip) {
+            return Err(internal_address(host, ip));
+        }
+        return Ok(None);
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|error| format!("url's host {} can't be resolved: {}", host, error))?
+        .collect();
+    if let Some(address) = addresses.iter().find(|address| is_internal(address.ip())) {
+        return Err(internal_address(host, address.ip()));
+    }
+    match addresses.first() {
+        Some(address) => Ok(Some(*address)),
+        None => Err(format!("url's host {} has no addresses", host)),
+    }
+}
+
+fn internal_address(host: &str, ip: IpAddr) -> String {
+    format!(
+        "url's host {} is the internal address {}, which isn't in WEBHOOK_ALLOWED_HOSTS",
+        host, ip
+    )
+}
+
+// a client for one request: redirects aren't followed, as they could lead to an internal
+// address, and a host name that was checked is only connected to at the checked address
+fn webhook_client(
+    settings: &WebhookSettings,
+    url: &reqwest::Url,
+    address: Option<SocketAddr>,
+) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(settings.timeout)
+        .redirect(reqwest::redirect::Policy::none());
+    if let (Some(host), Some(address)) = (url.host_str(), address) {
+        builder = builder.resolve(host, address);
+    }
+    builder.build()
+}
+
+// the HMAC-SHA256 of "<timestamp>.<body>" as hex, sent as `sha256=<signature>`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    id: i64,
+    event: &'a str,
+    created_at: DateTime<Utc>,
+    data: &'a Value,
+}
+
+async fn send_deliveries(
+    repository: Arc<dyn Repository>,
+    settings: WebhookSettings,
+    queued: Arc<Notify>,
+) {
+    loop {
+        match send_due_deliveries(&*repository, &settings).await {
+            // there may be more due
+            Ok(sent) if sent == BATCH_SIZE as usize => continue,
+            Ok(_) => {}
+            Err(error) => tracing::error!(%error, "sending webhooks failed"),
+        }
+        tokio::select! {
+            _ = queued.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+async fn send_due_deliveries(
+    repository: &dyn Repository,
+    settings: &WebhookSettings,
+) -> Result<usize> {
+    let lease = settings.timeout + POLL_INTERVAL;
+    let deliveries = repository
+        .claim_webhook_deliveries(BATCH_SIZE, lease)
+        .await?;
+
+    let results = join_all(
+        deliveries
+            .iter()
+            .map(|delivery| send_delivery(repository, settings, delivery)),
+    )
+    .await;
+    for result in results {
+        result?;
+    }
+    Ok(deliveries.len())
+}
+
+async fn send_delivery(
+    repository: &dyn Repository,
+    settings: &WebhookSettings,
+    delivery: &DueDelivery,
+) -> Result<()> {
+    let body = serde_json::to_vec(&Payload {
+        id: delivery.id,
+        event: &delivery.event_type,
+        created_at: delivery.created_at,
+        data: &delivery.data,
+    })
+    .map_err(|error| sqlx::Error::Decode(error.into()))?;
+    let timestamp = Utc::now().timestamp();
+    let signature = sign(&delivery.secret, timestamp, &body);
+
+    // the host may resolve to another address than when the webhook was created
+    let client = match reqwest::Url::parse(&delivery.url) {
+        Ok(url) => match check_webhook_url(&url, &settings.allowed_hosts).await {
+            Ok(address) => {
+                webhook_client(settings, &url, address).map_err(|error| error.to_string())
+            }
+            Err(error) => Err(error),
+        },
+        Err(error) => Err(error.to_string()),
+    };
+    let client = match client {
+        Ok(client) => client,
+        Err(error) => return record_failure(repository, settings, delivery, None, error).await,
+    };
+    let response = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(WEBHOOK_ID_HEADER, delivery.id)
+        .header(WEBHOOK_EVENT_HEADER, &delivery.event_type)
+        .header(WEBHOOK_TIMESTAMP_HEADER, timestamp)
+        .header(WEBHOOK_SIGNATURE_HEADER, format!("sha256={}", signature))
+        .body(body)
+        .send()
+        .await;
+    let (status_code, error) = match response {
+        Ok(response) if response.status().is_success() => (Some(response.status()), None),
+        // redirects aren't followed, so a 3xx is a failure too.
+        // Only the status line, the body is the receiver's and may be anything
+        Ok(response) => (Some(response.status()), Some(response.status().to_string())),
+        Err(error) => (error.status(), Some(error.to_string())),
+    };
+    let status_code = status_code.map(|status| i32::from(status.as_u16()));
+
+    match error {
+        None => {
+            repository
+                .record_webhook_delivered(delivery.id, status_code)
+                .await?;
+        }
+        Some(error) => {
+            record_failure(repository, settings, delivery, status_code, error).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn record_failure(
+    repository: &dyn Repository,
+    settings: &WebhookSettings,
+    delivery: &DueDelivery,
+    status_code: Option<i32>,
+    error: String,
+) -> Result<()> {
+    tracing::warn!(delivery_id = delivery.id, %error, "webhook delivery failed");
+    let error: String = error.chars().take(MAX_ERROR_LENGTH).collect();
+    let give_up = delivery.attempts + 1 >= settings.max_attempts;
+    repository
+        .record_webhook_failure(
+            delivery.id,
+            status_code,
+            &error,
+            give_up,
+            retry_delay(settings, delivery.attempts),
+        )
+        .await
+}
+
+// the delay doubles with every failed attempt, `attempts` is the count before this one
+fn retry_delay(settings: &WebhookSettings, attempts: i32) -> Duration {
+    let factor = 2f64.powi(attempts.clamp(0, 32));
+    Duration::from_secs_f64(
+        (settings.retry_base_delay.as_secs_f64() * factor).min(MAX_RETRY_DELAY.as_secs_f64()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn check(url: &str, allowed_hosts: &[&str]) -> std::result::Result<(), String> {
+        let allowed_hosts = allowed_hosts.iter().map(|host| host.to_string()).collect();
+        check_webhook_url(&reqwest::Url::parse(url).unwrap(), &allowed_hosts)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn refuses_internal_addresses() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://10.1.2.3/hook",
+            "http://172.16.0.1/hook",
+            "https://192.168.1.10/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://localhost:3000/hook",
+        ] {
+            let error = check(url, &[]).await.unwrap_err();
+            assert!(
+                error.contains("WEBHOOK_ALLOWED_HOSTS"),
+                "{}: {}",
+                url,
+                error
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn allows_public_and_allowed_hosts() {
+        assert_eq!(check("https://93.184.216.34/hook", &[]).await, Ok(()));
+        assert_eq!(check("http://[2606:4700::1111]/hook", &[]).await, Ok(()));
+        assert_eq!(
+            check("http://127.0.0.1:8080/hook", &["127.0.0.1"]).await,
+            Ok(())
+        );
+        assert_eq!(check("http://LocalHost/hook", &["localhost"]).await, Ok(()));
+        assert_eq!(check("http://[::1]/hook", &["[::1]"]).await, Ok(()));
+    }
+}
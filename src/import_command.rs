@@ -0,0 +1,116 @@
+/*
+`jd_crm_api import <file> [--entity-type topic|term|source] [--format json|ndjson|csv]
+[--mapping <column:field,...>] [--on-existing skip|update]`
+
+Imports a file into the database of DATABASE_URL like POST /v1/import does, and prints
+the report as JSON. The format is guessed from the file's extension when --format isn't
+given. Webhook deliveries are only queued, a running server sends them.
+ */
+use crate::helpers::fieldsets::EntityFields;
+use crate::helpers::import::{
+    import, importable_entity, parse_records, ColumnMapping, ImportFormat, OnExisting,
+};
+use crate::helpers::limits::RequestLimits;
+use crate::helpers::webhooks::Webhooks;
+use crate::{connect, Repository};
+use std::fs;
+use std::sync::Arc;
+
+const USAGE: &str = "usage: jd_crm_api import <file> [--entity-type topic|term|source] [--format json|ndjson|csv] [--mapping <column:field,...>] [--on-existing skip|update]";
+
+struct ImportArgs {
+    path: String,
+    default_entity: Option<&'static EntityFields>,
+    format: Option<ImportFormat>,
+    mapping: ColumnMapping,
+    on_existing: OnExisting,
+}
+
+// the arguments after `import`
+fn parse_args(args: &[String]) -> Result<ImportArgs, String> {
+    let mut paths = vec![];
+    let mut default_entity = None;
+    let mut format = None;
+    let mut mapping = ColumnMapping::default();
+    let mut on_existing = OnExisting::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--entity-type" => {
+                default_entity = Some(
+                    importable_entity(value()?)
+                        .ok_or("--entity-type must be topic, term or source")?,
+                )
+            }
+            "--format" => format = Some(value()?.parse()?),
+            "--mapping" => mapping = value()?.parse()?,
+            "--on-existing" => on_existing = value()?.parse()?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let [path] = <[String; 1]>::try_from(paths).map_err(|_| "pass one file".to_owned())?;
+    Ok(ImportArgs {
+        path,
+        default_entity,
+        format,
+        mapping,
+        on_existing,
+    })
+}
+
+// the process's exit code: 0 when every record was imported, 1 when some failed, 2 when none could be
+pub async fn run_import(db_uri: &str, args: &[String]) -> i32 {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return 2;
+        }
+    };
+    let Some(format) = args
+        .format
+        .or_else(|| ImportFormat::from_extension(&args.path))
+    else {
+        eprintln!(
+            "pass --format, the extension of {} isn't .json, .ndjson or .csv",
+            args.path
+        );
+        return 2;
+    };
+    let input = match fs::read_to_string(&args.path) {
+        Ok(input) => input,
+        Err(error) => {
+            eprintln!("{} can't be read: {}", args.path, error);
+            return 2;
+        }
+    };
+    let records = match parse_records(args.default_entity, format, &input, &args.mapping) {
+        Ok(records) => records,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    let repository: Arc<dyn Repository> = connect(db_uri).await;
+    let webhooks = Webhooks::queue_only(repository.clone());
+    let report = import(
+        &*repository,
+        &webhooks,
+        &RequestLimits::from_env(),
+        records,
+        args.on_existing,
+    )
+    .await;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("the report can be serialized")
+    );
+    if report.failed > 0 {
+        1
+    } else {
+        0
+    }
+}
@@ -1,23 +1,74 @@
-mod routes;
+mod archive_command;
 mod helpers;
-use axum::Router;
+mod import_command;
+mod repository;
+mod routes;
+mod vault_command;
+pub use archive_command::{run_export, run_restore};
+use axum::{http::Method, Router};
+use helpers::limits::RequestLimits;
+use helpers::read_cache::ReadCache;
+use helpers::webhooks::WebhookSettings;
+pub use import_command::run_import;
+pub use repository::{MemoryRepository, PgRepository, Repository, SqliteRepository};
 use routes::create_routes;
 use sqlx::postgres::PgPoolOptions;
+use std::net::SocketAddr;
+use std::sync::Arc;
+pub use vault_command::{run_export_vault, run_import_vault};
+
+// the application with its limits, read cache and webhooks configured from the environment
+pub fn app(repository: Arc<dyn Repository>) -> Router {
+    create_routes(
+        repository,
+        RequestLimits::from_env(),
+        ReadCache::from_env(),
+        WebhookSettings::from_env(),
+    )
+}
+
+// the method and path of every route in app(), for tests/openapi.rs
+pub fn route_table() -> Vec<(Method, String)> {
+    routes::route_table(&RequestLimits::from_env())
+}
 
 pub async fn run(db_uri: &str) {
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(db_uri)
-        .await
-        .expect("db pool failed to initialize");
+    serve(connect(db_uri).await).await
+}
+
+// the backend is chosen by the scheme of the url, sqlite: or postgres(ql):
+async fn connect(db_uri: &str) -> Arc<dyn Repository> {
+    if db_uri.starts_with("sqlite:") {
+        let repository = SqliteRepository::connect(db_uri)
+            .await
+            .expect("sqlite database failed to open");
+        Arc::new(repository)
+    } else {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(db_uri)
+            .await
+            .expect("db pool failed to initialize");
+        Arc::new(PgRepository::new(pool))
+    }
+}
+
+// serves the sample data of init.sql from memory, changes are lost when the server stops
+pub async fn run_mock() {
+    tracing::info!("serving the sample data from memory");
+    serve(Arc::new(MemoryRepository::with_fixtures())).await
+}
 
+async fn serve(repository: Arc<dyn Repository>) {
     // build our server/application
-    let app: Router = create_routes(pool);
+    let app: Router = app(repository);
 
     // run it with hyper on localhost:3000
     // 0.0.0.0 makes it compatible with docker containers
     axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
-        .serve(app.into_make_service())
+        // the client address is used to rate limit clients without a configured api key,
+        // without it requests aren't rate limited at all
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("server failed to start");
 }
@@ -1,11 +1,53 @@
 use dotenvy::dotenv;
-use jd_crm_api::run;
-use std::env;
+use jd_crm_api::{
+    run, run_export, run_export_vault, run_import, run_import_vault, run_mock, run_restore,
+};
+use std::{env, io, process};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    let args: Vec<String> = env::args().skip(1).collect();
+    // the commands print their reports on stdout, so their logs go to stderr
+    let command = args.first().map(String::as_str).filter(|command| {
+        [
+            "import",
+            "export",
+            "restore",
+            "export-vault",
+            "import-vault",
+        ]
+        .contains(command)
+    });
+    let log_writer = if command.is_some() {
+        BoxMakeWriter::new(io::stderr)
+    } else {
+        BoxMakeWriter::new(io::stdout)
+    };
+    // JSON logs, one object per line. The level is set with RUST_LOG, e.g. RUST_LOG=debug,
+    // sqlx only logs slow queries by default since it logs every statement at info
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,sqlx=warn")),
+        )
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(log_writer)
+        .init();
+    // --mock serves the sample data from memory instead of Postgres
+    if command.is_none() && args.iter().any(|arg| arg == "--mock") {
+        return run_mock().await;
+    }
     let db_uri = env::var("DATABASE_URL")
-        .expect("DATABASE_URL env var is required for connecting to the db and for sqlx macros");
-    run(&db_uri).await
+        .expect("DATABASE_URL env var is required for connecting to the db");
+    match command {
+        Some("import") => process::exit(run_import(&db_uri, &args[1..]).await),
+        Some("export") => process::exit(run_export(&db_uri, &args[1..]).await),
+        Some("restore") => process::exit(run_restore(&db_uri, &args[1..]).await),
+        Some("export-vault") => process::exit(run_export_vault(&db_uri, &args[1..]).await),
+        Some("import-vault") => process::exit(run_import_vault(&db_uri, &args[1..]).await),
+        _ => run(&db_uri).await,
+    }
 }
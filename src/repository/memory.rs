@@ -0,0 +1,1296 @@
+/*
+An in-memory repository for the tests and `--mock`, nothing is persisted.
+
+It behaves like the schema in init.sql where the API can tell: ids count up per table,
+slugs are unique, names of topics and terms are unique, links need both records to exist,
+versions are bumped on update, and every change is recorded as an event like the triggers
+do. with_fixtures starts with the same sample data as init.sql.
+ */
+use super::{
+    link_table_entity_types, new_webhook_secret, relation, Relation, Repository,
+    ARTICLE_LINK_TABLES,
+};
+use crate::helpers::archive::{
+    matching_values, restore, Archive, ArchiveLinkTable, ArchiveWriter, OnConflict, RestoreReport,
+};
+use crate::helpers::events::{Event, EventFeed, FeedMessage, EVENTS_PAGE_SIZE};
+use crate::helpers::fieldsets::{
+    entity_fields, select_fields, EntityFields, Field, FieldKind, SparseRecord, ARTICLE_FIELDS,
+    QUESTION_FIELDS, SOURCE_FIELDS, TERM_FIELDS, TOPIC_FIELDS,
+};
+use crate::helpers::handler_utils::{
+    process_optional_vec, CreateTopicOrTerm, UpdateOutcome, LINK_TABLES,
+};
+use crate::helpers::import::{ImportOutcome, ImportRecord};
+use crate::helpers::lookup::{slug_base, unique_slug, EntityKey};
+use crate::helpers::shared_types::{CreateSource, MediaType};
+use crate::helpers::webhooks::{CreateWebhook, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use futures::FutureExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::error::DatabaseError;
+use sqlx::Result;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+pub struct MemoryRepository {
+    data: Mutex<MemoryData>,
+    feed: EventFeed,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    // entity type -> id -> the record with all of its fields
+    records: HashMap<&'static str, BTreeMap<i32, SparseRecord>>,
+    // bridge table -> (id of its first type, id of its second type) -> when it was linked
+    links: HashMap<&'static str, BTreeMap<(i32, i32), DateTime<Utc>>>,
+    events: Vec<Event>,
+    webhooks: BTreeMap<i32, NewWebhook>,
+    deliveries: BTreeMap<i64, WebhookDelivery>,
+}
+
+impl MemoryRepository {
+    // without any records
+    pub fn new() -> Self {
+        MemoryRepository {
+            data: Mutex::new(MemoryData::default()),
+            feed: EventFeed::new(),
+        }
+    }
+
+    // with the sample data of init.sql
+    pub fn with_fixtures() -> Self {
+        let repository = MemoryRepository::new();
+        {
+            let mut data = repository.lock();
+            let feed = &repository.feed;
+            for (name, slug, url) in [
+                (
+                    "dictionary storm",
+                    "dictionary-storm",
+                    "https://www.merriam-webster.com/dictionary/storm",
+                ),
+                (
+                    "wikipedia tropical cyclone",
+                    "wikipedia-tropical-cyclone",
+                    "https://en.wikipedia.org/wiki/Tropical_cyclone",
+                ),
+                (
+                    "wikipedia atlantic hurricane",
+                    "wikipedia-atlantic-hurricane",
+                    "https://en.wikipedia.org/wiki/Atlantic_hurricane",
+                ),
+            ] {
+                let source = CreateSource {
+                    name: name.to_owned(),
+                    url: Some(url.to_owned()),
+                    author: None,
+                    author_url: None,
+                    media_type: Some(MediaType::Web),
+                    image_url: None,
+                    image_type: None,
+                    ai_generated: Some(false),
+                    related_terms: None,
+                    related_topics: None,
+                    related_sources: None,
+                };
+                data.insert(feed, &SOURCE_FIELDS, source_record(&source, slug));
+            }
+            data.insert(
+                feed,
+                &TOPIC_FIELDS,
+                fixture_topic_or_term(
+                    "topic",
+                    "Hurricane",
+                    "hurricane",
+                    "a tropical cyclone that forms in the Atlantic Ocean, primarily between the months of June and November.",
+                ),
+            );
+            data.insert(
+                feed,
+                &TERM_FIELDS,
+                fixture_topic_or_term(
+                    "term",
+                    "Storm",
+                    "storm",
+                    "a disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning",
+                ),
+            );
+            data.insert(
+                feed,
+                &TERM_FIELDS,
+                fixture_topic_or_term(
+                    "term",
+                    "Tropical Cycle",
+                    "tropical-cycle",
+                    "a rapidly rotating storm system characterized by a low-pressure center, a closed low-level atmospheric circulation, strong winds, and a spiral arrangement of thunderstorms that produce heavy rain and squalls.",
+                ),
+            );
+            data.insert(
+                feed,
+                &QUESTION_FIELDS,
+                record(json!({"question": "What is a storm?", "topic_id": 1})),
+            );
+            data.insert(
+                feed,
+                &ARTICLE_FIELDS,
+                record(
+                    json!({"title": "title1", "author": "author1", "publish_date": "2023-03-27"}),
+                ),
+            );
+            for (link_table, first_id, second_id) in [
+                ("terms_to_sources", 1, 1),
+                ("terms_to_sources", 2, 2),
+                ("topics_to_sources", 1, 3),
+                ("terms_to_topics", 1, 1),
+                ("terms_to_topics", 2, 1),
+                ("articles_to_topics", 1, 1),
+                ("articles_to_terms", 1, 1),
+                ("articles_to_terms", 1, 2),
+                ("articles_to_questions", 1, 1),
+            ] {
+                data.link(feed, link_table, first_id, second_id)
+                    .expect("the fixtures link existing records");
+            }
+        }
+        repository
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryData> {
+        // a panic while the lock was held can't leave a half written record behind
+        self.data.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl Default for MemoryRepository {
+    fn default() -> Self {
+        MemoryRepository::new()
+    }
+}
+
+fn record(value: Value) -> SparseRecord {
+    match value {
+        Value::Object(record) => record,
+        _ => SparseRecord::new(),
+    }
+}
+
+fn to_json<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn timestamp(record: &SparseRecord, field: &str) -> Option<DateTime<Utc>> {
+    serde_json::from_value(record.get(field)?.clone()).ok()
+}
+
+fn is_updated_since(record: &SparseRecord, updated_since: Option<DateTime<Utc>>) -> bool {
+    match updated_since {
+        None => true,
+        Some(updated_since) => timestamp(record, "updated_at") >= Some(updated_since),
+    }
+}
+
+// the columns of a topic or term the payload sets, arrays that are left out are empty
+fn topic_or_term_fields(topic_or_term: &str, payload: &CreateTopicOrTerm) -> SparseRecord {
+    let mut fields = record(json!({
+        "is_verified": payload.is_verified,
+        "brief_description": payload.brief_description,
+        "full_description": payload.full_description,
+        "bullet_points": process_optional_vec(&payload.bullet_points),
+        "examples": process_optional_vec(&payload.examples),
+        "parallels": process_optional_vec(&payload.parallels),
+        "ai_brief_description": payload.ai_brief_description,
+        "ai_full_description": payload.ai_full_description,
+        "ai_bullet_points": process_optional_vec(&payload.ai_bullet_points),
+        "ai_parallels": process_optional_vec(&payload.ai_parallels),
+        "ai_examples": process_optional_vec(&payload.ai_examples),
+    }));
+    fields.insert(topic_or_term.to_owned(), to_json(&payload.name));
+    fields
+}
+
+fn fixture_topic_or_term(
+    topic_or_term: &str,
+    name: &str,
+    slug: &str,
+    brief_description: &str,
+) -> SparseRecord {
+    let payload = CreateTopicOrTerm {
+        name: name.to_owned(),
+        is_verified: false,
+        brief_description: Some(brief_description.to_owned()),
+        full_description: None,
+        bullet_points: None,
+        examples: None,
+        parallels: None,
+        ai_brief_description: None,
+        ai_full_description: None,
+        ai_bullet_points: None,
+        ai_parallels: None,
+        ai_examples: None,
+        related_terms: None,
+        related_topics: None,
+        related_sources: None,
+    };
+    let mut fields = topic_or_term_fields(topic_or_term, &payload);
+    fields.insert("slug".to_owned(), to_json(slug));
+    // the sample rows in init.sql leave the arrays NULL
+    for (name, kind) in TOPIC_FIELDS.fields {
+        if matches!(kind, FieldKind::TextArray) {
+            fields.insert((*name).to_owned(), Value::Null);
+        }
+    }
+    fields
+}
+
+// the columns of a source the payload sets
+fn source_fields(payload: &CreateSource) -> SparseRecord {
+    record(json!({
+        "name": payload.name,
+        "url": payload.url,
+        "author": payload.author,
+        "author_url": payload.author_url,
+        "media_type": payload.media_type,
+        "image_url": payload.image_url,
+        "image_type": payload.image_type,
+        "ai_generated": payload.ai_generated,
+    }))
+}
+
+fn source_record(payload: &CreateSource, slug: &str) -> SparseRecord {
+    let mut fields = source_fields(payload);
+    fields.insert("slug".to_owned(), to_json(slug));
+    fields
+}
+
+// every bridge table, the ones in LINK_TABLES and the article ones
+fn link_tables() -> Vec<&'static str> {
+    let mut link_tables: Vec<&'static str> = LINK_TABLES
+        .values()
+        .flat_map(|children| children.values().copied())
+        .chain(
+            ARTICLE_LINK_TABLES
+                .iter()
+                .map(|(_, link_table)| *link_table),
+        )
+        .collect();
+    link_tables.sort_unstable();
+    link_tables.dedup();
+    link_tables
+}
+
+impl MemoryData {
+    fn table(&self, entity_type: &str) -> impl Iterator<Item = &SparseRecord> {
+        self.records
+            .get(entity_type)
+            .into_iter()
+            .flat_map(|records| records.values())
+    }
+
+    fn get(&self, entity_type: &str, id: i32) -> Option<&SparseRecord> {
+        self.records.get(entity_type)?.get(&id)
+    }
+
+    fn record_event(
+        &mut self,
+        feed: &EventFeed,
+        action: &str,
+        entity: (&str, i32),
+        related: Option<(&str, i32)>,
+    ) {
+        let event = Event {
+            id: self.events.len() as i64 + 1,
+            action: action.to_owned(),
+            entity_type: entity.0.to_owned(),
+            entity_id: entity.1,
+            related_type: related.map(|(related_type, _)| related_type.to_owned()),
+            related_id: related.map(|(_, related_id)| related_id),
+            created_at: Utc::now(),
+        };
+        self.events.push(event.clone());
+        feed.send(FeedMessage::Event(Arc::new(event)));
+    }
+
+    // adds the id, version and timestamps to the fields and returns the new id
+    fn insert(&mut self, feed: &EventFeed, entity: &EntityFields, fields: SparseRecord) -> i32 {
+        let records = self.records.entry(entity.entity_type).or_default();
+        let id = records.keys().next_back().map_or(1, |last_id| last_id + 1);
+        let now = to_json(Utc::now());
+        let mut record = SparseRecord::new();
+        // in the order of the entity's fields
+        for (name, _) in entity.fields {
+            let value = match *name {
+                "id" => json!(id),
+                "version" => json!(1),
+                "created_at" | "updated_at" => now.clone(),
+                name => fields.get(name).cloned().unwrap_or(Value::Null),
+            };
+            record.insert((*name).to_owned(), value);
+        }
+        records.insert(id, record);
+        self.record_event(feed, "created", (entity.entity_type, id), None);
+        id
+    }
+
+    // replaces the fields when the version is still `expected_version`, and bumps it
+    fn update(
+        &mut self,
+        feed: &EventFeed,
+        entity: &EntityFields,
+        id: i32,
+        expected_version: i32,
+        fields: SparseRecord,
+    ) -> Result<UpdateOutcome> {
+        let Some(current) = self.get(entity.entity_type, id) else {
+            return Ok(UpdateOutcome::NotFound);
+        };
+        let current_version = current
+            .get("version")
+            .and_then(Value::as_i64)
+            .unwrap_or_default() as i32;
+        if current_version != expected_version {
+            return Ok(UpdateOutcome::VersionConflict { current_version });
+        }
+        let was_verified = current.get("is_verified") == Some(&Value::Bool(true));
+        if let Some(name) = fields.get(entity.name_column) {
+            self.check_unique_name(entity, name, Some(id))?;
+        }
+
+        let record = self
+            .records
+            .get_mut(entity.entity_type)
+            .and_then(|records| records.get_mut(&id))
+            .ok_or(sqlx::Error::RowNotFound)?;
+        record.extend(fields);
+        // questions and articles have no version
+        if record.contains_key("version") {
+            record.insert("version".to_owned(), json!(current_version + 1));
+        }
+        record.insert("updated_at".to_owned(), to_json(Utc::now()));
+        let verified = !was_verified && record.get("is_verified") == Some(&Value::Bool(true));
+        self.record_event(feed, "updated", (entity.entity_type, id), None);
+        Ok(UpdateOutcome::Updated { verified })
+    }
+
+    // topics and terms have a unique name, like the unique_topic and unique_term constraints
+    fn check_unique_name(
+        &self,
+        entity: &EntityFields,
+        name: &Value,
+        except_id: Option<i32>,
+    ) -> Result<()> {
+        if !matches!(entity.entity_type, "topic" | "term") {
+            return Ok(());
+        }
+        let taken = self.table(entity.entity_type).any(|record| {
+            record.get(entity.name_column) == Some(name)
+                && record.get("id").and_then(Value::as_i64) != except_id.map(i64::from)
+        });
+        if taken {
+            return Err(MemoryError::unique_violation(&format!(
+                "unique_{}",
+                entity.entity_type
+            )));
+        }
+        Ok(())
+    }
+
+    // an exact match wins over other names that only differ in case
+    fn find_by_name(&self, entity: &EntityFields, name: &str) -> Option<&SparseRecord> {
+        let name_matches = |record: &SparseRecord, matches: &dyn Fn(&str) -> bool| {
+            record
+                .get(entity.name_column)
+                .and_then(Value::as_str)
+                .is_some_and(matches)
+        };
+        let lowercase_name = name.to_lowercase();
+        self.table(entity.entity_type)
+            .find(|record| name_matches(record, &|record_name| record_name == name))
+            .or_else(|| {
+                self.table(entity.entity_type).find(|record| {
+                    name_matches(record, &|record_name| {
+                        record_name.to_lowercase() == lowercase_name
+                    })
+                })
+            })
+    }
+
+    // sources don't have a unique name, the one with the name that has the url too
+    fn find_source(&self, name: &str, url: &Option<String>) -> Option<&SparseRecord> {
+        let url = to_json(url);
+        let sources: Vec<&SparseRecord> = self
+            .table("source")
+            .filter(|record| record.get("url").unwrap_or(&Value::Null) == &url)
+            .collect();
+        let source_name = |record: &SparseRecord| {
+            record
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let lowercase_name = name.to_lowercase();
+        sources
+            .iter()
+            .find(|record| source_name(record) == name)
+            .or_else(|| {
+                sources
+                    .iter()
+                    .find(|record| source_name(record).to_lowercase() == lowercase_name)
+            })
+            .copied()
+    }
+
+    // see Repository::import_records, a record that fails has changed nothing
+    fn import(
+        &mut self,
+        feed: &EventFeed,
+        entity: &EntityFields,
+        record: &ImportRecord,
+        update_existing: bool,
+    ) -> Result<ImportOutcome> {
+        let existing = match record {
+            ImportRecord::TopicOrTerm(_) => self.find_by_name(entity, record.name()),
+            ImportRecord::Source(payload) => self.find_source(&payload.name, &payload.url),
+        };
+        let existing = existing.map(|existing| {
+            let field = |name| {
+                existing
+                    .get(name)
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default()
+            };
+            (field("id") as i32, field("version") as i32)
+        });
+        let mut fields = match record {
+            ImportRecord::TopicOrTerm(payload) => topic_or_term_fields(entity.entity_type, payload),
+            ImportRecord::Source(payload) => source_fields(payload),
+        };
+        match existing {
+            Some((id, _)) if !update_existing => Ok(ImportOutcome::Skipped { id }),
+            Some((id, version)) => {
+                let outcome = self.update(feed, entity, id, version, fields)?;
+                Ok(ImportOutcome::from_update(id, outcome))
+            }
+            None => {
+                self.check_unique_name(entity, &to_json(record.name()), None)?;
+                let slug = self.slug_for(entity.entity_type, record.name());
+                fields.insert("slug".to_owned(), to_json(slug));
+                Ok(ImportOutcome::Created {
+                    id: self.insert(feed, entity, fields),
+                })
+            }
+        }
+    }
+
+    fn slug_for(&self, entity_type: &str, name: &str) -> String {
+        let base = slug_base(entity_type, name);
+        let prefix = format!("{}-", base);
+        let taken: HashSet<String> = self
+            .table(entity_type)
+            .filter_map(|record| record.get("slug")?.as_str())
+            .filter(|slug| *slug == base || slug.starts_with(&prefix))
+            .map(str::to_owned)
+            .collect();
+        unique_slug(&base, &taken)
+    }
+
+    // returns false when the link already existed
+    fn link(
+        &mut self,
+        feed: &EventFeed,
+        link_table: &'static str,
+        first_id: i32,
+        second_id: i32,
+    ) -> Result<bool> {
+        let (first_type, second_type) =
+            link_table_entity_types(link_table).ok_or(sqlx::Error::RowNotFound)?;
+        for (entity_type, id) in [(first_type, first_id), (second_type, second_id)] {
+            if self.get(entity_type, id).is_none() {
+                return Err(MemoryError::foreign_key_violation(link_table, entity_type));
+            }
+        }
+        let links = self.links.entry(link_table).or_default();
+        if links.contains_key(&(first_id, second_id)) {
+            return Ok(false);
+        }
+        links.insert((first_id, second_id), Utc::now());
+        self.record_event(
+            feed,
+            "linked",
+            (first_type, first_id),
+            Some((second_type, second_id)),
+        );
+        Ok(true)
+    }
+
+    // the ids of the records linked to the parent through the bridge table
+    fn linked_ids(&self, link_table: &str, parent_type: &str, parent_id: i32) -> Vec<i32> {
+        let Some((first_type, _)) = link_table_entity_types(link_table) else {
+            return vec![];
+        };
+        let parent_is_first = first_type == parent_type;
+        self.links
+            .get(link_table)
+            .into_iter()
+            .flat_map(|links| links.keys())
+            .filter_map(|(first_id, second_id)| match parent_is_first {
+                true if *first_id == parent_id => Some(*second_id),
+                false if *second_id == parent_id => Some(*first_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // returns the new delivery's id
+    fn queue_delivery(
+        &mut self,
+        webhook_id: i32,
+        event_type: &str,
+        data: Value,
+        replay_of: Option<i64>,
+    ) -> i64 {
+        let id = self
+            .deliveries
+            .keys()
+            .next_back()
+            .map_or(1, |last_id| last_id + 1);
+        let now = Utc::now();
+        self.deliveries.insert(
+            id,
+            WebhookDelivery {
+                id,
+                webhook_id,
+                event_type: event_type.to_owned(),
+                data,
+                status: "pending".to_owned(),
+                attempts: 0,
+                next_attempt_at: now,
+                last_status_code: None,
+                last_error: None,
+                replay_of,
+                created_at: now,
+                delivered_at: None,
+            },
+        );
+        id
+    }
+
+    // the child records related to the parent, ordered by id
+    fn related(&self, parent_type: &str, parent_id: i32, child_type: &str) -> Vec<&SparseRecord> {
+        let parent_column = format!("{}_id", parent_type);
+        let child_ids: Vec<i32> = match relation(parent_type, child_type) {
+            None => vec![],
+            Some(Relation::LinkTable(link_table)) => {
+                self.linked_ids(link_table, parent_type, parent_id)
+            }
+            Some(Relation::ChildColumn) => self
+                .table(child_type)
+                .filter(|child| {
+                    child.get(&parent_column).and_then(Value::as_i64) == Some(parent_id.into())
+                })
+                .filter_map(|child| child.get("id")?.as_i64())
+                .map(|id| id as i32)
+                .collect(),
+            Some(Relation::ParentColumn) => self
+                .get(parent_type, parent_id)
+                .and_then(|parent| parent.get(&format!("{}_id", child_type))?.as_i64())
+                .map(|id| vec![id as i32])
+                .unwrap_or_default(),
+            Some(Relation::TopicQuestions(link_table)) => {
+                let topic_ids = self.linked_ids(link_table, parent_type, parent_id);
+                self.table("question")
+                    .filter(|question| {
+                        question
+                            .get("topic_id")
+                            .and_then(Value::as_i64)
+                            .is_some_and(|topic_id| topic_ids.contains(&(topic_id as i32)))
+                    })
+                    .filter_map(|question| question.get("id")?.as_i64())
+                    .map(|id| id as i32)
+                    .collect()
+            }
+        };
+        let mut child_ids = child_ids;
+        child_ids.sort_unstable();
+        child_ids.dedup();
+        child_ids
+            .into_iter()
+            .filter_map(|id| self.get(child_type, id))
+            .collect()
+    }
+}
+
+// the writes of restore_archive, see helpers/archive.rs
+struct MemoryArchiveWriter<'d> {
+    data: &'d mut MemoryData,
+    feed: &'d EventFeed,
+}
+
+fn record_id(record: &SparseRecord) -> Option<i32> {
+    record.get("id")?.as_i64().map(|id| id as i32)
+}
+
+#[async_trait]
+impl ArchiveWriter for MemoryArchiveWriter<'_> {
+    async fn find_id(&mut self, entity: &EntityFields, name: &str) -> Result<Option<i32>> {
+        Ok(self.data.find_by_name(entity, name).and_then(record_id))
+    }
+
+    async fn find_matching_id(
+        &mut self,
+        entity: &EntityFields,
+        values: &SparseRecord,
+        columns: &[&str],
+    ) -> Result<Option<i32>> {
+        let matching = matching_values(entity, values, columns);
+        Ok(self
+            .data
+            .table(entity.entity_type)
+            .find(|record| {
+                matching
+                    .iter()
+                    .all(|(name, value)| record.get(name).unwrap_or(&Value::Null) == value)
+            })
+            .and_then(record_id))
+    }
+
+    async fn insert_record(&mut self, entity: &EntityFields, values: &SparseRecord) -> Result<i32> {
+        let mut fields = values.clone();
+        if let Some(name) = fields.get(entity.name_column) {
+            self.data.check_unique_name(entity, name, None)?;
+        }
+        let has_field = |field: &str| entity.fields.iter().any(|(name, _)| *name == field);
+        // the column defaults of init.sql
+        if has_field("is_verified") && !fields.contains_key("is_verified") {
+            fields.insert("is_verified".to_owned(), json!(false));
+        }
+        if has_field("slug") {
+            let name = fields
+                .get(entity.name_column)
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let slug = self.data.slug_for(entity.entity_type, name);
+            fields.insert("slug".to_owned(), to_json(slug));
+        }
+        Ok(self.data.insert(self.feed, entity, fields))
+    }
+
+    async fn update_record(
+        &mut self,
+        entity: &EntityFields,
+        id: i32,
+        values: &SparseRecord,
+    ) -> Result<()> {
+        let version = self
+            .data
+            .get(entity.entity_type, id)
+            .and_then(|record| record.get("version")?.as_i64())
+            .unwrap_or_default() as i32;
+        self.data
+            .update(self.feed, entity, id, version, values.clone())?;
+        Ok(())
+    }
+
+    async fn insert_link(
+        &mut self,
+        link_table: &ArchiveLinkTable,
+        from_id: i32,
+        to_id: i32,
+    ) -> Result<bool> {
+        if link_tables().contains(&link_table.table) {
+            return self.data.link(self.feed, link_table.table, from_id, to_id);
+        }
+        // the mind map, which records no events in init.sql
+        for (entity, id) in [(link_table.from, from_id), (link_table.to, to_id)] {
+            if self.data.get(entity.entity_type, id).is_none() {
+                return Err(MemoryError::foreign_key_violation(
+                    link_table.table,
+                    entity.entity_type,
+                ));
+            }
+        }
+        let links = self.data.links.entry(link_table.table).or_default();
+        Ok(links.insert((from_id, to_id), Utc::now()).is_none())
+    }
+}
+
+#[async_trait]
+impl Repository for MemoryRepository {
+    async fn get_records(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SparseRecord>> {
+        Ok(self
+            .lock()
+            .table(entity.entity_type)
+            .filter(|record| is_updated_since(record, updated_since))
+            .map(|record| select_fields(record, fields))
+            .collect())
+    }
+
+    fn stream_records(
+        &self,
+        entity: &'static EntityFields,
+        fields: Vec<Field>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>> {
+        let records: Vec<Result<SparseRecord>> = self
+            .lock()
+            .table(entity.entity_type)
+            .filter(|record| is_updated_since(record, updated_since))
+            .map(|record| Ok(select_fields(record, &fields)))
+            .collect();
+        stream::iter(records).boxed()
+    }
+
+    async fn get_record(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        id: i32,
+    ) -> Result<Option<SparseRecord>> {
+        Ok(self
+            .lock()
+            .get(entity.entity_type, id)
+            .map(|record| select_fields(record, fields)))
+    }
+
+    async fn get_records_page(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>> {
+        Ok(self
+            .lock()
+            .table(entity.entity_type)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|record| select_fields(record, fields))
+            .collect())
+    }
+
+    async fn get_related(
+        &self,
+        parent_type: &str,
+        parent_ids: &[i32],
+        child_type: &str,
+        fields: &[Field],
+    ) -> Result<HashMap<i32, Vec<SparseRecord>>> {
+        let data = self.lock();
+        let mut related: HashMap<i32, Vec<SparseRecord>> = HashMap::new();
+        for parent_id in parent_ids {
+            let children: Vec<SparseRecord> = data
+                .related(parent_type, *parent_id, child_type)
+                .into_iter()
+                .map(|child| select_fields(child, fields))
+                .collect();
+            if !children.is_empty() {
+                related.insert(*parent_id, children);
+            }
+        }
+        Ok(related)
+    }
+
+    async fn get_related_page(
+        &self,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>> {
+        Ok(self
+            .lock()
+            .related(parent_type, parent_id, child_type)
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|child| select_fields(child, fields))
+            .collect())
+    }
+
+    fn stream_terms_for_topic(
+        &self,
+        fields: Vec<Field>,
+        topic_id: i32,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>> {
+        let data = self.lock();
+        let mut terms: Vec<(i32, SparseRecord)> = data
+            .links
+            .get("terms_to_topics")
+            .into_iter()
+            .flatten()
+            .filter(|((_, linked_topic_id), _)| *linked_topic_id == topic_id)
+            .filter_map(|((term_id, _), linked_at)| {
+                let term = data.get("term", *term_id)?;
+                let modified = timestamp(term, "updated_at").max(Some(*linked_at));
+                let is_modified = updated_since.is_none_or(|since| modified >= Some(since));
+                is_modified.then(|| (*term_id, select_fields(term, &fields)))
+            })
+            .collect();
+        terms.sort_by_key(|(term_id, _)| *term_id);
+        stream::iter(terms.into_iter().map(|(_, term)| Ok(term))).boxed()
+    }
+
+    async fn get_terms_to_topic_last_modified(
+        &self,
+        topic_id: i32,
+    ) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .lock()
+            .links
+            .get("terms_to_topics")
+            .into_iter()
+            .flatten()
+            .filter(|((_, linked_topic_id), _)| *linked_topic_id == topic_id)
+            .map(|(_, linked_at)| *linked_at)
+            .max())
+    }
+
+    async fn find_entity_id(
+        &self,
+        entity: &EntityFields,
+        key: &EntityKey<'_>,
+    ) -> Result<Option<i32>> {
+        let data = self.lock();
+        let column_is = |record: &SparseRecord, column: &str, value: &str| {
+            record.get(column).and_then(Value::as_str) == Some(value)
+        };
+        let found = match key {
+            EntityKey::Id(id) => data.get(entity.entity_type, *id),
+            EntityKey::Name(name) => data.find_by_name(entity, name),
+            EntityKey::Slug(slug) => data
+                .table(entity.entity_type)
+                .find(|record| column_is(record, "slug", slug)),
+        };
+        Ok(found
+            .and_then(|record| record.get("id")?.as_i64())
+            .map(|id| id as i32))
+    }
+
+    async fn insert_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+    ) -> Result<i32> {
+        let entity = entity_fields(topic_or_term).ok_or(sqlx::Error::RowNotFound)?;
+        let mut data = self.lock();
+        let mut fields = topic_or_term_fields(topic_or_term, payload);
+        data.check_unique_name(entity, &to_json(&payload.name), None)?;
+        let slug = data.slug_for(topic_or_term, &payload.name);
+        fields.insert("slug".to_owned(), to_json(slug));
+        Ok(data.insert(&self.feed, entity, fields))
+    }
+
+    async fn insert_source(&self, payload: &CreateSource) -> Result<i32> {
+        let mut data = self.lock();
+        let slug = data.slug_for("source", &payload.name);
+        Ok(data.insert(&self.feed, &SOURCE_FIELDS, source_record(payload, &slug)))
+    }
+
+    async fn update_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome> {
+        let entity = entity_fields(topic_or_term).ok_or(sqlx::Error::RowNotFound)?;
+        let fields = topic_or_term_fields(topic_or_term, payload);
+        self.lock()
+            .update(&self.feed, entity, id, expected_version, fields)
+    }
+
+    async fn update_source(
+        &self,
+        payload: &CreateSource,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome> {
+        let outcome = self.lock().update(
+            &self.feed,
+            &SOURCE_FIELDS,
+            id,
+            expected_version,
+            source_fields(payload),
+        )?;
+        // sources can't be verified
+        Ok(match outcome {
+            UpdateOutcome::Updated { .. } => UpdateOutcome::Updated { verified: false },
+            outcome => outcome,
+        })
+    }
+
+    async fn import_records(
+        &self,
+        entity: &EntityFields,
+        records: &[&ImportRecord],
+        update_existing: bool,
+    ) -> Result<Vec<ImportOutcome>> {
+        let mut data = self.lock();
+        Ok(records
+            .iter()
+            .map(|record| {
+                data.import(&self.feed, entity, record, update_existing)
+                    .unwrap_or_else(|error| ImportOutcome::Failed {
+                        error: error.to_string(),
+                    })
+            })
+            .collect())
+    }
+
+    /*
+    The archive is restored on a copy of the records and links, which replaces them when
+    the restore succeeded. Its events are only sent then, like on commit.
+     */
+    async fn restore_archive(
+        &self,
+        archive: &Archive,
+        on_conflict: OnConflict,
+    ) -> Result<RestoreReport> {
+        let mut data = self.lock();
+        let mut copy = MemoryData {
+            records: data.records.clone(),
+            links: data.links.clone(),
+            events: data.events.clone(),
+            ..MemoryData::default()
+        };
+        let mut writer = MemoryArchiveWriter {
+            data: &mut copy,
+            feed: &EventFeed::new(),
+        };
+        let report = restore(&mut writer, archive, on_conflict)
+            .now_or_never()
+            .expect("the memory writes don't wait")?;
+        for event in &copy.events[data.events.len()..] {
+            self.feed.send(FeedMessage::Event(Arc::new(event.clone())));
+        }
+        data.records = copy.records;
+        data.links = copy.links;
+        data.events = copy.events;
+        Ok(report)
+    }
+
+    async fn get_link_rows(&self, link_table: &ArchiveLinkTable) -> Result<Vec<(i32, i32)>> {
+        Ok(self
+            .lock()
+            .links
+            .get(link_table.table)
+            .into_iter()
+            .flat_map(|links| links.keys().copied())
+            .collect())
+    }
+
+    async fn insert_links(
+        &self,
+        link_table: &str,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        child_ids: &[i32],
+    ) -> Result<Vec<i32>> {
+        let Some(link_table) = link_tables().into_iter().find(|known| *known == link_table) else {
+            return Err(sqlx::Error::RowNotFound);
+        };
+        let (first_type, _) =
+            link_table_entity_types(link_table).ok_or(sqlx::Error::RowNotFound)?;
+        let mut data = self.lock();
+        let mut inserted = vec![];
+        for child_id in child_ids {
+            let (first_id, second_id) = if first_type == parent_type {
+                (parent_id, *child_id)
+            } else if first_type == child_type {
+                (*child_id, parent_id)
+            } else {
+                return Err(sqlx::Error::RowNotFound);
+            };
+            if data.link(&self.feed, link_table, first_id, second_id)? {
+                inserted.push(*child_id);
+            }
+        }
+        Ok(inserted)
+    }
+
+    // the events are broadcast as they are recorded
+    fn start_event_feed(&self) -> EventFeed {
+        self.feed.clone()
+    }
+
+    async fn get_last_event_id(&self) -> Result<i64> {
+        Ok(self.lock().events.last().map_or(0, |event| event.id))
+    }
+
+    async fn get_events_since(&self, after_id: i64, entity_types: &[String]) -> Result<Vec<Event>> {
+        Ok(self
+            .lock()
+            .events
+            .iter()
+            .filter(|event| event.id > after_id && event.matches(entity_types))
+            .take(EVENTS_PAGE_SIZE as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_webhook(&self, payload: &CreateWebhook) -> Result<NewWebhook> {
+        let mut data = self.lock();
+        let id = data
+            .webhooks
+            .keys()
+            .next_back()
+            .map_or(1, |last_id| last_id + 1);
+        let webhook = NewWebhook {
+            id,
+            url: payload.url.clone(),
+            secret: payload.secret.clone().unwrap_or_else(new_webhook_secret),
+            event_types: payload.event_types.clone().unwrap_or_default(),
+            created_at: Utc::now(),
+        };
+        data.webhooks.insert(id, webhook.clone());
+        Ok(webhook)
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        Ok(self
+            .lock()
+            .webhooks
+            .values()
+            .map(|webhook| Webhook {
+                id: webhook.id,
+                url: webhook.url.clone(),
+                event_types: webhook.event_types.clone(),
+                created_at: webhook.created_at,
+            })
+            .collect())
+    }
+
+    // the deliveries are deleted with the webhook, like ON DELETE CASCADE
+    async fn delete_webhook(&self, id: i32) -> Result<bool> {
+        let mut data = self.lock();
+        if data.webhooks.remove(&id).is_none() {
+            return Ok(false);
+        }
+        data.deliveries
+            .retain(|_, delivery| delivery.webhook_id != id);
+        Ok(true)
+    }
+
+    async fn webhook_exists(&self, id: i32) -> Result<bool> {
+        Ok(self.lock().webhooks.contains_key(&id))
+    }
+
+    async fn get_webhook_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        Ok(self
+            .lock()
+            .deliveries
+            .values()
+            .rev()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .filter(|delivery| status.is_none_or(|status| delivery.status == status))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_webhook_delivery(&self, id: i64) -> Result<Option<WebhookDelivery>> {
+        Ok(self.lock().deliveries.get(&id).cloned())
+    }
+
+    async fn queue_webhook_deliveries(&self, event_type: &str, data: &Value) -> Result<u64> {
+        let mut memory = self.lock();
+        let webhook_ids: Vec<i32> = memory
+            .webhooks
+            .values()
+            .filter(|webhook| {
+                webhook.event_types.is_empty()
+                    || webhook
+                        .event_types
+                        .iter()
+                        .any(|subscribed| subscribed == event_type)
+            })
+            .map(|webhook| webhook.id)
+            .collect();
+        for webhook_id in &webhook_ids {
+            memory.queue_delivery(*webhook_id, event_type, data.clone(), None);
+        }
+        Ok(webhook_ids.len() as u64)
+    }
+
+    async fn replay_webhook_delivery(
+        &self,
+        webhook_id: i32,
+        delivery_id: i64,
+    ) -> Result<Option<i64>> {
+        let mut data = self.lock();
+        let Some(delivery) = data
+            .deliveries
+            .get(&delivery_id)
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        Ok(Some(data.queue_delivery(
+            webhook_id,
+            &delivery.event_type,
+            delivery.data,
+            Some(delivery_id),
+        )))
+    }
+
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<DueDelivery>> {
+        let mut data = self.lock();
+        let now = Utc::now();
+        let mut due: Vec<(DateTime<Utc>, i64)> = data
+            .deliveries
+            .values()
+            .filter(|delivery| delivery.status == "pending" && delivery.next_attempt_at <= now)
+            .map(|delivery| (delivery.next_attempt_at, delivery.id))
+            .collect();
+        due.sort_unstable();
+
+        let lease = chrono::Duration::from_std(lease).unwrap_or(chrono::Duration::zero());
+        let mut claimed = vec![];
+        for (_, id) in due.into_iter().take(limit as usize) {
+            let Some(delivery) = data.deliveries.get_mut(&id) else {
+                continue;
+            };
+            delivery.next_attempt_at = now + lease;
+            let delivery = delivery.clone();
+            let Some(webhook) = data.webhooks.get(&delivery.webhook_id) else {
+                continue;
+            };
+            claimed.push(DueDelivery {
+                id: delivery.id,
+                event_type: delivery.event_type,
+                data: delivery.data,
+                attempts: delivery.attempts,
+                created_at: delivery.created_at,
+                url: webhook.url.clone(),
+                secret: webhook.secret.clone(),
+            });
+        }
+        Ok(claimed)
+    }
+
+    async fn record_webhook_delivered(&self, id: i64, status_code: Option<i32>) -> Result<()> {
+        if let Some(delivery) = self.lock().deliveries.get_mut(&id) {
+            delivery.status = "delivered".to_owned();
+            delivery.attempts += 1;
+            delivery.last_status_code = status_code;
+            delivery.last_error = None;
+            delivery.delivered_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn record_webhook_failure(
+        &self,
+        id: i64,
+        status_code: Option<i32>,
+        error: &str,
+        give_up: bool,
+        retry_delay: Duration,
+    ) -> Result<()> {
+        if let Some(delivery) = self.lock().deliveries.get_mut(&id) {
+            delivery.attempts += 1;
+            delivery.last_status_code = status_code;
+            delivery.last_error = Some(error.to_owned());
+            delivery.status = if give_up { "failed" } else { "pending" }.to_owned();
+            delivery.next_attempt_at = Utc::now()
+                + chrono::Duration::from_std(retry_delay).unwrap_or(chrono::Duration::zero());
+        }
+        Ok(())
+    }
+}
+
+// a constraint violation, with the SQLSTATE and message Postgres would return
+#[derive(Debug)]
+pub struct MemoryError {
+    code: &'static str,
+    message: String,
+    constraint: String,
+}
+
+impl MemoryError {
+    fn unique_violation(constraint: &str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(MemoryError {
+            code: "23505",
+            message: format!(
+                "duplicate key value violates unique constraint \"{}\"",
+                constraint
+            ),
+            constraint: constraint.to_owned(),
+        }))
+    }
+
+    fn foreign_key_violation(link_table: &str, entity_type: &str) -> sqlx::Error {
+        let constraint = format!("{}_{}_id_fkey", link_table, entity_type);
+        sqlx::Error::Database(Box::new(MemoryError {
+            code: "23503",
+            message: format!(
+                "insert or update on table \"{}\" violates foreign key constraint \"{}\"",
+                link_table, constraint
+            ),
+            constraint,
+        }))
+    }
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for MemoryError {}
+
+impl DatabaseError for MemoryError {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed(self.code))
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        Some(&self.constraint)
+    }
+}
@@ -0,0 +1,344 @@
+/*
+The storage behind the handlers, the GraphQL resolvers, the change feed and the webhooks.
+
+They get an Arc<dyn Repository> from the AppState instead of a connection pool, so the same
+router runs on Postgres (PgRepository, the default), SQLite (SqliteRepository, for a
+DATABASE_URL starting with sqlite:) or in memory (MemoryRepository, for the tests and `--mock`). Records are read as SparseRecords with the fields of their EntityFields,
+the handlers turn them into Topic, Term etc. with from_record.
+ */
+mod memory;
+mod postgres;
+mod sqlite;
+
+use crate::helpers::archive::{Archive, ArchiveLinkTable, OnConflict, RestoreReport};
+use crate::helpers::events::{Event, EventFeed};
+use crate::helpers::fieldsets::{EntityFields, Field, SparseRecord};
+use crate::helpers::handler_utils::{CreateTopicOrTerm, UpdateOutcome, LINK_TABLES};
+use crate::helpers::import::{ImportOutcome, ImportRecord};
+use crate::helpers::lookup::EntityKey;
+use crate::helpers::metrics::{DB_POOL_ACQUIRE_WAIT_SECONDS, DB_POOL_CONNECTIONS};
+use crate::helpers::shared_types::CreateSource;
+use crate::helpers::webhooks::{CreateWebhook, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sqlx::pool::PoolConnection;
+use sqlx::{Database, Pool, Result};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub use memory::MemoryRepository;
+pub use postgres::PgRepository;
+pub use sqlite::SqliteRepository;
+
+// articles have no REST endpoints to link them, so their bridge tables aren't in LINK_TABLES
+const ARTICLE_LINK_TABLES: [(&str, &str); 3] = [
+    ("topic", "articles_to_topics"),
+    ("term", "articles_to_terms"),
+    ("question", "articles_to_questions"),
+];
+
+// how the records of one entity type are related to the records of another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Relation {
+    // a bridge table with a <parent>_id and a <child>_id column
+    LinkTable(&'static str),
+    // the child has a <parent>_id column, e.g. a topic's questions
+    ChildColumn,
+    // the parent has a <child>_id column, e.g. a question's topic
+    ParentColumn,
+    // the questions of the topics linked through the bridge table, e.g. a term's questions
+    TopicQuestions(&'static str),
+}
+
+pub fn relation(parent_type: &str, child_type: &str) -> Option<Relation> {
+    match (parent_type, child_type) {
+        ("topic", "question") => Some(Relation::ChildColumn),
+        ("question", "topic") => Some(Relation::ParentColumn),
+        ("article", other_type) | (other_type, "article") => ARTICLE_LINK_TABLES
+            .iter()
+            .find(|(entity_type, _)| *entity_type == other_type)
+            .map(|(_, link_table)| Relation::LinkTable(link_table)),
+        (parent_type, "question") => LINK_TABLES
+            .get(parent_type)?
+            .get("topic")
+            .map(|link_table| Relation::TopicQuestions(link_table)),
+        (parent_type, child_type) => LINK_TABLES
+            .get(parent_type)?
+            .get(child_type)
+            .map(|link_table| Relation::LinkTable(link_table)),
+    }
+}
+
+// "terms_to_topics" -> ("term", "topic"), the entity types of a bridge table's two id columns
+pub fn link_table_entity_types(link_table: &str) -> Option<(&str, &str)> {
+    let (first, second) = link_table.split_once("_to_")?;
+    Some((first.strip_suffix('s')?, second.strip_suffix('s')?))
+}
+
+// like platform.new_webhook_secret in init.sql, two random uuids without the dashes
+fn new_webhook_secret() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+// the connection pool gauges served at /metrics, read without taking a connection so a
+// scrape doesn't compete with the handlers for one
+fn record_pool_metrics<DB: Database>(db_pool: &Pool<DB>) {
+    let idle = db_pool.num_idle() as i64;
+    let size = db_pool.size() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set(size - idle);
+}
+
+// a connection from the pool, the time spent waiting for it is recorded at /metrics
+async fn acquire<DB: Database>(db_pool: &Pool<DB>) -> Result<PoolConnection<DB>> {
+    let start = Instant::now();
+    let conn = db_pool.acquire().await;
+    DB_POOL_ACQUIRE_WAIT_SECONDS.observe(start.elapsed().as_secs_f64());
+    conn
+}
+
+// a record with all of its entity's fields -> its type, e.g. Topic
+pub fn from_record<T: DeserializeOwned>(record: SparseRecord) -> Result<T> {
+    serde_json::from_value(Value::Object(record)).map_err(|error| sqlx::Error::Decode(error.into()))
+}
+
+// every record updated at or after `updated_since` as its type, e.g. Topic
+pub async fn get_all<T: DeserializeOwned>(
+    repository: &dyn Repository,
+    entity: &EntityFields,
+    updated_since: Option<DateTime<Utc>>,
+) -> Result<Vec<T>> {
+    let records = repository
+        .get_records(entity, entity.fields, updated_since)
+        .await?;
+    records.into_iter().map(from_record).collect()
+}
+
+// same records as get_all, yielded as they are read
+pub fn stream_all<T: DeserializeOwned>(
+    repository: &dyn Repository,
+    entity: &'static EntityFields,
+    updated_since: Option<DateTime<Utc>>,
+) -> impl Stream<Item = Result<T>> {
+    repository
+        .stream_records(entity, entity.fields.to_vec(), updated_since)
+        .map(|record| record.and_then(from_record))
+}
+
+// a record as its type, RowNotFound when there is no record with the id
+pub async fn get_one<T: DeserializeOwned>(
+    repository: &dyn Repository,
+    entity: &EntityFields,
+    id: i32,
+) -> Result<T> {
+    let record = repository
+        .get_record(entity, entity.fields, id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    from_record(record)
+}
+
+#[async_trait]
+pub trait Repository: Send + Sync {
+    // the records updated at or after `updated_since`, ordered by id
+    async fn get_records(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SparseRecord>>;
+
+    // same records as get_records, yielded as they are read
+    fn stream_records(
+        &self,
+        entity: &'static EntityFields,
+        fields: Vec<Field>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>>;
+
+    async fn get_record(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        id: i32,
+    ) -> Result<Option<SparseRecord>>;
+
+    // a page of the records ordered by id
+    async fn get_records_page(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>>;
+
+    // parent id -> the related child records ordered by id, parents without any are left out
+    async fn get_related(
+        &self,
+        parent_type: &str,
+        parent_ids: &[i32],
+        child_type: &str,
+        fields: &[Field],
+    ) -> Result<HashMap<i32, Vec<SparseRecord>>>;
+
+    // one page of the child records related to a single parent, ordered by id
+    async fn get_related_page(
+        &self,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>>;
+
+    // the terms linked to the topic, updated or linked at or after `updated_since`
+    fn stream_terms_for_topic(
+        &self,
+        fields: Vec<Field>,
+        topic_id: i32,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>>;
+
+    // when a term was last linked to the topic
+    async fn get_terms_to_topic_last_modified(
+        &self,
+        topic_id: i32,
+    ) -> Result<Option<DateTime<Utc>>>;
+
+    async fn find_entity_id(
+        &self,
+        entity: &EntityFields,
+        key: &EntityKey<'_>,
+    ) -> Result<Option<i32>>;
+
+    // returns the new record's id, its slug is generated from the name
+    async fn insert_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+    ) -> Result<i32>;
+
+    async fn insert_source(&self, payload: &CreateSource) -> Result<i32>;
+
+    // only updates the record when its version is still `expected_version`, and bumps it
+    async fn update_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome>;
+
+    async fn update_source(
+        &self,
+        payload: &CreateSource,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome>;
+
+    /*
+    Writes one batch of an import (see helpers/import.rs) in a transaction, a record that
+    fails is rolled back on its own. A record whose name is taken, matched like
+    EntityKey::Name and for sources with the same url, is skipped unless `update_existing`.
+    Returns an outcome per record.
+     */
+    async fn import_records(
+        &self,
+        entity: &EntityFields,
+        records: &[&ImportRecord],
+        update_existing: bool,
+    ) -> Result<Vec<ImportOutcome>>;
+
+    /*
+    Writes an archive (see helpers/archive.rs) in one transaction, nothing is written when
+    any of it fails.
+     */
+    async fn restore_archive(
+        &self,
+        archive: &Archive,
+        on_conflict: OnConflict,
+    ) -> Result<RestoreReport>;
+
+    // every row of the table as (id of its first column, id of its second column), ordered
+    async fn get_link_rows(&self, link_table: &ArchiveLinkTable) -> Result<Vec<(i32, i32)>>;
+
+    // returns the child ids whose links are new, existing links are left as they are
+    async fn insert_links(
+        &self,
+        link_table: &str,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        child_ids: &[i32],
+    ) -> Result<Vec<i32>>;
+
+    // the feed every change made through this repository is broadcast on
+    fn start_event_feed(&self) -> EventFeed;
+
+    async fn get_last_event_id(&self) -> Result<i64>;
+
+    // a page of the events after `after_id` that match the entity types, ordered by id
+    async fn get_events_since(&self, after_id: i64, entity_types: &[String]) -> Result<Vec<Event>>;
+
+    async fn insert_webhook(&self, payload: &CreateWebhook) -> Result<NewWebhook>;
+
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>>;
+
+    // false when there is no such webhook
+    async fn delete_webhook(&self, id: i32) -> Result<bool>;
+
+    async fn webhook_exists(&self, id: i32) -> Result<bool>;
+
+    // a page of the webhook's deliveries, newest first
+    async fn get_webhook_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<WebhookDelivery>>;
+
+    async fn get_webhook_delivery(&self, id: i64) -> Result<Option<WebhookDelivery>>;
+
+    // a delivery for every webhook subscribed to the event type, returns how many
+    async fn queue_webhook_deliveries(&self, event_type: &str, data: &Value) -> Result<u64>;
+
+    // the delivery's payload as a new delivery, None when the webhook has no such delivery
+    async fn replay_webhook_delivery(
+        &self,
+        webhook_id: i32,
+        delivery_id: i64,
+    ) -> Result<Option<i64>>;
+
+    // the pending deliveries that are due, they aren't due again for `lease`
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<DueDelivery>>;
+
+    async fn record_webhook_delivered(&self, id: i64, status_code: Option<i32>) -> Result<()>;
+
+    // a failed attempt, the delivery is retried after `retry_delay` unless `give_up`
+    async fn record_webhook_failure(
+        &self,
+        id: i64,
+        status_code: Option<i32>,
+        error: &str,
+        give_up: bool,
+        retry_delay: Duration,
+    ) -> Result<()>;
+
+    // updates the connection pool gauges served at /metrics
+    async fn record_pool_metrics(&self) {}
+}
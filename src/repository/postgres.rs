@@ -0,0 +1,1138 @@
+/*
+The Postgres repository, the schema is in init.sql.
+
+Queries are built at runtime rather than with the query! macros, so the crate builds
+without a database. Table and column names only come from EntityFields, LINK_TABLES and
+ARTICLE_LINK_TABLES, never from a request.
+ */
+use super::{acquire, record_pool_metrics, relation, Relation, Repository};
+use crate::helpers::archive::{
+    field_value, matching_values, restore, Archive, ArchiveLinkTable, ArchiveWriter, FieldValue,
+    OnConflict, RestoreReport,
+};
+use crate::helpers::events::{Event, EventFeed, FeedMessage, EVENTS_PAGE_SIZE};
+use crate::helpers::fieldsets::{
+    entity_fields, select_list, EntityFields, Field, FieldKind, SparseRecord,
+};
+use crate::helpers::handler_utils::{process_optional_vec, CreateTopicOrTerm, UpdateOutcome};
+use crate::helpers::import::{ImportOutcome, ImportRecord};
+use crate::helpers::lookup::{slug_base, unique_slug, EntityKey};
+use crate::helpers::shared_types::{CreateSource, ImageType, MediaType};
+use crate::helpers::webhooks::{CreateWebhook, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+use axum::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{
+    postgres::{PgArguments, PgListener, PgRow},
+    query::Query,
+    Acquire, PgConnection, PgPool, Postgres, Result, Row,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const EVENTS_CHANNEL: &str = "platform_events";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct PgRepository {
+    db_pool: PgPool,
+}
+
+impl PgRepository {
+    pub fn new(db_pool: PgPool) -> Self {
+        PgRepository { db_pool }
+    }
+}
+
+/*
+The slug is read before the insert, so a concurrent insert of the same name can take it
+first. The inserts skip a row whose slug is taken (ON CONFLICT (slug) DO NOTHING) and
+try again with the next slug, `tried` are the slugs that were taken that way.
+ */
+async fn slug_for(
+    conn: &mut PgConnection,
+    entity_type: &str,
+    name: &str,
+    tried: &HashSet<String>,
+) -> Result<String> {
+    let base = slug_base(entity_type, name);
+    let query = format!(
+        "SELECT slug FROM platform.{}s WHERE slug = $1 OR slug LIKE $1 || '-%'",
+        entity_type
+    );
+    let mut taken: HashSet<String> = sqlx::query_scalar(&query)
+        .bind(&base)
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .collect();
+    taken.extend(tried.iter().cloned());
+    Ok(unique_slug(&base, &taken))
+}
+
+// called after an update matched no rows, to tell a stale version apart from a missing record
+#[tracing::instrument(skip(conn), err)]
+async fn get_update_conflict(
+    conn: &mut PgConnection,
+    entity_type: &str,
+    id: i32,
+) -> Result<UpdateOutcome> {
+    let query = format!(
+        "SELECT version FROM platform.{}s WHERE id = $1",
+        entity_type
+    );
+    let current_version: Option<i32> = sqlx::query_scalar(&query)
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    match current_version {
+        Some(current_version) => Ok(UpdateOutcome::VersionConflict { current_version }),
+        None => Ok(UpdateOutcome::NotFound),
+    }
+}
+
+// an exact match wins over other names that only differ in case
+fn find_by_name_query(entity: &EntityFields, columns: &str) -> String {
+    format!(
+        "SELECT {columns} FROM platform.{table} WHERE lower({column}) = lower($1)
+        ORDER BY {column} = $1 DESC, id LIMIT 1",
+        columns = columns,
+        table = entity.table,
+        column = entity.name_column
+    )
+}
+
+// sources don't have a unique name, the one with the name that has the url too
+fn find_source_query(columns: &str) -> String {
+    format!(
+        "SELECT {} FROM platform.sources WHERE lower(name) = lower($1) AND url IS NOT DISTINCT FROM $2
+        ORDER BY name = $1 DESC, id LIMIT 1",
+        columns
+    )
+}
+
+pub fn row_to_record(row: &PgRow, fields: &[Field]) -> Result<SparseRecord> {
+    let mut record = SparseRecord::new();
+    for (name, kind) in fields {
+        let value = match kind {
+            FieldKind::Int => to_json(row.try_get::<Option<i32>, _>(*name)?),
+            FieldKind::Text => to_json(row.try_get::<Option<String>, _>(*name)?),
+            FieldKind::Bool => to_json(row.try_get::<Option<bool>, _>(*name)?),
+            FieldKind::TextArray => to_json(row.try_get::<Option<Vec<String>>, _>(*name)?),
+            FieldKind::Date => to_json(row.try_get::<Option<NaiveDate>, _>(*name)?),
+            FieldKind::Timestamp => to_json(row.try_get::<Option<DateTime<Utc>>, _>(*name)?),
+            FieldKind::MediaType => to_json(row.try_get::<Option<MediaType>, _>(*name)?),
+            FieldKind::ImageType => to_json(row.try_get::<Option<ImageType>, _>(*name)?),
+        };
+        record.insert((*name).to_owned(), value);
+    }
+    Ok(record)
+}
+
+fn to_json<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn list_query(entity: &EntityFields, fields: &[Field]) -> String {
+    format!(
+        "SELECT {} FROM platform.{} as {}
+        WHERE $1::timestamptz IS NULL OR {}.updated_at >= $1
+        ORDER BY {}.id",
+        select_list(fields, entity.table),
+        entity.table,
+        entity.table,
+        entity.table,
+        entity.table
+    )
+}
+
+// the columns are selected with select_list(.., "terms")
+const TERMS_FOR_TOPIC_FROM: &str = "FROM platform.terms as terms
+    INNER JOIN platform.terms_to_topics as terms_to_topics on terms.id = terms_to_topics.term_id
+    WHERE terms_to_topics.topic_id = $1
+    AND ($2::timestamptz IS NULL
+        OR GREATEST(terms.updated_at, terms_to_topics.updated_at) >= $2)
+    ORDER BY terms.id";
+
+// the child rows related to any of the parent ids in $1, with the parent's id as parent_id
+fn related_query(parent_type: &str, child_type: &str, fields: &[Field]) -> Option<String> {
+    let child = entity_fields(child_type)?;
+    let columns = select_list(fields, child.table);
+    let query = match relation(parent_type, child_type)? {
+        Relation::ChildColumn => format!(
+            "SELECT {columns}, {table}.{parent_type}_id AS parent_id
+            FROM platform.{table} as {table}
+            WHERE {table}.{parent_type}_id = ANY($1)
+            ORDER BY {table}.id",
+            columns = columns,
+            table = child.table,
+            parent_type = parent_type
+        ),
+        Relation::ParentColumn => format!(
+            "SELECT {columns}, parents.id AS parent_id
+            FROM platform.{table} as {table}
+            INNER JOIN platform.{parent_type}s as parents on parents.{child_type}_id = {table}.id
+            WHERE parents.id = ANY($1)
+            ORDER BY {table}.id",
+            columns = columns,
+            table = child.table,
+            parent_type = parent_type,
+            child_type = child_type
+        ),
+        Relation::TopicQuestions(link_table) => format!(
+            "SELECT {columns}, links.{parent_type}_id AS parent_id
+            FROM platform.questions as questions
+            INNER JOIN platform.{link_table} as links on links.topic_id = questions.topic_id
+            WHERE links.{parent_type}_id = ANY($1)
+            ORDER BY questions.id",
+            columns = columns,
+            parent_type = parent_type,
+            link_table = link_table
+        ),
+        Relation::LinkTable(link_table) => format!(
+            "SELECT {columns}, links.{parent_type}_id AS parent_id
+            FROM platform.{table} as {table}
+            INNER JOIN platform.{link_table} as links on links.{child_type}_id = {table}.id
+            WHERE links.{parent_type}_id = ANY($1)
+            ORDER BY {table}.id",
+            columns = columns,
+            parent_type = parent_type,
+            child_type = child_type,
+            table = child.table,
+            link_table = link_table
+        ),
+    };
+    Some(query)
+}
+
+#[async_trait]
+impl Repository for PgRepository {
+    #[tracing::instrument(skip(self, entity, fields), fields(table = entity.table), err)]
+    async fn get_records(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SparseRecord>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(&list_query(entity, fields))
+            .bind(updated_since)
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter().map(|row| row_to_record(row, fields)).collect()
+    }
+
+    fn stream_records(
+        &self,
+        entity: &'static EntityFields,
+        fields: Vec<Field>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>> {
+        let db_pool = self.db_pool.clone();
+        async_stream::try_stream! {
+            let mut conn = acquire(&db_pool).await?;
+            let query = list_query(entity, &fields);
+            let mut rows = sqlx::query(&query).bind(updated_since).fetch(&mut conn);
+            while let Some(row) = rows.try_next().await? {
+                yield row_to_record(&row, &fields)?;
+            }
+        }
+        .boxed()
+    }
+
+    #[tracing::instrument(skip(self, entity, fields), fields(table = entity.table), err)]
+    async fn get_record(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        id: i32,
+    ) -> Result<Option<SparseRecord>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "SELECT {} FROM platform.{} as {} WHERE {}.id = $1",
+            select_list(fields, entity.table),
+            entity.table,
+            entity.table,
+            entity.table
+        );
+        let row = sqlx::query(&query)
+            .bind(id)
+            .fetch_optional(&mut conn)
+            .await?;
+        row.map(|row| row_to_record(&row, fields)).transpose()
+    }
+
+    async fn get_records_page(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "SELECT {} FROM platform.{} as {} ORDER BY {}.id LIMIT $1 OFFSET $2",
+            select_list(fields, entity.table),
+            entity.table,
+            entity.table,
+            entity.table
+        );
+        let rows = sqlx::query(&query)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter().map(|row| row_to_record(row, fields)).collect()
+    }
+
+    #[tracing::instrument(skip(self, parent_ids, fields), err)]
+    async fn get_related(
+        &self,
+        parent_type: &str,
+        parent_ids: &[i32],
+        child_type: &str,
+        fields: &[Field],
+    ) -> Result<HashMap<i32, Vec<SparseRecord>>> {
+        let Some(query) = related_query(parent_type, child_type, fields) else {
+            return Ok(HashMap::new());
+        };
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(&query)
+            .bind(parent_ids)
+            .fetch_all(&mut conn)
+            .await?;
+
+        let mut related: HashMap<i32, Vec<SparseRecord>> = HashMap::new();
+        for row in &rows {
+            let parent_id: i32 = row.try_get("parent_id")?;
+            related
+                .entry(parent_id)
+                .or_default()
+                .push(row_to_record(row, fields)?);
+        }
+        Ok(related)
+    }
+
+    #[tracing::instrument(skip(self, fields), err)]
+    async fn get_related_page(
+        &self,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>> {
+        let Some(query) = related_query(parent_type, child_type, fields) else {
+            return Ok(vec![]);
+        };
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(&format!("{} LIMIT $2 OFFSET $3", query))
+            .bind(&[parent_id][..])
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter().map(|row| row_to_record(row, fields)).collect()
+    }
+
+    fn stream_terms_for_topic(
+        &self,
+        fields: Vec<Field>,
+        topic_id: i32,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>> {
+        let db_pool = self.db_pool.clone();
+        async_stream::try_stream! {
+            let mut conn = acquire(&db_pool).await?;
+            let query = format!("SELECT {} {}", select_list(&fields, "terms"), TERMS_FOR_TOPIC_FROM);
+            let mut rows = sqlx::query(&query)
+                .bind(topic_id)
+                .bind(updated_since)
+                .fetch(&mut conn);
+            while let Some(row) = rows.try_next().await? {
+                yield row_to_record(&row, &fields)?;
+            }
+        }
+        .boxed()
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    async fn get_terms_to_topic_last_modified(
+        &self,
+        topic_id: i32,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar(
+            "SELECT max(updated_at) FROM platform.terms_to_topics WHERE topic_id = $1",
+        )
+        .bind(topic_id)
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    async fn find_entity_id(
+        &self,
+        entity: &EntityFields,
+        key: &EntityKey<'_>,
+    ) -> Result<Option<i32>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        match key {
+            EntityKey::Id(id) => {
+                let query = format!("SELECT id FROM platform.{} WHERE id = $1", entity.table);
+                sqlx::query_scalar(&query)
+                    .bind(id)
+                    .fetch_optional(&mut conn)
+                    .await
+            }
+            EntityKey::Name(name) => {
+                sqlx::query_scalar(&find_by_name_query(entity, "id"))
+                    .bind(name)
+                    .fetch_optional(&mut conn)
+                    .await
+            }
+            EntityKey::Slug(slug) => {
+                let query = format!("SELECT id FROM platform.{} WHERE slug = $1", entity.table);
+                sqlx::query_scalar(&query)
+                    .bind(slug)
+                    .fetch_optional(&mut conn)
+                    .await
+            }
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(entity_type = topic_or_term, entity_name = %payload.name), err)]
+    async fn insert_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+    ) -> Result<i32> {
+        let mut conn = acquire(&self.db_pool).await?;
+        insert_topic_or_term(&mut conn, payload, topic_or_term).await
+    }
+
+    #[tracing::instrument(skip_all, fields(entity_name = %payload.name), err)]
+    async fn insert_source(&self, payload: &CreateSource) -> Result<i32> {
+        let mut conn = acquire(&self.db_pool).await?;
+        insert_source(&mut conn, payload).await
+    }
+
+    #[tracing::instrument(skip(self, payload), err)]
+    async fn update_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome> {
+        let mut conn = acquire(&self.db_pool).await?;
+        update_topic_or_term(&mut conn, payload, topic_or_term, id, expected_version).await
+    }
+
+    #[tracing::instrument(skip(self, payload), err)]
+    async fn update_source(
+        &self,
+        payload: &CreateSource,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome> {
+        let mut conn = acquire(&self.db_pool).await?;
+        update_source(&mut conn, payload, id, expected_version).await
+    }
+
+    #[tracing::instrument(skip_all, fields(entity_type = entity.entity_type, records = records.len()), err)]
+    async fn import_records(
+        &self,
+        entity: &EntityFields,
+        records: &[&ImportRecord],
+        update_existing: bool,
+    ) -> Result<Vec<ImportOutcome>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let mut transaction = conn.begin().await?;
+        let mut outcomes = vec![];
+        for record in records {
+            // a savepoint, so a failed record only rolls back its own changes
+            let mut savepoint = transaction.begin().await?;
+            match import_record(&mut savepoint, entity, record, update_existing).await {
+                Ok(outcome) => {
+                    savepoint.commit().await?;
+                    outcomes.push(outcome);
+                }
+                Err(error) => {
+                    savepoint.rollback().await?;
+                    outcomes.push(ImportOutcome::Failed {
+                        error: error.to_string(),
+                    });
+                }
+            }
+        }
+        transaction.commit().await?;
+        Ok(outcomes)
+    }
+
+    #[tracing::instrument(skip_all, err)]
+    async fn restore_archive(
+        &self,
+        archive: &Archive,
+        on_conflict: OnConflict,
+    ) -> Result<RestoreReport> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let mut transaction = conn.begin().await?;
+        let mut writer = PgArchiveWriter {
+            conn: &mut transaction,
+        };
+        let report = restore(&mut writer, archive, on_conflict).await?;
+        transaction.commit().await?;
+        Ok(report)
+    }
+
+    async fn get_link_rows(&self, link_table: &ArchiveLinkTable) -> Result<Vec<(i32, i32)>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "SELECT {from}, {to} FROM platform.{table} ORDER BY {from}, {to}",
+            from = link_table.from_column,
+            to = link_table.to_column,
+            table = link_table.table
+        );
+        sqlx::query_as(&query).fetch_all(&mut conn).await
+    }
+
+    #[tracing::instrument(skip(self, child_ids), err)]
+    async fn insert_links(
+        &self,
+        link_table: &str,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        child_ids: &[i32],
+    ) -> Result<Vec<i32>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "INSERT INTO platform.{} ({}_id, {}_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            link_table, child_type, parent_type
+        );
+        let mut inserted = vec![];
+        for child_id in child_ids {
+            let result = sqlx::query(&query)
+                .bind(child_id)
+                .bind(parent_id)
+                .execute(&mut conn)
+                .await?;
+            // rows_affected is 0 when the link already existed
+            if result.rows_affected() > 0 {
+                inserted.push(*child_id);
+            }
+        }
+        Ok(inserted)
+    }
+
+    // listens in the background, the connection is retried until it succeeds
+    fn start_event_feed(&self) -> EventFeed {
+        let feed = EventFeed::new();
+        tokio::spawn(listen(self.db_pool.clone(), feed.clone()));
+        feed
+    }
+
+    async fn get_last_event_id(&self) -> Result<i64> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar("SELECT coalesce(max(id), 0) FROM platform.events")
+            .fetch_one(&mut conn)
+            .await
+    }
+
+    async fn get_events_since(&self, after_id: i64, entity_types: &[String]) -> Result<Vec<Event>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as(
+            "SELECT * FROM platform.events
+            WHERE id > $1
+                AND (cardinality($2::text[]) = 0 OR entity_type = ANY($2) OR related_type = ANY($2))
+            ORDER BY id
+            LIMIT $3",
+        )
+        .bind(after_id)
+        .bind(entity_types)
+        .bind(EVENTS_PAGE_SIZE)
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    async fn insert_webhook(&self, payload: &CreateWebhook) -> Result<NewWebhook> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as(
+            "INSERT INTO platform.webhooks (url, secret, event_types)
+            VALUES ($1, coalesce($2, platform.new_webhook_secret()), coalesce($3, '{}'))
+            RETURNING id, url, secret, event_types, created_at",
+        )
+        .bind(&payload.url)
+        .bind(&payload.secret)
+        .bind(&payload.event_types)
+        .fetch_one(&mut conn)
+        .await
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as("SELECT id, url, event_types, created_at FROM platform.webhooks ORDER BY id")
+            .fetch_all(&mut conn)
+            .await
+    }
+
+    async fn delete_webhook(&self, id: i32) -> Result<bool> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let result = sqlx::query("DELETE FROM platform.webhooks WHERE id = $1")
+            .bind(id)
+            .execute(&mut conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn webhook_exists(&self, id: i32) -> Result<bool> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM platform.webhooks WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&mut conn)
+            .await
+    }
+
+    async fn get_webhook_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as(
+            "SELECT * FROM platform.webhook_deliveries
+            WHERE webhook_id = $1 AND ($2::text IS NULL OR status = $2)
+            ORDER BY id DESC
+            LIMIT $3 OFFSET $4",
+        )
+        .bind(webhook_id)
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    async fn get_webhook_delivery(&self, id: i64) -> Result<Option<WebhookDelivery>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as("SELECT * FROM platform.webhook_deliveries WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&mut conn)
+            .await
+    }
+
+    async fn queue_webhook_deliveries(&self, event_type: &str, data: &Value) -> Result<u64> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let result = sqlx::query(
+            "INSERT INTO platform.webhook_deliveries (webhook_id, event_type, data)
+            SELECT id, $1, $2 FROM platform.webhooks
+            WHERE cardinality(event_types) = 0 OR $1 = ANY(event_types)",
+        )
+        .bind(event_type)
+        .bind(data)
+        .execute(&mut conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn replay_webhook_delivery(
+        &self,
+        webhook_id: i32,
+        delivery_id: i64,
+    ) -> Result<Option<i64>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar(
+            "INSERT INTO platform.webhook_deliveries (webhook_id, event_type, data, replay_of)
+            SELECT webhook_id, event_type, data, id FROM platform.webhook_deliveries
+            WHERE webhook_id = $1 AND id = $2
+            RETURNING id",
+        )
+        .bind(webhook_id)
+        .bind(delivery_id)
+        .fetch_optional(&mut conn)
+        .await
+    }
+
+    // FOR UPDATE SKIP LOCKED, so several instances can send from the same table
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<DueDelivery>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as(
+            "UPDATE platform.webhook_deliveries AS deliveries
+            SET next_attempt_at = now() + make_interval(secs => $2)
+            FROM platform.webhooks AS webhooks
+            WHERE webhooks.id = deliveries.webhook_id
+                AND deliveries.id IN (
+                    SELECT id FROM platform.webhook_deliveries
+                    WHERE status = 'pending' AND next_attempt_at <= now()
+                    ORDER BY next_attempt_at
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+            RETURNING deliveries.id, deliveries.event_type, deliveries.data, deliveries.attempts,
+                deliveries.created_at, webhooks.url, webhooks.secret",
+        )
+        .bind(limit)
+        .bind(lease.as_secs_f64())
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    async fn record_webhook_delivered(&self, id: i64, status_code: Option<i32>) -> Result<()> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query(
+            "UPDATE platform.webhook_deliveries
+            SET status = 'delivered', attempts = attempts + 1, last_status_code = $2,
+                last_error = NULL, delivered_at = now()
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .execute(&mut conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_webhook_failure(
+        &self,
+        id: i64,
+        status_code: Option<i32>,
+        error: &str,
+        give_up: bool,
+        retry_delay: Duration,
+    ) -> Result<()> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query(
+            "UPDATE platform.webhook_deliveries
+            SET attempts = attempts + 1, last_status_code = $2, last_error = $3,
+                status = CASE WHEN $4 THEN 'failed' ELSE 'pending' END,
+                next_attempt_at = now() + make_interval(secs => $5)
+            WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .bind(error)
+        .bind(give_up)
+        .bind(retry_delay.as_secs_f64())
+        .execute(&mut conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_pool_metrics(&self) {
+        record_pool_metrics(&self.db_pool)
+    }
+}
+
+// the writes take a connection, so that import_records can run them in its transaction
+async fn insert_topic_or_term(
+    conn: &mut PgConnection,
+    payload: &CreateTopicOrTerm,
+    topic_or_term: &str,
+) -> Result<i32> {
+    let bullet_points = process_optional_vec(&payload.bullet_points);
+    let examples = process_optional_vec(&payload.examples);
+    let parallels = process_optional_vec(&payload.parallels);
+    let ai_bullet_points = process_optional_vec(&payload.ai_bullet_points);
+    let ai_parallels = process_optional_vec(&payload.ai_parallels);
+    let ai_examples = process_optional_vec(&payload.ai_examples);
+
+    let query_string = format!("INSERT INTO platform.{}s ({}, is_verified, brief_description, full_description,
+        bullet_points, examples, parallels, ai_brief_description, ai_full_description, ai_bullet_points, ai_parallels,
+        ai_examples, slug) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        ON CONFLICT (slug) DO NOTHING RETURNING id", topic_or_term, topic_or_term);
+
+    let mut tried = HashSet::new();
+    loop {
+        let slug = slug_for(&mut *conn, topic_or_term, &payload.name, &tried).await?;
+        let id = sqlx::query_scalar(&query_string)
+            .bind(&payload.name)
+            .bind(payload.is_verified)
+            .bind(&payload.brief_description)
+            .bind(&payload.full_description)
+            .bind(bullet_points.as_slice())
+            .bind(examples.as_slice())
+            .bind(parallels.as_slice())
+            .bind(&payload.ai_brief_description)
+            .bind(&payload.ai_full_description)
+            .bind(ai_bullet_points.as_slice())
+            .bind(ai_parallels.as_slice())
+            .bind(ai_examples.as_slice())
+            .bind(&slug)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(id) = id {
+            return Ok(id);
+        }
+        tried.insert(slug);
+    }
+}
+
+async fn insert_source(conn: &mut PgConnection, payload: &CreateSource) -> Result<i32> {
+    let mut tried = HashSet::new();
+    loop {
+        let slug = slug_for(&mut *conn, "source", &payload.name, &tried).await?;
+        let id = sqlx::query_scalar(
+            "INSERT INTO platform.sources
+                (name, url, author, author_url, media_type, image_url, image_type, ai_generated, slug)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING id",
+        )
+        .bind(&payload.name)
+        .bind(&payload.url)
+        .bind(&payload.author)
+        .bind(&payload.author_url)
+        .bind(payload.media_type)
+        .bind(&payload.image_url)
+        .bind(payload.image_type)
+        .bind(payload.ai_generated)
+        .bind(&slug)
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(id) = id {
+            return Ok(id);
+        }
+        tried.insert(slug);
+    }
+}
+
+/*
+Only updates the row when its version still matches `expected_version`,
+the bump_version trigger then increments it.
+ */
+async fn update_topic_or_term(
+    conn: &mut PgConnection,
+    payload: &CreateTopicOrTerm,
+    topic_or_term: &str,
+    id: i32,
+    expected_version: i32,
+) -> Result<UpdateOutcome> {
+    let bullet_points = process_optional_vec(&payload.bullet_points);
+    let examples = process_optional_vec(&payload.examples);
+    let parallels = process_optional_vec(&payload.parallels);
+    let ai_bullet_points = process_optional_vec(&payload.ai_bullet_points);
+    let ai_parallels = process_optional_vec(&payload.ai_parallels);
+    let ai_examples = process_optional_vec(&payload.ai_examples);
+
+    // the subquery reads the row as it was before the update
+    let query_string = format!(
+        "UPDATE platform.{}s AS records SET {} = $1, is_verified = $2, brief_description = $3,
+        full_description = $4, bullet_points = $5, examples = $6, parallels = $7,
+        ai_brief_description = $8, ai_full_description = $9, ai_bullet_points = $10,
+        ai_parallels = $11, ai_examples = $12
+        FROM (SELECT is_verified AS was_verified FROM platform.{}s WHERE id = $13) AS previous
+        WHERE records.id = $13 AND records.version = $14
+        RETURNING records.is_verified AND NOT previous.was_verified",
+        topic_or_term, topic_or_term, topic_or_term
+    );
+
+    let verified: Option<bool> = sqlx::query_scalar(&query_string)
+        .bind(&payload.name)
+        .bind(payload.is_verified)
+        .bind(&payload.brief_description)
+        .bind(&payload.full_description)
+        .bind(bullet_points.as_slice())
+        .bind(examples.as_slice())
+        .bind(parallels.as_slice())
+        .bind(&payload.ai_brief_description)
+        .bind(&payload.ai_full_description)
+        .bind(ai_bullet_points.as_slice())
+        .bind(ai_parallels.as_slice())
+        .bind(ai_examples.as_slice())
+        .bind(id)
+        .bind(expected_version)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    if let Some(verified) = verified {
+        return Ok(UpdateOutcome::Updated { verified });
+    }
+    get_update_conflict(conn, topic_or_term, id).await
+}
+
+async fn update_source(
+    conn: &mut PgConnection,
+    payload: &CreateSource,
+    id: i32,
+    expected_version: i32,
+) -> Result<UpdateOutcome> {
+    let updated_row = sqlx::query(
+        "UPDATE platform.sources SET
+            name = $1, url = $2, author = $3, author_url = $4, media_type = $5,
+            image_url = $6, image_type = $7, ai_generated = $8
+        WHERE id = $9 AND version = $10
+        RETURNING version",
+    )
+    .bind(&payload.name)
+    .bind(&payload.url)
+    .bind(&payload.author)
+    .bind(&payload.author_url)
+    .bind(payload.media_type)
+    .bind(&payload.image_url)
+    .bind(payload.image_type)
+    .bind(payload.ai_generated)
+    .bind(id)
+    .bind(expected_version)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if updated_row.is_some() {
+        return Ok(UpdateOutcome::Updated { verified: false });
+    }
+    get_update_conflict(conn, "source", id).await
+}
+
+async fn import_record(
+    conn: &mut PgConnection,
+    entity: &EntityFields,
+    record: &ImportRecord,
+    update_existing: bool,
+) -> Result<ImportOutcome> {
+    let existing: Option<(i32, i32)> = match record {
+        ImportRecord::TopicOrTerm(_) => {
+            sqlx::query_as(&find_by_name_query(entity, "id, version"))
+                .bind(record.name())
+                .fetch_optional(&mut *conn)
+                .await?
+        }
+        ImportRecord::Source(payload) => {
+            sqlx::query_as(&find_source_query("id, version"))
+                .bind(&payload.name)
+                .bind(&payload.url)
+                .fetch_optional(&mut *conn)
+                .await?
+        }
+    };
+    let outcome = match (existing, record) {
+        (Some((id, _)), _) if !update_existing => ImportOutcome::Skipped { id },
+        (Some((id, version)), ImportRecord::TopicOrTerm(payload)) => {
+            let outcome =
+                update_topic_or_term(conn, payload, entity.entity_type, id, version).await?;
+            ImportOutcome::from_update(id, outcome)
+        }
+        (Some((id, version)), ImportRecord::Source(payload)) => {
+            ImportOutcome::from_update(id, update_source(conn, payload, id, version).await?)
+        }
+        (None, ImportRecord::TopicOrTerm(payload)) => ImportOutcome::Created {
+            id: insert_topic_or_term(conn, payload, entity.entity_type).await?,
+        },
+        (None, ImportRecord::Source(payload)) => ImportOutcome::Created {
+            id: insert_source(conn, payload).await?,
+        },
+    };
+    Ok(outcome)
+}
+
+// the writes of restore_archive, see helpers/archive.rs
+struct PgArchiveWriter<'c> {
+    conn: &'c mut PgConnection,
+}
+
+fn bind_value(
+    query: Query<'_, Postgres, PgArguments>,
+    value: FieldValue,
+) -> Query<'_, Postgres, PgArguments> {
+    match value {
+        FieldValue::Int(value) => query.bind(value),
+        FieldValue::Text(value) => query.bind(value),
+        FieldValue::Bool(value) => query.bind(value),
+        FieldValue::TextArray(value) => query.bind(value),
+        FieldValue::Date(value) => query.bind(value),
+        FieldValue::Timestamp(value) => query.bind(value),
+        FieldValue::MediaType(value) => query.bind(value),
+        FieldValue::ImageType(value) => query.bind(value),
+    }
+}
+
+// the columns of the entity that are in `values`, with their values
+fn column_values(
+    entity: &EntityFields,
+    values: &SparseRecord,
+) -> Result<(Vec<&'static str>, Vec<FieldValue>)> {
+    let mut columns = vec![];
+    let mut column_values = vec![];
+    for (name, kind) in entity.fields {
+        let Some(value) = values.get(*name) else {
+            continue;
+        };
+        let value = field_value(*kind, value).ok_or_else(|| {
+            sqlx::Error::Decode(format!("{} isn't a valid {:?}", name, kind).into())
+        })?;
+        columns.push(*name);
+        column_values.push(value);
+    }
+    Ok((columns, column_values))
+}
+
+#[async_trait]
+impl ArchiveWriter for PgArchiveWriter<'_> {
+    async fn find_id(&mut self, entity: &EntityFields, name: &str) -> Result<Option<i32>> {
+        sqlx::query_scalar(&find_by_name_query(entity, "id"))
+            .bind(name)
+            .fetch_optional(&mut *self.conn)
+            .await
+    }
+
+    async fn find_matching_id(
+        &mut self,
+        entity: &EntityFields,
+        values: &SparseRecord,
+        columns: &[&str],
+    ) -> Result<Option<i32>> {
+        let (columns, column_values) =
+            column_values(entity, &matching_values(entity, values, columns))?;
+        let conditions: Vec<String> = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| format!("{} IS NOT DISTINCT FROM ${}", column, index + 1))
+            .collect();
+        let query = format!(
+            "SELECT id FROM platform.{} WHERE {} ORDER BY id LIMIT 1",
+            entity.table,
+            conditions.join(" AND ")
+        );
+        let mut select = sqlx::query(&query);
+        for value in column_values {
+            select = bind_value(select, value);
+        }
+        select
+            .fetch_optional(&mut *self.conn)
+            .await?
+            .map(|row| row.try_get("id"))
+            .transpose()
+    }
+
+    async fn insert_record(&mut self, entity: &EntityFields, values: &SparseRecord) -> Result<i32> {
+        let has_slug = entity.fields.iter().any(|(name, _)| *name == "slug");
+        let mut tried = HashSet::new();
+        loop {
+            let (mut columns, mut column_values) = column_values(entity, values)?;
+            let mut slug = None;
+            if has_slug {
+                let name = values
+                    .get(entity.name_column)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let next = slug_for(&mut *self.conn, entity.entity_type, name, &tried).await?;
+                columns.push("slug");
+                column_values.push(FieldValue::Text(Some(next.clone())));
+                slug = Some(next);
+            }
+            let query = if columns.is_empty() {
+                format!(
+                    "INSERT INTO platform.{} DEFAULT VALUES RETURNING id",
+                    entity.table
+                )
+            } else {
+                let placeholders: Vec<String> = (1..=columns.len())
+                    .map(|index| format!("${}", index))
+                    .collect();
+                format!(
+                    "INSERT INTO platform.{} ({}) VALUES ({}){} RETURNING id",
+                    entity.table,
+                    columns.join(", "),
+                    placeholders.join(", "),
+                    if has_slug {
+                        " ON CONFLICT (slug) DO NOTHING"
+                    } else {
+                        ""
+                    }
+                )
+            };
+            let mut insert = sqlx::query(&query);
+            for value in column_values {
+                insert = bind_value(insert, value);
+            }
+            match (insert.fetch_optional(&mut *self.conn).await?, slug) {
+                (Some(row), _) => return row.try_get("id"),
+                (None, Some(slug)) => {
+                    tried.insert(slug);
+                }
+                (None, None) => return Err(sqlx::Error::RowNotFound),
+            }
+        }
+    }
+
+    async fn update_record(
+        &mut self,
+        entity: &EntityFields,
+        id: i32,
+        values: &SparseRecord,
+    ) -> Result<()> {
+        let (columns, column_values) = column_values(entity, values)?;
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let assignments: Vec<String> = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| format!("{} = ${}", column, index + 1))
+            .collect();
+        let query = format!(
+            "UPDATE platform.{} SET {} WHERE id = ${}",
+            entity.table,
+            assignments.join(", "),
+            columns.len() + 1
+        );
+        let mut update = sqlx::query(&query);
+        for value in column_values {
+            update = bind_value(update, value);
+        }
+        update.bind(id).execute(&mut *self.conn).await?;
+        Ok(())
+    }
+
+    async fn insert_link(
+        &mut self,
+        link_table: &ArchiveLinkTable,
+        from_id: i32,
+        to_id: i32,
+    ) -> Result<bool> {
+        let query = format!(
+            "INSERT INTO platform.{} ({}, {}) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            link_table.table, link_table.from_column, link_table.to_column
+        );
+        let result = sqlx::query(&query)
+            .bind(from_id)
+            .bind(to_id)
+            .execute(&mut *self.conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+async fn listen(db_pool: PgPool, feed: EventFeed) {
+    loop {
+        if let Err(error) = forward_notifications(&db_pool, &feed).await {
+            tracing::error!(%error, "listening for events failed");
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// returns when the connection is lost
+async fn forward_notifications(db_pool: &PgPool, feed: &EventFeed) -> Result<()> {
+    let mut listener = PgListener::connect_with(db_pool).await?;
+    listener.listen(EVENTS_CHANNEL).await?;
+    feed.send(FeedMessage::Resync);
+
+    while let Some(notification) = listener.try_recv().await? {
+        match serde_json::from_str::<Event>(notification.payload()) {
+            Ok(event) => feed.send(FeedMessage::Event(Arc::new(event))),
+            Err(error) => tracing::error!(%error, "unexpected event notification"),
+        }
+    }
+    tracing::error!("the events connection was lost");
+    Ok(())
+}
@@ -0,0 +1,1324 @@
+/*
+The SQLite repository, for running the API on a laptop without Postgres. The schema is in
+init_sqlite.sql, it is created with the sample data when the database has no tables yet.
+
+The queries are the ones of PgRepository with SQLite's types: text[] columns and webhook
+payloads are JSON text, lists of ids are bound as a JSON array and read with json_each,
+and timestamps are text in the format of `timestamp` below, so they compare as text.
+SQLite has no LISTEN/NOTIFY, the events recorded by the triggers are polled instead.
+
+Writes with RETURNING use fetch_all: their change is only committed once the statement is
+done, which fetch_one and fetch_optional don't wait for, so a read on another connection
+right after them could miss it.
+ */
+use super::{acquire, new_webhook_secret, record_pool_metrics, relation, Relation, Repository};
+use crate::helpers::archive::{
+    field_value, matching_values, restore, Archive, ArchiveLinkTable, ArchiveWriter, FieldValue,
+    OnConflict, RestoreReport,
+};
+use crate::helpers::events::{Event, EventFeed, FeedMessage, EVENTS_PAGE_SIZE};
+use crate::helpers::fieldsets::{
+    entity_fields, select_list, EntityFields, Field, FieldKind, SparseRecord,
+};
+use crate::helpers::handler_utils::{process_optional_vec, CreateTopicOrTerm, UpdateOutcome};
+use crate::helpers::import::{ImportOutcome, ImportRecord};
+use crate::helpers::lookup::{slug_base, unique_slug, EntityKey};
+use crate::helpers::shared_types::{CreateSource, ImageType, MediaType};
+use crate::helpers::webhooks::{CreateWebhook, DueDelivery, NewWebhook, Webhook, WebhookDelivery};
+use axum::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{
+    query::Query,
+    sqlite::{
+        SqliteArguments, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow,
+    },
+    Acquire, Executor, Result, Row, Sqlite, SqliteConnection, SqlitePool,
+};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const INIT_SQL: &str = include_str!("../../database/init_sqlite.sql");
+
+// how often the events table is read for changes made outside this server, e.g. in sqlite3
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+#[derive(Clone)]
+pub struct SqliteRepository {
+    db_pool: SqlitePool,
+    // notified after every change made through this repository, so its events are sent
+    // without waiting for the next poll
+    changed: Arc<Notify>,
+}
+
+impl SqliteRepository {
+    /*
+    Opens the database at a sqlite: URL, e.g. sqlite://platform.db or sqlite::memory:, and
+    creates it from init_sqlite.sql when it has no tables.
+     */
+    pub async fn connect(db_uri: &str) -> Result<Self> {
+        let in_memory = db_uri.contains(":memory:") || db_uri.contains("mode=memory");
+        let mut options = SqliteConnectOptions::from_str(db_uri)?.create_if_missing(true);
+        // readers don't block the writer, and the other way around
+        if !in_memory {
+            options = options.journal_mode(SqliteJournalMode::Wal);
+        }
+        // an in-memory database is gone when its last connection closes
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+
+        let has_schema: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'topics')",
+        )
+        .fetch_one(&db_pool)
+        .await?;
+        if !has_schema {
+            tracing::info!("creating the schema and sample data of init_sqlite.sql");
+            let mut transaction = db_pool.begin().await?;
+            transaction.execute(INIT_SQL).await?;
+            transaction.commit().await?;
+        }
+
+        Ok(SqliteRepository {
+            db_pool,
+            changed: Arc::new(Notify::new()),
+        })
+    }
+}
+
+/*
+The slug is read before the insert, so a concurrent insert of the same name can take it
+first. The inserts skip a row whose slug is taken (ON CONFLICT (slug) DO NOTHING) and
+try again with the next slug, `tried` are the slugs that were taken that way.
+ */
+async fn slug_for(
+    conn: &mut SqliteConnection,
+    entity_type: &str,
+    name: &str,
+    tried: &HashSet<String>,
+) -> Result<String> {
+    let base = slug_base(entity_type, name);
+    let query = format!(
+        "SELECT slug FROM {}s WHERE slug = ?1 OR slug LIKE ?1 || '-%'",
+        entity_type
+    );
+    let mut taken: HashSet<String> = sqlx::query_scalar(&query)
+        .bind(&base)
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .collect();
+    taken.extend(tried.iter().cloned());
+    Ok(unique_slug(&base, &taken))
+}
+
+// called after an update matched no rows, to tell a stale version apart from a missing record
+#[tracing::instrument(skip(conn), err)]
+async fn get_update_conflict(
+    conn: &mut SqliteConnection,
+    entity_type: &str,
+    id: i32,
+) -> Result<UpdateOutcome> {
+    let query = format!("SELECT version FROM {}s WHERE id = ?1", entity_type);
+    let current_version: Option<i32> = sqlx::query_scalar(&query)
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    match current_version {
+        Some(current_version) => Ok(UpdateOutcome::VersionConflict { current_version }),
+        None => Ok(UpdateOutcome::NotFound),
+    }
+}
+
+// an exact match wins over other names that only differ in case
+fn find_by_name_query(entity: &EntityFields, columns: &str) -> String {
+    format!(
+        "SELECT {columns} FROM {table} WHERE lower({column}) = lower(?1)
+        ORDER BY {column} = ?1 DESC, id LIMIT 1",
+        columns = columns,
+        table = entity.table,
+        column = entity.name_column
+    )
+}
+
+// sources don't have a unique name, the one with the name that has the url too
+fn find_source_query(columns: &str) -> String {
+    format!(
+        "SELECT {} FROM sources WHERE lower(name) = lower(?1) AND url IS ?2
+        ORDER BY name = ?1 DESC, id LIMIT 1",
+        columns
+    )
+}
+
+// the format of the timestamp columns, millisecond precision like strftime's %f
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+// a modifier for strftime, e.g. strftime(.., 'now', '+30.000 seconds')
+fn seconds_from_now(delay: Duration) -> String {
+    format!("+{:.3} seconds", delay.as_secs_f64())
+}
+
+fn json_text<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "null".to_owned())
+}
+
+// a JSON text column
+fn try_get_json<T: DeserializeOwned>(row: &SqliteRow, name: &str) -> Result<T> {
+    let text: String = row.try_get(name)?;
+    serde_json::from_str(&text).map_err(|error| sqlx::Error::ColumnDecode {
+        index: name.to_owned(),
+        source: error.into(),
+    })
+}
+
+pub fn row_to_record(row: &SqliteRow, fields: &[Field]) -> Result<SparseRecord> {
+    let mut record = SparseRecord::new();
+    for (name, kind) in fields {
+        let value = match kind {
+            FieldKind::Int => to_json(row.try_get::<Option<i32>, _>(*name)?),
+            FieldKind::Text => to_json(row.try_get::<Option<String>, _>(*name)?),
+            FieldKind::Bool => to_json(row.try_get::<Option<bool>, _>(*name)?),
+            FieldKind::TextArray => match row.try_get::<Option<String>, _>(*name)? {
+                Some(_) => to_json(try_get_json::<Vec<String>>(row, name)?),
+                None => Value::Null,
+            },
+            FieldKind::Date => to_json(row.try_get::<Option<NaiveDate>, _>(*name)?),
+            FieldKind::Timestamp => to_json(row.try_get::<Option<DateTime<Utc>>, _>(*name)?),
+            FieldKind::MediaType => to_json(row.try_get::<Option<MediaType>, _>(*name)?),
+            FieldKind::ImageType => to_json(row.try_get::<Option<ImageType>, _>(*name)?),
+        };
+        record.insert((*name).to_owned(), value);
+    }
+    Ok(record)
+}
+
+fn to_json<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn webhook_from_row(row: &SqliteRow) -> Result<NewWebhook> {
+    Ok(NewWebhook {
+        id: row.try_get("id")?,
+        url: row.try_get("url")?,
+        secret: row.try_get("secret")?,
+        event_types: try_get_json(row, "event_types")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn delivery_from_row(row: &SqliteRow) -> Result<WebhookDelivery> {
+    Ok(WebhookDelivery {
+        id: row.try_get("id")?,
+        webhook_id: row.try_get("webhook_id")?,
+        event_type: row.try_get("event_type")?,
+        data: try_get_json(row, "data")?,
+        status: row.try_get("status")?,
+        attempts: row.try_get("attempts")?,
+        next_attempt_at: row.try_get("next_attempt_at")?,
+        last_status_code: row.try_get("last_status_code")?,
+        last_error: row.try_get("last_error")?,
+        replay_of: row.try_get("replay_of")?,
+        created_at: row.try_get("created_at")?,
+        delivered_at: row.try_get("delivered_at")?,
+    })
+}
+
+fn list_query(entity: &EntityFields, fields: &[Field]) -> String {
+    format!(
+        "SELECT {} FROM {} as {}
+        WHERE ?1 IS NULL OR {}.updated_at >= ?1
+        ORDER BY {}.id",
+        select_list(fields, entity.table),
+        entity.table,
+        entity.table,
+        entity.table,
+        entity.table
+    )
+}
+
+// the columns are selected with select_list(.., "terms")
+const TERMS_FOR_TOPIC_FROM: &str = "FROM terms as terms
+    INNER JOIN terms_to_topics as terms_to_topics on terms.id = terms_to_topics.term_id
+    WHERE terms_to_topics.topic_id = ?1
+    AND (?2 IS NULL OR max(terms.updated_at, terms_to_topics.updated_at) >= ?2)
+    ORDER BY terms.id";
+
+// the child rows related to any of the parent ids in the JSON array ?1, with the parent's id as parent_id
+fn related_query(parent_type: &str, child_type: &str, fields: &[Field]) -> Option<String> {
+    let child = entity_fields(child_type)?;
+    let columns = select_list(fields, child.table);
+    let query = match relation(parent_type, child_type)? {
+        Relation::ChildColumn => format!(
+            "SELECT {columns}, {table}.{parent_type}_id AS parent_id
+            FROM {table} as {table}
+            WHERE {table}.{parent_type}_id IN (SELECT value FROM json_each(?1))
+            ORDER BY {table}.id",
+            columns = columns,
+            table = child.table,
+            parent_type = parent_type
+        ),
+        Relation::ParentColumn => format!(
+            "SELECT {columns}, parents.id AS parent_id
+            FROM {table} as {table}
+            INNER JOIN {parent_type}s as parents on parents.{child_type}_id = {table}.id
+            WHERE parents.id IN (SELECT value FROM json_each(?1))
+            ORDER BY {table}.id",
+            columns = columns,
+            table = child.table,
+            parent_type = parent_type,
+            child_type = child_type
+        ),
+        Relation::TopicQuestions(link_table) => format!(
+            "SELECT {columns}, links.{parent_type}_id AS parent_id
+            FROM questions as questions
+            INNER JOIN {link_table} as links on links.topic_id = questions.topic_id
+            WHERE links.{parent_type}_id IN (SELECT value FROM json_each(?1))
+            ORDER BY questions.id",
+            columns = columns,
+            parent_type = parent_type,
+            link_table = link_table
+        ),
+        Relation::LinkTable(link_table) => format!(
+            "SELECT {columns}, links.{parent_type}_id AS parent_id
+            FROM {table} as {table}
+            INNER JOIN {link_table} as links on links.{child_type}_id = {table}.id
+            WHERE links.{parent_type}_id IN (SELECT value FROM json_each(?1))
+            ORDER BY {table}.id",
+            columns = columns,
+            parent_type = parent_type,
+            child_type = child_type,
+            table = child.table,
+            link_table = link_table
+        ),
+    };
+    Some(query)
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    #[tracing::instrument(skip(self, entity, fields), fields(table = entity.table), err)]
+    async fn get_records(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        updated_since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<SparseRecord>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(&list_query(entity, fields))
+            .bind(updated_since.map(timestamp))
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter().map(|row| row_to_record(row, fields)).collect()
+    }
+
+    fn stream_records(
+        &self,
+        entity: &'static EntityFields,
+        fields: Vec<Field>,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>> {
+        let db_pool = self.db_pool.clone();
+        async_stream::try_stream! {
+            let mut conn = acquire(&db_pool).await?;
+            let query = list_query(entity, &fields);
+            let mut rows = sqlx::query(&query)
+                .bind(updated_since.map(timestamp))
+                .fetch(&mut conn);
+            while let Some(row) = rows.try_next().await? {
+                yield row_to_record(&row, &fields)?;
+            }
+        }
+        .boxed()
+    }
+
+    #[tracing::instrument(skip(self, entity, fields), fields(table = entity.table), err)]
+    async fn get_record(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        id: i32,
+    ) -> Result<Option<SparseRecord>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "SELECT {} FROM {} as {} WHERE {}.id = ?1",
+            select_list(fields, entity.table),
+            entity.table,
+            entity.table,
+            entity.table
+        );
+        let row = sqlx::query(&query)
+            .bind(id)
+            .fetch_optional(&mut conn)
+            .await?;
+        row.map(|row| row_to_record(&row, fields)).transpose()
+    }
+
+    async fn get_records_page(
+        &self,
+        entity: &EntityFields,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "SELECT {} FROM {} as {} ORDER BY {}.id LIMIT ?1 OFFSET ?2",
+            select_list(fields, entity.table),
+            entity.table,
+            entity.table,
+            entity.table
+        );
+        let rows = sqlx::query(&query)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter().map(|row| row_to_record(row, fields)).collect()
+    }
+
+    #[tracing::instrument(skip(self, parent_ids, fields), err)]
+    async fn get_related(
+        &self,
+        parent_type: &str,
+        parent_ids: &[i32],
+        child_type: &str,
+        fields: &[Field],
+    ) -> Result<HashMap<i32, Vec<SparseRecord>>> {
+        let Some(query) = related_query(parent_type, child_type, fields) else {
+            return Ok(HashMap::new());
+        };
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(&query)
+            .bind(json_text(&parent_ids))
+            .fetch_all(&mut conn)
+            .await?;
+
+        let mut related: HashMap<i32, Vec<SparseRecord>> = HashMap::new();
+        for row in &rows {
+            let parent_id: i32 = row.try_get("parent_id")?;
+            related
+                .entry(parent_id)
+                .or_default()
+                .push(row_to_record(row, fields)?);
+        }
+        Ok(related)
+    }
+
+    #[tracing::instrument(skip(self, fields), err)]
+    async fn get_related_page(
+        &self,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        fields: &[Field],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<SparseRecord>> {
+        let Some(query) = related_query(parent_type, child_type, fields) else {
+            return Ok(vec![]);
+        };
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(&format!("{} LIMIT ?2 OFFSET ?3", query))
+            .bind(json_text(&[parent_id]))
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter().map(|row| row_to_record(row, fields)).collect()
+    }
+
+    fn stream_terms_for_topic(
+        &self,
+        fields: Vec<Field>,
+        topic_id: i32,
+        updated_since: Option<DateTime<Utc>>,
+    ) -> BoxStream<'static, Result<SparseRecord>> {
+        let db_pool = self.db_pool.clone();
+        async_stream::try_stream! {
+            let mut conn = acquire(&db_pool).await?;
+            let query = format!("SELECT {} {}", select_list(&fields, "terms"), TERMS_FOR_TOPIC_FROM);
+            let mut rows = sqlx::query(&query)
+                .bind(topic_id)
+                .bind(updated_since.map(timestamp))
+                .fetch(&mut conn);
+            while let Some(row) = rows.try_next().await? {
+                yield row_to_record(&row, &fields)?;
+            }
+        }
+        .boxed()
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    async fn get_terms_to_topic_last_modified(
+        &self,
+        topic_id: i32,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar("SELECT max(updated_at) FROM terms_to_topics WHERE topic_id = ?1")
+            .bind(topic_id)
+            .fetch_one(&mut conn)
+            .await
+    }
+
+    async fn find_entity_id(
+        &self,
+        entity: &EntityFields,
+        key: &EntityKey<'_>,
+    ) -> Result<Option<i32>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        match key {
+            EntityKey::Id(id) => {
+                let query = format!("SELECT id FROM {} WHERE id = ?1", entity.table);
+                sqlx::query_scalar(&query)
+                    .bind(id)
+                    .fetch_optional(&mut conn)
+                    .await
+            }
+            EntityKey::Name(name) => {
+                sqlx::query_scalar(&find_by_name_query(entity, "id"))
+                    .bind(name)
+                    .fetch_optional(&mut conn)
+                    .await
+            }
+            EntityKey::Slug(slug) => {
+                let query = format!("SELECT id FROM {} WHERE slug = ?1", entity.table);
+                sqlx::query_scalar(&query)
+                    .bind(slug)
+                    .fetch_optional(&mut conn)
+                    .await
+            }
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(entity_type = topic_or_term, entity_name = %payload.name), err)]
+    async fn insert_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+    ) -> Result<i32> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let id = insert_topic_or_term(&mut conn, payload, topic_or_term).await?;
+        self.changed.notify_one();
+        Ok(id)
+    }
+
+    #[tracing::instrument(skip_all, fields(entity_name = %payload.name), err)]
+    async fn insert_source(&self, payload: &CreateSource) -> Result<i32> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let id = insert_source(&mut conn, payload).await?;
+        self.changed.notify_one();
+        Ok(id)
+    }
+
+    #[tracing::instrument(skip(self, payload), err)]
+    async fn update_topic_or_term(
+        &self,
+        payload: &CreateTopicOrTerm,
+        topic_or_term: &str,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let outcome =
+            update_topic_or_term(&mut conn, payload, topic_or_term, id, expected_version).await?;
+        if let UpdateOutcome::Updated { .. } = outcome {
+            self.changed.notify_one();
+        }
+        Ok(outcome)
+    }
+
+    #[tracing::instrument(skip(self, payload), err)]
+    async fn update_source(
+        &self,
+        payload: &CreateSource,
+        id: i32,
+        expected_version: i32,
+    ) -> Result<UpdateOutcome> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let outcome = update_source(&mut conn, payload, id, expected_version).await?;
+        if let UpdateOutcome::Updated { .. } = outcome {
+            self.changed.notify_one();
+        }
+        Ok(outcome)
+    }
+
+    #[tracing::instrument(skip_all, fields(entity_type = entity.entity_type, records = records.len()), err)]
+    async fn import_records(
+        &self,
+        entity: &EntityFields,
+        records: &[&ImportRecord],
+        update_existing: bool,
+    ) -> Result<Vec<ImportOutcome>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let mut transaction = conn.begin().await?;
+        let mut outcomes = vec![];
+        for record in records {
+            // a savepoint, so a failed record only rolls back its own changes
+            let mut savepoint = transaction.begin().await?;
+            match import_record(&mut savepoint, entity, record, update_existing).await {
+                Ok(outcome) => {
+                    savepoint.commit().await?;
+                    outcomes.push(outcome);
+                }
+                Err(error) => {
+                    savepoint.rollback().await?;
+                    outcomes.push(ImportOutcome::Failed {
+                        error: error.to_string(),
+                    });
+                }
+            }
+        }
+        transaction.commit().await?;
+        self.changed.notify_one();
+        Ok(outcomes)
+    }
+
+    #[tracing::instrument(skip_all, err)]
+    async fn restore_archive(
+        &self,
+        archive: &Archive,
+        on_conflict: OnConflict,
+    ) -> Result<RestoreReport> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let mut transaction = conn.begin().await?;
+        let mut writer = SqliteArchiveWriter {
+            conn: &mut transaction,
+        };
+        let report = restore(&mut writer, archive, on_conflict).await?;
+        transaction.commit().await?;
+        self.changed.notify_one();
+        Ok(report)
+    }
+
+    async fn get_link_rows(&self, link_table: &ArchiveLinkTable) -> Result<Vec<(i32, i32)>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "SELECT {from}, {to} FROM {table} ORDER BY {from}, {to}",
+            from = link_table.from_column,
+            to = link_table.to_column,
+            table = link_table.table
+        );
+        sqlx::query_as(&query).fetch_all(&mut conn).await
+    }
+
+    #[tracing::instrument(skip(self, child_ids), err)]
+    async fn insert_links(
+        &self,
+        link_table: &str,
+        parent_type: &str,
+        parent_id: i32,
+        child_type: &str,
+        child_ids: &[i32],
+    ) -> Result<Vec<i32>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "INSERT INTO {} ({}_id, {}_id) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+            link_table, child_type, parent_type
+        );
+        let mut inserted = vec![];
+        for child_id in child_ids {
+            let result = sqlx::query(&query)
+                .bind(child_id)
+                .bind(parent_id)
+                .execute(&mut conn)
+                .await?;
+            // rows_affected is 0 when the link already existed
+            if result.rows_affected() > 0 {
+                inserted.push(*child_id);
+            }
+        }
+        if !inserted.is_empty() {
+            self.changed.notify_one();
+        }
+        Ok(inserted)
+    }
+
+    fn start_event_feed(&self) -> EventFeed {
+        let feed = EventFeed::new();
+        tokio::spawn(poll_events(
+            self.db_pool.clone(),
+            self.changed.clone(),
+            feed.clone(),
+        ));
+        feed
+    }
+
+    async fn get_last_event_id(&self) -> Result<i64> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar("SELECT coalesce(max(id), 0) FROM events")
+            .fetch_one(&mut conn)
+            .await
+    }
+
+    async fn get_events_since(&self, after_id: i64, entity_types: &[String]) -> Result<Vec<Event>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_as(
+            "SELECT * FROM events
+            WHERE id > ?1
+                AND (json_array_length(?2) = 0
+                    OR entity_type IN (SELECT value FROM json_each(?2))
+                    OR related_type IN (SELECT value FROM json_each(?2)))
+            ORDER BY id
+            LIMIT ?3",
+        )
+        .bind(after_id)
+        .bind(json_text(&entity_types))
+        .bind(EVENTS_PAGE_SIZE)
+        .fetch_all(&mut conn)
+        .await
+    }
+
+    async fn insert_webhook(&self, payload: &CreateWebhook) -> Result<NewWebhook> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let secret = payload.secret.clone().unwrap_or_else(new_webhook_secret);
+        let row = sqlx::query(
+            "INSERT INTO webhooks (url, secret, event_types)
+            VALUES (?1, ?2, ?3)
+            RETURNING id, url, secret, event_types, created_at",
+        )
+        .bind(&payload.url)
+        .bind(secret)
+        .bind(json_text(&payload.event_types.clone().unwrap_or_default()))
+        .fetch_all(&mut conn)
+        .await?
+        .pop()
+        .ok_or(sqlx::Error::RowNotFound)?;
+        webhook_from_row(&row)
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(
+            "SELECT id, url, secret, event_types, created_at FROM webhooks ORDER BY id",
+        )
+        .fetch_all(&mut conn)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                let webhook = webhook_from_row(row)?;
+                Ok(Webhook {
+                    id: webhook.id,
+                    url: webhook.url,
+                    event_types: webhook.event_types,
+                    created_at: webhook.created_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete_webhook(&self, id: i32) -> Result<bool> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = ?1")
+            .bind(id)
+            .execute(&mut conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn webhook_exists(&self, id: i32) -> Result<bool> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM webhooks WHERE id = ?1)")
+            .bind(id)
+            .fetch_one(&mut conn)
+            .await
+    }
+
+    async fn get_webhook_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let rows = sqlx::query(
+            "SELECT * FROM webhook_deliveries
+            WHERE webhook_id = ?1 AND (?2 IS NULL OR status = ?2)
+            ORDER BY id DESC
+            LIMIT ?3 OFFSET ?4",
+        )
+        .bind(webhook_id)
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&mut conn)
+        .await?;
+        rows.iter().map(delivery_from_row).collect()
+    }
+
+    async fn get_webhook_delivery(&self, id: i64) -> Result<Option<WebhookDelivery>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let row = sqlx::query("SELECT * FROM webhook_deliveries WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&mut conn)
+            .await?;
+        row.as_ref().map(delivery_from_row).transpose()
+    }
+
+    async fn queue_webhook_deliveries(&self, event_type: &str, data: &Value) -> Result<u64> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let result = sqlx::query(
+            "INSERT INTO webhook_deliveries (webhook_id, event_type, data)
+            SELECT id, ?1, ?2 FROM webhooks
+            WHERE json_array_length(event_types) = 0
+                OR ?1 IN (SELECT value FROM json_each(webhooks.event_types))",
+        )
+        .bind(event_type)
+        .bind(json_text(data))
+        .execute(&mut conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn replay_webhook_delivery(
+        &self,
+        webhook_id: i32,
+        delivery_id: i64,
+    ) -> Result<Option<i64>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query_scalar(
+            "INSERT INTO webhook_deliveries (webhook_id, event_type, data, replay_of)
+            SELECT webhook_id, event_type, data, id FROM webhook_deliveries
+            WHERE webhook_id = ?1 AND id = ?2
+            RETURNING id",
+        )
+        .bind(webhook_id)
+        .bind(delivery_id)
+        .fetch_all(&mut conn)
+        .await
+        .map(|mut ids| ids.pop())
+    }
+
+    // SQLite runs one write at a time, so the UPDATE claims the deliveries for this instance
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<DueDelivery>> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "UPDATE webhook_deliveries
+            SET next_attempt_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?2)
+            WHERE id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= {now}
+                ORDER BY next_attempt_at
+                LIMIT ?1
+            )
+            RETURNING id, event_type, data, attempts, created_at,
+                (SELECT url FROM webhooks WHERE webhooks.id = webhook_id) AS url,
+                (SELECT secret FROM webhooks WHERE webhooks.id = webhook_id) AS secret",
+            now = NOW
+        );
+        let rows = sqlx::query(&query)
+            .bind(limit)
+            .bind(seconds_from_now(lease))
+            .fetch_all(&mut conn)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(DueDelivery {
+                    id: row.try_get("id")?,
+                    event_type: row.try_get("event_type")?,
+                    data: try_get_json(row, "data")?,
+                    attempts: row.try_get("attempts")?,
+                    created_at: row.try_get("created_at")?,
+                    url: row.try_get("url")?,
+                    secret: row.try_get("secret")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn record_webhook_delivered(&self, id: i64, status_code: Option<i32>) -> Result<()> {
+        let mut conn = acquire(&self.db_pool).await?;
+        let query = format!(
+            "UPDATE webhook_deliveries
+            SET status = 'delivered', attempts = attempts + 1, last_status_code = ?2,
+                last_error = NULL, delivered_at = {now}
+            WHERE id = ?1",
+            now = NOW
+        );
+        sqlx::query(&query)
+            .bind(id)
+            .bind(status_code)
+            .execute(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn record_webhook_failure(
+        &self,
+        id: i64,
+        status_code: Option<i32>,
+        error: &str,
+        give_up: bool,
+        retry_delay: Duration,
+    ) -> Result<()> {
+        let mut conn = acquire(&self.db_pool).await?;
+        sqlx::query(
+            "UPDATE webhook_deliveries
+            SET attempts = attempts + 1, last_status_code = ?2, last_error = ?3,
+                status = CASE WHEN ?4 THEN 'failed' ELSE 'pending' END,
+                next_attempt_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?5)
+            WHERE id = ?1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .bind(error)
+        .bind(give_up)
+        .bind(seconds_from_now(retry_delay))
+        .execute(&mut conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn record_pool_metrics(&self) {
+        record_pool_metrics(&self.db_pool)
+    }
+}
+
+/*
+Sends the events recorded after the feed started, when this repository made a change and
+every POLL_INTERVAL for the changes made by other processes. SQLite writes one transaction
+at a time, so event ids are committed in order and none are skipped by reading after the
+last one sent.
+ */
+// the writes take a connection, so that import_records can run them in its transaction
+async fn insert_topic_or_term(
+    conn: &mut SqliteConnection,
+    payload: &CreateTopicOrTerm,
+    topic_or_term: &str,
+) -> Result<i32> {
+    let query_string = format!("INSERT INTO {}s ({}, is_verified, brief_description, full_description,
+        bullet_points, examples, parallels, ai_brief_description, ai_full_description, ai_bullet_points, ai_parallels,
+        ai_examples, slug) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+        ON CONFLICT (slug) DO NOTHING RETURNING id", topic_or_term, topic_or_term);
+
+    let mut tried = HashSet::new();
+    loop {
+        let slug = slug_for(&mut *conn, topic_or_term, &payload.name, &tried).await?;
+        let id = sqlx::query_scalar(&query_string)
+            .bind(&payload.name)
+            .bind(payload.is_verified)
+            .bind(&payload.brief_description)
+            .bind(&payload.full_description)
+            .bind(json_text(&process_optional_vec(&payload.bullet_points)))
+            .bind(json_text(&process_optional_vec(&payload.examples)))
+            .bind(json_text(&process_optional_vec(&payload.parallels)))
+            .bind(&payload.ai_brief_description)
+            .bind(&payload.ai_full_description)
+            .bind(json_text(&process_optional_vec(&payload.ai_bullet_points)))
+            .bind(json_text(&process_optional_vec(&payload.ai_parallels)))
+            .bind(json_text(&process_optional_vec(&payload.ai_examples)))
+            .bind(&slug)
+            .fetch_all(&mut *conn)
+            .await?
+            .pop();
+        if let Some(id) = id {
+            return Ok(id);
+        }
+        tried.insert(slug);
+    }
+}
+
+async fn insert_source(conn: &mut SqliteConnection, payload: &CreateSource) -> Result<i32> {
+    let mut tried = HashSet::new();
+    loop {
+        let slug = slug_for(&mut *conn, "source", &payload.name, &tried).await?;
+        let id = sqlx::query_scalar(
+            "INSERT INTO sources
+                (name, url, author, author_url, media_type, image_url, image_type, ai_generated, slug)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT (slug) DO NOTHING
+            RETURNING id",
+        )
+        .bind(&payload.name)
+        .bind(&payload.url)
+        .bind(&payload.author)
+        .bind(&payload.author_url)
+        .bind(payload.media_type)
+        .bind(&payload.image_url)
+        .bind(payload.image_type)
+        .bind(payload.ai_generated)
+        .bind(&slug)
+        .fetch_all(&mut *conn)
+        .await?
+        .pop();
+        if let Some(id) = id {
+            return Ok(id);
+        }
+        tried.insert(slug);
+    }
+}
+
+/*
+Only updates the row when its version still matches `expected_version`, the
+bump_version trigger then increments it. RETURNING can't read the row as it was
+before, so is_verified is read first: when the update matches the same version,
+it updated the row that was read.
+ */
+async fn update_topic_or_term(
+    conn: &mut SqliteConnection,
+    payload: &CreateTopicOrTerm,
+    topic_or_term: &str,
+    id: i32,
+    expected_version: i32,
+) -> Result<UpdateOutcome> {
+    let query = format!(
+        "SELECT is_verified FROM {}s WHERE id = ?1 AND version = ?2",
+        topic_or_term
+    );
+    let was_verified: Option<bool> = sqlx::query_scalar(&query)
+        .bind(id)
+        .bind(expected_version)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some(was_verified) = was_verified else {
+        return get_update_conflict(conn, topic_or_term, id).await;
+    };
+
+    let query_string = format!(
+        "UPDATE {}s SET {} = ?1, is_verified = ?2, brief_description = ?3,
+        full_description = ?4, bullet_points = ?5, examples = ?6, parallels = ?7,
+        ai_brief_description = ?8, ai_full_description = ?9, ai_bullet_points = ?10,
+        ai_parallels = ?11, ai_examples = ?12
+        WHERE id = ?13 AND version = ?14
+        RETURNING is_verified",
+        topic_or_term, topic_or_term
+    );
+
+    let is_verified: Option<bool> = sqlx::query_scalar(&query_string)
+        .bind(&payload.name)
+        .bind(payload.is_verified)
+        .bind(&payload.brief_description)
+        .bind(&payload.full_description)
+        .bind(json_text(&process_optional_vec(&payload.bullet_points)))
+        .bind(json_text(&process_optional_vec(&payload.examples)))
+        .bind(json_text(&process_optional_vec(&payload.parallels)))
+        .bind(&payload.ai_brief_description)
+        .bind(&payload.ai_full_description)
+        .bind(json_text(&process_optional_vec(&payload.ai_bullet_points)))
+        .bind(json_text(&process_optional_vec(&payload.ai_parallels)))
+        .bind(json_text(&process_optional_vec(&payload.ai_examples)))
+        .bind(id)
+        .bind(expected_version)
+        .fetch_all(&mut *conn)
+        .await?
+        .pop();
+
+    match is_verified {
+        Some(is_verified) => Ok(UpdateOutcome::Updated {
+            verified: is_verified && !was_verified,
+        }),
+        None => get_update_conflict(conn, topic_or_term, id).await,
+    }
+}
+
+async fn update_source(
+    conn: &mut SqliteConnection,
+    payload: &CreateSource,
+    id: i32,
+    expected_version: i32,
+) -> Result<UpdateOutcome> {
+    let result = sqlx::query(
+        "UPDATE sources SET
+            name = ?1, url = ?2, author = ?3, author_url = ?4, media_type = ?5,
+            image_url = ?6, image_type = ?7, ai_generated = ?8
+        WHERE id = ?9 AND version = ?10",
+    )
+    .bind(&payload.name)
+    .bind(&payload.url)
+    .bind(&payload.author)
+    .bind(&payload.author_url)
+    .bind(payload.media_type)
+    .bind(&payload.image_url)
+    .bind(payload.image_type)
+    .bind(payload.ai_generated)
+    .bind(id)
+    .bind(expected_version)
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        return Ok(UpdateOutcome::Updated { verified: false });
+    }
+    get_update_conflict(conn, "source", id).await
+}
+
+async fn import_record(
+    conn: &mut SqliteConnection,
+    entity: &EntityFields,
+    record: &ImportRecord,
+    update_existing: bool,
+) -> Result<ImportOutcome> {
+    let existing: Option<(i32, i32)> = match record {
+        ImportRecord::TopicOrTerm(_) => {
+            sqlx::query_as(&find_by_name_query(entity, "id, version"))
+                .bind(record.name())
+                .fetch_optional(&mut *conn)
+                .await?
+        }
+        ImportRecord::Source(payload) => {
+            sqlx::query_as(&find_source_query("id, version"))
+                .bind(&payload.name)
+                .bind(&payload.url)
+                .fetch_optional(&mut *conn)
+                .await?
+        }
+    };
+    let outcome = match (existing, record) {
+        (Some((id, _)), _) if !update_existing => ImportOutcome::Skipped { id },
+        (Some((id, version)), ImportRecord::TopicOrTerm(payload)) => {
+            let outcome =
+                update_topic_or_term(conn, payload, entity.entity_type, id, version).await?;
+            ImportOutcome::from_update(id, outcome)
+        }
+        (Some((id, version)), ImportRecord::Source(payload)) => {
+            ImportOutcome::from_update(id, update_source(conn, payload, id, version).await?)
+        }
+        (None, ImportRecord::TopicOrTerm(payload)) => ImportOutcome::Created {
+            id: insert_topic_or_term(conn, payload, entity.entity_type).await?,
+        },
+        (None, ImportRecord::Source(payload)) => ImportOutcome::Created {
+            id: insert_source(conn, payload).await?,
+        },
+    };
+    Ok(outcome)
+}
+
+// the writes of restore_archive, see helpers/archive.rs
+struct SqliteArchiveWriter<'c> {
+    conn: &'c mut SqliteConnection,
+}
+
+// text[] columns are JSON text
+fn bind_value<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    value: FieldValue,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    match value {
+        FieldValue::Int(value) => query.bind(value),
+        FieldValue::Text(value) => query.bind(value),
+        FieldValue::Bool(value) => query.bind(value),
+        FieldValue::TextArray(value) => query.bind(value.map(|values| json_text(&values))),
+        FieldValue::Date(value) => query.bind(value),
+        FieldValue::Timestamp(value) => query.bind(value.map(timestamp)),
+        FieldValue::MediaType(value) => query.bind(value),
+        FieldValue::ImageType(value) => query.bind(value),
+    }
+}
+
+// the columns of the entity that are in `values`, with their values
+fn column_values(
+    entity: &EntityFields,
+    values: &SparseRecord,
+) -> Result<(Vec<&'static str>, Vec<FieldValue>)> {
+    let mut columns = vec![];
+    let mut column_values = vec![];
+    for (name, kind) in entity.fields {
+        let Some(value) = values.get(*name) else {
+            continue;
+        };
+        let value = field_value(*kind, value).ok_or_else(|| {
+            sqlx::Error::Decode(format!("{} isn't a valid {:?}", name, kind).into())
+        })?;
+        columns.push(*name);
+        column_values.push(value);
+    }
+    Ok((columns, column_values))
+}
+
+#[async_trait]
+impl ArchiveWriter for SqliteArchiveWriter<'_> {
+    async fn find_id(&mut self, entity: &EntityFields, name: &str) -> Result<Option<i32>> {
+        sqlx::query_scalar(&find_by_name_query(entity, "id"))
+            .bind(name)
+            .fetch_optional(&mut *self.conn)
+            .await
+    }
+
+    async fn find_matching_id(
+        &mut self,
+        entity: &EntityFields,
+        values: &SparseRecord,
+        columns: &[&str],
+    ) -> Result<Option<i32>> {
+        let (columns, column_values) =
+            column_values(entity, &matching_values(entity, values, columns))?;
+        let conditions: Vec<String> = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| format!("{} IS ?{}", column, index + 1))
+            .collect();
+        let query = format!(
+            "SELECT id FROM {} WHERE {} ORDER BY id LIMIT 1",
+            entity.table,
+            conditions.join(" AND ")
+        );
+        let mut select = sqlx::query(&query);
+        for value in column_values {
+            select = bind_value(select, value);
+        }
+        select
+            .fetch_optional(&mut *self.conn)
+            .await?
+            .map(|row| row.try_get("id"))
+            .transpose()
+    }
+
+    async fn insert_record(&mut self, entity: &EntityFields, values: &SparseRecord) -> Result<i32> {
+        let has_slug = entity.fields.iter().any(|(name, _)| *name == "slug");
+        let mut tried = HashSet::new();
+        loop {
+            let (mut columns, mut column_values) = column_values(entity, values)?;
+            let mut slug = None;
+            if has_slug {
+                let name = values
+                    .get(entity.name_column)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let next = slug_for(&mut *self.conn, entity.entity_type, name, &tried).await?;
+                columns.push("slug");
+                column_values.push(FieldValue::Text(Some(next.clone())));
+                slug = Some(next);
+            }
+            let query = if columns.is_empty() {
+                format!("INSERT INTO {} DEFAULT VALUES RETURNING id", entity.table)
+            } else {
+                let placeholders: Vec<String> = (1..=columns.len())
+                    .map(|index| format!("?{}", index))
+                    .collect();
+                format!(
+                    "INSERT INTO {} ({}) VALUES ({}){} RETURNING id",
+                    entity.table,
+                    columns.join(", "),
+                    placeholders.join(", "),
+                    if has_slug {
+                        " ON CONFLICT (slug) DO NOTHING"
+                    } else {
+                        ""
+                    }
+                )
+            };
+            let mut insert = sqlx::query(&query);
+            for value in column_values {
+                insert = bind_value(insert, value);
+            }
+            match (insert.fetch_all(&mut *self.conn).await?.pop(), slug) {
+                (Some(row), _) => return row.try_get("id"),
+                (None, Some(slug)) => {
+                    tried.insert(slug);
+                }
+                (None, None) => return Err(sqlx::Error::RowNotFound),
+            }
+        }
+    }
+
+    async fn update_record(
+        &mut self,
+        entity: &EntityFields,
+        id: i32,
+        values: &SparseRecord,
+    ) -> Result<()> {
+        let (columns, column_values) = column_values(entity, values)?;
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let assignments: Vec<String> = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| format!("{} = ?{}", column, index + 1))
+            .collect();
+        let query = format!(
+            "UPDATE {} SET {} WHERE id = ?{}",
+            entity.table,
+            assignments.join(", "),
+            columns.len() + 1
+        );
+        let mut update = sqlx::query(&query);
+        for value in column_values {
+            update = bind_value(update, value);
+        }
+        update.bind(id).execute(&mut *self.conn).await?;
+        Ok(())
+    }
+
+    async fn insert_link(
+        &mut self,
+        link_table: &ArchiveLinkTable,
+        from_id: i32,
+        to_id: i32,
+    ) -> Result<bool> {
+        let query = format!(
+            "INSERT INTO {} ({}, {}) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+            link_table.table, link_table.from_column, link_table.to_column
+        );
+        let result = sqlx::query(&query)
+            .bind(from_id)
+            .bind(to_id)
+            .execute(&mut *self.conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+async fn poll_events(db_pool: SqlitePool, changed: Arc<Notify>, feed: EventFeed) {
+    let mut last_id = None;
+    loop {
+        if let Err(error) = send_new_events(&db_pool, &feed, &mut last_id).await {
+            tracing::error!(%error, "reading events failed");
+        }
+        tokio::select! {
+            _ = changed.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+async fn send_new_events(
+    db_pool: &SqlitePool,
+    feed: &EventFeed,
+    last_id: &mut Option<i64>,
+) -> Result<()> {
+    let Some(after_id) = *last_id else {
+        let id = sqlx::query_scalar("SELECT coalesce(max(id), 0) FROM events")
+            .fetch_one(db_pool)
+            .await?;
+        *last_id = Some(id);
+        return Ok(());
+    };
+    let mut after_id = after_id;
+    loop {
+        let events: Vec<Event> =
+            sqlx::query_as("SELECT * FROM events WHERE id > ?1 ORDER BY id LIMIT ?2")
+                .bind(after_id)
+                .bind(EVENTS_PAGE_SIZE)
+                .fetch_all(db_pool)
+                .await?;
+        let full_page = events.len() as i64 == EVENTS_PAGE_SIZE;
+        for event in events {
+            after_id = event.id;
+            *last_id = Some(after_id);
+            feed.send(FeedMessage::Event(Arc::new(event)));
+        }
+        if !full_page {
+            return Ok(());
+        }
+    }
+}
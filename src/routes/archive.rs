@@ -0,0 +1,111 @@
+/*
+Export and restore of the whole knowledge base, see helpers/archive.rs for the archive
+and the conflict policies.
+ */
+use crate::helpers::archive::{export_archive, parse_archive, ArchiveFormat, OnConflict};
+use crate::helpers::ndjson::{wants_ndjson, NDJSON_CONTENT_TYPE};
+use crate::helpers::read_cache::ReadCache;
+use crate::repository::Repository;
+use axum::{
+    extract::{Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreQueryParams {
+    /// What happens to a record whose name is taken: skip (the default), overwrite or rename
+    on_conflict: Option<OnConflict>,
+    /// json or ndjson, by default the format of the Content-Type, or json
+    format: Option<ArchiveFormat>,
+}
+
+/*
+GET /v1/archive
+Every record and link as one JSON archive, or as NDJSON with `Accept: application/x-ndjson`.
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/archive",
+    tag = "archive",
+    responses(
+        (status = 200, description = "The archive, as NDJSON with Accept: application/x-ndjson", body = Archive),
+    )
+)]
+pub async fn export_archive_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    headers: HeaderMap,
+) -> Response {
+    let archive = match export_archive(&*repository).await {
+        Ok(archive) => archive,
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+    if wants_ndjson(&headers) {
+        (
+            [(CONTENT_TYPE, HeaderValue::from_static(NDJSON_CONTENT_TYPE))],
+            archive.to_ndjson(),
+        )
+            .into_response()
+    } else {
+        Json(archive).into_response()
+    }
+}
+
+/*
+POST /v1/archive/restore?on_conflict=rename
+Content-Type: application/json
+Body: an archive from GET /v1/archive
+The report counts the records of each type by what was done with them and lists the
+renamed records, and the questions and links whose records weren't found.
+ */
+#[utoipa::path(
+    post,
+    path = "/v1/archive/restore",
+    tag = "archive",
+    params(RestoreQueryParams),
+    request_body(content = Archive, description = "An archive from GET /v1/archive, as JSON or NDJSON"),
+    responses(
+        (status = 200, description = "What was restored", body = RestoreReport),
+        (status = 400, description = "Not an archive, or an archive of a newer version"),
+        (status = 413, description = "The body is larger than MAX_IMPORT_BYTES"),
+    )
+)]
+pub async fn restore_archive_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    Query(params): Query<RestoreQueryParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let format = params
+        .format
+        .or_else(|| {
+            headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(ArchiveFormat::from_content_type)
+        })
+        .unwrap_or(ArchiveFormat::Json);
+    let archive = match parse_archive(format, &body) {
+        Ok(archive) => archive,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    match repository
+        .restore_archive(&archive, params.on_conflict.unwrap_or_default())
+        .await
+    {
+        Ok(report) => {
+            read_cache.invalidate_all();
+            Json(report).into_response()
+        }
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,97 @@
+/*
+Citations of a source and the bibliography of a topic, see helpers/citations.rs for the
+styles.
+ */
+use crate::helpers::citations::{
+    bibliography, cite, topic_sources, CitationStyle, CITATION_FIELDS,
+};
+use crate::helpers::fieldsets::{SOURCE_FIELDS, TOPIC_FIELDS};
+use crate::helpers::lookup::{resolve_entity_id, LookupParams};
+use crate::repository::Repository;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CitationQueryParams {
+    /// apa (the default), mla, chicago or bibtex
+    style: Option<CitationStyle>,
+}
+
+/*
+GET /v1/sources/wikipedia-tropical-cyclone/citation?style=mla
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/sources/{id}/citation",
+    tag = "citations",
+    params(
+        ("id" = String, Path, description = "The source's id, or its slug when it isn't numeric"),
+        CitationQueryParams,
+    ),
+    responses(
+        (status = 200, description = "The citation and the metadata it's missing", body = Citation),
+        (status = 404, description = "There's no such source"),
+    )
+)]
+pub async fn citation_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    lookup: LookupParams,
+    Query(params): Query<CitationQueryParams>,
+) -> Response {
+    let id = match resolve_entity_id(&*repository, &SOURCE_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    match repository
+        .get_record(&SOURCE_FIELDS, &CITATION_FIELDS, id)
+        .await
+    {
+        Ok(Some(source)) => Json(cite(&source, params.style.unwrap_or_default())).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+/*
+GET /v1/topics/hurricane/bibliography?style=bibtex
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/topics/{id}/bibliography",
+    tag = "citations",
+    params(
+        ("id" = String, Path, description = "The topic's id, or its slug when it isn't numeric"),
+        CitationQueryParams,
+    ),
+    responses(
+        (status = 200, description = "A citation per source", body = Bibliography),
+        (status = 404, description = "There's no such topic"),
+    )
+)]
+pub async fn bibliography_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    lookup: LookupParams,
+    Query(params): Query<CitationQueryParams>,
+) -> Response {
+    let topic_id = match resolve_entity_id(&*repository, &TOPIC_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    match topic_sources(&*repository, topic_id).await {
+        Ok(sources) => Json(bibliography(
+            topic_id,
+            &sources,
+            params.style.unwrap_or_default(),
+        ))
+        .into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,229 @@
+use crate::helpers::events::{Event, EventFeed, FeedMessage, EVENTS_PAGE_SIZE, EVENT_ENTITY_TYPES};
+use crate::repository::Repository;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures::Stream;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use utoipa::IntoParams;
+
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventQueryParams {
+    /// Comma separated entity types to receive (topic, term, source, question, article), all by default
+    types: Option<String>,
+    /// Only send the events after this id, like the Last-Event-ID header
+    last_event_id: Option<i64>,
+}
+
+// "term,topic" -> ["term", "topic"]
+fn parse_entity_types(types: &str) -> Result<Vec<String>, String> {
+    let mut entity_types: Vec<String> = vec![];
+    for entity_type in types
+        .split(',')
+        .map(str::trim)
+        .filter(|entity_type| !entity_type.is_empty())
+    {
+        if !EVENT_ENTITY_TYPES.contains(&entity_type) {
+            return Err(format!(
+                "unknown entity type \"{}\", expected one of: {}",
+                entity_type,
+                EVENT_ENTITY_TYPES.join(", ")
+            ));
+        }
+        if !entity_types.iter().any(|known| known == entity_type) {
+            entity_types.push(entity_type.to_owned());
+        }
+    }
+    Ok(entity_types)
+}
+
+fn sse_event(event: &Event) -> Result<SseEvent, sqlx::Error> {
+    SseEvent::default()
+        .id(event.id.to_string())
+        .json_data(event)
+        .map_err(|error| sqlx::Error::Decode(error.into()))
+}
+
+// how far below the highest id sent an event can still arrive, see event_stream
+const EVENT_ID_WINDOW: i64 = 1_000;
+
+// the ids sent in the window below the highest one, the ids at or below `floor` are never sent
+struct SentIds {
+    floor: i64,
+    ids: BTreeSet<i64>,
+}
+
+impl SentIds {
+    fn after(floor: i64) -> Self {
+        SentIds {
+            floor,
+            ids: BTreeSet::new(),
+        }
+    }
+
+    // true when the id is new, i.e. the event is to be sent
+    fn insert(&mut self, id: i64) -> bool {
+        if id <= self.floor || !self.ids.insert(id) {
+            return false;
+        }
+        let floor = id - EVENT_ID_WINDOW;
+        if floor > self.floor {
+            self.floor = floor;
+            self.ids = self.ids.split_off(&(floor + 1));
+        }
+        true
+    }
+}
+
+// reads the events above the floor from the repository, `send` is false to only mark them sent
+async fn read_events(
+    repository: &dyn Repository,
+    entity_types: &[String],
+    sent: &mut SentIds,
+    send: bool,
+) -> Result<Vec<Event>, sqlx::Error> {
+    let mut unsent = vec![];
+    let mut after_id = sent.floor;
+    loop {
+        let events = repository.get_events_since(after_id, entity_types).await?;
+        let more = events.len() as i64 == EVENTS_PAGE_SIZE;
+        for event in events {
+            after_id = event.id;
+            if sent.insert(event.id) && send {
+                unsent.push(event);
+            }
+        }
+        if !more {
+            return Ok(unsent);
+        }
+    }
+}
+
+/*
+Ids are handed out when a change is made and delivered when it commits, so an event can
+arrive after one with a higher id. A stream remembers the ids it sent in the last
+EVENT_ID_WINDOW ids and sends every event in that window it hasn't sent yet, both from the
+broadcast and when it reads the missed events from the table again. Only an event committed
+after EVENT_ID_WINDOW later ids can be skipped.
+
+A database error ends the stream, EventSource then reconnects with the Last-Event-ID.
+ */
+fn event_stream(
+    repository: Arc<dyn Repository>,
+    mut receiver: Receiver<FeedMessage>,
+    entity_types: Vec<String>,
+    mut sent: SentIds,
+    mut missed: bool,
+) -> impl Stream<Item = Result<SseEvent, sqlx::Error>> {
+    async_stream::try_stream! {
+        loop {
+            if missed {
+                missed = false;
+                for event in read_events(&*repository, &entity_types, &mut sent, true).await? {
+                    yield sse_event(&event)?;
+                }
+            }
+            match receiver.recv().await {
+                Ok(FeedMessage::Event(event)) => {
+                    if sent.insert(event.id) && event.matches(&entity_types) {
+                        yield sse_event(&event)?;
+                    }
+                }
+                Ok(FeedMessage::Resync) | Err(RecvError::Lagged(_)) => missed = true,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/*
+/events
+Server-sent events, one per created, updated, deleted, linked or unlinked record, e.g.
+id: 42
+data: {"id":42,"action":"linked","entity_type":"term","entity_id":7,"related_type":"topic","related_id":1,"created_at":"..."}
+
+- optional `types`: comma separated entity types, a link is sent when either side matches
+- `Last-Event-ID` header (EventSource sends it when it reconnects) or `last_event_id`:
+  the events after it are sent first, otherwise the stream starts with the next change
+ */
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(
+        EventQueryParams,
+        ("Last-Event-ID" = Option<i64>, Header, description = "Resume after this event id, takes precedence over `last_event_id`"),
+    ),
+    responses(
+        (status = 200, description = "A text/event-stream of changes, each event's data is a JSON object with id, action, entity_type, entity_id, related_type, related_id and created_at", body = String, content_type = "text/event-stream"),
+        (status = 400, description = "Unknown entity type in `types`, or a Last-Event-ID that isn't an event id"),
+    )
+)]
+pub async fn events_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(event_feed): State<EventFeed>,
+    headers: HeaderMap,
+    Query(params): Query<EventQueryParams>,
+) -> Response {
+    let entity_types = match parse_entity_types(params.types.as_deref().unwrap_or_default()) {
+        Ok(entity_types) => entity_types,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let last_event_id = match headers.get(LAST_EVENT_ID_HEADER) {
+        None => params.last_event_id,
+        Some(value) => match value.to_str().ok().and_then(|id| id.trim().parse().ok()) {
+            Some(id) => Some(id),
+            None => {
+                return (StatusCode::BAD_REQUEST, "Last-Event-ID must be an event id")
+                    .into_response()
+            }
+        },
+    };
+
+    // subscribed before the starting point is read, so no event falls in between
+    let receiver = event_feed.subscribe();
+    let (sent, missed) = match last_event_id {
+        // the client has seen the events up to its id
+        Some(last_event_id) => (SentIds::after(last_event_id), true),
+        // the events in the window that are already there aren't sent, the ones that
+        // commit later are
+        None => {
+            let sent = match repository.get_last_event_id().await {
+                Ok(last_event_id) => {
+                    let mut sent = SentIds::after(last_event_id - EVENT_ID_WINDOW);
+                    read_events(&*repository, &entity_types, &mut sent, false)
+                        .await
+                        .map(|_| sent)
+                }
+                Err(error) => Err(error),
+            };
+            match sent {
+                Ok(sent) => (sent, false),
+                Err(error) => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+                }
+            }
+        }
+    };
+
+    Sse::new(event_stream(
+        repository,
+        receiver,
+        entity_types,
+        sent,
+        missed,
+    ))
+    .keep_alive(KeepAlive::default())
+    .into_response()
+}
@@ -0,0 +1,212 @@
+/*
+Exports in the formats of other tools, see helpers/flashcards.rs for the flashcard files
+and helpers/graph.rs for the knowledge graph.
+ */
+use crate::helpers::fieldsets::{FieldKind, SparseRecord, TERM_FIELDS, TOPIC_FIELDS};
+use crate::helpers::flashcards::{
+    render_anki, render_quizlet, CardFields, Flashcard, FlashcardFormat,
+};
+use crate::helpers::graph::{load_graph, node_id, GraphFormat, GRAPH_ENTITIES};
+use crate::helpers::lookup::{resolve_entity_id, LookupParams};
+use crate::repository::Repository;
+use axum::{
+    extract::{Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FlashcardQueryParams {
+    /// Only the terms of this topic, by id or slug
+    topic: Option<String>,
+    /// anki (the default) for Anki's tab separated import, or quizlet for CSV
+    format: Option<FlashcardFormat>,
+    /// human (the default) for brief_description, bullet_points and examples, or ai for their ai_* fields
+    fields: Option<CardFields>,
+    /// Only verified terms
+    verified_only: Option<bool>,
+}
+
+/*
+GET /v1/export/flashcards?topic=hurricane&format=quizlet
+A card per term, terms with nothing for the back of the card are left out.
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/export/flashcards",
+    tag = "export",
+    params(FlashcardQueryParams),
+    responses(
+        (status = 200, description = "An Anki TSV or a Quizlet CSV file", body = String,
+            content_type = "text/tab-separated-values"),
+        (status = 404, description = "There's no such topic"),
+    )
+)]
+pub async fn flashcards_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    Query(params): Query<FlashcardQueryParams>,
+) -> Response {
+    let card_fields = params.fields.unwrap_or_default();
+    let mut fields = vec![("id", FieldKind::Int), ("term", FieldKind::Text)];
+    fields.extend(card_fields.fields());
+
+    let terms = match &params.topic {
+        Some(topic) => {
+            let lookup = LookupParams::from_path_segment(topic);
+            let topic_id = match resolve_entity_id(&*repository, &TOPIC_FIELDS, &lookup).await {
+                Ok(topic_id) => topic_id,
+                Err(error) => return error.into_response(),
+            };
+            repository
+                .get_related("topic", &[topic_id], "term", &fields)
+                .await
+                .map(|mut terms| terms.remove(&topic_id).unwrap_or_default())
+        }
+        None => repository.get_records(&TERM_FIELDS, &fields, None).await,
+    };
+    let terms: Vec<SparseRecord> = match terms {
+        Ok(terms) => terms
+            .into_iter()
+            .filter(|term| {
+                !params.verified_only.unwrap_or(false) || term["is_verified"] == Value::Bool(true)
+            })
+            .collect(),
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+
+    // the cards are tagged with all of a term's topics, not only the one asked for
+    let term_ids: Vec<i32> = terms
+        .iter()
+        .filter_map(|term| term["id"].as_i64())
+        .map(|id| id as i32)
+        .collect();
+    let mut topics = match repository
+        .get_related("term", &term_ids, "topic", &[("topic", FieldKind::Text)])
+        .await
+    {
+        Ok(topics) => topics,
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+    let cards: Vec<Flashcard> = terms
+        .iter()
+        .filter_map(|term| {
+            let id = term["id"].as_i64()? as i32;
+            let topic_names: Vec<String> = topics
+                .remove(&id)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|topic| topic["topic"].as_str().map(str::to_owned))
+                .collect();
+            Flashcard::from_term(term, card_fields, &topic_names)
+        })
+        .collect();
+
+    let format = params.format.unwrap_or_default();
+    let file = match format {
+        FlashcardFormat::Anki => render_anki(&cards),
+        FlashcardFormat::Quizlet => render_quizlet(&cards),
+    };
+    (
+        [
+            (CONTENT_TYPE, format.content_type().to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", format.file_name()),
+            ),
+        ],
+        file,
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GraphQueryParams {
+    /// json (the default) for d3, dot for Graphviz, or graphml
+    format: Option<GraphFormat>,
+    /// topic, term or source, with `id` only the neighbourhood of that record is exported
+    entity_type: Option<String>,
+    /// The id or slug of the record the neighbourhood is around
+    id: Option<String>,
+    /// How many edges away from the record the neighbourhood reaches, 1 by default
+    depth: Option<usize>,
+}
+
+/*
+GET /v1/export/graph?format=dot&entity_type=term&id=storm&depth=2
+Every topic, term and source and the links between them, or only the ones around a record.
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/export/graph",
+    tag = "export",
+    params(GraphQueryParams),
+    responses(
+        (status = 200, description = "The graph as node-link JSON, DOT or GraphML", body = KnowledgeGraph),
+        (status = 400, description = "Only one of entity_type and id, or an entity_type that isn't topic, term or source"),
+        (status = 404, description = "There's no such record"),
+    )
+)]
+pub async fn graph_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    Query(params): Query<GraphQueryParams>,
+) -> Response {
+    let start = match (&params.entity_type, &params.id) {
+        (Some(entity_type), Some(id)) => {
+            let Some(entity) = GRAPH_ENTITIES
+                .into_iter()
+                .find(|entity| entity.entity_type == entity_type)
+            else {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "entity_type must be topic, term or source",
+                )
+                    .into_response();
+            };
+            let lookup = LookupParams::from_path_segment(id);
+            match resolve_entity_id(&*repository, entity, &lookup).await {
+                Ok(id) => Some(node_id(entity.entity_type, id)),
+                Err(error) => return error.into_response(),
+            }
+        }
+        (None, None) => None,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "pass both entity_type and id, or neither for the whole graph",
+            )
+                .into_response()
+        }
+    };
+
+    let graph = match load_graph(&*repository).await {
+        Ok(graph) => graph,
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+    let graph = match start {
+        Some(start) => graph.neighbourhood(&start, params.depth.unwrap_or(1)),
+        None => graph,
+    };
+    let format = params.format.unwrap_or_default();
+    let body = match format {
+        GraphFormat::Json => return Json(graph).into_response(),
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Graphml => graph.to_graphml(),
+    };
+    ([(CONTENT_TYPE, format.content_type())], body).into_response()
+}
@@ -0,0 +1,630 @@
+/*
+GraphQL over topics, terms, sources, questions and articles at POST /graphql.
+
+The GraphQL types are kept here rather than on the REST types: they are read from the same
+records (see GraphNode) and the inputs are converted to the REST payloads.
+
+The relationship fields (e.g. Topic.terms, Question.topic) read the bridge tables through
+the batched loaders in helpers/graphql_loader.rs. The mutations do the same as the create
+and link endpoints.
+
+A query is rejected before it runs when it is nested deeper than GRAPHQL_MAX_DEPTH or when
+its complexity is over GRAPHQL_MAX_COMPLEXITY. Every field counts 1, a list counts its
+`limit` times the complexity of its fields, and a relationship list counts RELATED_LIST_COST
+times the complexity of its fields since its length isn't known before the query runs.
+ */
+use super::links::{insert_links, CreateLink};
+use super::relationships::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use super::sources::insert_source;
+use crate::helpers::fieldsets::{EntityFields, SOURCE_FIELDS, TERM_FIELDS, TOPIC_FIELDS};
+use crate::helpers::graphql_loader::{load_related, GraphNode, RelatedLoader};
+use crate::helpers::handler_utils::{
+    build_link_tables, insert_topic_or_term, CreateEntity, CreateTopicOrTerm,
+};
+use crate::helpers::limits::{check_array_lengths, RequestLimits};
+use crate::helpers::lookup::{EntityKey, LookupParams};
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::shared_types::{CreateSource, ImageType, MediaType};
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::{from_record, Repository};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, InputObject, Object, Result as GraphQLResult,
+    Schema, SimpleObject,
+};
+use axum::{extract::State, Json};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// how many records a relationship list is assumed to have when a query's complexity is counted
+const RELATED_LIST_COST: usize = 10;
+
+pub type GraphQLSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+#[derive(SimpleObject, Deserialize, Clone)]
+#[graphql(complex)]
+pub struct Topic {
+    id: i32,
+    topic: String,
+    slug: String,
+    is_verified: bool,
+    brief_description: Option<String>,
+    full_description: Option<String>,
+    bullet_points: Option<Vec<String>>,
+    examples: Option<Vec<String>>,
+    parallels: Option<Vec<String>>,
+    ai_brief_description: Option<String>,
+    ai_full_description: Option<String>,
+    ai_bullet_points: Option<Vec<String>>,
+    ai_parallels: Option<Vec<String>>,
+    ai_examples: Option<Vec<String>>,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl Topic {
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn terms(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Term>> {
+        load_related(ctx, "topic", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn sources(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Source>> {
+        load_related(ctx, "topic", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn questions(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Question>> {
+        load_related(ctx, "topic", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn articles(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Article>> {
+        load_related(ctx, "topic", self.id).await
+    }
+}
+
+impl GraphNode for Topic {
+    const ENTITY_TYPE: &'static str = "topic";
+}
+
+#[derive(SimpleObject, Deserialize, Clone)]
+#[graphql(complex)]
+pub struct Term {
+    id: i32,
+    term: String,
+    slug: String,
+    is_verified: bool,
+    brief_description: Option<String>,
+    full_description: Option<String>,
+    bullet_points: Option<Vec<String>>,
+    examples: Option<Vec<String>>,
+    parallels: Option<Vec<String>>,
+    ai_brief_description: Option<String>,
+    ai_full_description: Option<String>,
+    ai_bullet_points: Option<Vec<String>>,
+    ai_parallels: Option<Vec<String>>,
+    ai_examples: Option<Vec<String>>,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl Term {
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn topics(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Topic>> {
+        load_related(ctx, "term", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn sources(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Source>> {
+        load_related(ctx, "term", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn articles(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Article>> {
+        load_related(ctx, "term", self.id).await
+    }
+}
+
+impl GraphNode for Term {
+    const ENTITY_TYPE: &'static str = "term";
+}
+
+#[derive(SimpleObject, Deserialize, Clone)]
+#[graphql(complex)]
+pub struct Source {
+    id: i32,
+    name: Option<String>,
+    slug: String,
+    url: Option<String>,
+    author: Option<String>,
+    author_url: Option<String>,
+    media_type: Option<MediaType>,
+    image_url: Option<String>,
+    image_type: Option<ImageType>,
+    ai_generated: Option<bool>,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl Source {
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn topics(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Topic>> {
+        load_related(ctx, "source", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn terms(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Term>> {
+        load_related(ctx, "source", self.id).await
+    }
+}
+
+impl GraphNode for Source {
+    const ENTITY_TYPE: &'static str = "source";
+}
+
+#[derive(SimpleObject, Deserialize, Clone)]
+#[graphql(complex)]
+pub struct Question {
+    id: i32,
+    question: String,
+    topic_id: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl Question {
+    async fn topic(&self, ctx: &Context<'_>) -> GraphQLResult<Option<Topic>> {
+        let topics: Vec<Topic> = load_related(ctx, "question", self.id).await?;
+        Ok(topics.into_iter().next())
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn articles(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Article>> {
+        load_related(ctx, "question", self.id).await
+    }
+}
+
+impl GraphNode for Question {
+    const ENTITY_TYPE: &'static str = "question";
+}
+
+#[derive(SimpleObject, Deserialize, Clone)]
+#[graphql(complex)]
+pub struct Article {
+    id: i32,
+    title: Option<String>,
+    author: Option<String>,
+    publish_date: Option<NaiveDate>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl Article {
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn topics(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Topic>> {
+        load_related(ctx, "article", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn terms(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Term>> {
+        load_related(ctx, "article", self.id).await
+    }
+
+    #[graphql(complexity = "RELATED_LIST_COST * child_complexity")]
+    async fn questions(&self, ctx: &Context<'_>) -> GraphQLResult<Vec<Question>> {
+        load_related(ctx, "article", self.id).await
+    }
+}
+
+impl GraphNode for Article {
+    const ENTITY_TYPE: &'static str = "article";
+}
+
+// the inputs of the mutations, converted to the payloads of the REST routes
+#[derive(InputObject)]
+#[graphql(name = "CreateTopicOrTerm")]
+pub struct TopicOrTermInput {
+    name: String,
+    is_verified: bool,
+    brief_description: Option<String>,
+    full_description: Option<String>,
+    bullet_points: Option<Vec<String>>,
+    examples: Option<Vec<String>>,
+    parallels: Option<Vec<String>>,
+    ai_brief_description: Option<String>,
+    ai_full_description: Option<String>,
+    ai_bullet_points: Option<Vec<String>>,
+    ai_parallels: Option<Vec<String>>,
+    ai_examples: Option<Vec<String>>,
+    related_terms: Option<Vec<String>>,
+    related_topics: Option<Vec<String>>,
+    related_sources: Option<Vec<String>>,
+}
+
+impl From<TopicOrTermInput> for CreateTopicOrTerm {
+    fn from(input: TopicOrTermInput) -> Self {
+        CreateTopicOrTerm {
+            name: input.name,
+            is_verified: input.is_verified,
+            brief_description: input.brief_description,
+            full_description: input.full_description,
+            bullet_points: input.bullet_points,
+            examples: input.examples,
+            parallels: input.parallels,
+            ai_brief_description: input.ai_brief_description,
+            ai_full_description: input.ai_full_description,
+            ai_bullet_points: input.ai_bullet_points,
+            ai_parallels: input.ai_parallels,
+            ai_examples: input.ai_examples,
+            related_terms: input.related_terms,
+            related_topics: input.related_topics,
+            related_sources: input.related_sources,
+        }
+    }
+}
+
+#[derive(InputObject)]
+#[graphql(name = "CreateSource")]
+pub struct SourceInput {
+    name: String,
+    url: Option<String>,
+    author: Option<String>,
+    author_url: Option<String>,
+    media_type: Option<MediaType>,
+    image_url: Option<String>,
+    image_type: Option<ImageType>,
+    ai_generated: Option<bool>,
+    related_terms: Option<Vec<String>>,
+    related_topics: Option<Vec<String>>,
+    related_sources: Option<Vec<String>>,
+}
+
+impl From<SourceInput> for CreateSource {
+    fn from(input: SourceInput) -> Self {
+        CreateSource {
+            name: input.name,
+            url: input.url,
+            author: input.author,
+            author_url: input.author_url,
+            media_type: input.media_type,
+            image_url: input.image_url,
+            image_type: input.image_type,
+            ai_generated: input.ai_generated,
+            related_terms: input.related_terms,
+            related_topics: input.related_topics,
+            related_sources: input.related_sources,
+        }
+    }
+}
+
+#[derive(InputObject)]
+#[graphql(name = "CreateLink")]
+pub struct LinkInput {
+    parent_entity_type: String,
+    child_entity_type: String,
+    parent_id: i32,
+    related_term_ids: Option<Vec<i32>>,
+    related_topic_ids: Option<Vec<i32>>,
+    related_source_ids: Option<Vec<i32>>,
+}
+
+impl From<LinkInput> for CreateLink {
+    fn from(input: LinkInput) -> Self {
+        CreateLink {
+            parent_entity_type: input.parent_entity_type,
+            child_entity_type: input.child_entity_type,
+            parent_id: input.parent_id,
+            related_term_ids: input.related_term_ids,
+            related_topic_ids: input.related_topic_ids,
+            related_source_ids: input.related_source_ids,
+        }
+    }
+}
+
+fn page_complexity(limit: Option<i32>, child_complexity: usize) -> usize {
+    let limit = limit
+        .map(i64::from)
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    limit as usize * child_complexity
+}
+
+// the same paging as the relationship endpoints, see relationships.rs
+async fn list<T: GraphNode>(
+    ctx: &Context<'_>,
+    limit: Option<i32>,
+    offset: Option<i32>,
+) -> GraphQLResult<Vec<T>> {
+    let limit = limit.map(i64::from).unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE).into());
+    }
+    let offset = offset.map(i64::from).unwrap_or(0);
+    if offset < 0 {
+        return Err("offset can't be negative".into());
+    }
+    let records = ctx
+        .data::<Arc<dyn Repository>>()?
+        .get_records_page(T::entity(), T::entity().fields, limit, offset)
+        .await?;
+    Ok(records
+        .into_iter()
+        .map(from_record)
+        .collect::<sqlx::Result<_>>()?)
+}
+
+async fn get_by_id<T: GraphNode>(repository: &dyn Repository, id: i32) -> sqlx::Result<Option<T>> {
+    repository
+        .get_record(T::entity(), T::entity().fields, id)
+        .await?
+        .map(from_record)
+        .transpose()
+}
+
+// a topic, term or source by exactly one of id, name or slug, see helpers/lookup.rs
+async fn find<T: GraphNode>(
+    ctx: &Context<'_>,
+    entity: &EntityFields,
+    params: LookupParams,
+) -> GraphQLResult<Option<T>> {
+    let repository = ctx.data::<Arc<dyn Repository>>()?;
+    let key = EntityKey::from_params(&params)?;
+    match repository.find_entity_id(entity, &key).await? {
+        Some(id) => Ok(get_by_id(&**repository, id).await?),
+        None => Ok(None),
+    }
+}
+
+async fn created<T: GraphNode>(repository: &dyn Repository, id: i32) -> GraphQLResult<T> {
+    get_by_id(repository, id)
+        .await?
+        .ok_or_else(|| format!("the new {} was not found", T::ENTITY_TYPE).into())
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A topic by exactly one of its id, name (case-insensitive) or slug
+    async fn topic(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<i32>,
+        name: Option<String>,
+        slug: Option<String>,
+    ) -> GraphQLResult<Option<Topic>> {
+        find(ctx, &TOPIC_FIELDS, LookupParams { id, name, slug }).await
+    }
+
+    /// A page of topics ordered by id, `limit` is 50 by default and at most 500
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn topics(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> GraphQLResult<Vec<Topic>> {
+        list(ctx, limit, offset).await
+    }
+
+    /// A term by exactly one of its id, name (case-insensitive) or slug
+    async fn term(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<i32>,
+        name: Option<String>,
+        slug: Option<String>,
+    ) -> GraphQLResult<Option<Term>> {
+        find(ctx, &TERM_FIELDS, LookupParams { id, name, slug }).await
+    }
+
+    /// A page of terms ordered by id, `limit` is 50 by default and at most 500
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn terms(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> GraphQLResult<Vec<Term>> {
+        list(ctx, limit, offset).await
+    }
+
+    /// A source by exactly one of its id, name (case-insensitive) or slug
+    async fn source(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<i32>,
+        name: Option<String>,
+        slug: Option<String>,
+    ) -> GraphQLResult<Option<Source>> {
+        find(ctx, &SOURCE_FIELDS, LookupParams { id, name, slug }).await
+    }
+
+    /// A page of sources ordered by id, `limit` is 50 by default and at most 500
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn sources(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> GraphQLResult<Vec<Source>> {
+        list(ctx, limit, offset).await
+    }
+
+    async fn question(&self, ctx: &Context<'_>, id: i32) -> GraphQLResult<Option<Question>> {
+        Ok(get_by_id(&**ctx.data::<Arc<dyn Repository>>()?, id).await?)
+    }
+
+    /// A page of questions ordered by id, `limit` is 50 by default and at most 500
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn questions(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> GraphQLResult<Vec<Question>> {
+        list(ctx, limit, offset).await
+    }
+
+    async fn article(&self, ctx: &Context<'_>, id: i32) -> GraphQLResult<Option<Article>> {
+        Ok(get_by_id(&**ctx.data::<Arc<dyn Repository>>()?, id).await?)
+    }
+
+    /// A page of articles ordered by id, `limit` is 50 by default and at most 500
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn articles(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+    ) -> GraphQLResult<Vec<Article>> {
+        list(ctx, limit, offset).await
+    }
+}
+
+// same as new_topic_handler and new_term_handler
+async fn create_topic_or_term<T: GraphNode>(
+    ctx: &Context<'_>,
+    input: TopicOrTermInput,
+) -> GraphQLResult<T> {
+    let input = CreateTopicOrTerm::from(input);
+    let repository = &**ctx.data::<Arc<dyn Repository>>()?;
+    check_array_lengths(&input.array_fields(), ctx.data::<RequestLimits>()?)?;
+    let webhooks = ctx.data::<Webhooks>()?;
+    let insert_result = insert_topic_or_term(repository, &input, T::ENTITY_TYPE).await;
+    let link_insert_result = match &insert_result {
+        Ok(id) => {
+            webhooks
+                .record_created(T::ENTITY_TYPE, *id, input.is_verified())
+                .await;
+            build_link_tables(repository, webhooks, &input, T::ENTITY_TYPE, *id).await
+        }
+        Err(_) => Ok(()),
+    };
+    ctx.data::<Arc<ReadCache>>()?.invalidate_all();
+    let id = insert_result?;
+    link_insert_result?;
+    created(repository, id).await
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Same as POST /v1/topics, returns the new topic
+    async fn create_topic(
+        &self,
+        ctx: &Context<'_>,
+        input: TopicOrTermInput,
+    ) -> GraphQLResult<Topic> {
+        create_topic_or_term(ctx, input).await
+    }
+
+    /// Same as POST /v1/terms, returns the new term
+    async fn create_term(&self, ctx: &Context<'_>, input: TopicOrTermInput) -> GraphQLResult<Term> {
+        create_topic_or_term(ctx, input).await
+    }
+
+    /// Same as POST /v1/sources, returns the new source
+    async fn create_source(&self, ctx: &Context<'_>, input: SourceInput) -> GraphQLResult<Source> {
+        let input = CreateSource::from(input);
+        let repository = &**ctx.data::<Arc<dyn Repository>>()?;
+        check_array_lengths(&input.array_fields(), ctx.data::<RequestLimits>()?)?;
+        let webhooks = ctx.data::<Webhooks>()?;
+        let insert_result = insert_source(repository, &input).await;
+        let link_insert_result = match &insert_result {
+            Ok(id) => {
+                webhooks.record_event("source", "created", *id).await;
+                build_link_tables(repository, webhooks, &input, "source", *id).await
+            }
+            Err(_) => Ok(()),
+        };
+        ctx.data::<Arc<ReadCache>>()?.invalidate_all();
+        let id = insert_result?;
+        link_insert_result?;
+        created(repository, id).await
+    }
+
+    /// Same as POST /v1/links, existing links are left as they are
+    async fn link_entities(&self, ctx: &Context<'_>, input: LinkInput) -> GraphQLResult<bool> {
+        let input = CreateLink::from(input);
+        let repository = &**ctx.data::<Arc<dyn Repository>>()?;
+        check_array_lengths(&input.array_fields(), ctx.data::<RequestLimits>()?)?;
+        let insert_result = insert_links(&input, repository, ctx.data::<Webhooks>()?).await;
+        ctx.data::<Arc<ReadCache>>()?.invalidate_all();
+        insert_result?;
+        Ok(true)
+    }
+}
+
+pub fn build_schema(
+    repository: Arc<dyn Repository>,
+    limits: &RequestLimits,
+    read_cache: Arc<ReadCache>,
+    webhooks: Webhooks,
+) -> GraphQLSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(DataLoader::new(
+            RelatedLoader::<Topic>::new(repository.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            RelatedLoader::<Term>::new(repository.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            RelatedLoader::<Source>::new(repository.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            RelatedLoader::<Question>::new(repository.clone()),
+            tokio::spawn,
+        ))
+        .data(DataLoader::new(
+            RelatedLoader::<Article>::new(repository.clone()),
+            tokio::spawn,
+        ))
+        .data(repository)
+        .data(limits.clone())
+        .data(read_cache)
+        .data(webhooks)
+        .limit_depth(limits.graphql_max_depth)
+        .limit_complexity(limits.graphql_max_complexity)
+        .finish()
+}
+
+/*
+POST /graphql
+Body: {"query": "...", "variables": {...}}, e.g.
+{
+   "query": "{ topic(slug: \"storm\") { topic terms { term sources { name url } } } }"
+}
+The schema can be read with an introspection query. Errors, including queries over the depth
+or complexity limits, are returned in `errors` with a 200 like any GraphQL server.
+ */
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "A GraphQL request: `query`, and optionally `variables` and `operationName`"),
+    responses(
+        (status = 200, description = "The GraphQL response, with `data` and/or `errors`", body = Object),
+        (status = 413, description = "The body is larger than MAX_BODY_BYTES"),
+    )
+)]
+pub async fn graphql_handler(
+    State(schema): State<GraphQLSchema>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema.execute(request).await)
+}
@@ -2,6 +2,12 @@
 Example of a handler function.
 */
 
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "operations",
+    responses((status = 200, description = "The server is up", body = String, content_type = "text/plain"))
+)]
 // 'a is a lifetime annotation
 // syntax: https://doc.rust-lang.org/book/ch10-03-lifetime-syntax.html
 pub async fn hello_world<'a>() -> &'a str {
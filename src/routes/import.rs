@@ -0,0 +1,110 @@
+/*
+Bulk import of topics, terms and sources, see helpers/import.rs for the formats and how
+the records are written and linked.
+ */
+use crate::helpers::import::{
+    import, importable_entity, parse_records, ColumnMapping, ImportFormat, OnExisting,
+};
+use crate::helpers::limits::RequestLimits;
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::Repository;
+use axum::{
+    extract::{Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQueryParams {
+    /// topic, term or source, the type of the records without an `entity_type` of their own
+    entity_type: Option<String>,
+    /// json, ndjson or csv, by default the format of the Content-Type
+    format: Option<ImportFormat>,
+    /// CSV column -> payload field, e.g. `Word:name,Definition:brief_description,Notes:-` where `-` ignores the column
+    mapping: Option<String>,
+    /// What happens to a record whose name is taken: skip (the default) or update
+    on_existing: Option<OnExisting>,
+}
+
+/*
+POST /v1/import?entity_type=term&mapping=Word:name,Definition:brief_description
+Content-Type: text/csv
+Body: the file, e.g.
+Word,Definition,related_topics
+Storm,A violent disturbance of the atmosphere,Weather|Climate
+The report lists every record with its status and id, related names that weren't found
+are listed under `unresolved`.
+ */
+#[utoipa::path(
+    post,
+    path = "/v1/import",
+    tag = "import",
+    params(ImportQueryParams),
+    request_body(content = String, description = "A JSON array, NDJSON or CSV of CreateTopicOrTerm or CreateSource records",
+        content_type = "text/csv"),
+    responses(
+        (status = 200, description = "What was done with every record", body = ImportReport),
+        (status = 400, description = "Unknown format or entity_type, a body that isn't a JSON array, or a CSV column that isn't a field"),
+        (status = 413, description = "The body is larger than MAX_IMPORT_BYTES"),
+    )
+)]
+pub async fn import_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
+    Query(params): Query<ImportQueryParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let default_entity = match params.entity_type.as_deref().map(importable_entity) {
+        Some(Some(entity)) => Some(entity),
+        Some(None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "entity_type must be topic, term or source",
+            )
+                .into_response()
+        }
+        None => None,
+    };
+    let format = params.format.or_else(|| {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(ImportFormat::from_content_type)
+    });
+    let Some(format) = format else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "pass format=json, ndjson or csv, or a Content-Type of application/json, application/x-ndjson or text/csv",
+        )
+            .into_response();
+    };
+    let mapping = match params.mapping.as_deref().map(str::parse::<ColumnMapping>) {
+        Some(Ok(mapping)) => mapping,
+        Some(Err(message)) => return (StatusCode::BAD_REQUEST, message).into_response(),
+        None => ColumnMapping::default(),
+    };
+    let records = match parse_records(default_entity, format, &body, &mapping) {
+        Ok(records) => records,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let report = import(
+        &*repository,
+        &webhooks,
+        &limits,
+        records,
+        params.on_existing.unwrap_or_default(),
+    )
+    .await;
+    read_cache.invalidate_all();
+    Json(report).into_response()
+}
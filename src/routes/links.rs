@@ -1,4 +1,8 @@
 use crate::helpers::handler_utils::update_link_table;
+use crate::helpers::limits::{check_array_lengths, RequestLimits};
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::Repository;
 use axum::{
     extract::State,
     http::StatusCode,
@@ -6,75 +10,79 @@ use axum::{
     Json,
 };
 use serde::Deserialize;
-use sqlx::{FromRow, PgPool};
+use sqlx::Result;
+use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Deserialize, FromRow)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateLink {
-    parent_entity_type: String,
-    child_entity_type: String,
-    parent_id: i32,
-    related_term_ids: Option<Vec<i32>>,
-    related_topic_ids: Option<Vec<i32>>,
-    related_source_ids: Option<Vec<i32>>,
+    pub parent_entity_type: String,
+    pub child_entity_type: String,
+    pub parent_id: i32,
+    pub related_term_ids: Option<Vec<i32>>,
+    pub related_topic_ids: Option<Vec<i32>>,
+    pub related_source_ids: Option<Vec<i32>>,
 }
 
+impl CreateLink {
+    // every array field of the payload, checked against the max_array_length limit
+    pub fn array_fields(&self) -> [(&'static str, &Option<Vec<i32>>); 3] {
+        [
+            ("related_term_ids", &self.related_term_ids),
+            ("related_topic_ids", &self.related_topic_ids),
+            ("related_source_ids", &self.related_source_ids),
+        ]
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/links",
+    tag = "links",
+    request_body = CreateLink,
+    responses(
+        (status = 200, description = "The links were created, existing links are left as they are", body = String, content_type = "text/plain"),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+    )
+)]
 pub async fn new_link_handler(
-    State(db_pool): State<PgPool>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
     Json(payload): Json<CreateLink>,
 ) -> Response {
-    let mut terms_insert_result = Some(Ok(()));
-    let mut topics_insert_result = Some(Ok(()));
-    let mut sources_insert_result = Some(Ok(()));
-
-    if let Some(related_term_ids) = &payload.related_term_ids {
-        terms_insert_result = Some(
-            update_link_table(
-                &payload.parent_entity_type,
-                &payload.child_entity_type,
-                &payload.parent_id,
-                related_term_ids,
-                &db_pool,
-            )
-            .await,
-        );
-    }
-    if let Some(related_topic_ids) = &payload.related_topic_ids {
-        topics_insert_result = Some(
-            update_link_table(
-                &payload.parent_entity_type,
-                &payload.child_entity_type,
-                &payload.parent_id,
-                related_topic_ids,
-                &db_pool,
-            )
-            .await,
-        );
+    if let Err(message) = check_array_lengths(&payload.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
     }
-    if let Some(related_source_ids) = &payload.related_source_ids {
-        sources_insert_result = Some(
-            update_link_table(
-                &payload.parent_entity_type,
-                &payload.child_entity_type,
-                &payload.parent_id,
-                related_source_ids,
-                &db_pool,
-            )
-            .await,
-        );
+    let insert_result = insert_links(&payload, &*repository, &webhooks).await;
+    read_cache.invalidate_all();
+    match insert_result {
+        Ok(_) => "new link created".into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
+}
 
-    match (
-        terms_insert_result,
-        topics_insert_result,
-        sources_insert_result,
-    ) {
-        (Some(Ok(_)), Some(Ok(_)), Some(Ok(_))) => "new link created".into_response(),
-        (Some(Err(error)), _, _) | (_, Some(Err(error)), _) | (_, _, Some(Err(error))) => {
-            (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
-        }
-        _ => {
-            // no updates made
-            (StatusCode::BAD_REQUEST, "no related entities provided").into_response()
-        }
+// links the parent to every id in the payload's id arrays, existing links are left as they are
+pub async fn insert_links(
+    payload: &CreateLink,
+    repository: &dyn Repository,
+    webhooks: &Webhooks,
+) -> Result<()> {
+    let id_arrays = payload.array_fields();
+    for child_ids in id_arrays
+        .iter()
+        .filter_map(|(_, child_ids)| child_ids.as_ref())
+    {
+        update_link_table(
+            repository,
+            webhooks,
+            &payload.parent_entity_type,
+            &payload.child_entity_type,
+            payload.parent_id,
+            child_ids,
+        )
+        .await?;
     }
+    Ok(())
 }
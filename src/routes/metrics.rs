@@ -0,0 +1,29 @@
+use crate::helpers::metrics::REGISTRY;
+use crate::repository::Repository;
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prometheus::{Encoder, TextEncoder};
+use std::sync::Arc;
+
+/*
+ /metrics
+- returns all metrics in the Prometheus text format
+ */
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "All metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics_handler(State(repository): State<Arc<dyn Repository>>) -> Response {
+    repository.record_pool_metrics().await;
+
+    let mut buffer = vec![];
+    match TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        Ok(_) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], buffer).into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
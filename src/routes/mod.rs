@@ -2,53 +2,174 @@
 This file creates the routes.
 */
 
+mod archive;
+mod citations;
+mod events;
+mod export;
+mod graphql;
 mod hello_world;
+mod import;
 mod links;
+mod metrics;
+mod openapi;
+mod relationships;
+mod route_table;
 mod sources;
 mod terms;
 mod topics;
-use axum::http::Method;
+mod v1;
+mod webhooks;
+use crate::helpers::deprecation::{deprecated_alias, DEPRECATION_HEADER};
+use crate::helpers::events::EventFeed;
+use crate::helpers::limits::{rate_limit, RateLimiter, RequestLimits};
+use crate::helpers::metrics::track_metrics;
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::request_tracing::{make_request_span, on_request_end, REQUEST_ID_HEADER};
+use crate::helpers::webhooks::{WebhookSettings, Webhooks};
+use crate::repository::Repository;
+use axum::http::{
+    header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LINK},
+    HeaderName, Method,
+};
 use axum::{
-    extract::FromRef,
-    routing::{get, post},
-    Router,
+    extract::{DefaultBodyLimit, FromRef},
+    middleware, Router,
 };
+use events::{events_handler, LAST_EVENT_ID_HEADER};
+use graphql::{build_schema, graphql_handler, GraphQLSchema};
 use hello_world::hello_world;
 use links::new_link_handler;
-use sources::{get_all_sources_handler, get_source_handler, new_source_handler};
-use sqlx::postgres::PgPool;
+use metrics::metrics_handler;
+use openapi::{docs_handler, openapi_handler};
+use relationships::relationship_routes;
+use route_table::RouteTable;
+use sources::{
+    get_all_sources_handler, get_source_handler, new_source_handler, update_source_handler,
+};
+use std::sync::Arc;
 use terms::{
     get_all_terms_for_topic_handler, get_all_terms_handler, get_term_handler, new_term_handler,
+    update_term_handler,
+};
+use topics::{get_all_topics_handler, get_topic_handler, new_topic_handler, update_topic_handler};
+use tower_http::{
+    cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
 };
-use topics::{get_all_topics_handler, get_topic_handler, new_topic_handler};
-use tower_http::cors::{Any, CorsLayer};
+use v1::v1_routes;
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
-    pub db_pool: PgPool,
+    pub repository: Arc<dyn Repository>,
+    pub limits: RequestLimits,
+    pub read_cache: Arc<ReadCache>,
+    pub graphql_schema: GraphQLSchema,
+    pub events: EventFeed,
+    pub webhooks: Webhooks,
 }
 
-pub fn create_routes(db_pool: PgPool) -> Router {
-    let app_state: AppState = AppState { db_pool };
+pub fn create_routes(
+    repository: Arc<dyn Repository>,
+    limits: RequestLimits,
+    read_cache: ReadCache,
+    webhook_settings: WebhookSettings,
+) -> Router {
+    let rate_limiter = Arc::new(RateLimiter::new(&limits));
+    let body_limit = DefaultBodyLimit::max(limits.max_body_bytes);
+    let read_cache = Arc::new(read_cache);
+    let webhooks = Webhooks::start(repository.clone(), webhook_settings);
+    let graphql_schema = build_schema(
+        repository.clone(),
+        &limits,
+        read_cache.clone(),
+        webhooks.clone(),
+    );
+    let events = repository.start_event_feed();
+    let router = routes(&limits).router;
+    let app_state: AppState = AppState {
+        repository,
+        limits,
+        read_cache,
+        graphql_schema,
+        events,
+        webhooks,
+    };
+
+    let request_id_header = HeaderName::from_static(REQUEST_ID_HEADER);
 
     // Cors settings for all routes
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST])
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            CONTENT_TYPE,
+            IF_MATCH,
+            IF_NONE_MATCH,
+            IF_MODIFIED_SINCE,
+            HeaderName::from_static(LAST_EVENT_ID_HEADER),
+            request_id_header.clone(),
+        ])
+        .expose_headers([
+            ETAG,
+            LAST_MODIFIED,
+            LINK,
+            HeaderName::from_static(DEPRECATION_HEADER),
+            request_id_header.clone(),
+        ])
         .allow_origin(Any);
 
-    Router::new()
-        .route("/", get(hello_world))
-        .route("/topics", get(get_all_topics_handler))
-        .route("/topic", get(get_topic_handler))
-        .route("/terms", get(get_all_terms_handler))
-        .route("/term", get(get_term_handler))
-        .route("/terms-from-topic", get(get_all_terms_for_topic_handler))
-        .route("/new-topic", post(new_topic_handler))
-        .route("/new-term", post(new_term_handler))
-        .route("/sources", get(get_all_sources_handler))
-        .route("/new-source", post(new_source_handler))
-        .route("/source", get(get_source_handler))
-        .route("/link-entities", post(new_link_handler))
+    // the last layer added runs first: a request id is set (or the client's is kept),
+    // then the request span is opened with it, and it is copied onto the response
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(make_request_span)
+        .on_response(on_request_end)
+        // on_request_end already logs 5xx responses
+        .on_failure(());
+
+    router
+        .layer(body_limit)
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit))
+        .layer(middleware::from_fn(track_metrics))
         .layer(cors)
+        .layer(PropagateRequestIdLayer::new(request_id_header.clone()))
+        .layer(trace)
+        .layer(SetRequestIdLayer::new(request_id_header, MakeRequestUuid))
         .with_state(app_state)
 }
+
+// every route, without the state and the middleware
+fn routes(limits: &RequestLimits) -> RouteTable {
+    // deprecated aliases of the /v1 routes
+    let legacy_routes = RouteTable::default()
+        .get("/topics", get_all_topics_handler)
+        .get("/topic", get_topic_handler)
+        .put("/topic", update_topic_handler)
+        .get("/terms", get_all_terms_handler)
+        .get("/term", get_term_handler)
+        .put("/term", update_term_handler)
+        .get("/terms-from-topic", get_all_terms_for_topic_handler)
+        .merge(relationship_routes())
+        .post("/new-topic", new_topic_handler)
+        .post("/new-term", new_term_handler)
+        .get("/sources", get_all_sources_handler)
+        .post("/new-source", new_source_handler)
+        .get("/source", get_source_handler)
+        .put("/source", update_source_handler)
+        .post("/link-entities", new_link_handler)
+        .map_router(|router| router.route_layer(middleware::from_fn(deprecated_alias)));
+
+    RouteTable::default()
+        .get("/", hello_world)
+        .nest("/v1", v1_routes(limits))
+        .merge(legacy_routes)
+        .post("/graphql", graphql_handler)
+        .get("/events", events_handler)
+        .get("/metrics", metrics_handler)
+        .get("/openapi.json", openapi_handler)
+        .get("/docs", docs_handler)
+}
+
+// the method and path of every route, e.g. (GET, "/v1/topics/:id")
+pub fn route_table(limits: &RequestLimits) -> Vec<(Method, String)> {
+    routes(limits).routes
+}
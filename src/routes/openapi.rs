@@ -0,0 +1,334 @@
+/*
+The OpenAPI 3 document at /openapi.json and the docs page at /docs that renders it.
+
+The /v1 operations come from the #[utoipa::path] attributes on the handlers and the
+schemas from the request and response types, so the document can't drift from the code.
+The legacy aliases and the generated relationship routes share their handlers with other
+routes, so their operations are added here. tests/openapi.rs checks that every route in
+mod.rs and v1.rs is in the document.
+ */
+use super::archive::{__path_export_archive_handler, __path_restore_archive_handler};
+use super::citations::{__path_bibliography_handler, __path_citation_handler};
+use super::events::__path_events_handler;
+use super::export::{__path_flashcards_handler, __path_graph_handler};
+use super::graphql::__path_graphql_handler;
+use super::hello_world::__path_hello_world;
+use super::import::__path_import_handler;
+use super::links::{__path_new_link_handler, CreateLink};
+use super::metrics::__path_metrics_handler;
+use super::relationships::{related_pairs, RelatedQueryParams};
+use super::sources::{
+    __path_get_all_sources_handler, __path_get_source_handler, __path_new_source_handler,
+    __path_update_source_handler, Source,
+};
+use super::terms::{
+    __path_get_all_terms_for_topic_handler, __path_get_all_terms_handler, __path_get_term_handler,
+    __path_new_term_handler, __path_update_term_handler, Term,
+};
+use super::topics::{
+    __path_get_all_topics_handler, __path_get_topic_handler, __path_new_topic_handler,
+    __path_update_topic_handler, Topic,
+};
+use super::webhooks::{
+    __path_delete_webhook_handler, __path_get_all_webhooks_handler,
+    __path_get_webhook_deliveries_handler, __path_new_webhook_handler,
+    __path_replay_delivery_handler,
+};
+use crate::helpers::archive::{
+    Archive, ArchiveFormat, ArchiveLink, OnConflict, RenamedRecord, RestoreCounts, RestoreReport,
+};
+use crate::helpers::citations::{Bibliography, Citation, CitationStyle};
+use crate::helpers::fieldsets::EntityFields;
+use crate::helpers::flashcards::{CardFields, FlashcardFormat};
+use crate::helpers::graph::{GraphFormat, GraphLink, GraphNode, KnowledgeGraph};
+use crate::helpers::handler_utils::{CreateTopicOrTerm, UpdateTopicOrTerm};
+use crate::helpers::import::{ImportFormat, ImportReport, ImportRow, ImportStatus, OnExisting};
+use crate::helpers::lookup::LookupParams;
+use crate::helpers::shared_types::{CreateSource, ImageType, MediaType, UpdateSource};
+use crate::helpers::webhooks::{CreateWebhook, NewWebhook, Webhook, WebhookDelivery};
+use axum::{
+    http::header::CONTENT_TYPE,
+    response::{Html, IntoResponse},
+};
+use lazy_static::lazy_static;
+use utoipa::openapi::path::{
+    Operation, OperationBuilder, Parameter, ParameterBuilder, ParameterIn, PathItemType,
+};
+use utoipa::openapi::{
+    ArrayBuilder, Content, Deprecated, ObjectBuilder, OpenApi as OpenApiDocument, Ref, Required,
+    ResponseBuilder, SchemaType,
+};
+use utoipa::{IntoParams, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "jd_crm_api",
+        description = "Topics, terms and sources, and the links between them. The routes outside of /v1 are deprecated aliases kept until the clients have moved."
+    ),
+    paths(
+        get_all_topics_handler,
+        new_topic_handler,
+        get_topic_handler,
+        update_topic_handler,
+        get_all_terms_handler,
+        new_term_handler,
+        get_term_handler,
+        update_term_handler,
+        get_all_terms_for_topic_handler,
+        get_all_sources_handler,
+        new_source_handler,
+        get_source_handler,
+        update_source_handler,
+        citation_handler,
+        bibliography_handler,
+        new_link_handler,
+        get_all_webhooks_handler,
+        new_webhook_handler,
+        delete_webhook_handler,
+        get_webhook_deliveries_handler,
+        replay_delivery_handler,
+        import_handler,
+        export_archive_handler,
+        restore_archive_handler,
+        flashcards_handler,
+        graph_handler,
+        graphql_handler,
+        events_handler,
+        hello_world,
+        metrics_handler,
+        openapi_handler,
+        docs_handler,
+    ),
+    components(schemas(
+        Topic,
+        Term,
+        Source,
+        CreateTopicOrTerm,
+        UpdateTopicOrTerm,
+        CreateSource,
+        UpdateSource,
+        CreateLink,
+        MediaType,
+        ImageType,
+        Webhook,
+        NewWebhook,
+        CreateWebhook,
+        WebhookDelivery,
+        ImportFormat,
+        OnExisting,
+        ImportReport,
+        ImportRow,
+        ImportStatus,
+        Archive,
+        ArchiveLink,
+        ArchiveFormat,
+        OnConflict,
+        RestoreReport,
+        RestoreCounts,
+        RenamedRecord,
+        FlashcardFormat,
+        CardFields,
+        GraphFormat,
+        KnowledgeGraph,
+        GraphNode,
+        GraphLink,
+        CitationStyle,
+        Citation,
+        Bibliography,
+    )),
+    tags(
+        (name = "topics"),
+        (name = "terms"),
+        (name = "sources"),
+        (name = "citations", description = "Citations of sources in APA, MLA, Chicago or BibTeX"),
+        (name = "links", description = "Links between topics, terms and sources"),
+        (name = "webhooks", description = "Signed POSTs to other services when topics, terms and sources are created, verified or linked"),
+        (name = "import", description = "Bulk import of topics, terms and sources from JSON, NDJSON or CSV files"),
+        (name = "archive", description = "Export of every record and link, and restore into this or another database"),
+        (name = "export", description = "Flashcards for Anki or Quizlet, and the knowledge graph for Graphviz or d3"),
+        (name = "graphql", description = "The same records and their relationships over GraphQL"),
+        (name = "events", description = "A feed of every change, from any server instance or psql"),
+        (name = "operations", description = "Health, metrics and this documentation"),
+    )
+)]
+struct ApiDoc;
+
+// legacy route -> the /v1 route it is an alias of
+const LEGACY_ALIASES: [(&str, PathItemType, &str); 13] = [
+    ("/topics", PathItemType::Get, "/v1/topics"),
+    ("/new-topic", PathItemType::Post, "/v1/topics"),
+    ("/topic", PathItemType::Get, "/v1/topics/{id}"),
+    ("/topic", PathItemType::Put, "/v1/topics/{id}"),
+    ("/terms", PathItemType::Get, "/v1/terms"),
+    ("/new-term", PathItemType::Post, "/v1/terms"),
+    ("/term", PathItemType::Get, "/v1/terms/{id}"),
+    ("/term", PathItemType::Put, "/v1/terms/{id}"),
+    ("/sources", PathItemType::Get, "/v1/sources"),
+    ("/new-source", PathItemType::Post, "/v1/sources"),
+    ("/source", PathItemType::Get, "/v1/sources/{id}"),
+    ("/source", PathItemType::Put, "/v1/sources/{id}"),
+    ("/link-entities", PathItemType::Post, "/v1/links"),
+];
+
+lazy_static! {
+    static ref OPENAPI_JSON: String = openapi()
+        .to_pretty_json()
+        .expect("the OpenAPI document can be serialized");
+}
+
+pub fn openapi() -> OpenApiDocument {
+    let mut openapi = ApiDoc::openapi();
+
+    for (legacy_path, method, v1_path) in LEGACY_ALIASES {
+        let Some(operation) = openapi
+            .paths
+            .paths
+            .get(v1_path)
+            .and_then(|path_item| path_item.operations.get(&method))
+        else {
+            continue;
+        };
+        let operation = legacy_operation(operation.clone(), v1_path);
+        openapi
+            .paths
+            .paths
+            .entry(legacy_path.to_owned())
+            .or_default()
+            .operations
+            .insert(method, operation);
+    }
+    if let Some(path_item) = openapi.paths.paths.get_mut("/terms-from-topic") {
+        for operation in path_item.operations.values_mut() {
+            operation.deprecated = Some(Deprecated::True);
+        }
+    }
+
+    for (parent, child) in related_pairs() {
+        let v1_path = format!("/v1/{}/{{id}}/{}", parent.table, child.table);
+        let mut parameters = vec![ParameterBuilder::new()
+            .name("id")
+            .parameter_in(ParameterIn::Path)
+            .required(Required::True)
+            .description(Some(format!(
+                "The {}'s id, or its slug when it isn't numeric",
+                parent.entity_type
+            )))
+            .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String)))
+            .build()];
+        parameters.extend(RelatedQueryParams::into_params(|| Some(ParameterIn::Query)));
+        let operation = related_operation(parent, child, parameters);
+        openapi
+            .paths
+            .paths
+            .entry(v1_path.clone())
+            .or_default()
+            .operations
+            .insert(PathItemType::Get, operation.clone());
+
+        if (parent.entity_type, child.entity_type) == ("topic", "term") {
+            continue;
+        }
+        let legacy_path = format!("/{}-from-{}", child.table, parent.entity_type);
+        openapi
+            .paths
+            .paths
+            .entry(legacy_path)
+            .or_default()
+            .operations
+            .insert(PathItemType::Get, legacy_operation(operation, &v1_path));
+    }
+    openapi
+}
+
+/*
+A legacy route takes the record as ?id=, ?name= or ?slug= instead of in the path, and
+needs its own operationId since operationIds are unique within the document.
+ */
+fn legacy_operation(mut operation: Operation, v1_path: &str) -> Operation {
+    let mut parameters: Vec<Parameter> = operation.parameters.take().unwrap_or_default();
+    if let Some(position) = parameters
+        .iter()
+        .position(|parameter| parameter.parameter_in == ParameterIn::Path)
+    {
+        parameters.splice(
+            position..=position,
+            LookupParams::into_params(|| Some(ParameterIn::Query)),
+        );
+    }
+    operation.parameters = Some(parameters).filter(|parameters| !parameters.is_empty());
+    operation.operation_id = operation
+        .operation_id
+        .map(|operation_id| format!("legacy_{}", operation_id));
+    operation.description = Some(format!("Deprecated alias of `{}`.", v1_path));
+    operation.deprecated = Some(Deprecated::True);
+    operation
+}
+
+fn related_operation(
+    parent: &EntityFields,
+    child: &EntityFields,
+    parameters: Vec<Parameter>,
+) -> Operation {
+    let schema_name = capitalize(child.entity_type);
+    let records = ArrayBuilder::new().items(Ref::from_schema_name(schema_name));
+    OperationBuilder::new()
+        .tag(parent.table)
+        .operation_id(Some(format!(
+            "get_{}_of_{}",
+            child.table, parent.entity_type
+        )))
+        .summary(Some(format!(
+            "One page of the {} linked to a {}",
+            child.table, parent.entity_type
+        )))
+        .parameters(Some(parameters))
+        .response(
+            "200",
+            ResponseBuilder::new()
+                .description(format!(
+                    "The linked {}, ordered by id, only with the requested columns when `fields` is given",
+                    child.table
+                ))
+                .content("application/json", Content::new(records)),
+        )
+        .response(
+            "400",
+            ResponseBuilder::new()
+                .description("Unknown field in `fields`, or `limit` or `offset` out of range"),
+        )
+        .response(
+            "404",
+            ResponseBuilder::new().description(format!("No such {}", parent.entity_type)),
+        )
+        .build()
+}
+
+// "term" -> "Term", the name of its schema
+fn capitalize(entity_type: &str) -> String {
+    let mut characters = entity_type.chars();
+    match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters).collect(),
+        None => String::new(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "operations",
+    responses((status = 200, description = "This document", content_type = "application/json", body = Object))
+)]
+pub async fn openapi_handler() -> impl IntoResponse {
+    ([(CONTENT_TYPE, "application/json")], OPENAPI_JSON.as_str())
+}
+
+#[utoipa::path(
+    get,
+    path = "/docs",
+    tag = "operations",
+    responses((status = 200, description = "A page rendering /openapi.json", body = String, content_type = "text/html"))
+)]
+pub async fn docs_handler() -> Html<&'static str> {
+    Html(include_str!("docs.html"))
+}
@@ -0,0 +1,164 @@
+/*
+Reverse relationship reads, e.g. /topics-from-term?slug=storm or /v1/topics/1/sources.
+
+A route is added for every pair in LINK_TABLES, so a new bridge table gets its reads
+without any changes here. The legacy /terms-from-topic keeps its own handler (see terms.rs),
+which also supports updated_since and NDJSON.
+ */
+use super::route_table::RouteTable;
+use crate::helpers::conditional_get::conditional_json_response_with_last_modified;
+use crate::helpers::fieldsets::{entity_fields, parse_fields, select_list, EntityFields};
+use crate::helpers::handler_utils::LINK_TABLES;
+use crate::helpers::lookup::{resolve_entity_id, LookupParams};
+use crate::helpers::read_cache::ReadCache;
+use crate::repository::Repository;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+pub(super) const DEFAULT_PAGE_SIZE: i64 = 50;
+pub(super) const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RelatedQueryParams {
+    /// Page size, 50 by default and at most 500
+    limit: Option<i64>,
+    /// Number of records to skip, records are ordered by id
+    offset: Option<i64>,
+    /// Comma separated columns to return, see helpers/fieldsets.rs
+    fields: Option<String>,
+}
+
+// every (parent, child) pair of entity types in LINK_TABLES
+pub(super) fn related_pairs() -> Vec<(&'static EntityFields, &'static EntityFields)> {
+    let mut pairs = vec![];
+    for (parent_type, children) in LINK_TABLES.iter() {
+        for child_type in children.keys() {
+            if let (Some(parent), Some(child)) =
+                (entity_fields(parent_type), entity_fields(child_type))
+            {
+                pairs.push((parent, child));
+            }
+        }
+    }
+    pairs
+}
+
+fn add_related_route(
+    routes: RouteTable,
+    path: &str,
+    parent: &'static EntityFields,
+    child: &'static EntityFields,
+) -> RouteTable {
+    routes.get(
+        path,
+        move |State(repository): State<Arc<dyn Repository>>,
+              State(read_cache): State<Arc<ReadCache>>,
+              headers: HeaderMap,
+              lookup: LookupParams,
+              Query(params): Query<RelatedQueryParams>| {
+            get_related_handler(
+                repository, read_cache, headers, lookup, params, parent, child,
+            )
+        },
+    )
+}
+
+// /<child>s-from-<parent>?id=<parent id>
+pub fn relationship_routes() -> RouteTable {
+    let mut routes = RouteTable::default();
+    for (parent, child) in related_pairs() {
+        if (parent.entity_type, child.entity_type) == ("topic", "term") {
+            continue;
+        }
+        let path = format!("/{}-from-{}", child.table, parent.entity_type);
+        routes = add_related_route(routes, &path, parent, child);
+    }
+    routes
+}
+
+// /v1/<parent>s/:id/<child>s
+pub fn v1_relationship_routes() -> RouteTable {
+    let mut routes = RouteTable::default();
+    for (parent, child) in related_pairs() {
+        let path = format!("/{}/:id/{}", parent.table, child.table);
+        routes = add_related_route(routes, &path, parent, child);
+    }
+    routes
+}
+
+/*
+/<child>s-from-<parent> and /v1/<parent>s/:id/<child>s
+- `id`, `name` or `slug` of the parent, exactly one is required (see helpers/lookup.rs)
+- `limit` (default 50, at most 500) and `offset` page through the related records, ordered by id
+- optional `fields` as on the other GET endpoints
+ */
+async fn get_related_handler(
+    repository: Arc<dyn Repository>,
+    read_cache: Arc<ReadCache>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    params: RelatedQueryParams,
+    parent: &'static EntityFields,
+    child: &'static EntityFields,
+) -> Response {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_PAGE_SIZE),
+        )
+            .into_response();
+    }
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return (StatusCode::BAD_REQUEST, "offset can't be negative").into_response();
+    }
+    let fields = match params
+        .fields
+        .as_deref()
+        .map(|fields| parse_fields(fields, child))
+    {
+        None => child.fields.to_vec(),
+        Some(Ok(fields)) => fields,
+        Some(Err(message)) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let parent_id = match resolve_entity_id(&*repository, parent, &lookup).await {
+        Ok(parent_id) => parent_id,
+        Err(error) => return error.into_response(),
+    };
+
+    let cache_key = format!(
+        "{}-from-{}?id={}&fields={}&limit={}&offset={}",
+        child.table,
+        parent.entity_type,
+        parent_id,
+        select_list(&fields, child.table),
+        limit,
+        offset
+    );
+    let related = read_cache
+        .get_or_load(cache_key, || {
+            repository.get_related_page(
+                parent.entity_type,
+                parent_id,
+                child.entity_type,
+                &fields,
+                limit,
+                offset,
+            )
+        })
+        .await;
+
+    match related {
+        Ok(related) => conditional_json_response_with_last_modified(&headers, &*related, None),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
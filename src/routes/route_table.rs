@@ -0,0 +1,74 @@
+/*
+A router that keeps a list of its routes as (method, path), e.g. (GET, "/v1/topics/:id"),
+because axum's Router can't list them. tests/openapi.rs checks the list against the
+OpenAPI document, so every route is added through RouteTable with its method.
+ */
+use super::AppState;
+use axum::{
+    handler::Handler,
+    http::Method,
+    routing::{on, MethodFilter, MethodRouter},
+    Router,
+};
+
+#[derive(Default)]
+pub struct RouteTable {
+    pub router: Router<AppState>,
+    pub routes: Vec<(Method, String)>,
+}
+
+impl RouteTable {
+    pub fn get<H: Handler<T, AppState>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(Method::GET, path, handler, |route| route)
+    }
+
+    pub fn post<H: Handler<T, AppState>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(Method::POST, path, handler, |route| route)
+    }
+
+    pub fn put<H: Handler<T, AppState>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(Method::PUT, path, handler, |route| route)
+    }
+
+    pub fn delete<H: Handler<T, AppState>, T: 'static>(self, path: &str, handler: H) -> Self {
+        self.route(Method::DELETE, path, handler, |route| route)
+    }
+
+    // `layer` wraps the route, e.g. in a body limit of its own
+    pub fn route<H: Handler<T, AppState>, T: 'static>(
+        mut self,
+        method: Method,
+        path: &str,
+        handler: H,
+        layer: impl FnOnce(MethodRouter<AppState>) -> MethodRouter<AppState>,
+    ) -> Self {
+        let filter = MethodFilter::try_from(method.clone()).expect("a method axum can route");
+        // the method routers of a path are merged by axum
+        self.router = self.router.route(path, layer(on(filter, handler)));
+        self.routes.push((method, path.to_owned()));
+        self
+    }
+
+    pub fn merge(mut self, other: RouteTable) -> Self {
+        self.router = self.router.merge(other.router);
+        self.routes.extend(other.routes);
+        self
+    }
+
+    pub fn nest(mut self, prefix: &str, other: RouteTable) -> Self {
+        self.router = self.router.nest(prefix, other.router);
+        self.routes.extend(
+            other
+                .routes
+                .into_iter()
+                .map(|(method, path)| (method, format!("{}{}", prefix, path))),
+        );
+        self
+    }
+
+    // changes the router without adding routes, e.g. adds a route_layer
+    pub fn map_router(mut self, map: impl FnOnce(Router<AppState>) -> Router<AppState>) -> Self {
+        self.router = map(self.router);
+        self
+    }
+}
@@ -1,18 +1,39 @@
-use crate::helpers::handler_utils::build_link_tables;
-use crate::helpers::shared_types::{CreateSource, ImageType, MediaType};
+use crate::helpers::conditional_get::{
+    conditional_json_response, conditional_versioned_json_response, expected_version,
+    precondition_failed_response, precondition_required_response, update_if_match, LastModified,
+    RowVersion,
+};
+use crate::helpers::fieldsets::{sparse_list_response, sparse_record_response, SOURCE_FIELDS};
+use crate::helpers::handler_utils::{build_link_tables, CreateEntity, UpdateOutcome};
+use crate::helpers::includes::record_with_includes_response;
+use crate::helpers::limits::{check_array_lengths, RequestLimits};
+use crate::helpers::lookup::{resolve_entity_id, LookupParams};
+use crate::helpers::metrics::ENTITIES_CREATED_TOTAL;
+use crate::helpers::ndjson::{ndjson_response, wants_ndjson};
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::shared_types::{
+    CreateSource, ImageType, ListQueryParams, MediaType, RecordQueryParams, UpdateSource,
+};
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::{get_all, get_one, stream_all, Repository};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
+use futures::Stream;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool, Result};
+use sqlx::Result;
+use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Source {
     id: i32,
     name: Option<String>,
+    slug: String,
     url: Option<String>,
     author: Option<String>,
     author_url: Option<String>,
@@ -20,58 +41,130 @@ pub struct Source {
     image_url: Option<String>,
     image_type: Option<ImageType>,
     ai_generated: Option<bool>,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
 }
 
-#[derive(Deserialize)]
-pub struct GetSourceQueryParams {
-    id: i32,
+impl LastModified for Source {
+    fn last_modified(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated_at)
+    }
+}
+
+impl RowVersion for Source {
+    fn version(&self) -> i32 {
+        self.version
+    }
 }
 
 /*
  /sources
 - returns all sources
+- optional `updated_since` only returns sources created or updated at or after that time
+- streams one source per line with `Accept: application/x-ndjson`
+- optional `fields` returns only those columns, e.g. fields=id,name
  */
-pub async fn get_all_sources_handler(State(db_pool): State<PgPool>) -> Response {
-    let sources = get_all_sources(&db_pool).await;
+#[utoipa::path(
+    get,
+    path = "/v1/sources",
+    tag = "sources",
+    params(ListQueryParams),
+    responses(
+        (status = 200, description = "All sources, only with the requested columns when `fields` is given",
+            content(("application/json" = [Source]), ("application/x-ndjson" = Source))),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Unknown field in `fields`"),
+    )
+)]
+pub async fn get_all_sources_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
+    Query(params): Query<ListQueryParams>,
+) -> Response {
+    if let Some(fields) = &params.fields {
+        return sparse_list_response(
+            repository,
+            &read_cache,
+            &headers,
+            &SOURCE_FIELDS,
+            fields,
+            params.updated_since,
+        )
+        .await;
+    }
+    if wants_ndjson(&headers) {
+        return ndjson_response(stream_all_sources(&*repository, params.updated_since));
+    }
+    let cache_key = format!("sources?updated_since={:?}", params.updated_since);
+    let sources = read_cache
+        .get_or_load(cache_key, || {
+            get_all_sources(&*repository, params.updated_since)
+        })
+        .await;
     match sources {
-        Ok(sources) => (StatusCode::OK, Json(sources)).into_response(),
+        Ok(sources) => conditional_json_response(&headers, &*sources),
         // for errors Axum expects the axum::response::Response type
         // example output: error returned from database: relation "platform.tipics" does not exist
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_all_sources(db_pool: &PgPool) -> Result<Vec<Source>> {
-    let sources = sqlx::query_as::<_, Source>(
-        "SELECT id,
-        name,
-        url,
-        author,
-        author_url,
-        media_type,
-        image_url,
-        image_type,
-        ai_generated
-    FROM platform.sources",
-    )
-    .fetch_all(db_pool)
-    .await?;
-    Ok(sources)
+#[tracing::instrument(skip(repository), err)]
+pub async fn get_all_sources(
+    repository: &dyn Repository,
+    updated_since: Option<DateTime<Utc>>,
+) -> Result<Vec<Source>> {
+    get_all(repository, &SOURCE_FIELDS, updated_since).await
+}
+
+// same records as get_all_sources, yielded as they are read
+pub fn stream_all_sources(
+    repository: &dyn Repository,
+    updated_since: Option<DateTime<Utc>>,
+) -> impl Stream<Item = Result<Source>> {
+    stream_all(repository, &SOURCE_FIELDS, updated_since)
 }
 
 /*
 /new-source
-Body:
+Body (all fields are listed under CreateSource in /openapi.json):
 {
-   "value": "<new_source_name>"
+   "name": "<new_source_name>"
 }
 */
+#[utoipa::path(
+    post,
+    path = "/v1/sources",
+    tag = "sources",
+    request_body = CreateSource,
+    responses(
+        (status = 200, description = "The source was created", body = String, content_type = "text/plain"),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+        (status = 413, description = "The body is larger than MAX_BODY_BYTES"),
+    )
+)]
 pub async fn new_source_handler(
-    State(db_pool): State<PgPool>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
     Json(payload): Json<CreateSource>,
 ) -> Response {
-    let insert_result = insert_source(&payload, &db_pool).await;
-    let link_insert_result = build_link_tables(&payload, "source", &db_pool).await;
+    if let Err(message) = check_array_lengths(&payload.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let insert_result = insert_source(&*repository, &payload).await;
+    let link_insert_result = match &insert_result {
+        Ok(id) => {
+            // created goes out before the linked events of the related names
+            webhooks.record_event("source", "created", *id).await;
+            build_link_tables(&*repository, &webhooks, &payload, "source", *id).await
+        }
+        Err(_) => Ok(()),
+    };
+    read_cache.invalidate_all();
     match (insert_result, link_insert_result) {
         (Ok(_), Ok(_)) => "new source created".into_response(),
         (Err(error), _) | (_, Err(error)) => {
@@ -80,49 +173,143 @@ pub async fn new_source_handler(
     }
 }
 
-pub async fn insert_source(payload: &CreateSource, db_pool: &PgPool) -> Result<()> {
-    let _insert_result = sqlx::query(
-        "
-                INSERT INTO platform.sources 
-                    (name,
-                    url,
-                    author,
-                    author_url,
-                    media_type,
-                    image_url,
-                    image_type,
-                    ai_generated) 
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-    )
-    .bind(&payload.name)
-    .bind(&payload.url)
-    .bind(&payload.author)
-    .bind(&payload.author_url)
-    .bind(&payload.media_type)
-    .bind(&payload.image_url)
-    .bind(&payload.image_type)
-    .bind(&payload.ai_generated)
-    .execute(db_pool)
-    .await;
-
-    Ok(())
+// returns the new source's id
+pub async fn insert_source(repository: &dyn Repository, payload: &CreateSource) -> Result<i32> {
+    let id = repository.insert_source(payload).await?;
+    ENTITIES_CREATED_TOTAL.with_label_values(&["source"]).inc();
+    Ok(id)
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/sources/{id}",
+    tag = "sources",
+    params(
+        ("id" = String, Path, description = "The source's id, or its slug when it isn't numeric"),
+        RecordQueryParams,
+    ),
+    responses(
+        (status = 200, description = "The source, only with the requested columns when `fields` is given, with the related records when `include` is given", body = Source),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Unknown field in `fields` or `include`"),
+        (status = 404, description = "No source with this id or slug"),
+    )
+)]
 pub async fn get_source_handler(
-    State(db_pool): State<PgPool>,
-    params: axum::extract::Query<GetSourceQueryParams>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    Query(params): Query<RecordQueryParams>,
 ) -> Response {
-    let source = get_source(&db_pool, &params.id).await;
+    let id = match resolve_entity_id(&*repository, &SOURCE_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    if let Some(include) = &params.include {
+        return record_with_includes_response(
+            repository,
+            &read_cache,
+            &headers,
+            &SOURCE_FIELDS,
+            id,
+            params.fields.as_deref(),
+            include,
+        )
+        .await;
+    }
+    if let Some(fields) = &params.fields {
+        return sparse_record_response(
+            repository,
+            &read_cache,
+            &headers,
+            &SOURCE_FIELDS,
+            fields,
+            id,
+        )
+        .await;
+    }
+    let cache_key = format!("source?id={}", id);
+    let source = read_cache
+        .get_or_load(cache_key, || get_source(&*repository, id))
+        .await;
     match source {
-        Ok(source) => (StatusCode::OK, Json(source)).into_response(),
+        Ok(source) => conditional_versioned_json_response(&headers, &*source),
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_source(db_pool: &PgPool, id: &i32) -> Result<Source> {
-    let source = sqlx::query_as::<_, Source>("SELECT * from platform.sources where id = $1")
-        .bind(id)
-        .fetch_one(db_pool)
-        .await?;
-    Ok(source)
+#[tracing::instrument(skip(repository), err)]
+pub async fn get_source(repository: &dyn Repository, id: i32) -> Result<Source> {
+    get_one(repository, &SOURCE_FIELDS, id).await
+}
+
+/*
+PUT /source?id=<id>
+Replaces the source's fields, see update_term_handler for the version requirements.
+Body: same fields as /new-source
+ */
+#[utoipa::path(
+    put,
+    path = "/v1/sources/{id}",
+    tag = "sources",
+    params(
+        ("id" = String, Path, description = "The source's id, or its slug when it isn't numeric"),
+        ("If-Match" = Option<String>, Header, description = "The ETag of the version the edit is based on, e.g. \"3\", or * for any version, instead of `version` in the body"),
+    ),
+    request_body = UpdateSource,
+    responses(
+        (status = 200, description = "The updated source", body = Source),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+        (status = 404, description = "No source with this id or slug"),
+        (status = 412, description = "The source was changed since the given version, the body has the `current_version`"),
+        (status = 428, description = "Neither If-Match nor `version` was sent"),
+    )
+)]
+pub async fn update_source_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    Json(payload): Json<UpdateSource>,
+) -> Response {
+    let id = match resolve_entity_id(&*repository, &SOURCE_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    if let Err(message) = check_array_lengths(&payload.fields.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let Some(expected_version) = expected_version(&headers, payload.version) else {
+        return precondition_required_response();
+    };
+    let update_result = update_if_match(&expected_version, |version| {
+        repository.update_source(&payload.fields, id, version)
+    })
+    .await;
+    match update_result {
+        Ok(UpdateOutcome::Updated { .. }) => {}
+        Ok(UpdateOutcome::VersionConflict { current_version }) => {
+            return precondition_failed_response(current_version)
+        }
+        Ok(UpdateOutcome::NotFound) => {
+            return (StatusCode::NOT_FOUND, "source not found").into_response()
+        }
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    }
+
+    let link_insert_result =
+        build_link_tables(&*repository, &webhooks, &payload.fields, "source", id).await;
+    read_cache.invalidate_all();
+    let source = get_source(&*repository, id).await;
+    match (link_insert_result, source) {
+        (Ok(_), Ok(source)) => conditional_versioned_json_response(&HeaderMap::new(), &source),
+        (Err(error), _) | (_, Err(error)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    }
 }
@@ -1,17 +1,42 @@
-use crate::helpers::handler_utils::{build_link_tables, insert_topic_or_term, CreateTopicOrTerm};
+use crate::helpers::conditional_get::{
+    conditional_json_response, conditional_json_response_with_last_modified,
+    conditional_versioned_json_response, expected_version, precondition_failed_response,
+    precondition_required_response, update_if_match, LastModified, RowVersion,
+};
+use crate::helpers::fieldsets::{
+    parse_fields, sparse_list_response, sparse_record_response, SparseRecord, TERM_FIELDS,
+    TOPIC_FIELDS,
+};
+use crate::helpers::handler_utils::{
+    build_link_tables, insert_topic_or_term, CreateEntity, CreateTopicOrTerm, UpdateOutcome,
+    UpdateTopicOrTerm,
+};
+use crate::helpers::includes::record_with_includes_response;
+use crate::helpers::limits::{check_array_lengths, RequestLimits};
+use crate::helpers::lookup::{resolve_entity_id, LookupError, LookupParams};
+use crate::helpers::ndjson::{ndjson_response, wants_ndjson};
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::shared_types::{ListQueryParams, RecordQueryParams};
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::{from_record, get_all, get_one, stream_all, Repository};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, FromRow, PgPool, Result};
+use sqlx::Result;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Term {
     id: i32,
     term: String,
+    slug: String,
     is_verified: bool,
     brief_description: Option<String>,
     full_description: Option<String>,
@@ -23,89 +48,252 @@ pub struct Term {
     ai_bullet_points: Option<Vec<String>>,
     ai_parallels: Option<Vec<String>>,
     ai_examples: Option<Vec<String>>,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
 }
 
-#[derive(Deserialize)]
-pub struct AllTermsQueryParams {
-    topic: String,
+impl LastModified for Term {
+    fn last_modified(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated_at)
+    }
 }
 
-#[derive(Deserialize)]
-pub struct GetTermQueryParams {
-    id: i32,
+impl RowVersion for Term {
+    fn version(&self) -> i32 {
+        self.version
+    }
 }
 
-pub async fn get_all_terms_handler(State(db_pool): State<PgPool>) -> Response {
-    let terms = get_all_terms(&db_pool).await;
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AllTermsQueryParams {
+    // the topic is given by exactly one of its name (case-insensitive), id or slug
+    /// The topic's name, matched case-insensitively
+    topic: Option<String>,
+    /// The topic's id
+    topic_id: Option<i32>,
+    /// The topic's slug
+    topic_slug: Option<String>,
+    /// Only return terms created, updated or linked to the topic at or after this time (RFC 3339)
+    updated_since: Option<DateTime<Utc>>,
+    /// Comma separated columns to return, see helpers/fieldsets.rs
+    fields: Option<String>,
+}
+
+/*
+ /terms
+- returns all terms
+- optional `updated_since` only returns terms created or updated at or after that time
+- streams one term per line with `Accept: application/x-ndjson`
+- optional `fields` returns only those columns, e.g. fields=id,term
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/terms",
+    tag = "terms",
+    params(ListQueryParams),
+    responses(
+        (status = 200, description = "All terms, only with the requested columns when `fields` is given",
+            content(("application/json" = [Term]), ("application/x-ndjson" = Term))),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Unknown field in `fields`"),
+    )
+)]
+pub async fn get_all_terms_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
+    Query(params): Query<ListQueryParams>,
+) -> Response {
+    if let Some(fields) = &params.fields {
+        return sparse_list_response(
+            repository,
+            &read_cache,
+            &headers,
+            &TERM_FIELDS,
+            fields,
+            params.updated_since,
+        )
+        .await;
+    }
+    if wants_ndjson(&headers) {
+        return ndjson_response(stream_all_terms(&*repository, params.updated_since));
+    }
+    let cache_key = format!("terms?updated_since={:?}", params.updated_since);
+    let terms = read_cache
+        .get_or_load(cache_key, || {
+            get_all_terms(&*repository, params.updated_since)
+        })
+        .await;
     match terms {
-        Ok(terms) => (StatusCode::OK, Json(terms)).into_response(),
+        Ok(terms) => conditional_json_response(&headers, &*terms),
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_all_terms(db_pool: &PgPool) -> Result<Vec<Term>> {
-    let terms = sqlx::query_as::<_, Term>(
-        "SELECT id, term, is_verified, brief_description,
-    full_description, bullet_points, examples, parallels, ai_brief_description, ai_full_description,
-    ai_bullet_points, ai_parallels, ai_examples
-    FROM platform.terms",
-    )
-    .fetch_all(db_pool)
-    .await?;
-    Ok(terms)
+#[tracing::instrument(skip(repository), err)]
+pub async fn get_all_terms(
+    repository: &dyn Repository,
+    updated_since: Option<DateTime<Utc>>,
+) -> Result<Vec<Term>> {
+    get_all(repository, &TERM_FIELDS, updated_since).await
+}
+
+// same records as get_all_terms, yielded as they are read
+pub fn stream_all_terms(
+    repository: &dyn Repository,
+    updated_since: Option<DateTime<Utc>>,
+) -> impl Stream<Item = Result<Term>> {
+    stream_all(repository, &TERM_FIELDS, updated_since)
 }
 
 /*
 Ex1:
 http://localhost:3000/terms-from-topic?topic=new%20topic
+Ex2:
+http://localhost:3000/terms-from-topic?topic=new%20topic&updated_since=2023-04-01T00:00:00Z
+The topic can also be given as `topic_id` or `topic_slug`, an unknown topic is a 404.
+Streams one term per line with `Accept: application/x-ndjson`.
+Optional `fields` returns only those columns, e.g. fields=id,term
  */
+#[utoipa::path(
+    get,
+    path = "/terms-from-topic",
+    tag = "terms",
+    params(AllTermsQueryParams),
+    responses(
+        (status = 200, description = "The terms linked to the topic, only with the requested columns when `fields` is given",
+            content(("application/json" = [Term]), ("application/x-ndjson" = Term))),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Not exactly one of topic, topic_id or topic_slug, or an unknown field in `fields`"),
+        (status = 404, description = "No such topic"),
+    )
+)]
 pub async fn get_all_terms_for_topic_handler(
-    State(db_pool): State<PgPool>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
     params: axum::extract::Query<AllTermsQueryParams>,
 ) -> Response {
-    let terms = get_all_terms_for_a_topic(&db_pool, &params.topic).await;
+    let topic_lookup = LookupParams {
+        id: params.topic_id,
+        name: params.topic.clone(),
+        slug: params.topic_slug.clone(),
+    };
+    let topic_id = match resolve_entity_id(&*repository, &TOPIC_FIELDS, &topic_lookup).await {
+        Ok(topic_id) => topic_id,
+        Err(LookupError::BadRequest(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "pass exactly one of topic, topic_id or topic_slug",
+            )
+                .into_response()
+        }
+        Err(error) => return error.into_response(),
+    };
+    if let Some(fields) = &params.fields {
+        let fields = match parse_fields(fields, &TERM_FIELDS) {
+            Ok(fields) => fields,
+            Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+        };
+        let terms = repository.stream_terms_for_topic(fields, topic_id, params.updated_since);
+        if wants_ndjson(&headers) {
+            return ndjson_response(terms);
+        }
+        // not cached, the cache key would have to include every field combination
+        return match terms.try_collect::<Vec<SparseRecord>>().await {
+            Ok(terms) => conditional_json_response_with_last_modified(&headers, &terms, None),
+            Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+        };
+    }
+    if wants_ndjson(&headers) {
+        return ndjson_response(stream_all_terms_for_a_topic(
+            &*repository,
+            topic_id,
+            params.updated_since,
+        ));
+    }
+    let cache_key = format!(
+        "terms-from-topic?topic_id={}&updated_since={:?}",
+        topic_id, params.updated_since
+    );
+    let terms = read_cache
+        .get_or_load(cache_key, || async {
+            let terms: Vec<Term> =
+                stream_all_terms_for_a_topic(&*repository, topic_id, params.updated_since)
+                    .try_collect()
+                    .await?;
+            // linking an existing term to the topic changes the response without touching the term
+            let links_last_modified = repository
+                .get_terms_to_topic_last_modified(topic_id)
+                .await?;
+            let last_modified = terms.last_modified().max(links_last_modified);
+            Ok::<_, sqlx::Error>((terms, last_modified))
+        })
+        .await;
 
     match terms {
-        Ok(terms) => (StatusCode::OK, Json(terms)).into_response(),
+        Ok(terms) => {
+            let (terms, last_modified) = &*terms;
+            conditional_json_response_with_last_modified(&headers, terms, *last_modified)
+        }
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_all_terms_for_a_topic(db_pool: &PgPool, topic: &str) -> Result<Vec<Term>> {
-    // first get topic id
-    let record = query!("SELECT id from platform.topics where topic = $1", topic)
-        .fetch_one(db_pool)
-        .await?;
-
-    let terms: Vec<Term> = sqlx::query_as!(
-        Term,
-        "SELECT id, term, is_verified, brief_description,
-        full_description, bullet_points, examples, parallels, ai_brief_description, ai_full_description,
-        ai_bullet_points, ai_parallels, ai_examples FROM platform.terms as terms 
-        INNER JOIN platform.terms_to_topics as terms_to_topics on 
-        terms.id = terms_to_topics.term_id 
-        where terms_to_topics.topic_id = $1",
-        record.id
-    )
-    .fetch_all(db_pool)
-    .await?;
-
-    Ok(terms)
+// the terms linked to the topic, updated or linked at or after `updated_since`
+pub fn stream_all_terms_for_a_topic(
+    repository: &dyn Repository,
+    topic_id: i32,
+    updated_since: Option<DateTime<Utc>>,
+) -> impl Stream<Item = Result<Term>> {
+    repository
+        .stream_terms_for_topic(TERM_FIELDS.fields.to_vec(), topic_id, updated_since)
+        .map(|term| term.and_then(from_record))
 }
 
 /*
-/new-topic
-Body:
+/new-term
+Body (all fields are listed under CreateTopicOrTerm in /openapi.json):
 {
-   "topic": "<new_topic_name>"
+   "name": "<new_term_name>",
+   "is_verified": false
 }
 */
+#[utoipa::path(
+    post,
+    path = "/v1/terms",
+    tag = "terms",
+    request_body = CreateTopicOrTerm,
+    responses(
+        (status = 200, description = "The term was created", body = String, content_type = "text/plain"),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+        (status = 413, description = "The body is larger than MAX_BODY_BYTES"),
+    )
+)]
 pub async fn new_term_handler(
-    State(db_pool): State<PgPool>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
     Json(payload): Json<CreateTopicOrTerm>,
 ) -> Response {
-    let insert_result = insert_topic_or_term(&payload, "term", &db_pool).await;
-    let link_insert_result = build_link_tables(&payload, "term", &db_pool).await;
+    if let Err(message) = check_array_lengths(&payload.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let insert_result = insert_topic_or_term(&*repository, &payload, "term").await;
+    let link_insert_result = match &insert_result {
+        Ok(id) => {
+            // created goes out before the linked events of the related names
+            webhooks
+                .record_created("term", *id, payload.is_verified())
+                .await;
+            build_link_tables(&*repository, &webhooks, &payload, "term", *id).await
+        }
+        Err(_) => Ok(()),
+    };
+    read_cache.invalidate_all();
     match (insert_result, link_insert_result) {
         (Ok(_), Ok(_)) => "new term created".into_response(),
         (Err(error), _) | (_, Err(error)) => {
@@ -114,20 +302,134 @@ pub async fn new_term_handler(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/terms/{id}",
+    tag = "terms",
+    params(
+        ("id" = String, Path, description = "The term's id, or its slug when it isn't numeric"),
+        RecordQueryParams,
+    ),
+    responses(
+        (status = 200, description = "The term, only with the requested columns when `fields` is given, with the related records when `include` is given", body = Term),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Unknown field in `fields` or `include`"),
+        (status = 404, description = "No term with this id or slug"),
+    )
+)]
 pub async fn get_term_handler(
-    State(db_pool): State<PgPool>,
-    params: axum::extract::Query<GetTermQueryParams>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    Query(params): Query<RecordQueryParams>,
 ) -> Response {
-    let term = get_term(&db_pool, &params.id).await;
+    let id = match resolve_entity_id(&*repository, &TERM_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    if let Some(include) = &params.include {
+        return record_with_includes_response(
+            repository,
+            &read_cache,
+            &headers,
+            &TERM_FIELDS,
+            id,
+            params.fields.as_deref(),
+            include,
+        )
+        .await;
+    }
+    if let Some(fields) = &params.fields {
+        return sparse_record_response(repository, &read_cache, &headers, &TERM_FIELDS, fields, id)
+            .await;
+    }
+    let cache_key = format!("term?id={}", id);
+    let term = read_cache
+        .get_or_load(cache_key, || get_term(&*repository, id))
+        .await;
     match term {
-        Ok(term) => (StatusCode::OK, Json(term)).into_response(),
+        Ok(term) => conditional_versioned_json_response(&headers, &*term),
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_term(db_pool: &PgPool, id: &i32) -> Result<Term> {
-    let term = sqlx::query_as!(Term, "SELECT * from platform.terms where id = $1", id)
-        .fetch_one(db_pool)
-        .await?;
-    Ok(term)
+#[tracing::instrument(skip(repository), err)]
+pub async fn get_term(repository: &dyn Repository, id: i32) -> Result<Term> {
+    get_one(repository, &TERM_FIELDS, id).await
+}
+
+/*
+PUT /term?id=<id>
+Replaces the term's fields, related_* entries are linked in addition to the existing links.
+The version the edit is based on must be sent as If-Match: "<version>" (the ETag of GET /term)
+or as a "version" field in the body. A stale version is rejected with 412 and the current version.
+Body: same fields as /new-term
+ */
+#[utoipa::path(
+    put,
+    path = "/v1/terms/{id}",
+    tag = "terms",
+    params(
+        ("id" = String, Path, description = "The term's id, or its slug when it isn't numeric"),
+        ("If-Match" = Option<String>, Header, description = "The ETag of the version the edit is based on, e.g. \"3\", or * for any version, instead of `version` in the body"),
+    ),
+    request_body = UpdateTopicOrTerm,
+    responses(
+        (status = 200, description = "The updated term", body = Term),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+        (status = 404, description = "No term with this id or slug"),
+        (status = 412, description = "The term was changed since the given version, the body has the `current_version`"),
+        (status = 428, description = "Neither If-Match nor `version` was sent"),
+    )
+)]
+pub async fn update_term_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    Json(payload): Json<UpdateTopicOrTerm>,
+) -> Response {
+    let id = match resolve_entity_id(&*repository, &TERM_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    if let Err(message) = check_array_lengths(&payload.fields.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let Some(expected_version) = expected_version(&headers, payload.version) else {
+        return precondition_required_response();
+    };
+    let update_result = update_if_match(&expected_version, |version| {
+        repository.update_topic_or_term(&payload.fields, "term", id, version)
+    })
+    .await;
+    let verified = match update_result {
+        Ok(UpdateOutcome::Updated { verified }) => verified,
+        Ok(UpdateOutcome::VersionConflict { current_version }) => {
+            return precondition_failed_response(current_version)
+        }
+        Ok(UpdateOutcome::NotFound) => {
+            return (StatusCode::NOT_FOUND, "term not found").into_response()
+        }
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+
+    let link_insert_result =
+        build_link_tables(&*repository, &webhooks, &payload.fields, "term", id).await;
+    read_cache.invalidate_all();
+    if verified {
+        webhooks.record_event("term", "verified", id).await;
+    }
+    let term = get_term(&*repository, id).await;
+    match (link_insert_result, term) {
+        (Ok(_), Ok(term)) => conditional_versioned_json_response(&HeaderMap::new(), &term),
+        (Err(error), _) | (_, Err(error)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    }
 }
@@ -1,17 +1,39 @@
-use crate::helpers::handler_utils::{build_link_tables, insert_topic_or_term, CreateTopicOrTerm};
+use crate::helpers::conditional_get::{
+    conditional_json_response, conditional_versioned_json_response, expected_version,
+    precondition_failed_response, precondition_required_response, update_if_match, LastModified,
+    RowVersion,
+};
+use crate::helpers::fieldsets::{sparse_list_response, sparse_record_response, TOPIC_FIELDS};
+use crate::helpers::handler_utils::{
+    build_link_tables, insert_topic_or_term, CreateEntity, CreateTopicOrTerm, UpdateOutcome,
+    UpdateTopicOrTerm,
+};
+use crate::helpers::includes::record_with_includes_response;
+use crate::helpers::limits::{check_array_lengths, RequestLimits};
+use crate::helpers::lookup::{resolve_entity_id, LookupParams};
+use crate::helpers::ndjson::{ndjson_response, wants_ndjson};
+use crate::helpers::read_cache::ReadCache;
+use crate::helpers::shared_types::{ListQueryParams, RecordQueryParams};
+use crate::helpers::webhooks::Webhooks;
+use crate::repository::{get_all, get_one, stream_all, Repository};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
+use futures::Stream;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool, Result};
+use sqlx::Result;
+use std::sync::Arc;
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct Topic {
     id: i32,
     topic: String,
+    slug: String,
     is_verified: bool,
     brief_description: Option<String>,
     full_description: Option<String>,
@@ -23,53 +45,134 @@ pub struct Topic {
     ai_bullet_points: Option<Vec<String>>,
     ai_parallels: Option<Vec<String>>,
     ai_examples: Option<Vec<String>>,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
 }
 
-#[derive(Deserialize)]
-pub struct GetTopicQueryParams {
-    id: i32,
+impl LastModified for Topic {
+    fn last_modified(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated_at)
+    }
+}
+
+impl RowVersion for Topic {
+    fn version(&self) -> i32 {
+        self.version
+    }
 }
 
 /*
  /topics
 - returns all topics
+- optional `updated_since` only returns topics created or updated at or after that time
+- streams one topic per line with `Accept: application/x-ndjson`
+- optional `fields` returns only those columns, e.g. fields=id,topic
  */
-pub async fn get_all_topics_handler(State(db_pool): State<PgPool>) -> Response {
-    let topics = get_all_topics(&db_pool).await;
+#[utoipa::path(
+    get,
+    path = "/v1/topics",
+    tag = "topics",
+    params(ListQueryParams),
+    responses(
+        (status = 200, description = "All topics, only with the requested columns when `fields` is given",
+            content(("application/json" = [Topic]), ("application/x-ndjson" = Topic))),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Unknown field in `fields`"),
+    )
+)]
+pub async fn get_all_topics_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
+    Query(params): Query<ListQueryParams>,
+) -> Response {
+    if let Some(fields) = &params.fields {
+        return sparse_list_response(
+            repository,
+            &read_cache,
+            &headers,
+            &TOPIC_FIELDS,
+            fields,
+            params.updated_since,
+        )
+        .await;
+    }
+    if wants_ndjson(&headers) {
+        return ndjson_response(stream_all_topics(&*repository, params.updated_since));
+    }
+    let cache_key = format!("topics?updated_since={:?}", params.updated_since);
+    let topics = read_cache
+        .get_or_load(cache_key, || {
+            get_all_topics(&*repository, params.updated_since)
+        })
+        .await;
     match topics {
-        Ok(topics) => (StatusCode::OK, Json(topics)).into_response(),
+        Ok(topics) => conditional_json_response(&headers, &*topics),
         // for errors Axum expects the axum::response::Response type
         // example output: error returned from database: relation "platform.tipics" does not exist
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_all_topics(db_pool: &PgPool) -> Result<Vec<Topic>> {
-    let topics = sqlx::query_as::<_, Topic>(
-        "SELECT id, topic, is_verified, brief_description,
-    full_description, bullet_points, examples, parallels, ai_brief_description, ai_full_description,
-    ai_bullet_points, ai_parallels, ai_examples
-    FROM platform.topics",
-    )
-    .fetch_all(db_pool)
-    .await?;
-    Ok(topics)
+#[tracing::instrument(skip(repository), err)]
+pub async fn get_all_topics(
+    repository: &dyn Repository,
+    updated_since: Option<DateTime<Utc>>,
+) -> Result<Vec<Topic>> {
+    get_all(repository, &TOPIC_FIELDS, updated_since).await
+}
+
+// same records as get_all_topics, yielded as they are read
+pub fn stream_all_topics(
+    repository: &dyn Repository,
+    updated_since: Option<DateTime<Utc>>,
+) -> impl Stream<Item = Result<Topic>> {
+    stream_all(repository, &TOPIC_FIELDS, updated_since)
 }
 
 /*
 /new-topic
-Body:
+Body (all fields are listed under CreateTopicOrTerm in /openapi.json):
 {
-   "name": "<new_topic_name>"
+   "name": "<new_topic_name>",
+   "is_verified": false
 }
 */
+#[utoipa::path(
+    post,
+    path = "/v1/topics",
+    tag = "topics",
+    request_body = CreateTopicOrTerm,
+    responses(
+        (status = 200, description = "The topic was created", body = String, content_type = "text/plain"),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+        (status = 413, description = "The body is larger than MAX_BODY_BYTES"),
+    )
+)]
 pub async fn new_topic_handler(
-    State(db_pool): State<PgPool>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
     Json(payload): Json<CreateTopicOrTerm>,
 ) -> Response {
-    let insert_result = insert_topic_or_term(&payload, "topic", &db_pool).await;
+    if let Err(message) = check_array_lengths(&payload.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let insert_result = insert_topic_or_term(&*repository, &payload, "topic").await;
     // todo: look up how I can do error handling for both of these function calls since they both return Result
-    let link_insert_result = build_link_tables(&payload, "topic", &db_pool).await;
+    let link_insert_result = match &insert_result {
+        Ok(id) => {
+            // created goes out before the linked events of the related names
+            webhooks
+                .record_created("topic", *id, payload.is_verified())
+                .await;
+            build_link_tables(&*repository, &webhooks, &payload, "topic", *id).await
+        }
+        Err(_) => Ok(()),
+    };
+    read_cache.invalidate_all();
     match (insert_result, link_insert_result) {
         (Ok(_), Ok(_)) => "new topic created".into_response(),
         (Err(error), _) | (_, Err(error)) => {
@@ -78,20 +181,141 @@ pub async fn new_topic_handler(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/topics/{id}",
+    tag = "topics",
+    params(
+        ("id" = String, Path, description = "The topic's id, or its slug when it isn't numeric"),
+        RecordQueryParams,
+    ),
+    responses(
+        (status = 200, description = "The topic, only with the requested columns when `fields` is given, with the related records when `include` is given", body = Topic),
+        (status = 304, description = "Not modified since the ETag or Last-Modified the client has"),
+        (status = 400, description = "Unknown field in `fields` or `include`"),
+        (status = 404, description = "No topic with this id or slug"),
+    )
+)]
 pub async fn get_topic_handler(
-    State(db_pool): State<PgPool>,
-    params: axum::extract::Query<GetTopicQueryParams>,
+    State(repository): State<Arc<dyn Repository>>,
+    State(read_cache): State<Arc<ReadCache>>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    Query(params): Query<RecordQueryParams>,
 ) -> Response {
-    let topic = get_topic(&db_pool, &params.id).await;
+    let id = match resolve_entity_id(&*repository, &TOPIC_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    if let Some(include) = &params.include {
+        return record_with_includes_response(
+            repository,
+            &read_cache,
+            &headers,
+            &TOPIC_FIELDS,
+            id,
+            params.fields.as_deref(),
+            include,
+        )
+        .await;
+    }
+    if let Some(fields) = &params.fields {
+        return sparse_record_response(
+            repository,
+            &read_cache,
+            &headers,
+            &TOPIC_FIELDS,
+            fields,
+            id,
+        )
+        .await;
+    }
+    let cache_key = format!("topic?id={}", id);
+    let topic = read_cache
+        .get_or_load(cache_key, || get_topic(&*repository, id))
+        .await;
     match topic {
-        Ok(topic) => (StatusCode::OK, Json(topic)).into_response(),
+        Ok(topic) => conditional_versioned_json_response(&headers, &*topic),
         Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
     }
 }
 
-pub async fn get_topic(db_pool: &PgPool, id: &i32) -> Result<Topic> {
-    let topic = sqlx::query_as!(Topic, "SELECT * from platform.topics where id = $1", id)
-        .fetch_one(db_pool)
-        .await?;
-    Ok(topic)
+#[tracing::instrument(skip(repository), err)]
+pub async fn get_topic(repository: &dyn Repository, id: i32) -> Result<Topic> {
+    get_one(repository, &TOPIC_FIELDS, id).await
+}
+
+/*
+PUT /topic?id=<id>
+Replaces the topic's fields, related_* entries are linked in addition to the existing links.
+The version the edit is based on must be sent as If-Match: "<version>" (the ETag of GET /topic)
+or as a "version" field in the body. A stale version is rejected with 412 and the current version.
+Body: same fields as /new-topic
+ */
+#[utoipa::path(
+    put,
+    path = "/v1/topics/{id}",
+    tag = "topics",
+    params(
+        ("id" = String, Path, description = "The topic's id, or its slug when it isn't numeric"),
+        ("If-Match" = Option<String>, Header, description = "The ETag of the version the edit is based on, e.g. \"3\", or * for any version, instead of `version` in the body"),
+    ),
+    request_body = UpdateTopicOrTerm,
+    responses(
+        (status = 200, description = "The updated topic", body = Topic),
+        (status = 400, description = "An array in the body is longer than MAX_ARRAY_LENGTH"),
+        (status = 404, description = "No topic with this id or slug"),
+        (status = 412, description = "The topic was changed since the given version, the body has the `current_version`"),
+        (status = 428, description = "Neither If-Match nor `version` was sent"),
+    )
+)]
+pub async fn update_topic_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(limits): State<RequestLimits>,
+    State(read_cache): State<Arc<ReadCache>>,
+    State(webhooks): State<Webhooks>,
+    headers: HeaderMap,
+    lookup: LookupParams,
+    Json(payload): Json<UpdateTopicOrTerm>,
+) -> Response {
+    let id = match resolve_entity_id(&*repository, &TOPIC_FIELDS, &lookup).await {
+        Ok(id) => id,
+        Err(error) => return error.into_response(),
+    };
+    if let Err(message) = check_array_lengths(&payload.fields.array_fields(), &limits) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let Some(expected_version) = expected_version(&headers, payload.version) else {
+        return precondition_required_response();
+    };
+    let update_result = update_if_match(&expected_version, |version| {
+        repository.update_topic_or_term(&payload.fields, "topic", id, version)
+    })
+    .await;
+    let verified = match update_result {
+        Ok(UpdateOutcome::Updated { verified }) => verified,
+        Ok(UpdateOutcome::VersionConflict { current_version }) => {
+            return precondition_failed_response(current_version)
+        }
+        Ok(UpdateOutcome::NotFound) => {
+            return (StatusCode::NOT_FOUND, "topic not found").into_response()
+        }
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+
+    let link_insert_result =
+        build_link_tables(&*repository, &webhooks, &payload.fields, "topic", id).await;
+    read_cache.invalidate_all();
+    if verified {
+        webhooks.record_event("topic", "verified", id).await;
+    }
+    let topic = get_topic(&*repository, id).await;
+    match (link_insert_result, topic) {
+        (Ok(_), Ok(topic)) => conditional_versioned_json_response(&HeaderMap::new(), &topic),
+        (Err(error), _) | (_, Err(error)) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    }
 }
@@ -0,0 +1,77 @@
+/*
+The /v1 routes: resources at /v1/<entity>s and /v1/<entity>s/:id, where :id is the
+record's id or its slug, and their relationships at /v1/<entity>s/:id/<related>s.
+/v1/sources/:id/citation and /v1/topics/:id/bibliography cite sources.
+
+The handlers are the same as for the legacy routes in mod.rs, they read the record
+from the path instead of the query string (see helpers/lookup.rs).
+
+/v1/webhooks manages the webhook subscriptions, /v1/import imports files of records,
+/v1/archive exports and restores everything and /v1/export/... writes the files of other
+tools, none of them has a legacy route.
+ */
+use super::archive::{export_archive_handler, restore_archive_handler};
+use super::citations::{bibliography_handler, citation_handler};
+use super::export::{flashcards_handler, graph_handler};
+use super::import::import_handler;
+use super::links::new_link_handler;
+use super::relationships::v1_relationship_routes;
+use super::route_table::RouteTable;
+use super::sources::{
+    get_all_sources_handler, get_source_handler, new_source_handler, update_source_handler,
+};
+use super::terms::{
+    get_all_terms_handler, get_term_handler, new_term_handler, update_term_handler,
+};
+use super::topics::{
+    get_all_topics_handler, get_topic_handler, new_topic_handler, update_topic_handler,
+};
+use super::webhooks::{
+    delete_webhook_handler, get_all_webhooks_handler, get_webhook_deliveries_handler,
+    new_webhook_handler, replay_delivery_handler,
+};
+use crate::helpers::limits::RequestLimits;
+use axum::extract::DefaultBodyLimit;
+use axum::http::Method;
+
+pub fn v1_routes(limits: &RequestLimits) -> RouteTable {
+    // files are larger than the bodies of the other routes
+    let import_limit = DefaultBodyLimit::max(limits.max_import_bytes);
+    RouteTable::default()
+        .get("/topics", get_all_topics_handler)
+        .post("/topics", new_topic_handler)
+        .get("/topics/:id", get_topic_handler)
+        .put("/topics/:id", update_topic_handler)
+        .get("/terms", get_all_terms_handler)
+        .post("/terms", new_term_handler)
+        .get("/terms/:id", get_term_handler)
+        .put("/terms/:id", update_term_handler)
+        .get("/sources", get_all_sources_handler)
+        .post("/sources", new_source_handler)
+        .get("/sources/:id", get_source_handler)
+        .put("/sources/:id", update_source_handler)
+        .get("/sources/:id/citation", citation_handler)
+        .get("/topics/:id/bibliography", bibliography_handler)
+        .post("/links", new_link_handler)
+        .merge(v1_relationship_routes())
+        .get("/webhooks", get_all_webhooks_handler)
+        .post("/webhooks", new_webhook_handler)
+        .delete("/webhooks/:id", delete_webhook_handler)
+        .get("/webhooks/:id/deliveries", get_webhook_deliveries_handler)
+        .post(
+            "/webhooks/:id/deliveries/:delivery_id/replay",
+            replay_delivery_handler,
+        )
+        .route(Method::POST, "/import", import_handler, |route| {
+            route.layer(import_limit.clone())
+        })
+        .get("/archive", export_archive_handler)
+        .get("/export/flashcards", flashcards_handler)
+        .get("/export/graph", graph_handler)
+        .route(
+            Method::POST,
+            "/archive/restore",
+            restore_archive_handler,
+            |route| route.layer(import_limit),
+        )
+}
@@ -0,0 +1,222 @@
+/*
+Webhook subscriptions and their delivery log, see helpers/webhooks.rs for how the events
+are queued, signed and retried.
+ */
+use super::relationships::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::helpers::webhooks::{CreateWebhook, Webhooks, WEBHOOK_EVENT_TYPES};
+use crate::repository::Repository;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+const DELIVERY_STATUSES: [&str; 3] = ["pending", "delivered", "failed"];
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryQueryParams {
+    /// Only return the deliveries with this status: pending, delivered or failed
+    status: Option<String>,
+    /// Page size, 50 by default and at most 500
+    limit: Option<i64>,
+    /// Number of deliveries to skip, the newest are first
+    offset: Option<i64>,
+}
+
+async fn validate_webhook(
+    payload: &CreateWebhook,
+    webhooks: &Webhooks,
+) -> std::result::Result<(), String> {
+    let url = reqwest::Url::parse(&payload.url)
+        .map_err(|error| format!("url is not a valid URL: {}", error))?;
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err("url must be an http or https URL".to_owned());
+    }
+    webhooks.check_url(&url).await?;
+    if payload.secret.as_ref().is_some_and(String::is_empty) {
+        return Err("secret can't be empty".to_owned());
+    }
+    for event_type in payload.event_types.iter().flatten() {
+        if !WEBHOOK_EVENT_TYPES.contains(&event_type.as_str()) {
+            return Err(format!(
+                "unknown event type \"{}\", expected one of: {}",
+                event_type,
+                WEBHOOK_EVENT_TYPES.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+POST /v1/webhooks
+Body:
+{
+   "url": "https://search-indexer.internal/hooks/crm",
+   "event_types": ["term.created", "term.verified", "term.linked"]
+}
+The response has the secret the payloads are signed with, it isn't returned again.
+ */
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "The webhook was created", body = NewWebhook),
+        (status = 400, description = "Not an http(s) url, an internal address that isn't in WEBHOOK_ALLOWED_HOSTS, an empty secret or an unknown event type"),
+    )
+)]
+pub async fn new_webhook_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(webhooks): State<Webhooks>,
+    Json(payload): Json<CreateWebhook>,
+) -> Response {
+    if let Err(message) = validate_webhook(&payload, &webhooks).await {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    match repository.insert_webhook(&payload).await {
+        Ok(webhook) => (StatusCode::CREATED, Json(webhook)).into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "All webhooks, without their secrets", body = [Webhook]),
+    )
+)]
+pub async fn get_all_webhooks_handler(State(repository): State<Arc<dyn Repository>>) -> Response {
+    match repository.get_webhooks().await {
+        Ok(webhooks) => Json(webhooks).into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i32, Path, description = "The webhook's id")),
+    responses(
+        (status = 204, description = "The webhook and its delivery log were deleted"),
+        (status = 404, description = "No webhook with this id"),
+    )
+)]
+pub async fn delete_webhook_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    Path(id): Path<i32>,
+) -> Response {
+    match repository.delete_webhook(id).await {
+        Ok(false) => (StatusCode::NOT_FOUND, "webhook not found").into_response(),
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+/*
+GET /v1/webhooks/:id/deliveries
+The delivery log, newest first: every event sent to the webhook with its attempts, the
+status code and error of the last attempt, and when the next retry is due.
+ */
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(
+        ("id" = i32, Path, description = "The webhook's id"),
+        DeliveryQueryParams,
+    ),
+    responses(
+        (status = 200, description = "A page of the webhook's deliveries, newest first", body = [WebhookDelivery]),
+        (status = 400, description = "An unknown status, or limit or offset out of range"),
+        (status = 404, description = "No webhook with this id"),
+    )
+)]
+pub async fn get_webhook_deliveries_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    Path(id): Path<i32>,
+    Query(params): Query<DeliveryQueryParams>,
+) -> Response {
+    if let Some(status) = &params.status {
+        if !DELIVERY_STATUSES.contains(&status.as_str()) {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("status must be one of: {}", DELIVERY_STATUSES.join(", ")),
+            )
+                .into_response();
+        }
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_PAGE_SIZE),
+        )
+            .into_response();
+    }
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return (StatusCode::BAD_REQUEST, "offset can't be negative").into_response();
+    }
+
+    match repository.webhook_exists(id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "webhook not found").into_response(),
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    }
+    let deliveries = repository
+        .get_webhook_deliveries(id, params.status.as_deref(), limit, offset)
+        .await;
+    match deliveries {
+        Ok(deliveries) => Json(deliveries).into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+/*
+POST /v1/webhooks/:id/deliveries/:delivery_id/replay
+Sends the delivery's payload again as a new delivery, e.g. after the receiver was fixed.
+The data is the same as in the original, the record isn't read again.
+ */
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks/{id}/deliveries/{delivery_id}/replay",
+    tag = "webhooks",
+    params(
+        ("id" = i32, Path, description = "The webhook's id"),
+        ("delivery_id" = i64, Path, description = "The id of the delivery to send again"),
+    ),
+    responses(
+        (status = 202, description = "The new delivery, it is sent in the background", body = WebhookDelivery),
+        (status = 404, description = "The webhook has no delivery with this id"),
+    )
+)]
+pub async fn replay_delivery_handler(
+    State(repository): State<Arc<dyn Repository>>,
+    State(webhooks): State<Webhooks>,
+    Path((id, delivery_id)): Path<(i32, i64)>,
+) -> Response {
+    let replay_id = match webhooks.replay(id, delivery_id).await {
+        Ok(Some(replay_id)) => replay_id,
+        Ok(None) => return (StatusCode::NOT_FOUND, "delivery not found").into_response(),
+        Err(error) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
+    };
+    match repository.get_webhook_delivery(replay_id).await {
+        Ok(Some(delivery)) => (StatusCode::ACCEPTED, Json(delivery)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "delivery not found").into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
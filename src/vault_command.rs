@@ -0,0 +1,150 @@
+/*
+`jd_crm_api export-vault <directory>`
+`jd_crm_api import-vault <directory> [--on-conflict skip|overwrite|rename]`
+
+Export writes a note per topic and term of the database of DATABASE_URL to an Obsidian
+vault, import reads a vault back and prints the restore report as JSON, see
+helpers/obsidian.rs for the notes. Export replaces the notes of the same name and leaves
+the vault's other files alone. Import overwrites the records of the notes by default,
+since the notes are usually the records' own, edited in Obsidian.
+ */
+use crate::helpers::archive::OnConflict;
+use crate::helpers::obsidian::{export_vault, parse_vault, TERMS_FOLDER, TOPICS_FOLDER};
+use crate::{connect, Repository};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+const EXPORT_USAGE: &str = "usage: jd_crm_api export-vault <directory>";
+const IMPORT_USAGE: &str =
+    "usage: jd_crm_api import-vault <directory> [--on-conflict skip|overwrite|rename]";
+
+struct VaultArgs {
+    directory: String,
+    on_conflict: OnConflict,
+}
+
+// the arguments after `export-vault` or `import-vault`, --on-conflict only for an import
+fn parse_args(args: &[String], importing: bool) -> Result<VaultArgs, String> {
+    let mut directories = vec![];
+    let mut on_conflict = OnConflict::Overwrite;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--on-conflict" if importing => on_conflict = value()?.parse()?,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ => directories.push(arg.clone()),
+        }
+    }
+    let [directory] =
+        <[String; 1]>::try_from(directories).map_err(|_| "pass one directory".to_owned())?;
+    Ok(VaultArgs {
+        directory,
+        on_conflict,
+    })
+}
+
+// the process's exit code: 0 when the notes were written, 2 when they couldn't be
+pub async fn run_export_vault(db_uri: &str, args: &[String]) -> i32 {
+    let args = match parse_args(args, false) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, EXPORT_USAGE);
+            return 2;
+        }
+    };
+
+    let repository: Arc<dyn Repository> = connect(db_uri).await;
+    let notes = match export_vault(&*repository).await {
+        Ok(notes) => notes,
+        Err(error) => {
+            eprintln!("the records can't be read: {}", error);
+            return 2;
+        }
+    };
+    let vault = Path::new(&args.directory);
+    for note in notes {
+        let path = vault.join(&note.path);
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, note.contents));
+        if let Err(error) = written {
+            eprintln!("{} can't be written: {}", path.display(), error);
+            return 2;
+        }
+    }
+    0
+}
+
+// the Markdown files under the directory as (path relative to the vault, contents)
+fn read_notes(vault: &Path, directory: &Path, notes: &mut Vec<(String, String)>) -> io::Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_notes(vault, &path, notes)?;
+        } else if path.extension().is_some_and(|extension| extension == "md") {
+            let relative = path
+                .strip_prefix(vault)
+                .expect("the note is in the vault")
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            notes.push((relative, fs::read_to_string(&path)?));
+        }
+    }
+    Ok(())
+}
+
+// the process's exit code: 0 when every note and link was imported, 1 when some links
+// weren't because their records are missing, 2 when nothing was
+pub async fn run_import_vault(db_uri: &str, args: &[String]) -> i32 {
+    let args = match parse_args(args, true) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, IMPORT_USAGE);
+            return 2;
+        }
+    };
+    let vault = Path::new(&args.directory);
+    let mut notes = vec![];
+    for folder in [TOPICS_FOLDER, TERMS_FOLDER] {
+        if let Err(error) = read_notes(vault, &vault.join(folder), &mut notes) {
+            eprintln!("the notes of {} can't be read: {}", args.directory, error);
+            return 2;
+        }
+    }
+    // the same order on every platform
+    notes.sort();
+    let archive = match parse_vault(&notes) {
+        Ok(archive) => archive,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 2;
+        }
+    };
+
+    let repository: Arc<dyn Repository> = connect(db_uri).await;
+    let report = match repository.restore_archive(&archive, args.on_conflict).await {
+        Ok(report) => report,
+        Err(error) => {
+            eprintln!("the import failed, nothing was written: {}", error);
+            return 2;
+        }
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("the report can be serialized")
+    );
+    if report.unresolved.is_empty() {
+        0
+    } else {
+        1
+    }
+}
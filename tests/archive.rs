@@ -0,0 +1,315 @@
+/*
+GET /v1/archive and POST /v1/archive/restore with the sample data of init.sql: archives
+restored into an empty database and into the one they came from with each conflict policy,
+sources of the same name, NDJSON archives, and archives that can't be restored.
+
+Every test runs on each repository, like tests/router.rs.
+ */
+#[macro_use]
+mod common;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use common::{get, post_file, send};
+use hyper::Body;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+backend_tests!(
+    exports_every_record_and_link_by_name,
+    restores_into_an_empty_database,
+    skips_or_overwrites_records_whose_name_is_taken,
+    renames_records_whose_name_is_taken,
+    keeps_sources_of_the_same_name_apart,
+    round_trips_ndjson,
+    refuses_archives_it_cant_restore,
+);
+
+async fn restore(app: &Router, uri: &str, archive: &Value) -> Value {
+    let (status, report) = post_file(app, uri, "application/json", &archive.to_string()).await;
+    assert_eq!(status, StatusCode::OK, "POST {}: {}", uri, report);
+    report
+}
+
+// the archive without its exported_at, which differs between two exports
+async fn export(app: &Router) -> Value {
+    let mut archive = get(app, "/v1/archive").await;
+    archive["exported_at"] = Value::Null;
+    archive
+}
+
+// sorted, the records of an archive are in the order they were restored in
+fn names(records: &Value, name_field: &str) -> Vec<String> {
+    let mut names = common::names(records, name_field);
+    names.sort();
+    names
+}
+
+fn empty_app() -> Router {
+    jd_crm_api::app(Arc::new(jd_crm_api::MemoryRepository::new()))
+}
+
+async fn exports_every_record_and_link_by_name(app: Router) {
+    let archive = get(&app, "/v1/archive").await;
+    assert_eq!(archive["format"], "jd_crm_api archive");
+    assert_eq!(archive["version"], 1);
+    assert!(archive["exported_at"].is_string());
+
+    assert_eq!(names(&archive["topics"], "topic"), ["Hurricane"]);
+    assert_eq!(
+        names(&archive["terms"], "term"),
+        ["Storm", "Tropical Cycle"]
+    );
+    assert_eq!(archive["sources"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        archive["questions"],
+        json!([{"question": "What is a storm?", "topic": "Hurricane"}])
+    );
+    assert_eq!(archive["articles"][0]["title"], "title1");
+    assert_eq!(archive["articles"][0]["publish_date"], "2023-03-27");
+    // ids, slugs and versions are the database's own
+    for field in ["id", "slug", "version", "created_at", "topic_id"] {
+        assert_eq!(archive["terms"][0].get(field), None, "{}", field);
+        assert_eq!(archive["questions"][0].get(field), None, "{}", field);
+    }
+
+    // sources and articles are linked by their key
+    let source_key = archive["sources"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|source| source["name"] == "wikipedia atlantic hurricane")
+        .unwrap()["key"]
+        .clone();
+    assert!(source_key.as_str().unwrap().starts_with("source-"));
+    let article_key = &archive["articles"][0]["key"];
+    let links = archive["links"].as_array().unwrap();
+    assert_eq!(links.len(), 9);
+    for link in [
+        json!({"table": "terms_to_topics", "from": "Storm", "to": "Hurricane"}),
+        json!({"table": "topics_to_sources", "from": "Hurricane", "to": source_key}),
+        json!({"table": "articles_to_questions", "from": article_key, "to": "What is a storm?"}),
+    ] {
+        assert!(links.contains(&link), "{}", link);
+    }
+}
+
+async fn restores_into_an_empty_database(app: Router) {
+    let archive = export(&app).await;
+    let empty = empty_app();
+    let report = restore(&empty, "/v1/archive/restore", &archive).await;
+    assert_eq!(report["records"]["term"]["created"], 2);
+    assert_eq!(report["records"]["question"]["created"], 1);
+    assert_eq!(report["links_created"], 9);
+    assert_eq!(report["unresolved"], json!([]));
+
+    assert_eq!(export(&empty).await, archive);
+    let storm = get(&empty, "/v1/terms/storm?include=topics,sources").await;
+    assert_eq!(storm["version"], 1);
+    assert_eq!(names(&storm["topics"], "topic"), ["Hurricane"]);
+    assert_eq!(names(&storm["sources"], "name"), ["dictionary storm"]);
+}
+
+async fn skips_or_overwrites_records_whose_name_is_taken(app: Router) {
+    let mut archive = export(&app).await;
+    for term in archive["terms"].as_array_mut().unwrap() {
+        if term["term"] == "Storm" {
+            term["brief_description"] = json!("a violent disturbance of the atmosphere");
+        }
+    }
+
+    // skip is the default
+    let report = restore(&app, "/v1/archive/restore", &archive).await;
+    assert_eq!(
+        report["records"]["term"],
+        json!({"created": 0, "overwritten": 0, "renamed": 0, "skipped": 2})
+    );
+    assert_eq!(report["links_created"], 0);
+    assert_eq!(report["links_existing"], 9);
+    let storm = get(&app, "/v1/terms/storm").await;
+    assert_ne!(
+        storm["brief_description"],
+        "a violent disturbance of the atmosphere"
+    );
+
+    let report = restore(&app, "/v1/archive/restore?on_conflict=overwrite", &archive).await;
+    assert_eq!(report["records"]["term"]["overwritten"], 2);
+    let storm = get(&app, "/v1/terms/storm").await;
+    assert_eq!(
+        storm["brief_description"],
+        "a violent disturbance of the atmosphere"
+    );
+    assert_eq!(storm["version"], 2);
+    assert_eq!(get(&app, "/v1/terms").await.as_array().unwrap().len(), 2);
+}
+
+async fn renames_records_whose_name_is_taken(app: Router) {
+    let archive = json!({
+        "format": "jd_crm_api archive",
+        "version": 1,
+        "terms": [{"term": "Storm", "brief_description": "the archived storm"}, {"term": "Squall"}],
+        "links": [
+            {"table": "terms_to_topics", "from": "Storm", "to": "Hurricane"},
+            {"table": "terms_to_topics", "from": "Squall", "to": "Monsoon"},
+        ],
+    });
+    let report = restore(&app, "/v1/archive/restore?on_conflict=rename", &archive).await;
+    assert_eq!(
+        report["records"]["term"],
+        json!({"created": 1, "overwritten": 0, "renamed": 1, "skipped": 0})
+    );
+    assert_eq!(
+        report["renamed"],
+        json!([{"entity_type": "term", "name": "Storm", "restored_as": "Storm (2)"}])
+    );
+    assert_eq!(
+        report["unresolved"],
+        json!(["terms_to_topics: \"Squall\" -> \"Monsoon\""])
+    );
+
+    // the link is added to the renamed record, not the existing one
+    let renamed = get(&app, "/v1/terms/storm-2?include=topics").await;
+    assert_eq!(renamed["brief_description"], "the archived storm");
+    assert_eq!(names(&renamed["topics"], "topic"), ["Hurricane"]);
+    let storm = get(&app, "/v1/terms/storm").await;
+    assert_eq!(storm["id"], 1);
+    assert_eq!(storm["version"], 1);
+
+    // a second restore takes the next free name
+    let report = restore(&app, "/v1/archive/restore?on_conflict=rename", &archive).await;
+    assert_eq!(report["renamed"][0]["restored_as"], "Storm (3)");
+    assert_eq!(report["renamed"][1]["restored_as"], "Squall (2)");
+}
+
+// the sources the term is linked to, as (name, url)
+async fn term_sources(app: &Router, term: &str) -> Vec<(String, String)> {
+    let term = get(app, &format!("/v1/terms/{}?include=sources", term)).await;
+    let mut sources: Vec<(String, String)> = term["sources"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|source| {
+            let field = |name: &str| source[name].as_str().unwrap_or_default().to_owned();
+            (field("name"), field("url"))
+        })
+        .collect();
+    sources.sort();
+    sources
+}
+
+fn glossary(name: &str, url: &str) -> (String, String) {
+    (name.to_owned(), format!("https://example.com/{}", url))
+}
+
+async fn keeps_sources_of_the_same_name_apart(app: Router) {
+    for (url, term) in [("first", "Storm"), ("second", "Tropical Cycle")] {
+        let (status, body) = common::call(
+            &app,
+            Method::POST,
+            "/v1/sources",
+            &[],
+            Some(
+                json!({"name": "Glossary", "url": format!("https://example.com/{}", url),
+                "related_terms": [term]}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    let archive = export(&app).await;
+
+    let empty = empty_app();
+    let report = restore(&empty, "/v1/archive/restore", &archive).await;
+    assert_eq!(report["records"]["source"]["created"], 5);
+    assert_eq!(report["unresolved"], json!([]));
+    assert_eq!(export(&empty).await, archive);
+    let storm_sources = term_sources(&empty, "storm").await;
+    assert!(storm_sources.contains(&glossary("Glossary", "first")));
+    assert!(!storm_sources.contains(&glossary("Glossary", "second")));
+    let cycle_sources = term_sources(&empty, "tropical-cycle").await;
+    assert!(cycle_sources.contains(&glossary("Glossary", "second")));
+    assert!(!cycle_sources.contains(&glossary("Glossary", "first")));
+
+    // restored into the database it came from, each source matches the one with its url
+    let report = restore(&app, "/v1/archive/restore", &archive).await;
+    assert_eq!(report["records"]["source"]["skipped"], 5);
+    assert_eq!(report["links_created"], 0);
+    let report = restore(&app, "/v1/archive/restore?on_conflict=rename", &archive).await;
+    assert_eq!(report["records"]["source"]["renamed"], 5);
+    // and so are the terms, whose links go to the renamed records
+    let storm_sources = term_sources(&app, "storm-2").await;
+    assert!(storm_sources.contains(&glossary("Glossary (2)", "first")));
+    let cycle_sources = term_sources(&app, "tropical-cycle-2").await;
+    assert!(cycle_sources.contains(&glossary("Glossary (3)", "second")));
+}
+
+async fn round_trips_ndjson(app: Router) {
+    let (status, _, ndjson) = send(
+        &app,
+        Method::GET,
+        "/v1/archive",
+        &[("accept", "application/x-ndjson")],
+        Body::empty(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let lines: Vec<Value> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines[0]["format"], "jd_crm_api archive");
+    assert_eq!(lines[1]["type"], "topic");
+    assert_eq!(lines.last().unwrap()["type"], "link");
+    // the header, 1 topic, 2 terms, 3 sources, 1 question, 1 article and 9 links
+    assert_eq!(lines.len(), 18);
+
+    let empty = empty_app();
+    let (status, report) = post_file(
+        &empty,
+        "/v1/archive/restore",
+        "application/x-ndjson",
+        &ndjson,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["links_created"], 9);
+    assert_eq!(export(&empty).await, export(&app).await);
+}
+
+async fn refuses_archives_it_cant_restore(app: Router) {
+    let before = export(&app).await;
+    let cases = [
+        (
+            json!({"format": "jd_crm_api archive", "version": 2, "terms": [{"term": "Gale"}]}),
+            "version",
+        ),
+        (json!({"format": "something else", "version": 1}), "format"),
+        (
+            json!({"format": "jd_crm_api archive", "version": 1, "terms": [{"term": "Gale"}, {"term": "Squall", "colour": "grey"}]}),
+            "terms[1]",
+        ),
+        (
+            json!({"format": "jd_crm_api archive", "version": 1, "links": [{"table": "topics_to_articles", "from": "a", "to": "b"}]}),
+            "links[0]",
+        ),
+    ];
+    for (archive, message) in cases {
+        let (status, body) = post_file(
+            &app,
+            "/v1/archive/restore",
+            "application/json",
+            &archive.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", archive);
+        let body = body.as_str().unwrap_or_default();
+        assert!(
+            body.contains(message),
+            "{} doesn't mention {}",
+            body,
+            message
+        );
+    }
+    // nothing of the refused archives was written
+    assert_eq!(export(&app).await, before);
+}
@@ -0,0 +1,221 @@
+/*
+GET /v1/sources/:id/citation and GET /v1/topics/:id/bibliography with the sample data of
+init.sql and sources added with POST /v1/sources: the four styles, the forms of the media
+types, missing metadata, escaping in BibTeX and the sources of a topic and its terms.
+
+Every test runs on each repository, like tests/router.rs.
+ */
+#[macro_use]
+mod common;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use common::{call, get};
+use serde_json::{json, Value};
+
+backend_tests!(
+    cites_a_web_page_in_each_style,
+    cites_by_media_type,
+    reports_missing_metadata,
+    escapes_bibtex_fields,
+    cites_the_sources_of_a_topic_and_its_terms,
+    refuses_unknown_sources_and_styles,
+);
+
+async fn add_source(app: &Router, source: Value) {
+    let (status, _) = call(app, Method::POST, "/v1/sources", &[], Some(source)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+async fn cites_a_web_page_in_each_style(app: Router) {
+    add_source(
+        &app,
+        json!({"name": "Tropical Cyclones", "author": "National Hurricane Center",
+            "url": "https://www.nhc.noaa.gov/cyclones/", "media_type": "Web"}),
+    )
+    .await;
+
+    let citation = get(&app, "/v1/sources/tropical-cyclones/citation").await;
+    assert_eq!(citation["style"], "apa");
+    assert_eq!(
+        citation["text"],
+        "National Hurricane Center. (n.d.). Tropical Cyclones. https://www.nhc.noaa.gov/cyclones/"
+    );
+    assert_eq!(
+        citation["html"],
+        "National Hurricane Center. (n.d.). <i>Tropical Cyclones</i>. https://www.nhc.noaa.gov/cyclones/"
+    );
+    assert_eq!(citation["missing"], json!([]));
+    assert_eq!(
+        get(&app, "/v1/sources/tropical-cyclones/citation?style=mla").await["text"],
+        "National Hurricane Center. \u{201c}Tropical Cyclones.\u{201d} www.nhc.noaa.gov/cyclones."
+    );
+    assert_eq!(
+        get(&app, "/v1/sources/tropical-cyclones/citation?style=chicago").await["text"],
+        "National Hurricane Center. \u{201c}Tropical Cyclones.\u{201d} https://www.nhc.noaa.gov/cyclones/."
+    );
+    let bibtex = get(&app, "/v1/sources/tropical-cyclones/citation?style=bibtex").await;
+    assert_eq!(
+        bibtex["text"],
+        "@misc{tropical-cyclones,\n  author = {{National Hurricane Center}},\n  title = {Tropical Cyclones},\n  url = {https://www.nhc.noaa.gov/cyclones/},\n}"
+    );
+    assert_eq!(bibtex["html"], Value::Null);
+}
+
+async fn cites_by_media_type(app: Router) {
+    add_source(
+        &app,
+        json!({"name": "Storms & Their Winds", "author": "Doe, A.", "media_type": "Book"}),
+    )
+    .await;
+    add_source(
+        &app,
+        json!({"name": "Rapid intensification", "author": "Smith, J.", "media_type": "ScientificArticle",
+            "url": "https://doi.org/10.1000/182"}),
+    )
+    .await;
+    add_source(
+        &app,
+        json!({"name": "Inside the eye", "author": "Weather Channel", "media_type": "Video",
+            "url": "https://example.com/eye"}),
+    )
+    .await;
+
+    // a book is italicised and needs no url
+    let book = get(&app, "/v1/sources/storms-their-winds/citation").await;
+    assert_eq!(book["text"], "Doe, A. (n.d.). Storms & Their Winds.");
+    assert_eq!(
+        book["html"],
+        "Doe, A. (n.d.). <i>Storms &amp; Their Winds</i>."
+    );
+    assert_eq!(book["missing"], json!([]));
+    assert_eq!(
+        get(&app, "/v1/sources/storms-their-winds/citation?style=mla").await["text"],
+        "Doe, A. Storms & Their Winds."
+    );
+    assert_eq!(
+        get(&app, "/v1/sources/storms-their-winds/citation?style=bibtex").await["text"],
+        "@book{storms-their-winds,\n  author = {Doe, A.},\n  title = {Storms \\& Their Winds},\n}"
+    );
+
+    // an article's title isn't italicised in APA and is quoted in MLA
+    let article = get(&app, "/v1/sources/rapid-intensification/citation").await;
+    assert_eq!(article["html"], article["text"]);
+    assert_eq!(
+        article["text"],
+        "Smith, J. (n.d.). Rapid intensification. https://doi.org/10.1000/182"
+    );
+    assert!(get(
+        &app,
+        "/v1/sources/rapid-intensification/citation?style=bibtex"
+    )
+    .await["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("@article{rapid-intensification,"));
+
+    let video = get(&app, "/v1/sources/inside-the-eye/citation").await;
+    assert_eq!(
+        video["text"],
+        "Weather Channel. (n.d.). Inside the eye [Video]. https://example.com/eye"
+    );
+    assert_eq!(
+        get(&app, "/v1/sources/inside-the-eye/citation?style=chicago").await["text"],
+        "Weather Channel. Inside the eye. Video. https://example.com/eye."
+    );
+}
+
+async fn reports_missing_metadata(app: Router) {
+    // the sample sources have no author
+    let citation = get(&app, "/v1/sources/1/citation").await;
+    assert_eq!(citation["missing"], json!(["author"]));
+    assert_eq!(
+        citation["text"],
+        "dictionary storm. (n.d.). https://www.merriam-webster.com/dictionary/storm"
+    );
+
+    add_source(&app, json!({"name": "Notes from the field", "author": ""})).await;
+    let citation = get(&app, "/v1/sources/notes-from-the-field/citation?style=mla").await;
+    // without a media type the title is neither quoted nor italicised
+    assert_eq!(citation["text"], "Notes from the field.");
+    let apa = get(&app, "/v1/sources/notes-from-the-field/citation").await;
+    assert_eq!(apa["html"], apa["text"]);
+    assert_eq!(citation["missing"], json!(["author", "url", "media_type"]));
+    assert_eq!(
+        get(
+            &app,
+            "/v1/sources/notes-from-the-field/citation?style=bibtex"
+        )
+        .await["text"],
+        "@misc{notes-from-the-field,\n  title = {Notes from the field},\n}"
+    );
+}
+
+async fn escapes_bibtex_fields(app: Router) {
+    add_source(
+        &app,
+        json!({"name": "Wind {speeds}", "author": "Doe, A. & Roe, B.",
+            "url": "https://example.com/a}b{c\\d", "media_type": "Web"}),
+    )
+    .await;
+    assert_eq!(
+        get(&app, "/v1/sources/wind-speeds/citation?style=bibtex").await["text"],
+        "@misc{wind-speeds,\n  author = {Doe, A. \\& Roe, B.},\n  title = {Wind \\{speeds\\}},\n  url = {https://example.com/a%7Db%7Bc%5Cd},\n}"
+    );
+}
+
+async fn cites_the_sources_of_a_topic_and_its_terms(app: Router) {
+    // linked to Hurricane and to Storm, it is cited once
+    add_source(
+        &app,
+        json!({"name": "Storms", "author": "Doe, A.", "media_type": "Book",
+            "related_topics": ["Hurricane"], "related_terms": ["Storm"]}),
+    )
+    .await;
+
+    let bibliography = get(&app, "/v1/topics/hurricane/bibliography").await;
+    assert_eq!(bibliography["topic_id"], 1);
+    let source_ids: Vec<&Value> = bibliography["citations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|citation| &citation["source_id"])
+        .collect();
+    // by author, or by name without one
+    assert_eq!(source_ids, [1, 4, 3, 2]);
+    assert_eq!(
+        bibliography["text"],
+        "dictionary storm. (n.d.). https://www.merriam-webster.com/dictionary/storm
+Doe, A. (n.d.). Storms.
+wikipedia atlantic hurricane. (n.d.). https://en.wikipedia.org/wiki/Atlantic_hurricane
+wikipedia tropical cyclone. (n.d.). https://en.wikipedia.org/wiki/Tropical_cyclone"
+    );
+
+    let bibtex = get(&app, "/v1/topics/1/bibliography?style=bibtex").await;
+    let text = bibtex["text"].as_str().unwrap();
+    assert!(text.starts_with("@misc{dictionary-storm,"));
+    assert!(text.contains("}\n\n@book{storms,\n"));
+}
+
+async fn refuses_unknown_sources_and_styles(app: Router) {
+    let (status, _) = call(&app, Method::GET, "/v1/sources/999/citation", &[], None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(
+        &app,
+        Method::GET,
+        "/v1/topics/no-such-topic/bibliography",
+        &[],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(
+        &app,
+        Method::GET,
+        "/v1/sources/1/citation?style=harvard",
+        &[],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
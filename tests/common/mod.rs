@@ -0,0 +1,207 @@
+/*
+What the route tests share: backend_tests!, which runs every test on each repository, and
+helpers to call the router.
+
+The Postgres tests need a server at DATABASE_URL and are skipped without it. Each test
+gets a database of its own with the schema and sample data of init.sql, dropped when the
+test is done, so they can run at the same time and change the sample data.
+ */
+// every test file uses a different part of this module
+#![allow(dead_code, unused_macros)]
+
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use hyper::Body;
+use serde_json::Value;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Executor, PgPool};
+use std::str::FromStr;
+use tower::ServiceExt;
+
+// a test module per repository with every test in it
+macro_rules! backend_tests {
+    ($($test:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    let repository = jd_crm_api::MemoryRepository::with_fixtures();
+                    super::$test(jd_crm_api::app(std::sync::Arc::new(repository))).await
+                }
+            )*
+        }
+
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    // a new database with the sample data of init_sqlite.sql for every test
+                    let repository = jd_crm_api::SqliteRepository::connect("sqlite::memory:")
+                        .await
+                        .unwrap();
+                    super::$test(jd_crm_api::app(std::sync::Arc::new(repository))).await
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $test() {
+                    let Some(database) = crate::common::TestDatabase::create().await else {
+                        return;
+                    };
+                    let repository = std::sync::Arc::new(database.repository());
+                    super::$test(jd_crm_api::app(repository)).await;
+                    database.drop().await
+                }
+            )*
+        }
+    };
+}
+
+// init.sql without the psql commands that create the platform database and connect to it
+fn init_sql() -> &'static str {
+    let init_sql = include_str!("../../database/init.sql");
+    let schema = init_sql
+        .find("CREATE SCHEMA platform;")
+        .expect("init.sql creates the platform schema");
+    &init_sql[schema..]
+}
+
+// a database created from init.sql on the server of DATABASE_URL
+pub struct TestDatabase {
+    // a connection to the database of DATABASE_URL, to drop the test database from
+    server: PgPool,
+    name: String,
+    pool: PgPool,
+}
+
+impl TestDatabase {
+    // None when DATABASE_URL isn't set
+    pub async fn create() -> Option<Self> {
+        let Ok(database_url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL isn't set, skipping the Postgres test");
+            return None;
+        };
+        let options = PgConnectOptions::from_str(&database_url)
+            .expect("DATABASE_URL is a postgres url")
+            .disable_statement_logging()
+            .clone();
+        let server = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(options.clone())
+            .await
+            .expect("the database of DATABASE_URL accepts connections");
+        let name = format!("jd_crm_api_test_{}", uuid::Uuid::new_v4().simple());
+        server
+            .execute(format!("CREATE DATABASE {}", name).as_str())
+            .await
+            .unwrap();
+        let pool = PgPoolOptions::new()
+            .max_connections(3)
+            .connect_with(options.database(&name))
+            .await
+            .unwrap();
+        // without bind parameters the statements are sent as one simple query
+        pool.execute(init_sql()).await.unwrap();
+        Some(TestDatabase { server, name, pool })
+    }
+
+    pub fn repository(&self) -> jd_crm_api::PgRepository {
+        jd_crm_api::PgRepository::new(self.pool.clone())
+    }
+
+    // for changes made around the API, like another server instance or psql would
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    // the app's listener and webhook worker still hold connections, they are closed by force
+    pub async fn drop(self) {
+        self.pool.close().await;
+        self.server
+            .execute(format!("DROP DATABASE {} WITH (FORCE)", self.name).as_str())
+            .await
+            .unwrap();
+    }
+}
+
+// the status, headers and body of the response
+pub async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: impl Into<Body>,
+) -> (StatusCode, HeaderMap, String) {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(body.into()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, String::from_utf8_lossy(&body).into_owned())
+}
+
+// the body as JSON, or as a JSON string when it isn't JSON
+pub fn json_or_string(body: String) -> Value {
+    serde_json::from_str(&body).unwrap_or(Value::String(body))
+}
+
+// a request with a JSON body when there is one, and the response body as JSON
+pub async fn call(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut all_headers = vec![("content-type", "application/json")];
+    all_headers.extend_from_slice(headers);
+    let body = match body {
+        Some(body) => Body::from(body.to_string()),
+        None => Body::empty(),
+    };
+    let (status, _, body) = send(app, method, uri, &all_headers, body).await;
+    (status, json_or_string(body))
+}
+
+// a file POSTed with its content type, and the response body as JSON
+pub async fn post_file(
+    app: &Router,
+    uri: &str,
+    content_type: &str,
+    file: &str,
+) -> (StatusCode, Value) {
+    let (status, _, body) = send(
+        app,
+        Method::POST,
+        uri,
+        &[("content-type", content_type)],
+        file.to_owned(),
+    )
+    .await;
+    (status, json_or_string(body))
+}
+
+pub async fn get(app: &Router, uri: &str) -> Value {
+    let (status, body) = call(app, Method::GET, uri, &[], None).await;
+    assert_eq!(status, StatusCode::OK, "GET {}: {}", uri, body);
+    body
+}
+
+pub fn names(records: &Value, name_field: &str) -> Vec<String> {
+    records
+        .as_array()
+        .expect("a list of records")
+        .iter()
+        .map(|record| record[name_field].as_str().unwrap_or_default().to_owned())
+        .collect()
+}
@@ -0,0 +1,93 @@
+/*
+The /events stream on Postgres, where changes can commit in another order than their event
+ids were handed out in. The test is skipped without DATABASE_URL, see tests/common.
+ */
+mod common;
+
+use axum::body::BoxBody;
+use axum::http::{Request, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+// the events of an SSE body, read as they arrive
+struct EventReader {
+    body: BoxBody,
+    buffer: String,
+}
+
+impl EventReader {
+    // the id of the next event, keep-alive comments are skipped
+    async fn next_id(&mut self) -> i64 {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let event: String = self.buffer.drain(..end + 2).collect();
+                if let Some(id) = event.lines().find_map(|line| line.strip_prefix("id:")) {
+                    return id.trim().parse().unwrap();
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(10), self.body.data())
+                .await
+                .expect("an event arrives within 10 seconds")
+                .expect("the stream is open")
+                .unwrap();
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+// records an event in the transaction, which is sent when it commits
+async fn record_event(transaction: &mut Transaction<'_, Postgres>, topic_id: i32) -> i64 {
+    sqlx::query_scalar(
+        "INSERT INTO platform.events (action, entity_type, entity_id)
+        VALUES ('updated', 'topic', $1)
+        RETURNING id",
+    )
+    .bind(topic_id)
+    .fetch_one(transaction)
+    .await
+    .unwrap()
+}
+
+async fn record_committed_event(pool: &PgPool, topic_id: i32) -> i64 {
+    let mut transaction = pool.begin().await.unwrap();
+    let id = record_event(&mut transaction, topic_id).await;
+    transaction.commit().await.unwrap();
+    id
+}
+
+#[tokio::test]
+async fn sends_events_committed_out_of_id_order() {
+    let Some(database) = common::TestDatabase::create().await else {
+        return;
+    };
+    let app = jd_crm_api::app(Arc::new(database.repository()));
+    let response = app
+        .oneshot(Request::get("/events").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut events = EventReader {
+        body: response.into_body(),
+        buffer: String::new(),
+    };
+
+    // the LISTEN connection is up once an event arrives
+    let first = record_committed_event(database.pool(), 1).await;
+    assert_eq!(events.next_id().await, first);
+
+    let mut earlier = database.pool().begin().await.unwrap();
+    let earlier_id = record_event(&mut earlier, 1).await;
+    let later_id = record_committed_event(database.pool(), 2).await;
+    earlier.commit().await.unwrap();
+    assert!(earlier_id < later_id);
+
+    let sent = HashSet::from([events.next_id().await, events.next_id().await]);
+    assert_eq!(sent, HashSet::from([earlier_id, later_id]));
+    database.drop().await
+}
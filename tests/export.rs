@@ -0,0 +1,195 @@
+/*
+GET /v1/export/flashcards with the sample data of init.sql and terms added with
+/v1/import: the Anki and Quizlet files, the ai_* fields, and the topic and is_verified
+filters. GET /v1/export/graph in its three formats, whole or around one record.
+
+Every test runs on each repository, like tests/router.rs.
+ */
+#[macro_use]
+mod common;
+
+use axum::http::{header, Method, StatusCode};
+use axum::Router;
+use common::{call, post_file, send};
+use hyper::Body;
+use serde_json::{json, Value};
+
+backend_tests!(
+    exports_anki_cards_tagged_with_their_topics,
+    exports_quizlet_csv_from_the_ai_fields,
+    filters_by_topic_and_verification,
+    exports_the_knowledge_graph,
+    exports_the_neighbourhood_of_a_record,
+);
+
+async fn export(app: &Router, uri: &str) -> (String, String) {
+    let (status, headers, body) = send(app, Method::GET, uri, &[], Body::empty()).await;
+    assert_eq!(status, StatusCode::OK, "GET {}: {}", uri, body);
+    let content_type = headers[header::CONTENT_TYPE].to_str().unwrap().to_owned();
+    (content_type, body)
+}
+
+// Eye Wall is verified and linked to Hurricane and Tropical Storms, Gale has only ai_* fields
+async fn add_terms(app: &Router) {
+    let terms = json!([
+        {"entity_type": "topic", "name": "Tropical Storms"},
+        {"name": "Eye Wall", "is_verified": true, "brief_description": "the ring of <tall> storms around the eye",
+            "bullet_points": ["strongest winds", "heaviest rain"], "examples": ["Katrina's eye wall"],
+            "related_topics": ["Hurricane", "Tropical Storms"]},
+        {"name": "Gale", "ai_brief_description": "a very strong wind", "ai_bullet_points": ["force 8"]},
+    ]);
+    let (status, report) = post_file(
+        app,
+        "/v1/import?entity_type=term",
+        "application/json",
+        &terms.to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+}
+
+async fn exports_anki_cards_tagged_with_their_topics(app: Router) {
+    add_terms(&app).await;
+    let (content_type, file) = export(&app, "/v1/export/flashcards").await;
+    assert!(content_type.starts_with("text/tab-separated-values"));
+
+    let lines: Vec<&str> = file.lines().collect();
+    assert_eq!(
+        lines[..3],
+        ["#separator:tab", "#html:true", "#tags column:3"]
+    );
+    // Gale has nothing on the back without the ai_* fields
+    assert_eq!(lines.len(), 6);
+    assert_eq!(
+        lines[3],
+        "Storm\ta disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning\tHurricane"
+    );
+    assert_eq!(
+        lines[5],
+        "Eye Wall\tthe ring of &lt;tall&gt; storms around the eye<ul><li>strongest winds</li><li>heaviest rain</li></ul><i>Example:</i> Katrina's eye wall\tHurricane Tropical_Storms"
+    );
+}
+
+async fn exports_quizlet_csv_from_the_ai_fields(app: Router) {
+    add_terms(&app).await;
+    let (content_type, file) = export(&app, "/v1/export/flashcards?format=quizlet&fields=ai").await;
+    assert!(content_type.starts_with("text/csv"));
+    // only Gale has ai_* fields
+    assert_eq!(
+        file,
+        "term,definition\nGale,\"a very strong wind\n• force 8\"\n"
+    );
+
+    let (_, file) = export(&app, "/v1/export/flashcards?format=quizlet").await;
+    assert!(file.contains(
+        "Eye Wall,\"the ring of <tall> storms around the eye\n• strongest winds\n• heaviest rain\nExample: Katrina's eye wall\"\n"
+    ));
+}
+
+async fn filters_by_topic_and_verification(app: Router) {
+    add_terms(&app).await;
+    let fronts = |file: &str| -> Vec<String> {
+        file.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split('\t').next().unwrap().to_owned())
+            .collect()
+    };
+
+    let (_, file) = export(&app, "/v1/export/flashcards?topic=tropical-storms").await;
+    assert_eq!(fronts(&file), ["Eye Wall"]);
+    // the cards keep all of their topics as tags
+    assert!(file.ends_with("\tHurricane Tropical_Storms\n"));
+    let (_, file) = export(&app, "/v1/export/flashcards?topic=1").await;
+    assert_eq!(fronts(&file), ["Storm", "Tropical Cycle", "Eye Wall"]);
+    let (_, file) = export(&app, "/v1/export/flashcards?verified_only=true").await;
+    assert_eq!(fronts(&file), ["Eye Wall"]);
+
+    let (status, _) = call(
+        &app,
+        Method::GET,
+        "/v1/export/flashcards?topic=monsoon",
+        &[],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+fn node_ids(graph: &Value) -> Vec<String> {
+    graph["nodes"]
+        .as_array()
+        .expect("the graph has nodes")
+        .iter()
+        .map(|node| node["id"].as_str().unwrap_or_default().to_owned())
+        .collect()
+}
+
+async fn exports_the_knowledge_graph(app: Router) {
+    let (content_type, graph) = export(&app, "/v1/export/graph").await;
+    assert_eq!(content_type, "application/json");
+    let graph: Value = serde_json::from_str(&graph).unwrap();
+    assert_eq!(
+        node_ids(&graph),
+        ["topic:1", "term:1", "term:2", "source:1", "source:2", "source:3"]
+    );
+    assert_eq!(
+        graph["nodes"][1],
+        json!({"id": "term:1", "type": "term", "label": "Storm", "slug": "storm"})
+    );
+    assert_eq!(graph["links"].as_array().unwrap().len(), 5);
+    assert_eq!(
+        graph["links"][0],
+        json!({"source": "term:1", "target": "topic:1", "table": "terms_to_topics"})
+    );
+
+    let (content_type, dot) = export(&app, "/v1/export/graph?format=dot").await;
+    assert!(content_type.starts_with("text/vnd.graphviz"));
+    assert!(dot.starts_with("digraph knowledge {\n"));
+    assert!(dot.contains("  \"term:1\" [label=\"Storm\", type=\"term\", shape=ellipse];\n"));
+    assert!(dot.contains("  \"topic:1\" -> \"source:3\" [label=\"topics_to_sources\"];\n"));
+
+    let (content_type, graphml) = export(&app, "/v1/export/graph?format=graphml").await;
+    assert!(content_type.starts_with("application/graphml+xml"));
+    assert!(graphml.contains(
+        "<node id=\"source:1\"><data key=\"type\">source</data><data key=\"label\">dictionary storm</data>"
+    ));
+    assert!(graphml.contains(
+        "<edge source=\"term:2\" target=\"source:2\"><data key=\"table\">terms_to_sources</data></edge>"
+    ));
+    assert!(graphml.ends_with("</graph>\n</graphml>\n"));
+}
+
+async fn exports_the_neighbourhood_of_a_record(app: Router) {
+    let graph = |uri: &'static str| {
+        let app = app.clone();
+        async move { serde_json::from_str::<Value>(&export(&app, uri).await.1).unwrap() }
+    };
+
+    let storm = graph("/v1/export/graph?entity_type=term&id=storm").await;
+    assert_eq!(node_ids(&storm), ["topic:1", "term:1", "source:1"]);
+    assert_eq!(storm["links"].as_array().unwrap().len(), 2);
+    // the hurricane topic's other term and source are two edges away
+    let storm = graph("/v1/export/graph?entity_type=term&id=1&depth=2").await;
+    assert_eq!(
+        node_ids(&storm),
+        ["topic:1", "term:1", "term:2", "source:1", "source:3"]
+    );
+    let source = graph("/v1/export/graph?entity_type=source&id=2&depth=0").await;
+    assert_eq!(node_ids(&source), ["source:2"]);
+    assert_eq!(source["links"], json!([]));
+
+    for (uri, status) in [
+        ("/v1/export/graph?entity_type=term", StatusCode::BAD_REQUEST),
+        (
+            "/v1/export/graph?entity_type=article&id=1",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/v1/export/graph?entity_type=term&id=gale",
+            StatusCode::NOT_FOUND,
+        ),
+    ] {
+        let (actual, _) = call(&app, Method::GET, uri, &[], None).await;
+        assert_eq!(actual, status, "{}", uri);
+    }
+}
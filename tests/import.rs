@@ -0,0 +1,236 @@
+/*
+POST /v1/import with the sample data of init.sql: files mixing topics, terms and sources
+with forward references, CSV column mappings, records that are skipped, updated or fail on
+their own, and sources that share a name.
+
+Every test runs on each repository, like tests/router.rs.
+ */
+#[macro_use]
+mod common;
+
+use axum::http::StatusCode;
+use axum::Router;
+use common::{get, names, post_file};
+use serde_json::{json, Value};
+
+backend_tests!(
+    imports_records_of_every_type_with_forward_references,
+    imports_csv_with_a_column_mapping,
+    updates_existing_records_when_asked,
+    matches_sources_by_name_and_url,
+);
+
+async fn import(app: &Router, uri: &str, content_type: &str, body: &str) -> Value {
+    let (status, report) = post_file(app, uri, content_type, body).await;
+    assert_eq!(status, StatusCode::OK, "POST {}: {}", uri, report);
+    report
+}
+
+// (status, name) of every row of the report
+fn statuses(report: &Value) -> Vec<(String, String)> {
+    report["rows"]
+        .as_array()
+        .expect("the report has rows")
+        .iter()
+        .map(|row| {
+            (
+                row["status"].as_str().unwrap_or_default().to_owned(),
+                row["name"].as_str().unwrap_or_default().to_owned(),
+            )
+        })
+        .collect()
+}
+
+async fn imports_records_of_every_type_with_forward_references(app: Router) {
+    // the term comes before the topic and the source it names
+    let file = [
+        json!({"entity_type": "term", "name": "Eye Wall", "brief_description": "the ring of storms around the eye",
+            "related_topics": ["Typhoon"], "related_sources": ["noaa glossary"]}),
+        json!({"entity_type": "topic", "name": "Typhoon", "is_verified": true}),
+        json!({"entity_type": "source", "name": "noaa glossary", "url": "https://www.noaa.gov", "media_type": "Web"}),
+        json!({"entity_type": "term", "name": "storm"}),
+        json!({"entity_type": "term", "name": "Bad Term", "bullet_points": "not a list"}),
+        json!({"entity_type": "question", "name": "What is an eye wall?"}),
+        json!({"name": "Storm Surge", "related_topics": ["Hurricane", "Monsoon"]}),
+    ]
+    .iter()
+    .map(Value::to_string)
+    .collect::<Vec<String>>()
+    .join("\n");
+
+    let report = import(
+        &app,
+        "/v1/import?entity_type=term",
+        "application/x-ndjson",
+        &file,
+    )
+    .await;
+    assert_eq!(
+        statuses(&report),
+        [
+            ("created", "Eye Wall"),
+            ("created", "Typhoon"),
+            ("created", "noaa glossary"),
+            // matched case-insensitively with the sample data's Storm
+            ("skipped", "storm"),
+            ("failed", "Bad Term"),
+            ("failed", "What is an eye wall?"),
+            ("created", "Storm Surge"),
+        ]
+        .map(|(status, name)| (status.to_owned(), name.to_owned()))
+    );
+    assert_eq!(
+        [&report["created"], &report["skipped"], &report["failed"]],
+        [4, 1, 2]
+    );
+    assert_eq!(report["rows"][0]["row"], 1);
+    assert_eq!(report["rows"][3]["id"], 1);
+    assert_eq!(report["rows"][6]["entity_type"], "term");
+    assert_eq!(report["rows"][6]["unresolved"], json!(["Monsoon"]));
+
+    let eye_wall = get(&app, "/v1/terms/eye-wall?include=topics,sources").await;
+    assert_eq!(eye_wall["is_verified"], false);
+    assert_eq!(names(&eye_wall["topics"], "topic"), ["Typhoon"]);
+    assert_eq!(names(&eye_wall["sources"], "name"), ["noaa glossary"]);
+    let topic = get(&app, "/v1/topics/typhoon").await;
+    assert_eq!(topic["is_verified"], true);
+    let hurricane_terms = get(&app, "/v1/topics/hurricane/terms").await;
+    assert!(names(&hurricane_terms, "term").contains(&"Storm Surge".to_owned()));
+    let terms = get(&app, "/v1/terms").await;
+    assert!(!names(&terms, "term").contains(&"Bad Term".to_owned()));
+}
+
+async fn imports_csv_with_a_column_mapping(app: Router) {
+    let file = "Word,Definition,bullet_points,is_verified,Notes
+Cyclone,a system of winds rotating around a low,inward winds | low pressure,yes,from the old glossary
+Gale,a very strong wind,,maybe,
+";
+    let report = import(
+        &app,
+        "/v1/import?entity_type=term&mapping=Word:name,Definition:brief_description,Notes:-",
+        "text/csv",
+        file,
+    )
+    .await;
+    assert_eq!(
+        statuses(&report),
+        [("created", "Cyclone"), ("failed", "")]
+            .map(|(status, name)| (status.to_owned(), name.to_owned()))
+    );
+    // the header is line 1
+    assert_eq!(report["rows"][1]["row"], 3);
+    assert_eq!(
+        report["rows"][1]["error"],
+        "is_verified must be true or false, not maybe"
+    );
+
+    let cyclone = get(&app, "/v1/terms/cyclone").await;
+    assert_eq!(
+        cyclone["brief_description"],
+        "a system of winds rotating around a low"
+    );
+    assert_eq!(
+        cyclone["bullet_points"],
+        json!(["inward winds", "low pressure"])
+    );
+    assert_eq!(cyclone["is_verified"], true);
+
+    // Notes isn't a field and isn't mapped
+    let (status, _) = post_file(
+        &app,
+        "/v1/import?entity_type=term&mapping=Word:name",
+        "text/csv",
+        file,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // without a Content-Type the format has to be given
+    let (status, _) = post_file(&app, "/v1/import?entity_type=term", "text/plain", file).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn updates_existing_records_when_asked(app: Router) {
+    let file = json!([
+        {"name": "Storm", "brief_description": "a violent disturbance of the atmosphere", "is_verified": true},
+        {"name": "Squall", "related_terms": ["Storm"]},
+    ])
+    .to_string();
+
+    let report = import(
+        &app,
+        "/v1/import?entity_type=topic&on_existing=skip",
+        "application/json",
+        &file,
+    )
+    .await;
+    // topics and terms have their own names
+    assert_eq!(report["created"], 2);
+
+    let report = import(
+        &app,
+        "/v1/import?entity_type=term&on_existing=update",
+        "application/json",
+        &file,
+    )
+    .await;
+    assert_eq!(
+        statuses(&report),
+        [("updated", "Storm"), ("created", "Squall")]
+            .map(|(status, name)| (status.to_owned(), name.to_owned()))
+    );
+    let storm = get(&app, "/v1/terms/storm").await;
+    assert_eq!(storm["id"], 1);
+    assert_eq!(storm["version"], 2);
+    assert_eq!(storm["is_verified"], true);
+    assert_eq!(
+        storm["brief_description"],
+        "a violent disturbance of the atmosphere"
+    );
+    // a term isn't linked to other terms
+    assert_eq!(report["rows"][1].get("unresolved"), None);
+}
+
+async fn matches_sources_by_name_and_url(app: Router) {
+    let file = json!([
+        {"name": "dictionary storm", "url": "https://www.merriam-webster.com/dictionary/storm"},
+        {"name": "dictionary storm", "url": "https://www.collinsdictionary.com/dictionary/english/storm"},
+        {"name": "dictionary storm"},
+    ])
+    .to_string();
+    let report = import(
+        &app,
+        "/v1/import?entity_type=source",
+        "application/json",
+        &file,
+    )
+    .await;
+    // only the sample data's source has the same url
+    assert_eq!(
+        statuses(&report),
+        [
+            ("skipped", "dictionary storm"),
+            ("created", "dictionary storm"),
+            ("created", "dictionary storm"),
+        ]
+        .map(|(status, name)| (status.to_owned(), name.to_owned()))
+    );
+    assert_eq!(report["rows"][0]["id"], 1);
+    let sources = get(&app, "/v1/sources").await;
+    assert_eq!(
+        names(&sources, "name")
+            .iter()
+            .filter(|name| *name == "dictionary storm")
+            .count(),
+        3
+    );
+
+    // a second import matches the new ones too
+    let report = import(
+        &app,
+        "/v1/import?entity_type=source",
+        "application/json",
+        &file,
+    )
+    .await;
+    assert_eq!(report["skipped"], 3);
+}
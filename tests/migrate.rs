@@ -0,0 +1,132 @@
+/*
+database/migrate.sql on Postgres: it brings a database with the schema of an older init.sql
+up to date, and leaves a current one as it is. The tests are skipped without DATABASE_URL,
+see tests/common.
+ */
+mod common;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool};
+
+// without bind parameters the statements are sent as one simple query
+async fn migrate(pool: &PgPool) {
+    pool.execute(include_str!("../database/migrate.sql"))
+        .await
+        .unwrap();
+}
+
+// what init.sql added since the first version, taken off the database it created
+const OLD_SCHEMA: &str = "
+DROP TABLE platform.webhook_deliveries, platform.webhooks, platform.events;
+DROP FUNCTION platform.new_webhook_secret, platform.record_entity_event,
+    platform.record_link_event, platform.notify_event CASCADE;
+DROP FUNCTION platform.set_updated_at, platform.bump_version CASCADE;
+DO $$
+DECLARE
+    table_name text;
+BEGIN
+    FOR table_name IN
+        SELECT columns.table_name FROM information_schema.columns
+        WHERE table_schema = 'platform' AND column_name = 'updated_at'
+    LOOP
+        EXECUTE format(
+            'ALTER TABLE platform.%I DROP COLUMN created_at, DROP COLUMN updated_at',
+            table_name
+        );
+    END LOOP;
+END;
+$$;
+ALTER TABLE platform.sources DROP COLUMN version, DROP COLUMN slug;
+ALTER TABLE platform.topics DROP COLUMN version, DROP COLUMN slug;
+ALTER TABLE platform.terms DROP COLUMN version, DROP COLUMN slug;
+INSERT INTO platform.terms (term) VALUES ('1984'), ('C++'), ('C'), ('Storm!');
+";
+
+// the created_at, updated_at and version of the term Storm
+async fn storm(pool: &PgPool) -> (DateTime<Utc>, DateTime<Utc>, i32) {
+    sqlx::query_as(
+        "SELECT created_at, updated_at, version FROM platform.terms WHERE term = 'Storm'",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn migrates_a_database_of_the_first_schema() {
+    let Some(database) = common::TestDatabase::create().await else {
+        return;
+    };
+    let pool = database.pool();
+    pool.execute(OLD_SCHEMA).await.unwrap();
+    migrate(pool).await;
+
+    // the slugs the API would have given the terms, in the order of their ids
+    let slugs: Vec<(String, String)> =
+        sqlx::query_as("SELECT term, slug FROM platform.terms ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        slugs,
+        [
+            ("Storm", "storm"),
+            ("Tropical Cycle", "tropical-cycle"),
+            ("1984", "term-1984"),
+            ("C++", "c"),
+            ("C", "c-2"),
+            ("Storm!", "storm-2"),
+        ]
+        .map(|(term, slug)| (term.to_owned(), slug.to_owned()))
+    );
+
+    // the existing rows get the time of the migration and version 1
+    let (created_at, updated_at, version) = storm(pool).await;
+    assert_eq!(created_at, updated_at);
+    assert_eq!(version, 1);
+
+    // the triggers are there: updates set updated_at, bump the version and are recorded
+    // as events
+    sqlx::query("UPDATE platform.terms SET is_verified = true WHERE term = 'Storm'")
+        .execute(pool)
+        .await
+        .unwrap();
+    let (_, updated_at, version) = storm(pool).await;
+    assert!(updated_at > created_at);
+    assert_eq!(version, 2);
+    let events: i64 = sqlx::query_scalar("SELECT count(*) FROM platform.events")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert!(events > 0);
+
+    // webhooks get a secret when they are created without one
+    let secret: String = sqlx::query_scalar(
+        "INSERT INTO platform.webhooks (url) VALUES ('https://example.com/hook') RETURNING secret",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    assert_eq!(secret.len(), 64);
+    database.drop().await
+}
+
+// every term as JSON, in the order of their ids
+async fn terms(pool: &PgPool) -> String {
+    sqlx::query_scalar("SELECT json_agg(terms ORDER BY id)::text FROM platform.terms")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn leaves_a_current_database_as_it_is() {
+    let Some(database) = common::TestDatabase::create().await else {
+        return;
+    };
+    let before = terms(database.pool()).await;
+    migrate(database.pool()).await;
+    migrate(database.pool()).await;
+    assert_eq!(terms(database.pool()).await, before);
+    database.drop().await
+}
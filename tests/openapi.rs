@@ -0,0 +1,134 @@
+/*
+Every route of the router has to be in the document served at /openapi.json with its
+method, and every operation in the document has to be a route, so a new handler can't ship
+without its #[utoipa::path] and a documented operation can't point at a missing route.
+
+The routes are the (method, path) pairs from jd_crm_api::route_table(), which lists what
+create_routes registers, the relationship routes from LINK_TABLES included.
+ */
+use axum::http::{Request, StatusCode};
+use hyper::Body;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+// (GET, "/topics/:id") -> ("get", "/topics/{id}"), the path template used by OpenAPI
+fn registered_operations() -> BTreeSet<(String, String)> {
+    jd_crm_api::route_table()
+        .into_iter()
+        .map(|(method, path)| {
+            let path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(name) => format!("{{{}}}", name),
+                    None => segment.to_owned(),
+                })
+                .collect::<Vec<String>>()
+                .join("/");
+            (method.as_str().to_lowercase(), path)
+        })
+        .collect()
+}
+
+fn documented_operations(document: &Value) -> BTreeSet<(String, String)> {
+    let paths = document["paths"]
+        .as_object()
+        .expect("the document has paths");
+    paths
+        .iter()
+        .flat_map(|(path, operations)| {
+            operations
+                .as_object()
+                .expect("a path has operations")
+                .keys()
+                .map(move |method| (method.clone(), path.clone()))
+        })
+        .collect()
+}
+
+async fn openapi_document() -> Value {
+    // the document doesn't need any records
+    let response = jd_crm_api::app(Arc::new(jd_crm_api::MemoryRepository::new()))
+        .oneshot(
+            Request::builder()
+                .uri("/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).expect("/openapi.json is JSON")
+}
+
+#[tokio::test]
+async fn every_route_is_documented() {
+    let document = openapi_document().await;
+    let routes = registered_operations();
+    assert!(
+        routes.len() > 30,
+        "not all routes were listed: {:?}",
+        routes
+    );
+    // a route that answers two methods has both in the table
+    assert!(routes.contains(&("put".to_owned(), "/v1/topics/{id}".to_owned())));
+    assert!(routes.contains(&("get".to_owned(), "/v1/topics/{id}".to_owned())));
+
+    let operations = documented_operations(&document);
+    let missing: Vec<&(String, String)> = routes.difference(&operations).collect();
+    assert!(
+        missing.is_empty(),
+        "routes missing from /openapi.json: {:?}",
+        missing
+    );
+    let unrouted: Vec<&(String, String)> = operations.difference(&routes).collect();
+    assert!(
+        unrouted.is_empty(),
+        "operations in /openapi.json without a route: {:?}",
+        unrouted
+    );
+}
+
+#[tokio::test]
+async fn legacy_routes_are_deprecated() {
+    let document = openapi_document().await;
+    for (path, operations) in document["paths"].as_object().unwrap() {
+        for (method, operation) in operations.as_object().unwrap() {
+            let deprecated = operation["deprecated"] == Value::Bool(true);
+            let legacy = ["/topic", "/term", "/source", "/new-", "/link-entities"]
+                .iter()
+                .any(|prefix| path.starts_with(prefix))
+                || path.contains("-from-");
+            assert_eq!(deprecated, legacy, "{} {}", method, path);
+        }
+    }
+}
+
+#[tokio::test]
+async fn request_bodies_match_the_rust_types() {
+    let document = openapi_document().await;
+    let schemas = &document["components"]["schemas"];
+    for name in [
+        "CreateTopicOrTerm",
+        "CreateSource",
+        "CreateLink",
+        "Topic",
+        "Term",
+        "Source",
+        "MediaType",
+        "ImageType",
+    ] {
+        assert!(schemas[name].is_object(), "{} is missing", name);
+    }
+    // the /new-topic comment used to say "topic"
+    let required = schemas["CreateTopicOrTerm"]["required"].as_array().unwrap();
+    assert!(required.contains(&Value::from("name")));
+
+    let new_topic = &document["paths"]["/new-topic"]["post"];
+    assert_eq!(
+        new_topic["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/CreateTopicOrTerm"
+    );
+}
@@ -0,0 +1,320 @@
+/*
+The whole router with the sample data of init.sql: reads with fields, include and lookups
+by slug, creating and linking records, optimistic concurrency on updates, and GraphQL.
+
+Every test runs on each repository, the in-memory one, SQLite and Postgres, to check they
+behave the same, see tests/common.
+ */
+#[macro_use]
+mod common;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use common::{call, get, names};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+backend_tests!(
+    reads_the_sample_data,
+    reads_related_records,
+    creates_and_links_records,
+    updates_need_the_current_version,
+    updates_compare_if_match_strongly,
+    answers_graphql_queries,
+    gives_records_created_at_the_same_time_their_own_slug,
+);
+
+async fn reads_the_sample_data(app: Router) {
+    let topics = get(&app, "/v1/topics").await;
+    assert_eq!(names(&topics, "topic"), ["Hurricane"]);
+    assert_eq!(topics[0]["version"], 1);
+
+    let term = get(&app, "/v1/terms/tropical-cycle").await;
+    assert_eq!(term["term"], "Tropical Cycle");
+    assert_eq!(term["id"], 2);
+
+    let sources = get(&app, "/v1/sources?fields=id,media_type").await;
+    assert_eq!(sources[0], json!({"id": 1, "media_type": "Web"}));
+
+    let (status, _) = call(&app, Method::GET, "/v1/topics/tornado", &[], None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(&app, Method::GET, "/v1/topics?fields=color", &[], None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn reads_related_records(app: Router) {
+    let topic = get(
+        &app,
+        "/v1/topics/hurricane?fields=id,topic&include=terms,questions",
+    )
+    .await;
+    assert_eq!(topic["topic"], "Hurricane");
+    assert_eq!(names(&topic["terms"], "term"), ["Storm", "Tropical Cycle"]);
+    assert_eq!(names(&topic["questions"], "question"), ["What is a storm?"]);
+
+    // a term's questions are the questions of its topics
+    let term = get(&app, "/v1/terms/storm?include=questions").await;
+    assert_eq!(names(&term["questions"], "question"), ["What is a storm?"]);
+
+    let sources = get(&app, "/v1/topics/1/sources?fields=name").await;
+    assert_eq!(sources, json!([{"name": "wikipedia atlantic hurricane"}]));
+
+    let terms = get(&app, "/terms-from-topic?topic=hurricane").await;
+    assert_eq!(names(&terms, "term"), ["Storm", "Tropical Cycle"]);
+
+    let page = get(&app, "/v1/topics/1/terms?limit=1&offset=1").await;
+    assert_eq!(names(&page, "term"), ["Tropical Cycle"]);
+}
+
+async fn creates_and_links_records(app: Router) {
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/v1/terms",
+        &[],
+        Some(json!({
+            "name": "Storm Surge",
+            "is_verified": false,
+            "related_topics": ["hurricane"],
+            "related_sources": ["dictionary storm", "no such source"],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let term = get(&app, "/v1/terms/storm-surge?include=topics,sources").await;
+    assert_eq!(term["id"], 3);
+    assert_eq!(term["bullet_points"], json!([]));
+    assert_eq!(names(&term["topics"], "topic"), ["Hurricane"]);
+    assert_eq!(names(&term["sources"], "name"), ["dictionary storm"]);
+
+    // names are unique, slugs are made unique
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/terms",
+        &[],
+        Some(json!({"name": "Storm Surge", "is_verified": false})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/terms",
+        &[],
+        Some(json!({"name": "storm surge", "is_verified": false})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        get(&app, "/v1/terms/storm-surge-2").await["term"],
+        "storm surge"
+    );
+
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/v1/links",
+        &[],
+        Some(json!({
+            "parent_entity_type": "source",
+            "child_entity_type": "topic",
+            "parent_id": 2,
+            "related_topic_ids": [1],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let topics = get(&app, "/v1/sources/2/topics").await;
+    assert_eq!(names(&topics, "topic"), ["Hurricane"]);
+
+    // links need both records
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/links",
+        &[],
+        Some(json!({
+            "parent_entity_type": "source",
+            "child_entity_type": "topic",
+            "parent_id": 2,
+            "related_topic_ids": [42],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+async fn updates_need_the_current_version(app: Router) {
+    let update = json!({"name": "Hurricane", "is_verified": true, "brief_description": "updated"});
+
+    let (status, _) = call(
+        &app,
+        Method::PUT,
+        "/v1/topics/hurricane",
+        &[],
+        Some(update.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+
+    let (status, topic) = call(
+        &app,
+        Method::PUT,
+        "/v1/topics/hurricane",
+        &[("if-match", "\"1\"")],
+        Some(update.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", topic);
+    assert_eq!(topic["version"], 2);
+    assert_eq!(topic["brief_description"], "updated");
+    assert_eq!(topic["is_verified"], true);
+
+    let (status, conflict) = call(
+        &app,
+        Method::PUT,
+        "/v1/topics/hurricane",
+        &[("if-match", "\"1\"")],
+        Some(update),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(conflict["current_version"], 2);
+
+    let (status, source) = call(
+        &app,
+        Method::PUT,
+        "/v1/sources/1",
+        &[],
+        Some(json!({"name": "dictionary storm", "media_type": "Book", "version": 1})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", source);
+    assert_eq!(source["media_type"], "Book");
+    assert_eq!(source["slug"], "dictionary-storm");
+}
+
+// If-Match: * matches any version, a list matches by any of its tags, a weak tag never
+async fn updates_compare_if_match_strongly(app: Router) {
+    async fn put(app: &Router, if_match: &str, uri: &str) -> (StatusCode, Value) {
+        let update = json!({"name": "Storm", "is_verified": true});
+        call(
+            app,
+            Method::PUT,
+            uri,
+            &[("if-match", if_match)],
+            Some(update),
+        )
+        .await
+    }
+
+    let (status, conflict) = put(&app, "W/\"1\"", "/v1/terms/storm").await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(conflict["current_version"], 1);
+
+    let (status, term) = put(&app, "\"7\", \"1\"", "/v1/terms/storm").await;
+    assert_eq!(status, StatusCode::OK, "{}", term);
+    assert_eq!(term["version"], 2);
+
+    let (status, conflict) = put(&app, "\"7\", \"1\"", "/v1/terms/storm").await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(conflict["current_version"], 2);
+
+    let (status, term) = put(&app, "*", "/v1/terms/storm").await;
+    assert_eq!(status, StatusCode::OK, "{}", term);
+    assert_eq!(term["version"], 3);
+
+    let (status, _) = put(&app, "*", "/v1/terms/9999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+async fn answers_graphql_queries(app: Router) {
+    let query = "{
+        topic(slug: \"hurricane\") { topic terms { term sources { name } } questions { question } }
+        articles { title publishDate topics { topic } }
+    }";
+    let (status, response) = call(
+        &app,
+        Method::POST,
+        "/graphql",
+        &[],
+        Some(json!({"query": query})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response.get("errors"), None, "{}", response);
+    let topic = &response["data"]["topic"];
+    assert_eq!(names(&topic["terms"], "term"), ["Storm", "Tropical Cycle"]);
+    assert_eq!(
+        topic["terms"][1]["sources"],
+        json!([{"name": "wikipedia tropical cyclone"}])
+    );
+    assert_eq!(names(&topic["questions"], "question"), ["What is a storm?"]);
+    let article = &response["data"]["articles"][0];
+    assert_eq!(article["publishDate"], "2023-03-27");
+    assert_eq!(names(&article["topics"], "topic"), ["Hurricane"]);
+
+    let mutation = "mutation {
+        createTopic(input: { name: \"Typhoon\", isVerified: false, relatedTerms: [\"Storm\"] }) {
+            id slug terms { term }
+        }
+    }";
+    let (_, response) = call(
+        &app,
+        Method::POST,
+        "/graphql",
+        &[],
+        Some(json!({"query": mutation})),
+    )
+    .await;
+    assert_eq!(response.get("errors"), None, "{}", response);
+    let topic = &response["data"]["createTopic"];
+    assert_eq!(topic["id"], 2);
+    assert_eq!(topic["slug"], "typhoon");
+    assert_eq!(names(&topic["terms"], "term"), ["Storm"]);
+}
+
+// every name is slugified to "tropical-storm", the slug is picked before the insert
+async fn gives_records_created_at_the_same_time_their_own_slug(app: Router) {
+    let names = [
+        "Tropical Storm",
+        "tropical storm",
+        "TROPICAL STORM",
+        "Tropical-Storm",
+        "Tropical  Storm",
+        "tropical_storm",
+    ];
+    let creates = names.iter().map(|name| {
+        call(
+            &app,
+            Method::POST,
+            "/v1/topics",
+            &[],
+            Some(json!({"name": name, "is_verified": false})),
+        )
+    });
+    for (status, body) in futures::future::join_all(creates).await {
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let topics = get(&app, "/v1/topics?fields=topic,slug").await;
+    let slugs: HashSet<String> = names
+        .iter()
+        .map(|name| {
+            let topic = topics
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|topic| topic["topic"] == *name)
+                .unwrap_or_else(|| panic!("{} was created", name));
+            topic["slug"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    let expected: HashSet<String> = ["tropical-storm".to_owned()]
+        .into_iter()
+        .chain((2..=names.len()).map(|suffix| format!("tropical-storm-{}", suffix)))
+        .collect();
+    assert_eq!(slugs, expected);
+}
@@ -0,0 +1,191 @@
+/*
+The export-vault and import-vault commands with the sample data of init.sql in a SQLite
+file: the notes of the topics and terms, notes edited and added in Obsidian, and vaults
+that can't be imported.
+ */
+mod common;
+
+use axum::Router;
+use common::{get, names};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// a directory with a SQLite database and a vault, removed when the test is done
+struct Workspace {
+    directory: PathBuf,
+}
+
+impl Workspace {
+    fn new() -> Self {
+        let directory =
+            std::env::temp_dir().join(format!("jd_crm_api-vault-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        Workspace { directory }
+    }
+
+    fn db_uri(&self) -> String {
+        format!("sqlite://{}", self.directory.join("platform.db").display())
+    }
+
+    fn vault(&self) -> PathBuf {
+        self.directory.join("vault")
+    }
+
+    fn args(&self, extra: &[&str]) -> Vec<String> {
+        let mut args = vec![self.vault().display().to_string()];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        args
+    }
+
+    async fn app(&self) -> Router {
+        let repository = jd_crm_api::SqliteRepository::connect(&self.db_uri())
+            .await
+            .unwrap();
+        jd_crm_api::app(Arc::new(repository))
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.directory).ok();
+    }
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error))
+}
+
+#[tokio::test]
+async fn exports_a_note_per_topic_and_term() {
+    let workspace = Workspace::new();
+    let exit_code = jd_crm_api::run_export_vault(&workspace.db_uri(), &workspace.args(&[])).await;
+    assert_eq!(exit_code, 0);
+
+    let vault = workspace.vault();
+    assert!(vault.join("Terms/Tropical Cycle.md").is_file());
+    assert_eq!(
+        read(&vault.join("Terms/Storm.md")),
+        "---
+id: 1
+is_verified: false
+sources:
+  - \"dictionary storm\"
+---
+# Storm
+
+## Brief description
+
+a disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning
+
+## Topics
+
+- [[Hurricane]]
+"
+    );
+    let hurricane = read(&vault.join("Topics/Hurricane.md"));
+    assert!(hurricane.contains("sources:\n  - \"wikipedia atlantic hurricane\"\n"));
+    assert!(hurricane.ends_with("## Terms\n\n- [[Storm]]\n- [[Tropical Cycle]]\n"));
+}
+
+#[tokio::test]
+async fn imports_notes_edited_in_obsidian() {
+    let workspace = Workspace::new();
+    let db_uri = workspace.db_uri();
+    assert_eq!(
+        jd_crm_api::run_export_vault(&db_uri, &workspace.args(&[])).await,
+        0
+    );
+    let vault = workspace.vault();
+
+    // Storm is verified and gets bullet points, its brief description is removed
+    let storm = read(&vault.join("Terms/Storm.md"))
+        .replace("is_verified: false", "is_verified: true")
+        .replace(
+            "## Brief description\n\na disturbance of the atmosphere marked by wind and usually by rain, snow, hail, sleet, or thunder and lightning\n\n",
+            "## Bullet points\n\n- wind\n- rain\n\n## My notes\n\nleft out\n\n",
+        );
+    fs::write(vault.join("Terms/Storm.md"), storm).unwrap();
+    // a new topic with Hurricane on its mind map, and a new term in a subfolder
+    fs::write(
+        vault.join("Topics/Atlantic Weather.md"),
+        "---\nis_verified: true\nsources: [\"wikipedia tropical cyclone\"]\ntags: [weather]\n---\n# Atlantic Weather\n\n## Subtopics\n\n- [[Hurricane|hurricanes]]\n\n## Terms\n\n- [[Terms/Storm]]\n",
+    )
+    .unwrap();
+    fs::create_dir_all(vault.join("Terms/Wind")).unwrap();
+    fs::write(
+        vault.join("Terms/Wind/Gale.md"),
+        "## Full description\n\na very strong wind\n\n## Topics\n\n- [[Atlantic Weather]], [[Monsoon]]\n",
+    )
+    .unwrap();
+
+    // Monsoon isn't a topic
+    assert_eq!(
+        jd_crm_api::run_import_vault(&db_uri, &workspace.args(&[])).await,
+        1
+    );
+
+    let app = workspace.app().await;
+    let storm = get(&app, "/v1/terms/storm").await;
+    assert_eq!(storm["id"], 1);
+    assert_eq!(storm["version"], 2);
+    assert_eq!(storm["is_verified"], true);
+    assert_eq!(storm["brief_description"], Value::Null);
+    assert_eq!(storm["bullet_points"], serde_json::json!(["wind", "rain"]));
+    // a note without a title is named after its file
+    let gale = get(&app, "/v1/terms/gale?include=topics").await;
+    assert_eq!(gale["full_description"], "a very strong wind");
+    assert_eq!(names(&gale["topics"], "topic"), ["Atlantic Weather"]);
+    let topic = get(&app, "/v1/topics/atlantic-weather?include=terms,sources").await;
+    assert_eq!(topic["is_verified"], true);
+    assert_eq!(names(&topic["terms"], "term"), ["Storm", "Gale"]);
+    assert_eq!(
+        names(&topic["sources"], "name"),
+        ["wikipedia tropical cyclone"]
+    );
+
+    // the mind map is exported again
+    fs::remove_dir_all(&vault).unwrap();
+    assert_eq!(
+        jd_crm_api::run_export_vault(&db_uri, &workspace.args(&[])).await,
+        0
+    );
+    assert!(read(&vault.join("Topics/Atlantic Weather.md"))
+        .contains("## Subtopics\n\n- [[Hurricane]]\n"));
+}
+
+#[tokio::test]
+async fn refuses_vaults_it_cant_import() {
+    let workspace = Workspace::new();
+    let db_uri = workspace.db_uri();
+    let vault = workspace.vault();
+    fs::create_dir_all(vault.join("Terms")).unwrap();
+    fs::write(vault.join("Terms/Squall.md"), "# Squall\n").unwrap();
+    fs::write(
+        vault.join("Terms/Gale.md"),
+        "---\nis_verified: maybe\n---\n# Gale\n",
+    )
+    .unwrap();
+    assert_eq!(
+        jd_crm_api::run_import_vault(&db_uri, &workspace.args(&[])).await,
+        2
+    );
+
+    fs::write(vault.join("Terms/Gale.md"), "# Squall\n").unwrap();
+    assert_eq!(
+        jd_crm_api::run_import_vault(&db_uri, &workspace.args(&["--on-conflict", "merge"])).await,
+        2
+    );
+    assert_eq!(
+        jd_crm_api::run_import_vault(&db_uri, &workspace.args(&[])).await,
+        2
+    );
+
+    // nothing of the refused vaults was written
+    let app = workspace.app().await;
+    assert_eq!(
+        names(&get(&app, "/v1/terms").await, "term"),
+        ["Storm", "Tropical Cycle"]
+    );
+}
@@ -0,0 +1,379 @@
+/*
+Webhooks are sent to a receiver started by the test: the first request fails, so the
+delivery is retried, every request is signed with the webhook's secret, and the delivery
+can be replayed from the log. Linked events are sent for the links that are new, however
+they are made. A redirect isn't followed, the delivery fails instead.
+
+The test runs on the in-memory, the SQLite and the Postgres repository, the last one is
+skipped without DATABASE_URL, see tests/common.
+ */
+mod common;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, Method, StatusCode},
+    response::Redirect,
+    routing::post,
+    Router,
+};
+use common::call;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const SECRET: &str = "webhook-test-secret";
+
+struct ReceivedRequest {
+    headers: HeaderMap,
+    body: String,
+}
+
+impl ReceivedRequest {
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_else(|| panic!("the request has no {} header", name))
+    }
+
+    // checks the signature the way a receiver would and returns the payload
+    fn verified_payload(&self) -> Value {
+        let timestamp = self.header("webhook-timestamp");
+        let signature = self
+            .header("webhook-signature")
+            .strip_prefix("sha256=")
+            .expect("the signature starts with sha256=");
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", timestamp, self.body).as_bytes());
+        mac.verify_slice(&hex::decode(signature).unwrap())
+            .expect("the signature matches the body");
+        serde_json::from_str(&self.body).expect("the body is JSON")
+    }
+}
+
+#[derive(Clone)]
+struct Receiver {
+    requests: mpsc::UnboundedSender<ReceivedRequest>,
+    received: Arc<AtomicUsize>,
+}
+
+// the first request fails with a 500, the ones after it succeed
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: String) -> StatusCode {
+    receiver
+        .requests
+        .send(ReceivedRequest { headers, body })
+        .unwrap();
+    match receiver.received.fetch_add(1, Ordering::SeqCst) {
+        0 => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::OK,
+    }
+}
+
+// returns the receiver's URL and the requests it gets
+fn start_receiver() -> (String, mpsc::UnboundedReceiver<ReceivedRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, requests) = mpsc::unbounded_channel();
+    let receiver = Router::new()
+        .route("/hook", post(receive))
+        .with_state(Receiver {
+            requests: sender,
+            received: Arc::new(AtomicUsize::new(0)),
+        });
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(receiver.into_make_service()),
+    );
+    (format!("http://{}/hook", address), requests)
+}
+
+// answers every request with a redirect to `location`
+fn start_redirect(location: String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let redirect = Router::new().route(
+        "/hook",
+        post(move || async move { Redirect::temporary(&location) }),
+    );
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(redirect.into_make_service()),
+    );
+    format!("http://{}/hook", address)
+}
+
+// the receiver is on a loopback address, which webhooks aren't sent to by default
+fn allow_the_receiver() {
+    std::env::set_var("WEBHOOK_ALLOWED_HOSTS", "127.0.0.1");
+}
+
+async fn next_request(requests: &mut mpsc::UnboundedReceiver<ReceivedRequest>) -> ReceivedRequest {
+    tokio::time::timeout(Duration::from_secs(10), requests.recv())
+        .await
+        .expect("a webhook request arrives within 10 seconds")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_retried_and_replayed_in_memory() {
+    webhooks_are_signed_retried_and_replayed(
+        Arc::new(jd_crm_api::MemoryRepository::with_fixtures()),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_retried_and_replayed_on_sqlite() {
+    let repository = jd_crm_api::SqliteRepository::connect("sqlite::memory:")
+        .await
+        .unwrap();
+    webhooks_are_signed_retried_and_replayed(Arc::new(repository)).await
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_retried_and_replayed_on_postgres() {
+    let Some(database) = common::TestDatabase::create().await else {
+        return;
+    };
+    webhooks_are_signed_retried_and_replayed(Arc::new(database.repository())).await;
+    database.drop().await
+}
+
+#[tokio::test]
+async fn linked_events_are_sent_for_new_links_in_memory() {
+    linked_events_are_sent_for_new_links(Arc::new(jd_crm_api::MemoryRepository::with_fixtures()))
+        .await
+}
+
+#[tokio::test]
+async fn linked_events_are_sent_for_new_links_on_sqlite() {
+    let repository = jd_crm_api::SqliteRepository::connect("sqlite::memory:")
+        .await
+        .unwrap();
+    linked_events_are_sent_for_new_links(Arc::new(repository)).await
+}
+
+#[tokio::test]
+async fn linked_events_are_sent_for_new_links_on_postgres() {
+    let Some(database) = common::TestDatabase::create().await else {
+        return;
+    };
+    linked_events_are_sent_for_new_links(Arc::new(database.repository())).await;
+    database.drop().await
+}
+
+async fn webhooks_are_signed_retried_and_replayed(repository: Arc<dyn jd_crm_api::Repository>) {
+    // the first retry is due after 100ms instead of 30s
+    std::env::set_var("WEBHOOK_RETRY_BASE_MS", "100");
+    allow_the_receiver();
+    let app = jd_crm_api::app(repository);
+    let (url, mut requests) = start_receiver();
+
+    let (status, webhook) = call(
+        &app,
+        Method::POST,
+        "/v1/webhooks",
+        &[],
+        Some(json!({"url": url, "secret": SECRET, "event_types": ["term.created"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", webhook);
+    assert_eq!(webhook["secret"], SECRET);
+    let webhook_id = webhook["id"].as_i64().unwrap();
+
+    let term = "webhook test";
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/terms",
+        &[],
+        Some(json!({"name": term, "is_verified": false})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let failed = next_request(&mut requests).await;
+    let retried = next_request(&mut requests).await;
+    for request in [&failed, &retried] {
+        let payload = request.verified_payload();
+        assert_eq!(payload["event"], "term.created");
+        assert_eq!(payload["data"]["term"], term);
+        assert_eq!(request.header("webhook-event"), "term.created");
+    }
+    let delivery_id = failed.header("webhook-id").to_owned();
+    assert_eq!(retried.header("webhook-id"), delivery_id);
+
+    // the log is updated after the receiver has answered
+    let deliveries_uri = format!("/v1/webhooks/{}/deliveries", webhook_id);
+    let mut delivery = Value::Null;
+    for _ in 0..10 {
+        let (status, deliveries) = call(&app, Method::GET, &deliveries_uri, &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        delivery = deliveries[0].clone();
+        if delivery["status"] == "delivered" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert_eq!(delivery["status"], "delivered", "{}", delivery);
+    assert_eq!(delivery["id"].to_string(), delivery_id);
+    assert_eq!(delivery["attempts"], 2);
+    assert_eq!(delivery["last_status_code"], 200);
+
+    let (status, replay) = call(
+        &app,
+        Method::POST,
+        &format!("{}/{}/replay", deliveries_uri, delivery_id),
+        &[],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", replay);
+    assert_eq!(replay["replay_of"].to_string(), delivery_id);
+    let replayed = next_request(&mut requests).await;
+    assert_eq!(replayed.header("webhook-id"), replay["id"].to_string());
+    assert_eq!(
+        replayed.verified_payload()["data"],
+        retried.verified_payload()["data"]
+    );
+
+    let (status, _) = call(
+        &app,
+        Method::DELETE,
+        &format!("/v1/webhooks/{}", webhook_id),
+        &[],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+async fn linked_events_are_sent_for_new_links(repository: Arc<dyn jd_crm_api::Repository>) {
+    allow_the_receiver();
+    let app = jd_crm_api::app(repository);
+    let (url, _requests) = start_receiver();
+    let (status, webhook) = call(
+        &app,
+        Method::POST,
+        "/v1/webhooks",
+        &[],
+        Some(json!({"url": url, "secret": SECRET, "event_types": ["topic.linked"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", webhook);
+
+    // the related names of a new term
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/terms",
+        &[],
+        Some(json!({"name": "Gust", "is_verified": false, "related_topics": ["Hurricane"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, gust) = call(&app, Method::GET, "/v1/terms/gust", &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+    // the same link again
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/links",
+        &[],
+        Some(
+            json!({"parent_entity_type": "term", "child_entity_type": "topic",
+            "parent_id": gust["id"], "related_topic_ids": [1]}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // an import's related names
+    let (status, _) = common::post_file(
+        &app,
+        "/v1/import?entity_type=term",
+        "application/json",
+        &json!([{"name": "Squall", "related_topics": ["Hurricane"]}]).to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, deliveries) = call(
+        &app,
+        Method::GET,
+        &format!("/v1/webhooks/{}/deliveries", webhook["id"]),
+        &[],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let mut linked: Vec<(String, Value)> = deliveries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|delivery| {
+            assert_eq!(delivery["event_type"], "topic.linked");
+            assert_eq!(delivery["data"]["child_ids"], json!([1]));
+            (
+                delivery["data"]["parent_entity_type"]
+                    .as_str()
+                    .unwrap()
+                    .to_owned(),
+                delivery["data"]["parent_id"].clone(),
+            )
+        })
+        .collect();
+    linked.sort_by_key(|(_, parent_id)| parent_id.as_i64());
+    assert_eq!(linked.len(), 2, "{}", deliveries);
+    assert_eq!(linked[0], ("term".to_owned(), gust["id"].clone()));
+}
+
+// the storage doesn't matter here, so it only runs on the in-memory repository
+#[tokio::test]
+async fn redirects_to_internal_addresses_are_not_followed() {
+    allow_the_receiver();
+    let app = jd_crm_api::app(Arc::new(jd_crm_api::MemoryRepository::with_fixtures()));
+    // localhost isn't allowed, only 127.0.0.1
+    let (internal_url, mut requests) = start_receiver();
+    let url = start_redirect(internal_url.replace("127.0.0.1", "localhost"));
+    let (status, webhook) = call(
+        &app,
+        Method::POST,
+        "/v1/webhooks",
+        &[],
+        Some(json!({"url": url, "secret": SECRET, "event_types": ["term.created"]})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", webhook);
+
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/v1/terms",
+        &[],
+        Some(json!({"name": "redirected", "is_verified": false})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let deliveries_uri = format!("/v1/webhooks/{}/deliveries", webhook["id"]);
+    let mut delivery = Value::Null;
+    for _ in 0..10 {
+        let (status, deliveries) = call(&app, Method::GET, &deliveries_uri, &[], None).await;
+        assert_eq!(status, StatusCode::OK);
+        delivery = deliveries[0].clone();
+        if !delivery["last_status_code"].is_null() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert_eq!(delivery["last_status_code"], 307, "{}", delivery);
+    assert_ne!(delivery["status"], "delivered");
+    assert!(requests.try_recv().is_err(), "the redirect was followed");
+}